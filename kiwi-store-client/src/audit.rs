@@ -0,0 +1,80 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Represents what the audit records are searched for. Criteria that are not given match every record.
+pub struct AuditQuery {
+    /// Key the record must have changed
+    pub key: Option<String>,
+    /// Earliest time of the record, inclusive
+    pub from: Option<DateTime<Utc>>,
+    /// Latest time of the record, exclusive
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Parses a time given in RFC 3339 format, e.g. `2026-10-19T08:30:00Z`,
+/// or as a date, e.g. `2026-10-19`, which stands for the start of the day in UTC.
+pub fn parse_time(text: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+        .ok_or_else(|| format!("Expected a date or an RFC 3339 time: {}", text))
+}
+
+/// Searches an audit log and its rotated files, oldest first.
+///
+/// # Returns
+/// Returns the matching records as they are written in the log, one JSON object per line.
+pub fn search(path: &Path, query: &AuditQuery) -> std::io::Result<Vec<String>> {
+    let mut records = Vec::new();
+    for file in files(path) {
+        for line in BufReader::new(File::open(file)?).lines() {
+            let line = line?;
+            let Ok(record) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if query.matches(&record) {
+                records.push(line);
+            }
+        }
+    }
+    Ok(records)
+}
+
+impl AuditQuery {
+    fn matches(&self, record: &Value) -> bool {
+        if let Some(key) = &self.key {
+            let keys = record["keys"].as_array();
+            if !keys.is_some_and(|keys| keys.iter().any(|k| k.as_str() == Some(key))) {
+                return false;
+            }
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        let Some(time) = record["time"]
+            .as_str()
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        else {
+            return false;
+        };
+        self.from.is_none_or(|from| time >= from) && self.to.is_none_or(|to| time < to)
+    }
+}
+
+/// Returns the files of an audit log, oldest first: `audit.log.N` to `audit.log.1` and then `audit.log`.
+fn files(path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = (1..)
+        .map(|n| PathBuf::from(format!("{}.{}", path.display(), n)))
+        .take_while(|file| file.exists())
+        .collect();
+    files.reverse();
+    files.push(path.to_path_buf());
+    files
+}
@@ -1,4 +1,9 @@
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::audit::parse_time;
+use crate::dump::{Conflict, Format};
 
 #[derive(Parser)]
 #[command(
@@ -10,6 +15,31 @@ use clap::{Parser, Subcommand};
 pub struct Cli {
     #[arg(long, short, default_value = "127.0.0.1:5544")]
     pub address: String,
+    #[arg(
+        long,
+        short,
+        global = true,
+        help = "Namespace to run the command in, selected on the same connection"
+    )]
+    pub namespace: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Admin password, sent with AUTH on the same connection before the command"
+    )]
+    pub password: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Name of the connection, shown by CLIENT LIST on the server"
+    )]
+    pub client_name: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "W3C trace context, e.g. 00-<trace id>-<span id>-01, the server traces the command as its child"
+    )]
+    pub traceparent: Option<String>,
     #[command(subcommand)]
     pub argument: Arguments,
 }
@@ -22,10 +52,397 @@ pub enum Arguments {
     Get { key: String },
     #[command(name = "remove", about = "Remove a key")]
     Remove { key: String },
+    #[command(
+        name = "history",
+        about = "Show the versions kept of a key, newest first"
+    )]
+    History { key: String },
+    #[command(name = "getat", about = "Get the value a key held at a point in time")]
+    GetAt {
+        key: String,
+        #[arg(value_parser = parse_time, help = "Date (2026-10-19) or RFC 3339 time (2026-10-19T08:30:00Z)")]
+        time: DateTime<Utc>,
+    },
+    #[command(
+        name = "rollback",
+        about = "Write a version from the history of a key as its value"
+    )]
+    Rollback { key: String, version: u64 },
     #[command(name = "ping", about = "Ping the server for health check")]
     Ping,
     #[command(name = "list", about = "List all keys in the store")]
-    List,
+    List {
+        #[arg(
+            long = "match",
+            short,
+            help = "Only list keys matching a glob pattern, fetched page by page with SCAN"
+        )]
+        pattern: Option<String>,
+    },
     #[command(name = "stats", about = "Get store statistics")]
     Stats,
+    #[command(
+        name = "info",
+        about = "Get the server report: server, clients, memory, persistence, replication, commandstats or keyspace"
+    )]
+    Info {
+        #[arg(help = "Section to get, every section if omitted")]
+        section: Option<String>,
+        #[arg(long, help = "Get the report as JSON")]
+        json: bool,
+    },
+    #[command(name = "lpush", about = "Push a value to the head of a list")]
+    LPush { key: String, value: String },
+    #[command(name = "rpush", about = "Push a value to the tail of a list")]
+    RPush { key: String, value: String },
+    #[command(name = "lpop", about = "Pop a value from the head of a list")]
+    LPop { key: String },
+    #[command(name = "rpop", about = "Pop a value from the tail of a list")]
+    RPop { key: String },
+    #[command(name = "lrange", about = "Get a range of items from a list")]
+    LRange {
+        key: String,
+        #[arg(default_value_t = 0, allow_hyphen_values = true)]
+        start: i64,
+        #[arg(default_value_t = -1, allow_hyphen_values = true)]
+        stop: i64,
+    },
+    #[command(name = "llen", about = "Get the length of a list")]
+    LLen { key: String },
+    #[command(
+        name = "blpop",
+        about = "Pop a value from the head of a list, waiting for one if it is empty"
+    )]
+    BLPop {
+        key: String,
+        #[arg(default_value_t = 0, help = "Seconds to wait, 0 waits forever")]
+        timeout: u64,
+    },
+    #[command(name = "sadd", about = "Add a member to a set")]
+    SAdd { key: String, member: String },
+    #[command(name = "srem", about = "Remove a member from a set")]
+    SRem { key: String, member: String },
+    #[command(name = "sismember", about = "Check whether a member is in a set")]
+    SIsMember { key: String, member: String },
+    #[command(name = "smembers", about = "Get all members of a set")]
+    SMembers { key: String },
+    #[command(name = "sinter", about = "Get the members common to all given sets")]
+    SInter {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    #[command(name = "sunion", about = "Get the members of any of the given sets")]
+    SUnion {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    #[command(name = "zadd", about = "Add a member with a score to a sorted set")]
+    ZAdd {
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        score: f64,
+        member: String,
+    },
+    #[command(name = "zincrby", about = "Increment the score of a sorted set member")]
+    ZIncrBy {
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        increment: f64,
+        member: String,
+    },
+    #[command(name = "zrem", about = "Remove a member from a sorted set")]
+    ZRem { key: String, member: String },
+    #[command(name = "zrange", about = "Get sorted set members by rank")]
+    ZRange {
+        key: String,
+        #[arg(default_value_t = 0, allow_hyphen_values = true)]
+        start: i64,
+        #[arg(default_value_t = -1, allow_hyphen_values = true)]
+        stop: i64,
+        #[arg(long, help = "Include the scores in the output")]
+        with_scores: bool,
+    },
+    #[command(
+        name = "zrangebyscore",
+        about = "Get sorted set members within a score range"
+    )]
+    ZRangeByScore {
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        min: String,
+        #[arg(allow_hyphen_values = true)]
+        max: String,
+        #[arg(long, help = "Include the scores in the output")]
+        with_scores: bool,
+    },
+    #[command(
+        name = "replicaof",
+        about = "Make the server follow a leader, or use `replicaof no one` to make it a leader again"
+    )]
+    ReplicaOf { host: String, port: String },
+    #[command(name = "json", about = "Work with JSON documents")]
+    Json {
+        #[command(subcommand)]
+        action: JsonArguments,
+    },
+    #[command(name = "shutdown", about = "Stop the server gracefully")]
+    Shutdown {
+        #[arg(long, help = "Exit without saving the data")]
+        nosave: bool,
+    },
+    #[command(name = "cluster", about = "Manage the hash slots of a sharded cluster")]
+    Cluster {
+        #[command(subcommand)]
+        action: ClusterArguments,
+    },
+    #[command(
+        name = "config",
+        about = "Inspect and change the settings of the running server"
+    )]
+    Config {
+        #[command(subcommand)]
+        action: ConfigArguments,
+    },
+    #[command(
+        name = "client",
+        about = "Inspect and end the connections of the server"
+    )]
+    Client {
+        #[command(subcommand)]
+        action: ClientArguments,
+    },
+    #[command(
+        name = "audit",
+        about = "Search the audit log written by the server, with its rotated files"
+    )]
+    Audit {
+        #[arg(help = "Path of the audit log, the audit_log setting of the server")]
+        file: PathBuf,
+        #[arg(long, help = "Only records that changed this key")]
+        key: Option<String>,
+        #[arg(long, value_parser = parse_time, help = "Only records at or after this date or RFC 3339 time")]
+        from: Option<DateTime<Utc>>,
+        #[arg(long, value_parser = parse_time, help = "Only records before this date or RFC 3339 time")]
+        to: Option<DateTime<Utc>>,
+    },
+    #[command(
+        name = "export",
+        about = "Export the keys of the store to a JSON, CSV or .env file, streamed with DUMP"
+    )]
+    Export {
+        #[arg(help = "File to write, the standard output if not given")]
+        file: Option<PathBuf>,
+        #[arg(
+            long,
+            short,
+            value_enum,
+            help = "Format of the file, guessed from its name if not given, JSON on the standard output"
+        )]
+        format: Option<Format>,
+        #[arg(
+            long = "match",
+            short = 'm',
+            help = "Only export keys matching a glob pattern"
+        )]
+        pattern: Option<String>,
+    },
+    #[command(
+        name = "import",
+        about = "Import the keys of a JSON, CSV or .env file into the store, streamed with RESTORE"
+    )]
+    Import {
+        file: PathBuf,
+        #[arg(
+            long,
+            short,
+            value_enum,
+            help = "Format of the file, guessed from its name if not given"
+        )]
+        format: Option<Format>,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "fail",
+            help = "What to do with a key that already exists"
+        )]
+        on_conflict: Conflict,
+        #[arg(long, help = "Only check the entries and report what would be written")]
+        dry_run: bool,
+    },
+    #[command(
+        name = "settings",
+        about = "Push appsettings.json and .env files to the store and pull them back"
+    )]
+    Settings {
+        #[command(subcommand)]
+        action: SettingsArguments,
+    },
+    #[command(
+        name = "monitor",
+        about = "Print every command the server processes until interrupted"
+    )]
+    Monitor,
+    #[command(
+        name = "slowlog",
+        about = "Inspect the commands that took longer than the slow log threshold"
+    )]
+    SlowLog {
+        #[command(subcommand)]
+        action: SlowLogArguments,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SettingsArguments {
+    #[command(
+        name = "push",
+        about = "Write the settings of a file as flattened keys, showing the adds, changes and removals first"
+    )]
+    Push {
+        file: PathBuf,
+        #[command(flatten)]
+        shape: SettingsShape,
+        #[arg(
+            long,
+            requires = "prefix",
+            help = "Also remove the keys under the prefix that are not in the file"
+        )]
+        prune: bool,
+        #[arg(long, help = "Only show the changes")]
+        dry_run: bool,
+    },
+    #[command(
+        name = "pull",
+        about = "Write the keys of the store back as an appsettings.json or a .env file"
+    )]
+    Pull {
+        #[arg(help = "File to write, the standard output if not given")]
+        file: Option<PathBuf>,
+        #[command(flatten)]
+        shape: SettingsShape,
+    },
+}
+
+#[derive(Args)]
+pub struct SettingsShape {
+    #[arg(
+        long,
+        short,
+        value_enum,
+        help = "json or env, guessed from the name of the file if not given, JSON on the standard output"
+    )]
+    pub format: Option<Format>,
+    #[arg(
+        long,
+        default_value = ":",
+        help = "Separator of the key segments, e.g. Logging:LogLevel:Default"
+    )]
+    pub separator: String,
+    #[arg(
+        long,
+        help = "Prefix of the keys in the store, e.g. Orders: keeps the settings of a service apart"
+    )]
+    pub prefix: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum ClientArguments {
+    #[command(name = "list", about = "List the connected clients")]
+    List,
+    #[command(
+        name = "kill",
+        about = "End the connections of an address (IP:port) or, with --user, of a user"
+    )]
+    Kill {
+        #[arg(required_unless_present = "user", conflicts_with = "user")]
+        address: Option<String>,
+        #[arg(long, help = "User whose connections are ended, e.g. admin or default")]
+        user: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SlowLogArguments {
+    #[command(name = "get", about = "Get the latest slow commands, newest first")]
+    Get {
+        #[arg(default_value_t = 10)]
+        count: usize,
+    },
+    #[command(name = "len", about = "Get the number of commands in the slow log")]
+    Len,
+    #[command(name = "reset", about = "Empty the slow log")]
+    Reset,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigArguments {
+    #[command(name = "get", about = "Get the settings matching a glob pattern")]
+    Get {
+        #[arg(default_value = "*")]
+        pattern: String,
+    },
+    #[command(name = "set", about = "Change a setting of the running server")]
+    Set { name: String, value: String },
+    #[command(
+        name = "rewrite",
+        about = "Write the running settings to the configuration file of the server"
+    )]
+    Rewrite,
+    #[command(
+        name = "reload",
+        about = "Load the configuration of the server again and apply it"
+    )]
+    Reload,
+}
+
+#[derive(Subcommand)]
+pub enum JsonArguments {
+    #[command(name = "set", about = "Set the value at a path of a JSON document")]
+    Set {
+        key: String,
+        #[arg(help = "Path inside the document, $ for the root")]
+        path: String,
+        json: String,
+    },
+    #[command(name = "get", about = "Get the value at a path of a JSON document")]
+    Get {
+        key: String,
+        #[arg(default_value = "$")]
+        path: String,
+    },
+    #[command(name = "del", about = "Delete the value at a path of a JSON document")]
+    Del {
+        key: String,
+        #[arg(default_value = "$")]
+        path: String,
+    },
+    #[command(
+        name = "numincrby",
+        about = "Increment the number at a path of a JSON document"
+    )]
+    NumIncrBy {
+        key: String,
+        path: String,
+        #[arg(allow_hyphen_values = true)]
+        increment: f64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ClusterArguments {
+    #[command(
+        name = "slots",
+        about = "Show the slot ranges and the node owning each of them"
+    )]
+    Slots,
+    #[command(
+        name = "migrate",
+        about = "Move a range of slots with their keys to another node"
+    )]
+    Migrate {
+        start: u16,
+        end: u16,
+        #[arg(help = "Address of the node receiving the slots, in the format IP:port")]
+        node: String,
+    },
 }
@@ -1,12 +1,64 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::dump::Conflict;
+
 #[derive(Debug)]
 #[allow(dead_code)]
+#[rustfmt::skip]
 pub enum Command {
     Set { key: String, value: String },
     Get { key: String },
     Remove { key: String },
+    History { key: String },
+    Dump { pattern: Option<String> },
+    Restore { conflict: Conflict, dry_run: bool },
+    GetAt { key: String, time: DateTime<Utc> },
+    Rollback { key: String, version: u64 },
     Ping,
     List,
     Stats,
+    Info { section: Option<String>, json: bool },
+    Scan { cursor: String, pattern: String },
+    Select { name: String },
+    ReplicaOf { host: String, port: String },
+    Shutdown { nosave: bool },
+    ClusterSlots,
+    ConfigGet { pattern: String },
+    ConfigSet { name: String, value: String },
+    ConfigRewrite,
+    ConfigReload,
+    SlowLogGet { count: usize },
+    SlowLogLen,
+    SlowLogReset,
+    Monitor,
+    ClientList,
+    ClientSetName { name: String },
+    TraceParent { value: String },
+    ClientKill { address: Option<String>, user: Option<String> },
+    Auth { password: String },
+    ClusterMigrate { start: u16, end: u16, node: String },
+    LPush { key: String, value: String },
+    RPush { key: String, value: String },
+    LPop { key: String },
+    RPop { key: String },
+    LRange { key: String, start: i64, stop: i64 },
+    LLen { key: String },
+    BLPop { key: String, timeout: u64 },
+    SAdd { key: String, member: String },
+    SRem { key: String, member: String },
+    SIsMember { key: String, member: String },
+    SMembers { key: String },
+    SInter { keys: Vec<String> },
+    SUnion { keys: Vec<String> },
+    ZAdd { key: String, score: f64, member: String },
+    ZIncrBy { key: String, increment: f64, member: String },
+    ZRem { key: String, member: String },
+    ZRange { key: String, start: i64, stop: i64, with_scores: bool },
+    JsonSet { key: String, path: String, json: String },
+    JsonGet { key: String, path: String },
+    JsonDel { key: String, path: String },
+    JsonNumIncrBy { key: String, path: String, increment: f64 },
+    ZRangeByScore { key: String, min: String, max: String, with_scores: bool },
 }
 
 #[allow(dead_code)]
@@ -16,9 +68,156 @@ impl Command {
             Command::Set { key, value } => format!("SET {} {}", key, value),
             Command::Get { key } => format!("GET {}", key),
             Command::Remove { key } => format!("REMOVE {}", key),
+            Command::History { key } => format!("HISTORY {}", key),
+            Command::Dump { pattern: None } => "DUMP".to_string(),
+            Command::Dump {
+                pattern: Some(pattern),
+            } => format!("DUMP MATCH {}", pattern),
+            Command::Restore { conflict, dry_run } => format!(
+                "RESTORE {}{}",
+                conflict.as_option(),
+                if *dry_run { " DRYRUN" } else { "" }
+            ),
+            Command::GetAt { key, time } => format!(
+                "GETAT {} {}",
+                key,
+                time.to_rfc3339_opts(SecondsFormat::Millis, true)
+            ),
+            Command::Rollback { key, version } => format!("ROLLBACK {} {}", key, version),
             Command::Ping => "PING".to_string(),
             Command::List => "LIST".to_string(),
             Command::Stats => "STATS".to_string(),
+            Command::Info { section, json } => {
+                let mut message = "INFO".to_string();
+                if let Some(section) = section {
+                    message = format!("{} {}", message, section);
+                }
+                if *json {
+                    message += " JSON";
+                }
+                message
+            }
+            Command::Select { name } => format!("SELECT {}", name),
+            Command::ReplicaOf { host, port } => format!("REPLICAOF {} {}", host, port),
+            Command::Shutdown { nosave: true } => "SHUTDOWN NOSAVE".to_string(),
+            Command::Shutdown { nosave: false } => "SHUTDOWN".to_string(),
+            Command::ClusterSlots => "CLUSTER SLOTS".to_string(),
+            Command::ConfigGet { pattern } => format!("CONFIG GET {}", pattern),
+            Command::ConfigSet { name, value } => format!("CONFIG SET {} {}", name, value),
+            Command::ConfigRewrite => "CONFIG REWRITE".to_string(),
+            Command::ConfigReload => "CONFIG RELOAD".to_string(),
+            Command::SlowLogGet { count } => format!("SLOWLOG GET {}", count),
+            Command::SlowLogLen => "SLOWLOG LEN".to_string(),
+            Command::SlowLogReset => "SLOWLOG RESET".to_string(),
+            Command::Monitor => "MONITOR".to_string(),
+            Command::ClientList => "CLIENT LIST".to_string(),
+            Command::ClientSetName { name } => format!("CLIENT SETNAME {}", name),
+            Command::TraceParent { value } => format!("TRACEPARENT {}", value),
+            Command::ClientKill {
+                user: Some(user), ..
+            } => format!("CLIENT KILL USER {}", user),
+            Command::ClientKill { address, .. } => {
+                format!(
+                    "CLIENT KILL ADDR {}",
+                    address.as_deref().unwrap_or_default()
+                )
+            }
+            Command::Auth { password } => format!("AUTH {}", password),
+            Command::ClusterMigrate { start, end, node } => {
+                format!("CLUSTER MIGRATE {} {} {}", start, end, node)
+            }
+            Command::Scan { cursor, pattern } => {
+                format!("SCAN {} MATCH {} COUNT 25", cursor, pattern)
+            }
+            Command::LPush { key, value } => format!("LPUSH {} {}", key, value),
+            Command::RPush { key, value } => format!("RPUSH {} {}", key, value),
+            Command::LPop { key } => format!("LPOP {}", key),
+            Command::RPop { key } => format!("RPOP {}", key),
+            Command::LRange { key, start, stop } => format!("LRANGE {} {} {}", key, start, stop),
+            Command::LLen { key } => format!("LLEN {}", key),
+            Command::BLPop { key, timeout } => format!("BLPOP {} {}", key, timeout),
+            Command::SAdd { key, member } => format!("SADD {} {}", key, member),
+            Command::SRem { key, member } => format!("SREM {} {}", key, member),
+            Command::SIsMember { key, member } => format!("SISMEMBER {} {}", key, member),
+            Command::SMembers { key } => format!("SMEMBERS {}", key),
+            Command::SInter { keys } => format!("SINTER {}", keys.join(" ")),
+            Command::SUnion { keys } => format!("SUNION {}", keys.join(" ")),
+            Command::ZAdd { key, score, member } => format!("ZADD {} {} {}", key, score, member),
+            Command::ZIncrBy {
+                key,
+                increment,
+                member,
+            } => format!("ZINCRBY {} {} {}", key, increment, member),
+            Command::ZRem { key, member } => format!("ZREM {} {}", key, member),
+            Command::ZRange {
+                key,
+                start,
+                stop,
+                with_scores,
+            } => with_scores_suffix(format!("ZRANGE {} {} {}", key, start, stop), *with_scores),
+            Command::JsonSet { key, path, json } => format!("JSON.SET {} {} {}", key, path, json),
+            Command::JsonGet { key, path } => format!("JSON.GET {} {}", key, path),
+            Command::JsonDel { key, path } => format!("JSON.DEL {} {}", key, path),
+            Command::JsonNumIncrBy {
+                key,
+                path,
+                increment,
+            } => format!("JSON.NUMINCRBY {} {} {}", key, path, increment),
+            Command::ZRangeByScore {
+                key,
+                min,
+                max,
+                with_scores,
+            } => with_scores_suffix(
+                format!("ZRANGEBYSCORE {} {} {}", key, min, max),
+                *with_scores,
+            ),
         }
     }
 }
+
+impl Command {
+    /// Returns the keys the command works on, used to find the node owning them.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::Get { key }
+            | Command::Remove { key }
+            | Command::History { key }
+            | Command::GetAt { key, .. }
+            | Command::Rollback { key, .. }
+            | Command::LPush { key, .. }
+            | Command::RPush { key, .. }
+            | Command::LPop { key }
+            | Command::RPop { key }
+            | Command::LRange { key, .. }
+            | Command::LLen { key }
+            | Command::BLPop { key, .. }
+            | Command::SAdd { key, .. }
+            | Command::SRem { key, .. }
+            | Command::SIsMember { key, .. }
+            | Command::SMembers { key }
+            | Command::ZAdd { key, .. }
+            | Command::ZIncrBy { key, .. }
+            | Command::ZRem { key, .. }
+            | Command::ZRange { key, .. }
+            | Command::ZRangeByScore { key, .. }
+            | Command::JsonSet { key, .. }
+            | Command::JsonGet { key, .. }
+            | Command::JsonDel { key, .. }
+            | Command::JsonNumIncrBy { key, .. } => vec![key.as_str()],
+            Command::SInter { keys } | Command::SUnion { keys } => {
+                keys.iter().map(|k| k.as_str()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn with_scores_suffix(message: String, with_scores: bool) -> String {
+    if with_scores {
+        message + " WITHSCORES"
+    } else {
+        message
+    }
+}
@@ -0,0 +1,226 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+/// Line that ends the entries of DUMP and RESTORE
+pub const END: &str = "END";
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+/// Represents the file formats the store can be exported to and imported from
+pub enum Format {
+    /// Array of entries with their keys, types and values
+    Json,
+    /// `key,type,value` rows, values of other types than string are written as JSON
+    Csv,
+    /// `KEY="value"` lines, only string values
+    Env,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+/// Represents what the import does with a key that already exists
+pub enum Conflict {
+    /// Replace the value of the key
+    Overwrite,
+    /// Leave the key as it is
+    Skip,
+    /// Stop at the first key that already exists, the entries before it are already written.
+    /// The import checks the file with a dry run first, so it usually stops before writing
+    /// anything, unless the key is written by another client in between
+    Fail,
+}
+
+#[derive(Serialize, Deserialize)]
+/// Represents a key of the store as the server dumps and restores it
+pub struct Entry {
+    pub key: String,
+    /// `string`, `list`, `set`, `zset` or `json`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+/// Represents a row of a CSV file. A file without a `type` column holds strings.
+struct Row {
+    key: String,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    value: String,
+}
+
+impl Format {
+    /// Guesses the format of a file from its name: `.json`, `.csv`, or `.env` and `*.env`.
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".json") {
+            Some(Format::Json)
+        } else if name.ends_with(".csv") {
+            Some(Format::Csv)
+        } else if name.ends_with(".env") || name.starts_with(".env") {
+            Some(Format::Env)
+        } else {
+            None
+        }
+    }
+}
+
+impl Conflict {
+    /// Returns the option RESTORE takes for the policy.
+    pub fn as_option(&self) -> &'static str {
+        match self {
+            Conflict::Overwrite => "OVERWRITE",
+            Conflict::Skip => "SKIP",
+            Conflict::Fail => "FAIL",
+        }
+    }
+}
+
+/// Writes the entries of an export one at a time, so the store is never held in memory as a whole.
+pub struct Exporter<W: Write> {
+    sink: Sink<W>,
+    written: usize,
+    /// Entries a .env file can't hold: other types than string and keys that are not variable names
+    pub skipped: usize,
+}
+
+enum Sink<W: Write> {
+    Json(W),
+    Csv(Box<csv::Writer<W>>),
+    Env(W),
+}
+
+impl<W: Write> Exporter<W> {
+    pub fn new(format: Format, out: W) -> Self {
+        let sink = match format {
+            Format::Json => Sink::Json(out),
+            Format::Csv => Sink::Csv(Box::new(csv::Writer::from_writer(out))),
+            Format::Env => Sink::Env(out),
+        };
+        Self {
+            sink,
+            written: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Writes an entry in the format of the export.
+    pub fn write(&mut self, entry: &Entry) -> Result<(), String> {
+        match &mut self.sink {
+            Sink::Json(out) => {
+                let separator = if self.written == 0 { "[\n" } else { ",\n" };
+                let json = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+                write!(out, "{}  {}", separator, json).map_err(|e| e.to_string())?;
+            }
+            Sink::Csv(csv) => {
+                let value = match &entry.value {
+                    Value::String(text) if entry.kind == "string" => text.clone(),
+                    value => value.to_string(),
+                };
+                csv.serialize(Row {
+                    key: entry.key.clone(),
+                    kind: Some(entry.kind.clone()),
+                    value,
+                })
+                .map_err(|e| e.to_string())?;
+            }
+            Sink::Env(out) => match &entry.value {
+                Value::String(text) if entry.kind == "string" && is_variable_name(&entry.key) => {
+                    writeln!(out, "{}={}", entry.key, quote(text)).map_err(|e| e.to_string())?;
+                }
+                _ => {
+                    self.skipped += 1;
+                    return Ok(());
+                }
+            },
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Ends the export, closing the JSON array and flushing what is buffered.
+    ///
+    /// # Returns
+    /// Returns the number of entries written.
+    pub fn finish(mut self) -> Result<usize, String> {
+        match &mut self.sink {
+            Sink::Json(out) => {
+                let end = if self.written == 0 { "[]\n" } else { "\n]\n" };
+                out.write_all(end.as_bytes()).map_err(|e| e.to_string())?;
+                out.flush().map_err(|e| e.to_string())?;
+            }
+            Sink::Csv(csv) => csv.flush().map_err(|e| e.to_string())?,
+            Sink::Env(out) => out.flush().map_err(|e| e.to_string())?,
+        }
+        Ok(self.written)
+    }
+}
+
+/// Reads the entries of a file to import. CSV and .env files are read a line at a time,
+/// a JSON file is read as a whole.
+pub fn read(
+    format: Format,
+    path: &Path,
+) -> Result<Box<dyn Iterator<Item = Result<Entry, String>>>, String> {
+    let error = |e: &dyn std::fmt::Display| format!("{} can't be read: {}", path.display(), e);
+    match format {
+        Format::Json => {
+            let file = File::open(path).map_err(|e| error(&e))?;
+            let entries: Vec<Entry> =
+                serde_json::from_reader(BufReader::new(file)).map_err(|e| error(&e))?;
+            Ok(Box::new(entries.into_iter().map(Ok)))
+        }
+        Format::Csv => {
+            let reader = csv::Reader::from_path(path).map_err(|e| error(&e))?;
+            Ok(Box::new(reader.into_deserialize::<Row>().map(|row| {
+                let row = row.map_err(|e| e.to_string())?;
+                let kind = row.kind.unwrap_or_else(|| "string".to_string());
+                let value = if kind == "string" {
+                    Value::String(row.value)
+                } else {
+                    serde_json::from_str(&row.value)
+                        .map_err(|e| format!("Invalid {} value of {}: {}", kind, row.key, e))?
+                };
+                Ok(Entry {
+                    key: row.key,
+                    kind,
+                    value,
+                })
+            })))
+        }
+        Format::Env => {
+            let lines = dotenvy::from_path_iter(path).map_err(|e| error(&e))?;
+            Ok(Box::new(lines.map(|line| {
+                let (key, value) = line.map_err(|e| e.to_string())?;
+                Ok(Entry {
+                    key,
+                    kind: "string".to_string(),
+                    value: Value::String(value),
+                })
+            })))
+        }
+    }
+}
+
+/// Checks whether a key can be written as a variable of a .env file.
+pub fn is_variable_name(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Quotes a .env value, escaping what the double quotes would otherwise interpret.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
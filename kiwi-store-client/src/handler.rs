@@ -1,41 +1,685 @@
 use clap::Parser;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::TcpStream;
 
-use crate::cli::{Arguments, Cli};
+use crate::audit::{self, AuditQuery};
+use crate::cli::{
+    Arguments, Cli, ClientArguments, ClusterArguments, ConfigArguments, JsonArguments,
+    SettingsArguments, SettingsShape, SlowLogArguments,
+};
 use crate::command::Command;
+use crate::dump::{self, Conflict, END, Entry, Exporter, Format};
+use crate::settings::{self, Diff};
+use crate::slots::SlotCache;
 
 pub async fn handle_command() -> tokio::io::Result<()> {
     let cli = Cli::parse();
+    let session = Session {
+        namespace: cli.namespace.clone(),
+        password: cli.password.clone(),
+        name: cli.client_name.clone(),
+        traceparent: cli.traceparent.clone(),
+    };
+    let pretty_json = matches!(
+        cli.argument,
+        Arguments::Json { .. } | Arguments::Info { json: true, .. }
+    );
 
-    let message: String = match cli.argument {
-        Arguments::Set { key, value } => Command::Set { key, value }.to_message(),
-        Arguments::Get { key } => Command::Get { key }.to_message(),
-        Arguments::Remove { key } => Command::Remove { key }.to_message(),
-        Arguments::Ping => Command::Ping.to_message(),
-        Arguments::List => Command::List.to_message(),
-        Arguments::Stats => Command::Stats.to_message(),
+    let command: Command = match cli.argument {
+        Arguments::Set { key, value } => Command::Set { key, value },
+        Arguments::Get { key } => Command::Get { key },
+        Arguments::Remove { key } => Command::Remove { key },
+        Arguments::History { key } => Command::History { key },
+        Arguments::GetAt { key, time } => Command::GetAt { key, time },
+        Arguments::Rollback { key, version } => Command::Rollback { key, version },
+        Arguments::Ping => Command::Ping,
+        Arguments::List { pattern: None } => Command::List,
+        Arguments::List {
+            pattern: Some(pattern),
+        } => {
+            let keys = scan_keys(&cli.address, &session, &pattern).await?;
+            if keys.is_empty() {
+                println!("NO MATCHING KEYS");
+            } else {
+                println!("{}", keys.join("\n"));
+            }
+            return Ok(());
+        }
+        Arguments::Stats => Command::Stats,
+        Arguments::Info { section, json } => Command::Info { section, json },
+        Arguments::LPush { key, value } => Command::LPush { key, value },
+        Arguments::RPush { key, value } => Command::RPush { key, value },
+        Arguments::LPop { key } => Command::LPop { key },
+        Arguments::RPop { key } => Command::RPop { key },
+        Arguments::LRange { key, start, stop } => Command::LRange { key, start, stop },
+        Arguments::LLen { key } => Command::LLen { key },
+        Arguments::BLPop { key, timeout } => Command::BLPop { key, timeout },
+        Arguments::SAdd { key, member } => Command::SAdd { key, member },
+        Arguments::SRem { key, member } => Command::SRem { key, member },
+        Arguments::SIsMember { key, member } => Command::SIsMember { key, member },
+        Arguments::SMembers { key } => Command::SMembers { key },
+        Arguments::SInter { keys } => Command::SInter { keys },
+        Arguments::SUnion { keys } => Command::SUnion { keys },
+        Arguments::ZAdd { key, score, member } => Command::ZAdd { key, score, member },
+        Arguments::ZIncrBy {
+            key,
+            increment,
+            member,
+        } => Command::ZIncrBy {
+            key,
+            increment,
+            member,
+        },
+        Arguments::ZRem { key, member } => Command::ZRem { key, member },
+        Arguments::ZRange {
+            key,
+            start,
+            stop,
+            with_scores,
+        } => Command::ZRange {
+            key,
+            start,
+            stop,
+            with_scores,
+        },
+        Arguments::ZRangeByScore {
+            key,
+            min,
+            max,
+            with_scores,
+        } => Command::ZRangeByScore {
+            key,
+            min,
+            max,
+            with_scores,
+        },
+        Arguments::ReplicaOf { host, port } => Command::ReplicaOf { host, port },
+        Arguments::Json { action } => match action {
+            JsonArguments::Set { key, path, json } => Command::JsonSet { key, path, json },
+            JsonArguments::Get { key, path } => Command::JsonGet { key, path },
+            JsonArguments::Del { key, path } => Command::JsonDel { key, path },
+            JsonArguments::NumIncrBy {
+                key,
+                path,
+                increment,
+            } => Command::JsonNumIncrBy {
+                key,
+                path,
+                increment,
+            },
+        },
+        Arguments::Shutdown { nosave } => Command::Shutdown { nosave },
+        Arguments::Cluster { action } => match action {
+            ClusterArguments::Slots => Command::ClusterSlots,
+            ClusterArguments::Migrate { start, end, node } => {
+                Command::ClusterMigrate { start, end, node }
+            }
+        },
+        Arguments::Config { action } => match action {
+            ConfigArguments::Get { pattern } => Command::ConfigGet { pattern },
+            ConfigArguments::Set { name, value } => Command::ConfigSet { name, value },
+            ConfigArguments::Rewrite => Command::ConfigRewrite,
+            ConfigArguments::Reload => Command::ConfigReload,
+        },
+        Arguments::SlowLog { action } => match action {
+            SlowLogArguments::Get { count } => Command::SlowLogGet { count },
+            SlowLogArguments::Len => Command::SlowLogLen,
+            SlowLogArguments::Reset => Command::SlowLogReset,
+        },
+        Arguments::Client { action } => match action {
+            ClientArguments::List => Command::ClientList,
+            ClientArguments::Kill { address, user } => Command::ClientKill { address, user },
+        },
+        Arguments::Audit {
+            file,
+            key,
+            from,
+            to,
+        } => {
+            let records = audit::search(&file, &AuditQuery { key, from, to })?;
+            if records.is_empty() {
+                println!("NO MATCHING RECORDS");
+            } else {
+                println!("{}", records.join("\n"));
+            }
+            return Ok(());
+        }
+        Arguments::Monitor => return monitor(&cli.address, &session).await,
+        Arguments::Export {
+            file,
+            format,
+            pattern,
+        } => {
+            let format = match (format, &file) {
+                (Some(format), _) => format,
+                (None, Some(file)) => file_format(file)?,
+                (None, None) => Format::Json,
+            };
+            let command = Command::Dump { pattern };
+            return export(&cli.address, &session, &command, format, file.as_deref()).await;
+        }
+        Arguments::Import {
+            file,
+            format,
+            on_conflict,
+            dry_run,
+        } => {
+            let format = match format {
+                Some(format) => format,
+                None => file_format(&file)?,
+            };
+            // A conflict is found with a dry run first, so a failed import usually writes nothing.
+            // A key written by another client after the check still stops the import midway
+            if matches!(on_conflict, Conflict::Fail) && !dry_run {
+                let check =
+                    import(&cli.address, &session, &file, format, on_conflict, true).await?;
+                if check.starts_with("ERROR") {
+                    println!("{}", check.trim());
+                    return Ok(());
+                }
+            }
+            let response =
+                import(&cli.address, &session, &file, format, on_conflict, dry_run).await?;
+            println!("{}", response.trim());
+            return Ok(());
+        }
+        Arguments::Settings { action } => {
+            return match action {
+                SettingsArguments::Push {
+                    file,
+                    shape,
+                    prune,
+                    dry_run,
+                } => push_settings(&cli.address, &session, &file, &shape, prune, dry_run).await,
+                SettingsArguments::Pull { file, shape } => {
+                    pull_settings(&cli.address, &session, file.as_deref(), &shape).await
+                }
+            };
+        }
     };
 
-    let message = message + "\n";
+    let Some(response) = send_routed(&cli.address, &session, &command).await? else {
+        eprintln!("Connection closed by the server.");
+        return Ok(());
+    };
 
-    let mut stream = TcpStream::connect(cli.address).await?;
-    stream.write_all(message.as_bytes()).await?;
+    if response.trim() == "PONG" {
+        println!("Server is alive!");
+    } else if let Some(pretty) = pretty_json
+        .then(|| serde_json::from_str::<serde_json::Value>(response.trim()).ok())
+        .flatten()
+        .and_then(|json| serde_json::to_string_pretty(&json).ok())
+    {
+        println!("{}", pretty);
+    } else {
+        println!("{}", response.trim_end());
+    }
 
-    let mut buffer = vec![0; 1024];
-    let bytes_read = stream.read(&mut buffer).await?;
+    Ok(())
+}
 
-    if bytes_read == 0 {
+/// Represents what is set up on a connection before the command is sent
+struct Session {
+    /// Namespace selected with SELECT
+    namespace: Option<String>,
+    /// Admin password sent with AUTH
+    password: Option<String>,
+    /// Name of the connection sent with CLIENT SETNAME
+    name: Option<String>,
+    /// Trace context sent with TRACEPARENT
+    traceparent: Option<String>,
+}
+
+/// Maximum number of `MOVED` redirects followed for a single command
+const MAX_REDIRECTS: usize = 5;
+
+/// Sends a command to the node that owns its keys and follows `MOVED` redirects.
+/// The slot map of a sharded cluster is cached between runs, so later commands
+/// go straight to the right node.
+async fn send_routed(
+    address: &str,
+    session: &Session,
+    command: &Command,
+) -> tokio::io::Result<Option<String>> {
+    let mut cache = SlotCache::load(address);
+    let mut target = command
+        .keys()
+        .first()
+        .and_then(|key| cache.node_for(key))
+        .unwrap_or_else(|| address.to_string());
+    let message = command.to_message();
+
+    for _ in 0..MAX_REDIRECTS {
+        let response = match send(&target, session, &message).await {
+            // A cached node may be gone, start over from the given address
+            Err(_) if target != address => {
+                target = address.to_string();
+                continue;
+            }
+            result => result?,
+        };
+        let Some(moved) = response
+            .as_deref()
+            .and_then(|r| r.trim().strip_prefix("MOVED "))
+        else {
+            return Ok(response);
+        };
+        // A shard answers with `MOVED <slot> <node>`, a Raft follower with `MOVED <leader>`
+        target = match moved.split_once(' ') {
+            Some((_, node)) => {
+                let node = node.to_string();
+                refresh_slots(&mut cache, &node).await;
+                node
+            }
+            None => moved.to_string(),
+        };
+    }
+    Err(tokio::io::Error::other("Too many redirects"))
+}
+
+/// Fetches the slot map from a node and stores it in the cache.
+async fn refresh_slots(cache: &mut SlotCache, node: &str) {
+    let session = Session {
+        namespace: None,
+        password: None,
+        name: None,
+        traceparent: None,
+    };
+    if let Ok(Some(response)) = send(node, &session, &Command::ClusterSlots.to_message()).await {
+        cache.update(&response);
+        cache.save();
+    }
+}
+
+/// Sends a single command to the server and reads its response.
+/// If a password is given, the connection authenticates first, and if a namespace,
+/// a client name or a trace context is given, they are set on the same connection.
+/// Returns `None` if the server closed the connection without answering.
+async fn send(
+    address: &str,
+    session: &Session,
+    message: &str,
+) -> tokio::io::Result<Option<String>> {
+    match open(address, session).await? {
+        Some(mut stream) => exchange(&mut stream, message).await,
+        None => Ok(None),
+    }
+}
+
+/// Connects to the server and sets up the session on the connection.
+/// Returns `None` if the server closed the connection during the setup.
+async fn open(address: &str, session: &Session) -> tokio::io::Result<Option<TcpStream>> {
+    let mut stream = TcpStream::connect(address).await?;
+
+    let auth = session.password.as_ref().map(|password| Command::Auth {
+        password: password.clone(),
+    });
+    let select = session
+        .namespace
+        .as_ref()
+        .map(|name| Command::Select { name: name.clone() });
+    let name = session
+        .name
+        .as_ref()
+        .map(|name| Command::ClientSetName { name: name.clone() });
+    let trace = session
+        .traceparent
+        .as_ref()
+        .map(|value| Command::TraceParent {
+            value: value.clone(),
+        });
+    for setup in auth
+        .iter()
+        .chain(select.iter())
+        .chain(name.iter())
+        .chain(trace.iter())
+    {
+        match exchange(&mut stream, &setup.to_message()).await? {
+            Some(response) if response.trim() == "OK" => {}
+            Some(response) => return Err(tokio::io::Error::other(response.trim().to_string())),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(stream))
+}
+
+/// Sends MONITOR and prints the command feed of the server until the connection is closed.
+async fn monitor(address: &str, session: &Session) -> tokio::io::Result<()> {
+    let Some(mut stream) = open(address, session).await? else {
         eprintln!("Connection closed by the server.");
         return Ok(());
+    };
+    let message = format!("{}\n", Command::Monitor.to_message());
+    stream.write_all(message.as_bytes()).await?;
+
+    let mut lines = BufReader::new(stream).lines();
+    match lines.next_line().await? {
+        Some(line) if line == "OK" => println!("Monitoring {}, press Ctrl-C to stop.", address),
+        Some(line) => {
+            println!("{}", line);
+            return Ok(());
+        }
+        None => {
+            eprintln!("Connection closed by the server.");
+            return Ok(());
+        }
     }
-    let response = String::from_utf8_lossy(&buffer[..bytes_read]);
+    while let Some(line) = lines.next_line().await? {
+        println!("{}", line);
+    }
+    eprintln!("Connection closed by the server.");
+    Ok(())
+}
 
-    if response.trim() == "PONG" {
-        println!("Server is alive!");
-    } else {
-        println!("{}", response);
+/// Sends DUMP and writes the entries it streams to a file, or to the standard output,
+/// one at a time as they arrive.
+async fn export(
+    address: &str,
+    session: &Session,
+    command: &Command,
+    format: Format,
+    file: Option<&Path>,
+) -> tokio::io::Result<()> {
+    let out: Box<dyn Write> = match file {
+        Some(file) => Box::new(std::io::BufWriter::new(File::create(file)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut exporter = Exporter::new(format, out);
+    read_dump(address, session, command, |entry| exporter.write(&entry)).await?;
+
+    let skipped = exporter.skipped;
+    let written = exporter.finish().map_err(tokio::io::Error::other)?;
+    if let Some(file) = file {
+        eprintln!("Exported {} keys to {}", written, file.display());
+    }
+    if skipped > 0 {
+        eprintln!(
+            "Skipped {} keys a .env file can't hold: values other than strings or keys that are not variable names",
+            skipped
+        );
+    }
+    Ok(())
+}
+
+/// Sends DUMP and passes the entries it streams to a function, one at a time as they arrive.
+async fn read_dump(
+    address: &str,
+    session: &Session,
+    command: &Command,
+    mut each: impl FnMut(Entry) -> Result<(), String>,
+) -> tokio::io::Result<()> {
+    let Some(mut stream) = open(address, session).await? else {
+        return Err(tokio::io::Error::other("Connection closed by the server."));
+    };
+    stream
+        .write_all(format!("{}\n", command.to_message()).as_bytes())
+        .await?;
+
+    let mut lines = BufReader::new(stream).lines();
+    loop {
+        let Some(line) = lines.next_line().await? else {
+            return Err(tokio::io::Error::other("Connection closed by the server."));
+        };
+        if line == END {
+            return Ok(());
+        }
+        if let Some(error) = line.strip_prefix("ERROR: ") {
+            return Err(tokio::io::Error::other(error.to_string()));
+        }
+        let entry: Entry = serde_json::from_str(&line).map_err(tokio::io::Error::other)?;
+        each(entry).map_err(tokio::io::Error::other)?;
+    }
+}
+
+/// Sends RESTORE followed by the entries of a file, read and sent one at a time, and `END`.
+/// Returns the response of the server.
+async fn import(
+    address: &str,
+    session: &Session,
+    file: &Path,
+    format: Format,
+    conflict: Conflict,
+    dry_run: bool,
+) -> tokio::io::Result<String> {
+    let entries = dump::read(format, file).map_err(tokio::io::Error::other)?;
+    restore(address, session, entries, conflict, dry_run).await
+}
+
+/// Sends RESTORE followed by the entries, one line each, and `END`.
+/// Returns the response of the server.
+async fn restore(
+    address: &str,
+    session: &Session,
+    entries: impl Iterator<Item = Result<Entry, String>>,
+    conflict: Conflict,
+    dry_run: bool,
+) -> tokio::io::Result<String> {
+    let Some(stream) = open(address, session).await? else {
+        return Err(tokio::io::Error::other("Connection closed by the server."));
+    };
+    let mut stream = BufWriter::new(stream);
+    let command = Command::Restore { conflict, dry_run };
+    stream
+        .write_all(format!("{}\n", command.to_message()).as_bytes())
+        .await?;
+    for entry in entries {
+        let entry = entry.map_err(tokio::io::Error::other)?;
+        let line = serde_json::to_string(&entry).map_err(tokio::io::Error::other)?;
+        stream.write_all(format!("{}\n", line).as_bytes()).await?;
+    }
+    stream.write_all(format!("{}\n", END).as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = String::new();
+    BufReader::new(stream.into_inner())
+        .read_line(&mut response)
+        .await?;
+    if response.is_empty() {
+        return Err(tokio::io::Error::other("Connection closed by the server."));
+    }
+    Ok(response)
+}
+
+/// Reads the settings of a file and writes them to the store under the prefix,
+/// printing the keys added, changed and removed first.
+async fn push_settings(
+    address: &str,
+    session: &Session,
+    file: &Path,
+    shape: &SettingsShape,
+    prune: bool,
+    dry_run: bool,
+) -> tokio::io::Result<()> {
+    let format = match shape.format {
+        Some(format) => format,
+        None => file_format(file)?,
+    };
+    let prefix = shape.prefix.as_deref().unwrap_or_default();
+    let read = settings::read(file, format, &shape.separator).map_err(tokio::io::Error::other)?;
+    if !read.empty.is_empty() {
+        println!(
+            "Empty and null settings are left out, the store can't hold them: {}",
+            read.empty.join(", ")
+        );
+    }
+    let wanted: BTreeMap<String, String> = read
+        .values
+        .into_iter()
+        .map(|(key, value)| (format!("{}{}", prefix, key), value))
+        .collect();
+    let current = fetch_settings(address, session, prefix).await?;
+    let mut diff = Diff::new(&current, &wanted);
+
+    for line in diff.describe() {
+        println!("{}", line);
+    }
+    println!(
+        "{} to add, {} to change, {} to remove",
+        diff.added.len(),
+        diff.changed.len(),
+        diff.removed.len()
+    );
+    if !prune && !diff.removed.is_empty() {
+        println!("Keys to remove are kept, use --prune to remove them");
+        diff.removed.clear();
+    }
+    if dry_run || diff.is_empty() {
+        return Ok(());
+    }
+
+    let entries = || {
+        let added = diff.added.iter().map(|(key, value)| (key, value));
+        let changed = diff.changed.iter().map(|(key, _, value)| (key, value));
+        added.chain(changed).map(|(key, value)| {
+            Ok(Entry {
+                key: key.clone(),
+                kind: "string".to_string(),
+                value: serde_json::Value::String(value.clone()),
+            })
+        })
+    };
+    // Every setting is checked with a dry run first, so a rejected one writes nothing
+    for dry_run in [true, false] {
+        let response = restore(address, session, entries(), Conflict::Overwrite, dry_run).await?;
+        if response.starts_with("ERROR") {
+            println!("{}", response.trim());
+            if response.contains("Key exceeds maximum length")
+                && let Some(longest) = wanted.keys().max_by_key(|key| key.len())
+            {
+                println!(
+                    "Settings keys, with the prefix, must fit in the max_key_length of the namespace, \
+                     20 in the default mode. The longest is {} ({}), raise the limit with \
+                     CONFIG SET max_key_length or the mode of the namespace",
+                    longest,
+                    longest.len()
+                );
+            }
+            return Ok(());
+        }
+    }
+    for (key, _) in &diff.removed {
+        let command = Command::Remove { key: key.clone() };
+        if let Some(response) = send_routed(address, session, &command).await?
+            && response.starts_with("ERROR")
+        {
+            println!("{}", response.trim());
+            return Ok(());
+        }
     }
+    println!("Settings of {} pushed", file.display());
+    Ok(())
+}
 
+/// Writes the settings under the prefix back as an appsettings.json or a .env file,
+/// or to the standard output.
+async fn pull_settings(
+    address: &str,
+    session: &Session,
+    file: Option<&Path>,
+    shape: &SettingsShape,
+) -> tokio::io::Result<()> {
+    let format = match (shape.format, file) {
+        (Some(format), _) => format,
+        (None, Some(file)) => file_format(file)?,
+        (None, None) => Format::Json,
+    };
+    let prefix = shape.prefix.as_deref().unwrap_or_default();
+    let current: BTreeMap<String, String> = fetch_settings(address, session, prefix)
+        .await?
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.strip_prefix(prefix)?.to_string(), value)))
+        .collect();
+    let text =
+        settings::render(&current, format, &shape.separator).map_err(tokio::io::Error::other)?;
+    match file {
+        Some(file) => {
+            std::fs::write(file, text)?;
+            eprintln!("Pulled {} settings to {}", current.len(), file.display());
+        }
+        None => print!("{}", text),
+    }
     Ok(())
 }
+
+/// Collects the string keys starting with a prefix, with their values, using DUMP.
+async fn fetch_settings(
+    address: &str,
+    session: &Session,
+    prefix: &str,
+) -> tokio::io::Result<BTreeMap<String, String>> {
+    let command = Command::Dump {
+        pattern: (!prefix.is_empty()).then(|| format!("{}*", prefix)),
+    };
+    let mut current = BTreeMap::new();
+    read_dump(address, session, &command, |entry| {
+        if let serde_json::Value::String(value) = entry.value
+            && entry.kind == "string"
+            && entry.key.starts_with(prefix)
+        {
+            current.insert(entry.key, value);
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(current)
+}
+
+/// Returns the format of a file guessed from its name.
+fn file_format(file: &Path) -> tokio::io::Result<Format> {
+    Format::of(file).ok_or_else(|| {
+        tokio::io::Error::other(format!(
+            "Format of {} can't be guessed from its name, use --format",
+            file.display()
+        ))
+    })
+}
+
+/// Writes one command to an open connection and reads the response.
+async fn exchange(stream: &mut TcpStream, message: &str) -> tokio::io::Result<Option<String>> {
+    let message = format!("{}\n", message);
+    stream.write_all(message.as_bytes()).await?;
+
+    let mut buffer = vec![0; 1024];
+    let bytes_read = stream.read(&mut buffer).await?;
+
+    if bytes_read == 0 {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&buffer[..bytes_read]).into_owned(),
+    ))
+}
+
+/// Collects the keys matching a pattern by walking the store with SCAN,
+/// so the server never holds its lock for the whole key space.
+async fn scan_keys(
+    address: &str,
+    session: &Session,
+    pattern: &str,
+) -> tokio::io::Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut cursor = "0".to_string();
+    loop {
+        let message = Command::Scan {
+            cursor,
+            pattern: pattern.to_string(),
+        }
+        .to_message();
+        let Some(response) = send(address, session, &message).await? else {
+            return Err(tokio::io::Error::other("Connection closed by the server."));
+        };
+        if let Some(error) = response.strip_prefix("ERROR: ") {
+            return Err(tokio::io::Error::other(error.trim().to_string()));
+        }
+        let mut lines = response.lines();
+        cursor = lines.next().unwrap_or("0").to_string();
+        keys.extend(lines.filter(|l| !l.is_empty()).map(|l| l.to_string()));
+        if cursor == "0" {
+            return Ok(keys);
+        }
+    }
+}
@@ -1,6 +1,11 @@
+mod audit;
 mod cli;
 mod command;
+mod dump;
 mod handler;
+mod settings;
+mod slots;
+mod tests;
 
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
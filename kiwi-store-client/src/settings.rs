@@ -0,0 +1,246 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::dump::{self, Format};
+
+/// Separator of the key segments in the variable names of a .env file, as .NET reads them
+const ENV_SEPARATOR: &str = "__";
+
+#[derive(Debug, Default, PartialEq)]
+/// Represents the settings read from a file
+pub struct FileSettings {
+    pub values: BTreeMap<String, String>,
+    /// Keys with a null or empty value, which the store can't hold, so they are left out
+    pub empty: Vec<String>,
+}
+
+/// Represents how the settings of a file differ from the keys in the store
+pub struct Diff {
+    pub added: Vec<(String, String)>,
+    /// Keys with their values in the store and in the file
+    pub changed: Vec<(String, String, String)>,
+    pub removed: Vec<(String, String)>,
+}
+
+/// Flattens a JSON document into keys the way .NET configuration does: the names of nested
+/// objects are joined with the separator and array items get their index as a segment,
+/// e.g. `Logging:LogLevel:Default` or `AllowedHosts:0`. Values are kept as text.
+///
+/// # Returns
+/// Returns the keys with their values, the null and empty values apart, or an error for a name
+/// with whitespace.
+pub fn flatten(document: &Value, separator: &str) -> Result<FileSettings, String> {
+    fn walk(value: &Value, key: String, separator: &str, settings: &mut FileSettings) {
+        let join = |segment: &str| {
+            if key.is_empty() {
+                segment.to_string()
+            } else {
+                format!("{}{}{}", key, separator, segment)
+            }
+        };
+        match value {
+            Value::Object(fields) => {
+                for (name, field) in fields {
+                    walk(field, join(name), separator, settings);
+                }
+            }
+            Value::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    walk(item, join(&index.to_string()), separator, settings);
+                }
+            }
+            Value::Null => settings.empty.push(key),
+            Value::String(text) if text.is_empty() => settings.empty.push(key),
+            Value::String(text) => {
+                settings.values.insert(key, text.clone());
+            }
+            value => {
+                settings.values.insert(key, value.to_string());
+            }
+        }
+    }
+
+    let mut settings = FileSettings::default();
+    walk(document, String::new(), separator, &mut settings);
+    if let Some(key) = settings
+        .values
+        .keys()
+        .chain(&settings.empty)
+        .find(|key| key.contains(char::is_whitespace))
+    {
+        return Err(format!("Key can't contain whitespace: {}", key));
+    }
+    Ok(settings)
+}
+
+/// Builds the JSON document of flattened keys back. Objects whose names are the indexes
+/// 0, 1, 2... become arrays, and values that read as a number or a boolean are written as one.
+pub fn unflatten(settings: &BTreeMap<String, String>, separator: &str) -> Result<Value, String> {
+    let mut root = Map::new();
+    for (key, value) in settings {
+        let segments: Vec<&str> = key.split(separator).collect();
+        let (last, parents) = segments.split_last().unwrap_or((&"", &[]));
+        let mut object = &mut root;
+        for segment in parents {
+            let child = object
+                .entry(segment.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            object = match child {
+                Value::Object(child) => child,
+                _ => return Err(format!("{} is both a value and a section", key)),
+            };
+        }
+        if object.contains_key(*last) {
+            return Err(format!("{} is both a value and a section", key));
+        }
+        object.insert(last.to_string(), typed(value));
+    }
+    Ok(arrays(Value::Object(root)))
+}
+
+/// Reads the settings of an appsettings.json or a .env file. The variable names of a .env file
+/// are split on `__`, so `Logging__LogLevel__Default` becomes `Logging:LogLevel:Default`.
+pub fn read(path: &Path, format: Format, separator: &str) -> Result<FileSettings, String> {
+    let error = |e: &dyn std::fmt::Display| format!("{} can't be read: {}", path.display(), e);
+    match format {
+        Format::Json => {
+            let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+            let document: Value = serde_json::from_str(&text).map_err(|e| error(&e))?;
+            flatten(&document, separator)
+        }
+        Format::Env => {
+            let mut settings = FileSettings::default();
+            for line in dotenvy::from_path_iter(path).map_err(|e| error(&e))? {
+                let (name, value) = line.map_err(|e| error(&e))?;
+                let key = name.replace(ENV_SEPARATOR, separator);
+                if value.is_empty() {
+                    settings.empty.push(key);
+                } else {
+                    settings.values.insert(key, value);
+                }
+            }
+            Ok(settings)
+        }
+        Format::Csv => Err("Settings are read from JSON or .env files".to_string()),
+    }
+}
+
+/// Writes settings as an appsettings.json document or as the variables of a .env file.
+pub fn render(
+    settings: &BTreeMap<String, String>,
+    format: Format,
+    separator: &str,
+) -> Result<String, String> {
+    match format {
+        Format::Json => {
+            let document = unflatten(settings, separator)?;
+            serde_json::to_string_pretty(&document)
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string())
+        }
+        Format::Env => {
+            let mut lines = String::new();
+            for (key, value) in settings {
+                let name = key.replace(separator, ENV_SEPARATOR);
+                if !dump::is_variable_name(&name) {
+                    return Err(format!("{} can't be a variable name of a .env file", key));
+                }
+                lines.push_str(&format!("{}={}\n", name, dump::quote(value)));
+            }
+            Ok(lines)
+        }
+        Format::Csv => Err("Settings are written as JSON or .env files".to_string()),
+    }
+}
+
+impl Diff {
+    /// Compares the keys in the store with the settings of a file.
+    pub fn new(current: &BTreeMap<String, String>, wanted: &BTreeMap<String, String>) -> Self {
+        let mut diff = Diff {
+            added: Vec::new(),
+            changed: Vec::new(),
+            removed: Vec::new(),
+        };
+        for (key, value) in wanted {
+            match current.get(key) {
+                None => diff.added.push((key.clone(), value.clone())),
+                Some(old) if old != value => {
+                    diff.changed.push((key.clone(), old.clone(), value.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for (key, value) in current {
+            if !wanted.contains_key(key) {
+                diff.removed.push((key.clone(), value.clone()));
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Describes the differences a line each, `+` for the keys to add, `~` for the keys to change
+    /// and `-` for the keys to remove.
+    pub fn describe(&self) -> Vec<String> {
+        let added = self
+            .added
+            .iter()
+            .map(|(key, value)| format!("+ {} = {}", key, value));
+        let changed = self
+            .changed
+            .iter()
+            .map(|(key, old, new)| format!("~ {} = {} (was {})", key, new, old));
+        let removed = self
+            .removed
+            .iter()
+            .map(|(key, old)| format!("- {} (was {})", key, old));
+        added.chain(changed).chain(removed).collect()
+    }
+}
+
+/// Returns a value that reads as a number or a boolean as one, and any other value as a string.
+fn typed(value: &str) -> Value {
+    match serde_json::from_str::<Value>(value) {
+        // A value that wouldn't be written back as it is, like `1e3` or ` 1`, stays a string
+        Ok(typed @ (Value::Bool(_) | Value::Number(_))) => {
+            let text = typed.to_string();
+            if text == value {
+                typed
+            } else {
+                Value::String(value.to_string())
+            }
+        }
+        _ => Value::String(value.to_string()),
+    }
+}
+
+/// Turns the objects whose names are the indexes 0, 1, 2... into arrays.
+fn arrays(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            let is_array = !fields.is_empty()
+                && (0..fields.len()).all(|index| fields.contains_key(&index.to_string()));
+            if is_array {
+                let mut fields = fields;
+                Value::Array(
+                    (0..fields.len())
+                        .map(|index| arrays(fields.remove(&index.to_string()).unwrap_or_default()))
+                        .collect(),
+                )
+            } else {
+                Value::Object(
+                    fields
+                        .into_iter()
+                        .map(|(name, field)| (name, arrays(field)))
+                        .collect(),
+                )
+            }
+        }
+        value => value,
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+/// Number of hash slots the key space of a sharded cluster is split into
+const SLOT_COUNT: u16 = 16384;
+
+/// Returns the hash slot of a key, computed the same way as the server does.
+/// If the key contains a `{tag}`, only the tag is hashed.
+pub fn key_slot(key: &str) -> u16 {
+    let bytes = key.as_bytes();
+    let hashed = match key.find('{') {
+        Some(open) => match key[open + 1..].find('}') {
+            Some(close) if close > 0 => &bytes[open + 1..open + 1 + close],
+            _ => bytes,
+        },
+        None => bytes,
+    };
+    crc16(hashed) % SLOT_COUNT
+}
+
+/// CRC16 with the XMODEM polynomial
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Slot map of a sharded cluster, kept in a temporary file per entry address
+/// so every run of the client can send commands straight to the owning node.
+pub struct SlotCache {
+    path: PathBuf,
+    ranges: Vec<(u16, u16, String)>,
+}
+
+impl SlotCache {
+    /// Loads the cached slot map of the cluster reached through an address.
+    /// Returns an empty map if nothing is cached yet.
+    pub fn load(address: &str) -> Self {
+        let file_name = format!("kiwi-store-slots-{}.json", address.replace([':', '/'], "_"));
+        let path = std::env::temp_dir().join(file_name);
+        let ranges = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self { path, ranges }
+    }
+
+    /// Returns the node owning the slot of a key, if it is cached.
+    pub fn node_for(&self, key: &str) -> Option<String> {
+        let slot = key_slot(key);
+        self.ranges
+            .iter()
+            .find(|(start, end, _)| *start <= slot && slot <= *end)
+            .map(|(_, _, node)| node.clone())
+    }
+
+    /// Replaces the map with the `CLUSTER SLOTS` response of a node.
+    /// Every line of the response is formatted as `<start> <end> <node>`.
+    pub fn update(&mut self, response: &str) {
+        let ranges: Vec<(u16, u16, String)> = response
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let start = parts.next()?.parse().ok()?;
+                let end = parts.next()?.parse().ok()?;
+                let node = parts.next()?.to_string();
+                Some((start, end, node))
+            })
+            .collect();
+        if !ranges.is_empty() {
+            self.ranges = ranges;
+        }
+    }
+
+    /// Writes the map to its file. A cache that can't be written is simply rebuilt on the next run.
+    pub fn save(&self) {
+        if let Ok(content) = serde_json::to_string(&self.ranges) {
+            let _ = std::fs::write(&self.path, content);
+        }
+    }
+}
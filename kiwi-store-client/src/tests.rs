@@ -0,0 +1,241 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests {
+    use crate::audit::{AuditQuery, parse_time, search};
+    use crate::cli::Cli;
+    use crate::dump::{Entry, Exporter, Format, read};
+    use crate::settings::{self, Diff, flatten, render, unflatten};
+    use clap::CommandFactory;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_cli_arguments() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_audit_parse_time() {
+        assert_eq!(
+            parse_time("2026-10-19").unwrap().to_rfc3339(),
+            "2026-10-19T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_time("2026-10-19T10:30:00+02:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2026-10-19T08:30:00+00:00"
+        );
+        assert!(parse_time("19.10.2026").is_err());
+    }
+
+    #[test]
+    fn test_audit_search() {
+        let path = std::env::temp_dir().join(format!(
+            "kiwi-store-client-audit-{}.log",
+            std::process::id()
+        ));
+        let record = |time: &str, key: &str| {
+            format!(
+                "{{\"time\":\"{}\",\"command\":\"SET\",\"keys\":[\"{}\"],\"ok\":true}}\n",
+                time, key
+            )
+        };
+        let rotated = format!("{}.1", path.display());
+        std::fs::write(&rotated, record("2026-10-18T23:59:59.000Z", "ApiKey")).unwrap();
+        std::fs::write(
+            &path,
+            record("2026-10-19T08:30:00.000Z", "ApiKey")
+                + "not a record\n"
+                + &record("2026-10-19T09:00:00.000Z", "Db")
+                + &record("2026-10-20T00:00:00.000Z", "ApiKey"),
+        )
+        .unwrap();
+
+        let everything = AuditQuery {
+            key: None,
+            from: None,
+            to: None,
+        };
+        let records = search(&path, &everything).unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records[0].contains("2026-10-18"));
+
+        let query = AuditQuery {
+            key: Some("ApiKey".to_string()),
+            from: Some(parse_time("2026-10-19").unwrap()),
+            to: Some(parse_time("2026-10-20").unwrap()),
+        };
+        let records = search(&path, &query).unwrap();
+        assert_eq!(
+            records,
+            vec![record("2026-10-19T08:30:00.000Z", "ApiKey").trim_end()]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+
+    fn entry(key: &str, kind: &str, value: serde_json::Value) -> Entry {
+        Entry {
+            key: key.to_string(),
+            kind: kind.to_string(),
+            value,
+        }
+    }
+
+    /// Exports the entries to a file of the format and reads them back.
+    ///
+    /// # Returns
+    /// Returns the entries read, as JSON, and the number of entries the export skipped.
+    fn round_trip(
+        format: Format,
+        name: &str,
+        entries: &[Entry],
+    ) -> (Vec<serde_json::Value>, usize) {
+        let path = std::env::temp_dir().join(format!("kiwi-store-{}-{}", std::process::id(), name));
+        let mut exporter = Exporter::new(format, std::fs::File::create(&path).unwrap());
+        for entry in entries {
+            exporter.write(entry).unwrap();
+        }
+        let skipped = exporter.skipped;
+        exporter.finish().unwrap();
+        let read: Vec<serde_json::Value> = read(format, &path)
+            .unwrap()
+            .map(|entry| serde_json::to_value(entry.unwrap()).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        (read, skipped)
+    }
+
+    fn sample() -> Vec<Entry> {
+        vec![
+            entry("Theme", "string", json!("Dark \"mode\", $HOME \\ done")),
+            entry("Empty", "string", json!("")),
+            entry("Jobs", "list", json!(["resize", "upload"])),
+            entry("Scores", "zset", json!([["alice", 12.5], ["bob", 40.0]])),
+            entry("Db", "json", json!({"port": 5432, "hosts": ["a", "b"]})),
+        ]
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let entries = sample();
+        let (read, skipped) = round_trip(Format::Json, "export.json", &entries);
+        let written: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .collect();
+        assert_eq!((read, skipped), (written, 0));
+        assert!(round_trip(Format::Json, "empty.json", &[]).0.is_empty());
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let entries = sample();
+        let (read, skipped) = round_trip(Format::Csv, "export.csv", &entries);
+        let written: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .collect();
+        assert_eq!((read, skipped), (written, 0));
+    }
+
+    #[test]
+    fn test_env_round_trip() {
+        let mut entries = sample();
+        entries.push(entry("Not-A-Variable", "string", json!("skipped")));
+        let (read, skipped) = round_trip(Format::Env, "export.env", &entries);
+        let strings: Vec<serde_json::Value> = entries[..2]
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .collect();
+        assert_eq!((read, skipped), (strings, 4));
+    }
+
+    fn settings_of(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_flatten_and_unflatten_settings() {
+        let document = json!({
+            "Logging": { "LogLevel": { "Default": "Information" } },
+            "AllowedHosts": ["a.com", "b.com"],
+            "Port": 8080,
+            "Tls": false,
+            "Version": "1e3",
+            "ConnectionStrings": { "Db": "", "Cache": null }
+        });
+        let flattened = flatten(&document, ":").unwrap();
+        assert_eq!(
+            flattened.values,
+            settings_of(&[
+                ("AllowedHosts:0", "a.com"),
+                ("AllowedHosts:1", "b.com"),
+                ("Logging:LogLevel:Default", "Information"),
+                ("Port", "8080"),
+                ("Tls", "false"),
+                ("Version", "1e3"),
+            ])
+        );
+        assert_eq!(
+            flattened.empty,
+            vec!["ConnectionStrings:Cache", "ConnectionStrings:Db"]
+        );
+
+        let mut expected = document.clone();
+        expected
+            .as_object_mut()
+            .unwrap()
+            .remove("ConnectionStrings");
+        assert_eq!(unflatten(&flattened.values, ":").unwrap(), expected);
+
+        assert!(flatten(&json!({ "Log Level": "Debug" }), ":").is_err());
+        assert_eq!(
+            unflatten(&settings_of(&[("Port", "1"), ("Port:Http", "2")]), ":"),
+            Err("Port:Http is both a value and a section".to_string())
+        );
+    }
+
+    #[test]
+    fn test_settings_diff() {
+        let current = settings_of(&[("Port", "8080"), ("Tls", "false"), ("Old", "1")]);
+        let wanted = settings_of(&[("Port", "9090"), ("Tls", "false"), ("New", "2")]);
+        let diff = Diff::new(&current, &wanted);
+        assert_eq!(diff.added, vec![("New".to_string(), "2".to_string())]);
+        assert_eq!(
+            diff.changed,
+            vec![("Port".to_string(), "8080".to_string(), "9090".to_string())]
+        );
+        assert_eq!(diff.removed, vec![("Old".to_string(), "1".to_string())]);
+        assert_eq!(
+            diff.describe(),
+            vec!["+ New = 2", "~ Port = 9090 (was 8080)", "- Old (was 1)"]
+        );
+        assert!(Diff::new(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn test_env_settings_round_trip() {
+        let values = settings_of(&[
+            ("Logging:LogLevel:Default", "Information"),
+            ("Greeting", "Hello \"$USER\" \\ welcome"),
+        ]);
+        let text = render(&values, Format::Env, ":").unwrap();
+        assert!(text.contains("Logging__LogLevel__Default=\"Information\""));
+
+        let path =
+            std::env::temp_dir().join(format!("kiwi-store-{}-settings.env", std::process::id()));
+        std::fs::write(&path, text + "Empty=\n").unwrap();
+        let read = settings::read(&path, Format::Env, ":").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.values, values);
+        assert_eq!(read.empty, vec!["Empty"]);
+
+        assert!(render(&settings_of(&[("Log-Level", "Debug")]), Format::Env, ":").is_err());
+    }
+}
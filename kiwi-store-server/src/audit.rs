@@ -0,0 +1,228 @@
+//! Audit log of the Kiwi Store server.
+//!
+//! With `audit_log` set, every command a client sends to change the data is appended to the file
+//! as a JSON line: when it ran, the client address, name and user, the namespace, the command,
+//! its keys and whether it succeeded. Values are never written. With `audit_key` set, their
+//! HMAC-SHA256 under that key is, so the trail shows that a value changed and whoever holds the key
+//! can match it against a known value, while short values can't be guessed from the log alone.
+//!
+//! The record is queued before the response is sent and a dedicated thread writes the records
+//! in order, so the file operations don't hold up the connections. The queue is flushed when the
+//! server shuts down. Once the file grows past `audit_log_max_bytes` it is rotated: `audit.log`
+//! becomes `audit.log.1`, the older files move up by one and the one past `audit_log_max_files`
+//! is removed.
+
+use crate::clients::ClientInfo;
+use crate::command::Command;
+use crate::config::Configuration;
+use crate::crypto::hmac_sha256_hex;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use tokio::sync::oneshot;
+use tracing::error;
+
+#[derive(Serialize)]
+/// Represents a line of the audit log
+struct AuditRecord<'a> {
+    /// When the command ran, in RFC 3339 format in UTC
+    time: String,
+    client: String,
+    /// Name given with CLIENT SETNAME
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    user: &'a str,
+    namespace: &'a str,
+    command: String,
+    keys: Vec<&'a str>,
+    /// HMAC-SHA256 under `audit_key` of everything after the key, e.g. the value of SET
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_hmac: Option<String>,
+    ok: bool,
+}
+
+struct Writer {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+/// Represents a message to the writer thread
+enum Message {
+    Record {
+        line: String,
+        max_bytes: u64,
+        max_files: usize,
+    },
+    /// Asks for a reply once the records queued before it are written
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Clone, Default)]
+/// Represents the audit log of the server, shared by all connections.
+/// The default audit log is turned off and records nothing
+pub struct AuditLog {
+    sender: Option<Sender<Message>>,
+}
+
+impl AuditLog {
+    /// Opens the audit log, creating the file if it doesn't exist and appending to it if it does,
+    /// and starts the thread writing it.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the audit log file
+    pub fn open(path: &str) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+        let file = open_append(&path)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+        let mut writer = Writer { path, file, size };
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                for message in receiver {
+                    match message {
+                        Message::Record {
+                            line,
+                            max_bytes,
+                            max_files,
+                        } => {
+                            if let Err(e) = writer.write(&line, max_bytes, max_files) {
+                                error!(
+                                    "Audit log {} can't be written: {}",
+                                    writer.path.display(),
+                                    e
+                                );
+                            }
+                        }
+                        Message::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            })
+            .map_err(|e| format!("Audit log writer can't be started: {}", e))?;
+        Ok(Self {
+            sender: Some(sender),
+        })
+    }
+
+    /// Checks whether the audit log is turned on.
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Waits until the records queued so far are written.
+    pub async fn flush(&self) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let (done, written) = oneshot::channel();
+        if sender.send(Message::Flush(done)).is_ok() {
+            let _ = written.await;
+        }
+    }
+
+    /// Records a command if it changes the data. Commands redirected to another node with
+    /// `MOVED` are left to that node.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration holding the rotation limits and the audit key
+    /// * `client` - The client that sent the command
+    /// * `namespace` - The namespace the command ran in
+    /// * `cmd` - The parsed command line
+    /// * `line` - The command line
+    /// * `response` - The response sent for it
+    pub fn record(
+        &self,
+        config: &Configuration,
+        client: &ClientInfo,
+        namespace: &str,
+        cmd: &Command,
+        line: &str,
+        response: &str,
+    ) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if !cmd.is_mutating() || response.starts_with("MOVED") {
+            return;
+        }
+        let keys = cmd.keys();
+        // The command name and the key are kept, the rest is hashed
+        let skip = if keys.is_empty() { 1 } else { 2 };
+        let value = line.trim().splitn(skip + 1, ' ').nth(skip);
+        let record = AuditRecord {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            client: client.address.to_string(),
+            name: client.name.as_deref(),
+            user: &client.user,
+            namespace,
+            command: line
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_uppercase(),
+            keys,
+            value_hmac: value
+                .zip(config.audit_key.as_deref())
+                .map(|(value, key)| hmac_sha256_hex(key, value)),
+            ok: !response.starts_with("ERROR"),
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(json) => json + "\n",
+            Err(e) => {
+                error!("Audit record can't be written: {}", e);
+                return;
+            }
+        };
+        let message = Message::Record {
+            line,
+            max_bytes: config.audit_log_max_bytes,
+            max_files: config.audit_log_max_files,
+        };
+        if sender.send(message).is_err() {
+            error!("Audit log writer has stopped, the record is lost");
+        }
+    }
+}
+
+impl Writer {
+    fn write(&mut self, line: &str, max_bytes: u64, max_files: usize) -> Result<(), String> {
+        if self.size > 0 && self.size + line.len() as u64 > max_bytes {
+            self.rotate(max_files)?;
+        }
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|e| e.to_string())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Moves every file up by one, removing the oldest, and starts a new file.
+    fn rotate(&mut self, max_files: usize) -> Result<(), String> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let _ = fs::remove_file(rotated(max_files));
+        for n in (1..max_files).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1)).map_err(|e| e.to_string())?;
+            }
+        }
+        fs::rename(&self.path, rotated(1)).map_err(|e| e.to_string())?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Audit log {} can't be opened: {}", path.display(), e))
+}
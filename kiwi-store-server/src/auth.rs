@@ -0,0 +1,80 @@
+//! Admin sessions of the Kiwi Store server.
+//!
+//! The CONFIG commands change the running server and MONITOR shows the commands of every
+//! client, so they need an admin session. A connection
+//! becomes one with `AUTH <password>` when an admin password is configured. Without a password,
+//! only the connections from the local machine are admin sessions, since the server has no
+//! other way to tell its clients apart.
+
+use crate::config::Configuration;
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+/// Error message sent when a command needs an admin session
+pub const NOT_ADMIN: &str = "ERR_NOT_ADMIN This command needs an admin session, use AUTH";
+
+/// Checks whether a connection is an admin session.
+///
+/// # Arguments
+/// * `config` - The configuration holding the admin password
+/// * `ip` - The IP address of the client
+/// * `authenticated` - Whether the connection has authenticated with AUTH
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::auth::is_admin;
+/// use kiwi_store_server::config::Configuration;
+///
+/// let config = Configuration::default();
+/// assert!(is_admin(&config, "127.0.0.1".parse().unwrap(), false));
+/// assert!(!is_admin(&config, "10.0.0.7".parse().unwrap(), false));
+/// ```
+pub fn is_admin(config: &Configuration, ip: IpAddr, authenticated: bool) -> bool {
+    authenticated || (config.admin_password.is_none() && ip.is_loopback())
+}
+
+/// Settings whose values are secrets, left out of the command lines shown to other clients
+const SECRET_SETTINGS: [&str; 3] = ["admin_password", "encryption_key", "audit_key"];
+
+/// Returns a command line without the secrets it carries, so it can be shown to other clients.
+/// The password of AUTH and the value of CONFIG SET for a secret setting become `***`.
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::auth::redact_secrets;
+///
+/// assert_eq!(redact_secrets("AUTH hunter2"), "AUTH ***");
+/// assert_eq!(redact_secrets("config set admin_password hunter2"), "config set admin_password ***");
+/// assert_eq!(redact_secrets("SET Theme Dark"), "SET Theme Dark");
+/// ```
+pub fn redact_secrets(line: &str) -> Cow<'_, str> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [cmd, _, ..] if cmd.eq_ignore_ascii_case("AUTH") => format!("{} ***", cmd).into(),
+        [cmd, sub, name, _, ..]
+            if cmd.eq_ignore_ascii_case("CONFIG")
+                && sub.eq_ignore_ascii_case("SET")
+                && SECRET_SETTINGS.contains(&name.to_lowercase().as_str()) =>
+        {
+            format!("{} {} {} ***", cmd, sub, name).into()
+        }
+        _ => line.into(),
+    }
+}
+
+/// Checks the password given with AUTH against the admin password.
+///
+/// # Returns
+/// Returns `Ok(())` if the password is right, or the reason it is refused.
+pub fn authenticate(config: &Configuration, password: &str) -> Result<(), String> {
+    match &config.admin_password {
+        None => Err("No admin password is configured".to_string()),
+        Some(expected) if constant_time_eq(expected.as_bytes(), password.as_bytes()) => Ok(()),
+        Some(_) => Err("Invalid password".to_string()),
+    }
+}
+
+/// Compares two byte strings in a time that doesn't depend on where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,22 @@
+use crate::config::Settings;
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(
+    name = "kiwi-store-server",
+    version = "1.0",
+    author = "Burak Selim Şenyurt",
+    about = "A lightweight in-memory key-value store server",
+    long_about = "A lightweight in-memory key-value store server.\n\
+        Settings are read from the configuration file, then from the environment variables \
+        and then from the flags, each overriding the ones before it."
+)]
+pub struct Cli {
+    #[arg(long, short, help = "Path of a TOML configuration file")]
+    pub config: Option<PathBuf>,
+    #[arg(long, help = "Print the effective configuration and exit")]
+    pub print_config: bool,
+    #[command(flatten)]
+    pub settings: Settings,
+}
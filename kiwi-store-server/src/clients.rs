@@ -0,0 +1,213 @@
+//! Registry of the clients connected to the Kiwi Store server.
+//!
+//! Every connection served within the connection limits is registered with its address, the name
+//! it gives itself with CLIENT SETNAME, when it connected, the last command it sent, the bytes it
+//! sent and received and the user it authenticated as. CLIENT LIST shows the registry, and
+//! CLIENT KILL ends the connections matching an address or a user. A connection updates its own
+//! entry through a `ClientHandle`, so its commands don't lock the whole registry.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+/// User of the connections that have not authenticated
+pub const DEFAULT_USER: &str = "default";
+
+/// User of the connections that have authenticated with the admin password
+pub const ADMIN_USER: &str = "admin";
+
+#[derive(Debug, Clone)]
+/// Represents what is known about a connected client
+pub struct ClientInfo {
+    /// Unique and increasing number of the connection
+    pub id: u64,
+    pub address: SocketAddr,
+    /// Name given with CLIENT SETNAME
+    pub name: Option<String>,
+    pub connected: Instant,
+    /// When the last command was received
+    pub active: Instant,
+    /// Name of the last command, without its arguments
+    pub last_command: Option<String>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub user: String,
+    /// Namespace the connection works in
+    pub namespace: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Represents the connections CLIENT KILL ends
+pub enum KillFilter {
+    Address(String),
+    User(String),
+}
+
+struct Entry {
+    handle: ClientHandle,
+    kill: Arc<Notify>,
+}
+
+#[derive(Clone)]
+/// Represents the entry of a connection in the registry
+pub struct ClientHandle {
+    info: Arc<Mutex<ClientInfo>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    clients: BTreeMap<u64, Entry>,
+}
+
+#[derive(Clone, Default)]
+/// Represents the connected clients, shared by all connections
+pub struct Clients {
+    inner: Arc<Mutex<Inner>>,
+}
+
+/// Holds the registration of a connection, which is removed when the guard is dropped
+pub struct ClientGuard {
+    clients: Clients,
+    id: u64,
+    handle: ClientHandle,
+    kill: Arc<Notify>,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a connection in the default namespace.
+    ///
+    /// # Returns
+    /// Returns the guard of the registration, which also tells when the connection is killed.
+    pub fn register(&self, address: SocketAddr, namespace: &str) -> ClientGuard {
+        let kill = Arc::new(Notify::new());
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        let now = Instant::now();
+        let handle = ClientHandle {
+            info: Arc::new(Mutex::new(ClientInfo {
+                id,
+                address,
+                name: None,
+                connected: now,
+                active: now,
+                last_command: None,
+                bytes_in: 0,
+                bytes_out: 0,
+                user: DEFAULT_USER.to_string(),
+                namespace: namespace.to_string(),
+            })),
+        };
+        inner.clients.insert(
+            id,
+            Entry {
+                handle: handle.clone(),
+                kill: Arc::clone(&kill),
+            },
+        );
+        ClientGuard {
+            clients: self.clone(),
+            id,
+            handle,
+            kill,
+        }
+    }
+
+    /// Returns the connected clients, ordered by id.
+    pub fn list(&self) -> Vec<ClientInfo> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .clients
+            .values()
+            .map(|entry| entry.handle.info().clone())
+            .collect()
+    }
+
+    /// Ends the connections matching a filter. A connection stops at its next await point,
+    /// even in the middle of a blocking command, and its socket is closed.
+    ///
+    /// # Returns
+    /// Returns the number of connections killed.
+    pub fn kill(&self, filter: &KillFilter) -> usize {
+        let inner = self.inner.lock().unwrap();
+        let mut killed = 0;
+        for entry in inner.clients.values() {
+            let info = entry.handle.info();
+            let matches = match filter {
+                KillFilter::Address(address) => info.address.to_string() == *address,
+                KillFilter::User(user) => info.user == *user,
+            };
+            if matches {
+                entry.kill.notify_one();
+                killed += 1;
+            }
+        }
+        killed
+    }
+}
+
+impl ClientInfo {
+    /// Describes the client as a CLIENT LIST line.
+    ///
+    /// # Example
+    /// ```text
+    /// id=3 addr=127.0.0.1:53412 name=worker age=12 idle=0 user=admin namespace=default cmd=GET bytes_in=120 bytes_out=45
+    /// ```
+    pub fn describe(&self) -> String {
+        format!(
+            "id={} addr={} name={} age={} idle={} user={} namespace={} cmd={} bytes_in={} bytes_out={}",
+            self.id,
+            self.address,
+            self.name.as_deref().unwrap_or(""),
+            self.connected.elapsed().as_secs(),
+            self.active.elapsed().as_secs(),
+            self.user,
+            self.namespace,
+            self.last_command.as_deref().unwrap_or("NULL"),
+            self.bytes_in,
+            self.bytes_out
+        )
+    }
+}
+
+impl ClientHandle {
+    /// Changes what is known about the connection.
+    pub fn update(&self, change: impl FnOnce(&mut ClientInfo)) {
+        change(&mut self.info.lock().unwrap());
+    }
+
+    /// Returns what is known about the connection, locked until the guard is dropped.
+    pub fn info(&self) -> MutexGuard<'_, ClientInfo> {
+        self.info.lock().unwrap()
+    }
+}
+
+impl ClientGuard {
+    /// Returns the id of the connection.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the entry of the connection, to update it.
+    pub fn handle(&self) -> ClientHandle {
+        self.handle.clone()
+    }
+
+    /// Waits until the connection is killed with CLIENT KILL.
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.clients.inner.lock().unwrap().clients.remove(&self.id);
+    }
+}
@@ -1,10 +1,18 @@
 //! Commands for the Kiwi Store server
 
-use log::{error, warn};
+use chrono::DateTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
 
-use crate::config::Configuration;
+use crate::clients::KillFilter;
+use crate::config::{Configuration, SETTINGS};
+use crate::dump::Conflict;
+use crate::info::INFO_SECTIONS;
+use crate::sharding::SLOT_COUNT;
+use crate::store::json_path;
+use crate::telemetry::TraceParent;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Represents the key-value store commands
 pub enum Command {
     /// Set command with a key and value
@@ -13,12 +21,168 @@ pub enum Command {
     Get { key: String },
     /// Remove command with a key
     Remove { key: String },
+    /// HISTORY command to get the versions kept of a key, newest first
+    History { key: String },
+    /// GETAT command to get the value a key held at a Unix timestamp or an RFC 3339 time
+    GetAt {
+        key: String,
+        time: Option<SystemTime>,
+    },
+    /// ROLLBACK command to write a version from the history of a key as its current value
+    Rollback { key: String, version: Option<u64> },
     /// List command to list all keys
     List,
     /// Ping command to check server status
     Ping,
     /// Stats command to get server statistics
     Stats,
+    /// INFO command to get a section of the server report, or every section, as text or JSON
+    Info { section: Option<String>, json: bool },
+    /// LPUSH command to push a value to the head of a list
+    LeftPush { key: String, value: String },
+    /// RPUSH command to push a value to the tail of a list
+    RightPush { key: String, value: String },
+    /// LPOP command to pop a value from the head of a list
+    LeftPop { key: String },
+    /// RPOP command to pop a value from the tail of a list
+    RightPop { key: String },
+    /// LRANGE command to get a range of items from a list
+    ListRange {
+        key: String,
+        start: Option<i64>,
+        stop: Option<i64>,
+    },
+    /// LLEN command to get the length of a list
+    ListLength { key: String },
+    /// BLPOP command to pop from the head of a list, waiting up to `timeout` seconds (0 waits forever)
+    BlockingLeftPop { key: String, timeout: Option<u64> },
+    /// SADD command to add a member to a set
+    SetAdd { key: String, member: String },
+    /// SREM command to remove a member from a set
+    SetRemove { key: String, member: String },
+    /// SISMEMBER command to check whether a member is in a set
+    SetIsMember { key: String, member: String },
+    /// SMEMBERS command to get all members of a set
+    SetMembers { key: String },
+    /// SINTER command to get the members common to all given sets
+    SetIntersect { keys: Vec<String> },
+    /// SUNION command to get the members of any of the given sets
+    SetUnion { keys: Vec<String> },
+    /// ZADD command to add a member with a score to a sorted set
+    SortedSetAdd {
+        key: String,
+        score: Option<f64>,
+        member: String,
+    },
+    /// ZINCRBY command to increment the score of a sorted set member
+    SortedSetIncrement {
+        key: String,
+        increment: Option<f64>,
+        member: String,
+    },
+    /// ZREM command to remove a member from a sorted set
+    SortedSetRemove { key: String, member: String },
+    /// ZRANGE command to get sorted set members by rank
+    SortedSetRange {
+        key: String,
+        start: Option<i64>,
+        stop: Option<i64>,
+        with_scores: bool,
+    },
+    /// ZRANGEBYSCORE command to get sorted set members within a score range
+    SortedSetRangeByScore {
+        key: String,
+        min: Option<f64>,
+        max: Option<f64>,
+        with_scores: bool,
+    },
+    /// JSON.SET command to set the value at a path of a JSON document
+    JsonSet {
+        key: String,
+        path: String,
+        json: String,
+    },
+    /// JSON.GET command to get the value at a path of a JSON document
+    JsonGet { key: String, path: String },
+    /// JSON.DEL command to delete the value at a path of a JSON document
+    JsonDelete { key: String, path: String },
+    /// JSON.NUMINCRBY command to increment a number at a path of a JSON document
+    JsonIncrement {
+        key: String,
+        path: String,
+        increment: Option<f64>,
+    },
+    /// KEYS command to list the keys matching a glob pattern
+    Keys { pattern: String },
+    /// SCAN command to walk the keys incrementally
+    Scan {
+        cursor: String,
+        pattern: Option<String>,
+        count: Option<usize>,
+    },
+    /// DUMP command to stream every key matching an optional glob pattern as JSON lines
+    Dump { pattern: Option<String> },
+    /// RESTORE command to write the JSON lines sent after it until END, or only check them on a dry run
+    Restore { conflict: Conflict, dry_run: bool },
+    /// SELECT command to switch the namespace of the connection
+    Select { name: String },
+    /// FLUSHNS command to remove every key of a namespace, the current one if no name is given
+    FlushNamespace { name: Option<String> },
+    /// SYNC command sent by a replica to receive a full copy of the store and the later writes
+    Sync,
+    /// REPLICAOF command to follow a leader, or to become a leader again when no address is given
+    ReplicaOf { leader: Option<String> },
+    /// CLUSTER SLOTS command to get the slot ranges and the node owning each of them
+    ClusterSlots,
+    /// CLUSTER SETSLOT command to record a new owner for a range of slots
+    ClusterSetSlot {
+        start: Option<u16>,
+        end: Option<u16>,
+        node: String,
+    },
+    /// CLUSTER IMPORTING command sent by a migrating node, so the connection may write keys of
+    /// slots it doesn't own yet
+    ClusterImporting,
+    /// CLUSTER MIGRATE command to move a range of slots with their keys to another node
+    ClusterMigrate {
+        start: Option<u16>,
+        end: Option<u16>,
+        node: String,
+    },
+    /// CONFIG GET command to get the settings matching a glob pattern
+    ConfigGet { pattern: String },
+    /// CONFIG SET command to change a setting of the running server
+    ConfigSet { name: String, value: String },
+    /// CONFIG REWRITE command to write the running settings to the configuration file
+    ConfigRewrite,
+    /// CONFIG RELOAD command to load the configuration again and apply it without a restart
+    ConfigReload,
+    /// AUTH command to open an admin session with the admin password
+    Auth { password: String },
+    /// RATELIMITS command to get the rate limits and the tokens left to each client
+    RateLimits,
+    /// MODE command to get the mode profile of the current namespace
+    Mode,
+    /// CLIENT LIST command to get the connected clients
+    ClientList,
+    /// CLIENT SETNAME command to name the connection
+    ClientSetName { name: String },
+    /// CLIENT KILL command to end the connections of an address or a user
+    ClientKill { filter: Option<KillFilter> },
+    /// TRACEPARENT command to make the next commands of the connection part of a client trace
+    TraceParent { value: String },
+    /// MONITOR command to turn the connection into a live feed of the commands the server processes
+    Monitor,
+    /// SLOWLOG GET command to get the latest slow commands, 10 if no count is given
+    SlowLogGet { count: Option<usize> },
+    /// SLOWLOG LEN command to get the number of commands in the slow log
+    SlowLogLen,
+    /// SLOWLOG RESET command to empty the slow log
+    SlowLogReset,
+    /// SHUTDOWN command to stop the server gracefully, saving the data unless NOSAVE is given
+    Shutdown { save: Option<bool> },
+    /// RAFT command sent by a cluster peer to exchange consensus messages on the connection
+    Raft,
     /// Invalid command with the command string
     Invalid(String),
 }
@@ -55,6 +219,30 @@ impl Command {
     /// let cmd = Command::parse("STATS");
     /// assert_eq!(cmd, Command::Stats);
     ///
+    /// let cmd = Command::parse("INFO memory JSON");
+    /// assert_eq!(cmd, Command::Info { section: Some("memory".to_string()), json: true });
+    ///
+    /// let cmd = Command::parse("RPUSH Jobs resize");
+    /// assert_eq!(cmd, Command::RightPush { key: "Jobs".to_string(), value: "resize".to_string() });
+    ///
+    /// let cmd = Command::parse("BLPOP Jobs 5");
+    /// assert_eq!(cmd, Command::BlockingLeftPop { key: "Jobs".to_string(), timeout: Some(5) });
+    ///
+    /// let cmd = Command::parse("SINTER BetaUsers Admins");
+    /// assert_eq!(cmd, Command::SetIntersect { keys: vec!["BetaUsers".to_string(), "Admins".to_string()] });
+    ///
+    /// let cmd = Command::parse("ZADD SlowEndpoints 120.5 /orders");
+    /// assert_eq!(cmd, Command::SortedSetAdd { key: "SlowEndpoints".to_string(), score: Some(120.5), member: "/orders".to_string() });
+    ///
+    /// let cmd = Command::parse("JSON.GET Db $.port");
+    /// assert_eq!(cmd, Command::JsonGet { key: "Db".to_string(), path: "$.port".to_string() });
+    ///
+    /// let cmd = Command::parse("SCAN 0 MATCH Db* COUNT 100");
+    /// assert_eq!(cmd, Command::Scan { cursor: "0".to_string(), pattern: Some("Db*".to_string()), count: Some(100) });
+    ///
+    /// let cmd = Command::parse("REPLICAOF 127.0.0.1 5544");
+    /// assert_eq!(cmd, Command::ReplicaOf { leader: Some("127.0.0.1:5544".to_string()) });
+    ///
     /// let cmd = Command::parse("INVALID COMMAND");
     /// assert_eq!(cmd, Command::Invalid("INVALID COMMAND".to_string()));
     /// ```
@@ -76,26 +264,400 @@ impl Command {
                 let key = parts.next().unwrap_or("").to_string();
                 Command::Remove { key }
             }
+            "HISTORY" => {
+                let key = parts.next().unwrap_or("").to_string();
+                Command::History { key }
+            }
+            "GETAT" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let time = parts.next().and_then(parse_time);
+                Command::GetAt { key, time }
+            }
+            "ROLLBACK" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let version = parts.next().and_then(|v| v.parse().ok());
+                Command::Rollback { key, version }
+            }
+            "LPUSH" | "RPUSH" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let value = parts.collect::<Vec<&str>>().join(" ");
+                if cmd == "LPUSH" {
+                    Command::LeftPush { key, value }
+                } else {
+                    Command::RightPush { key, value }
+                }
+            }
+            "LPOP" => {
+                let key = parts.next().unwrap_or("").to_string();
+                Command::LeftPop { key }
+            }
+            "RPOP" => {
+                let key = parts.next().unwrap_or("").to_string();
+                Command::RightPop { key }
+            }
+            "LRANGE" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let start = parts.next().and_then(|s| s.parse().ok());
+                let stop = parts.next().and_then(|s| s.parse().ok());
+                Command::ListRange { key, start, stop }
+            }
+            "LLEN" => {
+                let key = parts.next().unwrap_or("").to_string();
+                Command::ListLength { key }
+            }
+            "BLPOP" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let timeout = parts.next().and_then(|s| s.parse().ok());
+                Command::BlockingLeftPop { key, timeout }
+            }
+            "SADD" | "SREM" | "SISMEMBER" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let member = parts.collect::<Vec<&str>>().join(" ");
+                match cmd.as_str() {
+                    "SADD" => Command::SetAdd { key, member },
+                    "SREM" => Command::SetRemove { key, member },
+                    _ => Command::SetIsMember { key, member },
+                }
+            }
+            "SMEMBERS" => {
+                let key = parts.next().unwrap_or("").to_string();
+                Command::SetMembers { key }
+            }
+            "SINTER" => Command::SetIntersect {
+                keys: parts.map(|k| k.to_string()).collect(),
+            },
+            "SUNION" => Command::SetUnion {
+                keys: parts.map(|k| k.to_string()).collect(),
+            },
+            "ZADD" | "ZINCRBY" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let score = parts.next().and_then(|s| s.parse().ok());
+                let member = parts.collect::<Vec<&str>>().join(" ");
+                if cmd == "ZADD" {
+                    Command::SortedSetAdd { key, score, member }
+                } else {
+                    Command::SortedSetIncrement {
+                        key,
+                        increment: score,
+                        member,
+                    }
+                }
+            }
+            "ZREM" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let member = parts.collect::<Vec<&str>>().join(" ");
+                Command::SortedSetRemove { key, member }
+            }
+            "ZRANGE" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let start = parts.next().and_then(|s| s.parse().ok());
+                let stop = parts.next().and_then(|s| s.parse().ok());
+                let with_scores = parts
+                    .next()
+                    .is_some_and(|s| s.eq_ignore_ascii_case("WITHSCORES"));
+                Command::SortedSetRange {
+                    key,
+                    start,
+                    stop,
+                    with_scores,
+                }
+            }
+            "ZRANGEBYSCORE" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let min = parts.next().and_then(|s| s.parse().ok());
+                let max = parts.next().and_then(|s| s.parse().ok());
+                let with_scores = parts
+                    .next()
+                    .is_some_and(|s| s.eq_ignore_ascii_case("WITHSCORES"));
+                Command::SortedSetRangeByScore {
+                    key,
+                    min,
+                    max,
+                    with_scores,
+                }
+            }
+            "JSON.SET" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let path = parts.next().unwrap_or("").to_string();
+                // The document is taken as it is, whitespace in its strings is part of the value
+                let json = rest_after(input, 3).to_string();
+                Command::JsonSet { key, path, json }
+            }
+            "JSON.GET" | "JSON.DEL" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let path = parts.next().unwrap_or("$").to_string();
+                if cmd == "JSON.GET" {
+                    Command::JsonGet { key, path }
+                } else {
+                    Command::JsonDelete { key, path }
+                }
+            }
+            "JSON.NUMINCRBY" => {
+                let key = parts.next().unwrap_or("").to_string();
+                let path = parts.next().unwrap_or("").to_string();
+                let increment = parts.next().and_then(|s| s.parse().ok());
+                Command::JsonIncrement {
+                    key,
+                    path,
+                    increment,
+                }
+            }
+            "KEYS" => {
+                let pattern = parts.next().unwrap_or("").to_string();
+                Command::Keys { pattern }
+            }
+            "SCAN" => {
+                let cursor = parts.next().unwrap_or("").to_string();
+                let mut pattern = None;
+                let mut count = None;
+                while let Some(option) = parts.next() {
+                    match option.to_uppercase().as_str() {
+                        "MATCH" => pattern = parts.next().map(|p| p.to_string()),
+                        "COUNT" => {
+                            count = Some(parts.next().and_then(|c| c.parse().ok()).unwrap_or(0))
+                        }
+                        _ => return Command::Invalid(format!("{} {}", cmd, option)),
+                    }
+                }
+                Command::Scan {
+                    cursor,
+                    pattern,
+                    count,
+                }
+            }
+            "DUMP" => {
+                let mut pattern = None;
+                while let Some(option) = parts.next() {
+                    match option.to_uppercase().as_str() {
+                        "MATCH" => pattern = Some(parts.next().unwrap_or("").to_string()),
+                        _ => return Command::Invalid(format!("{} {}", cmd, option)),
+                    }
+                }
+                Command::Dump { pattern }
+            }
+            "RESTORE" => {
+                let mut conflict = Conflict::Fail;
+                let mut dry_run = false;
+                for option in parts {
+                    match option.to_uppercase().as_str() {
+                        "OVERWRITE" => conflict = Conflict::Overwrite,
+                        "SKIP" => conflict = Conflict::Skip,
+                        "FAIL" => conflict = Conflict::Fail,
+                        "DRYRUN" => dry_run = true,
+                        _ => return Command::Invalid(format!("{} {}", cmd, option)),
+                    }
+                }
+                Command::Restore { conflict, dry_run }
+            }
+            "SELECT" => {
+                let name = parts.next().unwrap_or("").to_string();
+                Command::Select { name }
+            }
+            "FLUSHNS" => {
+                let name = parts.next().map(|n| n.to_string());
+                Command::FlushNamespace { name }
+            }
+            "SYNC" => Command::Sync,
+            "RAFT" => Command::Raft,
+            "RATELIMITS" => Command::RateLimits,
+            "MODE" => Command::Mode,
+            "MONITOR" => Command::Monitor,
+            "TRACEPARENT" => Command::TraceParent {
+                value: parts.next().unwrap_or("").to_string(),
+            },
+            "CLIENT" => match parts.next().map(|p| p.to_uppercase()).as_deref() {
+                Some("LIST") => Command::ClientList,
+                Some("SETNAME") => Command::ClientSetName {
+                    name: parts.next().unwrap_or("").to_string(),
+                },
+                Some("KILL") => {
+                    let filter = match (parts.next(), parts.next()) {
+                        (Some(kind), Some(user)) if kind.eq_ignore_ascii_case("USER") => {
+                            Some(KillFilter::User(user.to_string()))
+                        }
+                        (Some(kind), Some(address)) if kind.eq_ignore_ascii_case("ADDR") => {
+                            Some(KillFilter::Address(address.to_string()))
+                        }
+                        (Some(address), None) => Some(KillFilter::Address(address.to_string())),
+                        _ => None,
+                    };
+                    Command::ClientKill { filter }
+                }
+                Some(sub) => Command::Invalid(format!("{} {}", cmd, sub)),
+                None => Command::Invalid(cmd),
+            },
+            "SLOWLOG" => match parts.next().map(|p| p.to_uppercase()).as_deref() {
+                Some("GET") => Command::SlowLogGet {
+                    count: match parts.next() {
+                        Some(count) => count.parse().ok(),
+                        None => Some(10),
+                    },
+                },
+                Some("LEN") => Command::SlowLogLen,
+                Some("RESET") => Command::SlowLogReset,
+                Some(sub) => Command::Invalid(format!("{} {}", cmd, sub)),
+                None => Command::Invalid(cmd),
+            },
+            "CONFIG" => match parts.next().map(|p| p.to_uppercase()).as_deref() {
+                Some("GET") => Command::ConfigGet {
+                    pattern: parts.next().unwrap_or("").to_string(),
+                },
+                Some("SET") => Command::ConfigSet {
+                    name: parts.next().unwrap_or("").to_string(),
+                    // Values like forbidden keys may contain spaces
+                    value: parts.collect::<Vec<&str>>().join(" "),
+                },
+                Some("REWRITE") => Command::ConfigRewrite,
+                Some("RELOAD") => Command::ConfigReload,
+                Some(sub) => Command::Invalid(format!("{} {}", cmd, sub)),
+                None => Command::Invalid(cmd),
+            },
+            "AUTH" => Command::Auth {
+                password: parts.next().unwrap_or("").to_string(),
+            },
+            "SHUTDOWN" => match parts.next().map(|p| p.to_uppercase()).as_deref() {
+                None => Command::Shutdown { save: None },
+                Some("SAVE") => Command::Shutdown { save: Some(true) },
+                Some("NOSAVE") => Command::Shutdown { save: Some(false) },
+                Some(option) => Command::Invalid(format!("{} {}", cmd, option)),
+            },
+            "CLUSTER" => {
+                let sub = parts.next().unwrap_or("").to_uppercase();
+                match sub.as_str() {
+                    "SLOTS" => return Command::ClusterSlots,
+                    "IMPORTING" => return Command::ClusterImporting,
+                    _ => {}
+                }
+                let start = parts.next().and_then(|s| s.parse().ok());
+                let end = parts.next().and_then(|s| s.parse().ok());
+                let node = parts.next().unwrap_or("").to_string();
+                match sub.as_str() {
+                    "SETSLOT" => Command::ClusterSetSlot { start, end, node },
+                    "MIGRATE" => Command::ClusterMigrate { start, end, node },
+                    _ => Command::Invalid(format!("{} {}", cmd, sub)),
+                }
+            }
+            "REPLICAOF" => {
+                let host = parts.next().unwrap_or("");
+                let port = parts.next().unwrap_or("");
+                if host.eq_ignore_ascii_case("NO") && port.eq_ignore_ascii_case("ONE") {
+                    Command::ReplicaOf { leader: None }
+                } else {
+                    Command::ReplicaOf {
+                        leader: Some(format!("{}:{}", host, port)),
+                    }
+                }
+            }
             "LIST" => Command::List,
             "STATS" => Command::Stats,
+            "INFO" => {
+                let mut section = None;
+                let mut json = false;
+                for part in parts {
+                    if part.eq_ignore_ascii_case("JSON") {
+                        json = true;
+                    } else {
+                        section = Some(part.to_lowercase());
+                    }
+                }
+                Command::Info { section, json }
+            }
             "PING" => Command::Ping,
             _ => Command::Invalid(cmd),
         }
     }
 
+    /// Checks whether the command changes the data store.
+    /// Mutating commands are streamed to replicas and refused by them.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::command::Command;
+    ///
+    /// assert!(Command::parse("SET UseHttps Off").is_mutating());
+    /// assert!(!Command::parse("GET UseHttps").is_mutating());
+    /// ```
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Command::Set { .. }
+                | Command::Remove { .. }
+                | Command::Rollback { .. }
+                | Command::LeftPush { .. }
+                | Command::RightPush { .. }
+                | Command::LeftPop { .. }
+                | Command::RightPop { .. }
+                | Command::BlockingLeftPop { .. }
+                | Command::SetAdd { .. }
+                | Command::SetRemove { .. }
+                | Command::SortedSetAdd { .. }
+                | Command::SortedSetIncrement { .. }
+                | Command::SortedSetRemove { .. }
+                | Command::JsonSet { .. }
+                | Command::JsonDelete { .. }
+                | Command::JsonIncrement { .. }
+                | Command::FlushNamespace { .. }
+                | Command::Restore { .. }
+        )
+    }
+
+    /// Returns the keys the command reads or writes, used to route it to the node owning them.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::command::Command;
+    ///
+    /// assert_eq!(Command::parse("SINTER Admins Editors").keys(), vec!["Admins", "Editors"]);
+    /// assert!(Command::parse("STATS").keys().is_empty());
+    /// ```
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Set { key, .. }
+            | Command::Get { key }
+            | Command::Remove { key }
+            | Command::History { key }
+            | Command::GetAt { key, .. }
+            | Command::Rollback { key, .. }
+            | Command::LeftPush { key, .. }
+            | Command::RightPush { key, .. }
+            | Command::LeftPop { key }
+            | Command::RightPop { key }
+            | Command::ListRange { key, .. }
+            | Command::ListLength { key }
+            | Command::BlockingLeftPop { key, .. }
+            | Command::SetAdd { key, .. }
+            | Command::SetRemove { key, .. }
+            | Command::SetIsMember { key, .. }
+            | Command::SetMembers { key }
+            | Command::SortedSetAdd { key, .. }
+            | Command::SortedSetIncrement { key, .. }
+            | Command::SortedSetRemove { key, .. }
+            | Command::SortedSetRange { key, .. }
+            | Command::SortedSetRangeByScore { key, .. }
+            | Command::JsonSet { key, .. }
+            | Command::JsonGet { key, .. }
+            | Command::JsonDelete { key, .. }
+            | Command::JsonIncrement { key, .. } => vec![key.as_str()],
+            Command::SetIntersect { keys } | Command::SetUnion { keys } => {
+                keys.iter().map(|k| k.as_str()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
     /// Validates the command against the provided configuration
-    /// 
+    ///
     /// # Arguments
     /// * `config` - The configuration to validate against
-    /// 
+    ///
     /// # Returns
     /// Returns `Ok(Self)` if the command is valid, or an `Err(String)` with an error message if it is not.
-    /// 
+    ///
     /// # Example
     /// ```rust
     /// use kiwi_store_server::command::Command;
     /// use kiwi_store_server::config::Configuration;
-    /// let config = Configuration::from_env();
+    /// let config = Configuration::default();
     /// let cmd = Command::parse("SET UseHttps Off");
     /// let validated_cmd = cmd.validate(&config);
     /// assert!(validated_cmd.is_ok());
@@ -132,7 +694,338 @@ impl Command {
                 }
                 Ok(self)
             }
-            Command::List | Command::Stats | Command::Ping => Ok(self),
+            Command::LeftPush { ref key, ref value }
+            | Command::RightPush { ref key, ref value } => {
+                if key.is_empty() || value.is_empty() {
+                    error!("Key or value is empty");
+                    return Err("Key or value cannot be empty".to_string());
+                }
+                validate_key(key, config)?;
+                if value.len() > config.max_value_length {
+                    error!("Value exceeds maximum length: {}", value.len());
+                    return Err(format!("Value exceeds maximum length: {}", value.len()));
+                }
+                Ok(self)
+            }
+            Command::History { ref key } => {
+                validate_key(key, config)?;
+                Ok(self)
+            }
+            Command::GetAt { ref key, time } => {
+                validate_key(key, config)?;
+                if time.is_none() {
+                    error!("Invalid time for key: {}", key);
+                    return Err("Time must be a Unix timestamp or an RFC 3339 time".to_string());
+                }
+                Ok(self)
+            }
+            Command::Rollback { ref key, version } => {
+                validate_key(key, config)?;
+                if version.is_none_or(|v| v == 0) {
+                    error!("Invalid version for key: {}", key);
+                    return Err("Version must be a positive integer".to_string());
+                }
+                Ok(self)
+            }
+            Command::LeftPop { ref key }
+            | Command::RightPop { ref key }
+            | Command::ListLength { ref key } => {
+                validate_key(key, config)?;
+                Ok(self)
+            }
+            Command::ListRange {
+                ref key,
+                start,
+                stop,
+            } => {
+                validate_key(key, config)?;
+                if start.is_none() || stop.is_none() {
+                    error!("Invalid range for key: {}", key);
+                    return Err("Start and stop must be integers".to_string());
+                }
+                Ok(self)
+            }
+            Command::BlockingLeftPop { ref key, timeout } => {
+                validate_key(key, config)?;
+                if timeout.is_none() {
+                    error!("Invalid timeout for key: {}", key);
+                    return Err("Timeout must be a non-negative integer".to_string());
+                }
+                Ok(self)
+            }
+            Command::SetAdd {
+                ref key,
+                ref member,
+            }
+            | Command::SetRemove {
+                ref key,
+                ref member,
+            }
+            | Command::SetIsMember {
+                ref key,
+                ref member,
+            }
+            | Command::SortedSetRemove {
+                ref key,
+                ref member,
+            } => {
+                validate_key(key, config)?;
+                validate_member(member, config)?;
+                Ok(self)
+            }
+            Command::SetMembers { ref key } => {
+                validate_key(key, config)?;
+                Ok(self)
+            }
+            Command::SetIntersect { ref keys } | Command::SetUnion { ref keys } => {
+                if keys.is_empty() {
+                    error!("No keys given for set operation");
+                    return Err("At least one key is required".to_string());
+                }
+                for key in keys {
+                    validate_key(key, config)?;
+                }
+                Ok(self)
+            }
+            Command::SortedSetAdd {
+                ref key,
+                score,
+                ref member,
+            }
+            | Command::SortedSetIncrement {
+                ref key,
+                increment: score,
+                ref member,
+            } => {
+                validate_key(key, config)?;
+                if score.is_none_or(f64::is_nan) {
+                    error!("Invalid score for key: {}", key);
+                    return Err("Score must be a number".to_string());
+                }
+                validate_member(member, config)?;
+                Ok(self)
+            }
+            Command::SortedSetRange {
+                ref key,
+                start,
+                stop,
+                ..
+            } => {
+                validate_key(key, config)?;
+                if start.is_none() || stop.is_none() {
+                    error!("Invalid range for key: {}", key);
+                    return Err("Start and stop must be integers".to_string());
+                }
+                Ok(self)
+            }
+            Command::SortedSetRangeByScore {
+                ref key, min, max, ..
+            } => {
+                validate_key(key, config)?;
+                if min.is_none_or(f64::is_nan) || max.is_none_or(f64::is_nan) {
+                    error!("Invalid score range for key: {}", key);
+                    return Err("Min and max must be numbers".to_string());
+                }
+                Ok(self)
+            }
+            Command::JsonSet {
+                ref key,
+                ref path,
+                ref json,
+            } => {
+                validate_key(key, config)?;
+                json_path::parse(path)?;
+                let document = serde_json::from_str::<serde_json::Value>(json).map_err(|e| {
+                    error!("Invalid JSON document for key {}: {}", key, e);
+                    format!("Invalid JSON document: {}", e)
+                })?;
+                let length = document.to_string().len();
+                if length > config.max_value_length {
+                    error!("Value exceeds maximum length: {}", length);
+                    return Err(format!("Value exceeds maximum length: {}", length));
+                }
+                Ok(self)
+            }
+            Command::JsonGet { ref key, ref path } | Command::JsonDelete { ref key, ref path } => {
+                validate_key(key, config)?;
+                json_path::parse(path)?;
+                Ok(self)
+            }
+            Command::JsonIncrement {
+                ref key,
+                ref path,
+                increment,
+            } => {
+                validate_key(key, config)?;
+                json_path::parse(path)?;
+                if increment.is_none_or(|i| !i.is_finite()) {
+                    error!("Invalid increment for key: {}", key);
+                    return Err("Increment must be a number".to_string());
+                }
+                Ok(self)
+            }
+            Command::Keys { ref pattern } => {
+                if pattern.is_empty() {
+                    error!("Pattern is empty");
+                    return Err("Pattern cannot be empty".to_string());
+                }
+                Ok(self)
+            }
+            Command::Scan {
+                ref cursor,
+                ref pattern,
+                count,
+            } => {
+                if cursor.is_empty() {
+                    error!("Cursor is empty");
+                    return Err("Cursor cannot be empty".to_string());
+                }
+                if pattern.as_ref().is_some_and(|p| p.is_empty()) {
+                    error!("Pattern is empty");
+                    return Err("Pattern cannot be empty".to_string());
+                }
+                if count == Some(0) {
+                    error!("Invalid scan count");
+                    return Err("Count must be a positive integer".to_string());
+                }
+                Ok(self)
+            }
+            Command::Dump { ref pattern } => {
+                if pattern.as_ref().is_some_and(|p| p.is_empty()) {
+                    error!("Pattern is empty");
+                    return Err("Pattern cannot be empty".to_string());
+                }
+                Ok(self)
+            }
+            Command::Select { ref name } => {
+                validate_namespace(name, config)?;
+                Ok(self)
+            }
+            Command::FlushNamespace { ref name } => {
+                if let Some(name) = name {
+                    validate_namespace(name, config)?;
+                }
+                Ok(self)
+            }
+            Command::ReplicaOf { ref leader } => {
+                if let Some(leader) = leader {
+                    let valid = leader.rsplit_once(':').is_some_and(|(host, port)| {
+                        !host.is_empty() && port.parse::<u16>().is_ok()
+                    });
+                    if !valid {
+                        error!("Invalid leader address: {}", leader);
+                        return Err(format!("Invalid leader address: {}", leader));
+                    }
+                }
+                Ok(self)
+            }
+            Command::ClusterSetSlot {
+                start,
+                end,
+                ref node,
+            }
+            | Command::ClusterMigrate {
+                start,
+                end,
+                ref node,
+            } => {
+                let (Some(start), Some(end)) = (start, end) else {
+                    error!("Invalid slot range");
+                    return Err("Start and end slots must be integers".to_string());
+                };
+                if start > end || end >= SLOT_COUNT {
+                    error!("Invalid slot range: {}-{}", start, end);
+                    return Err(format!(
+                        "Slot range must be within 0-{}: {}-{}",
+                        SLOT_COUNT - 1,
+                        start,
+                        end
+                    ));
+                }
+                let valid = node
+                    .rsplit_once(':')
+                    .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+                if !valid {
+                    error!("Invalid node address: {}", node);
+                    return Err(format!("Invalid node address: {}", node));
+                }
+                Ok(self)
+            }
+            Command::ConfigGet { ref pattern } => {
+                if pattern.is_empty() {
+                    error!("Setting pattern is empty");
+                    return Err("Setting name or pattern cannot be empty".to_string());
+                }
+                Ok(self)
+            }
+            Command::ConfigSet { ref name, .. } => {
+                if !SETTINGS.contains(&name.to_lowercase().as_str()) {
+                    error!("Unknown setting: {}", name);
+                    return Err(format!("Unknown setting: {}", name));
+                }
+                Ok(self)
+            }
+            Command::Auth { ref password } => {
+                if password.is_empty() {
+                    error!("Password is empty");
+                    return Err("Password cannot be empty".to_string());
+                }
+                Ok(self)
+            }
+            Command::Info {
+                section: Some(ref section),
+                ..
+            } if section != "all" && !INFO_SECTIONS.contains(&section.as_str()) => {
+                error!("Unknown INFO section: {}", section);
+                Err(format!(
+                    "Unknown INFO section: {}, expected one of all, {}",
+                    section,
+                    INFO_SECTIONS.join(", ")
+                ))
+            }
+            Command::ClientSetName { ref name } => {
+                if name.is_empty() {
+                    error!("Client name is empty");
+                    return Err("Client name cannot be empty".to_string());
+                }
+                Ok(self)
+            }
+            Command::TraceParent { ref value } => {
+                if let Err(e) = TraceParent::parse(value) {
+                    error!("{}", e);
+                    return Err(e);
+                }
+                Ok(self)
+            }
+            Command::ClientKill { filter: None } => {
+                error!("Invalid client filter");
+                Err("Expected CLIENT KILL <addr>, CLIENT KILL ADDR <addr> or CLIENT KILL USER <user>"
+                    .to_string())
+            }
+            Command::SlowLogGet { count: None } => {
+                error!("Invalid slow log count");
+                Err("Count must be a non-negative integer".to_string())
+            }
+            Command::List
+            | Command::Stats
+            | Command::Info { .. }
+            | Command::SlowLogGet { .. }
+            | Command::SlowLogLen
+            | Command::SlowLogReset
+            | Command::Monitor
+            | Command::Restore { .. }
+            | Command::ClientList
+            | Command::ClientKill { .. }
+            | Command::Ping
+            | Command::Sync
+            | Command::Raft
+            | Command::ClusterSlots
+            | Command::ClusterImporting
+            | Command::RateLimits
+            | Command::Mode
+            | Command::ConfigRewrite
+            | Command::ConfigReload
+            | Command::Shutdown { .. } => Ok(self),
             Command::Invalid(cmd) => {
                 error!("Invalid command: {}", cmd);
                 Err(format!("Invalid command: {}", cmd))
@@ -140,3 +1033,78 @@ impl Command {
         }
     }
 }
+
+/// Validates a key that is read or written by a command
+fn validate_key(key: &str, config: &Configuration) -> Result<(), String> {
+    if key.is_empty() {
+        error!("Key is empty");
+        return Err("Key cannot be empty".to_string());
+    }
+    if key.len() > config.max_key_length {
+        error!("Key exceeds maximum length: {}", key.len());
+        return Err(format!("Key exceeds maximum length: {}", key.len()));
+    }
+    if key.chars().any(|c| config.forbidden_keys.contains(&c)) {
+        warn!("Key contains forbidden characters: {}", key);
+        return Err(format!("Key contains forbidden characters: {}", key));
+    }
+    Ok(())
+}
+
+/// Validates a set member, which follows the same limits as a value
+fn validate_member(member: &str, config: &Configuration) -> Result<(), String> {
+    if member.is_empty() {
+        error!("Member is empty");
+        return Err("Member cannot be empty".to_string());
+    }
+    if member.len() > config.max_value_length {
+        error!("Member exceeds maximum length: {}", member.len());
+        return Err(format!("Member exceeds maximum length: {}", member.len()));
+    }
+    Ok(())
+}
+
+/// Validates a namespace name, which may only hold letters, digits, `-` and `_`
+fn validate_namespace(name: &str, config: &Configuration) -> Result<(), String> {
+    if name.is_empty() {
+        error!("Namespace is empty");
+        return Err("Namespace cannot be empty".to_string());
+    }
+    if name.len() > config.max_key_length {
+        error!("Namespace exceeds maximum length: {}", name.len());
+        return Err(format!("Namespace exceeds maximum length: {}", name.len()));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        warn!("Namespace contains invalid characters: {}", name);
+        return Err(format!("Namespace contains invalid characters: {}", name));
+    }
+    Ok(())
+}
+
+/// Returns what follows the first words of a line, with the whitespace within it kept
+fn rest_after(line: &str, words: usize) -> &str {
+    let mut rest = line.trim();
+    for _ in 0..words {
+        rest = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest);
+    }
+    rest.trim_start()
+}
+
+/// Parses a point in time given in Unix seconds, e.g. `1760862000.5`,
+/// or in RFC 3339 format, e.g. `2026-10-19T08:30:00Z`
+fn parse_time(text: &str) -> Option<SystemTime> {
+    if let Ok(secs) = text.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs)
+            .ok()
+            .and_then(|elapsed| UNIX_EPOCH.checked_add(elapsed));
+    }
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(SystemTime::from)
+}
@@ -1,59 +1,1001 @@
+use crate::crypto;
+use crate::mode::{ModeProfile, builtin_modes};
+use crate::store::history::Retention;
+use crate::telemetry::LOG_FORMATS;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Command categories a rate limit can be given for
+const RATE_LIMIT_CATEGORIES: [&str; 3] = ["read", "write", "admin"];
+
+/// Characters that can never be part of a key since they break the line protocol
+const PROTOCOL_CHARACTERS: [char; 3] = ['\n', '\r', '\0'];
+
+/// Names of the settings, as used in the configuration file and by CONFIG GET and CONFIG SET.
+/// The environment variables are the same names in upper case.
+pub const SETTINGS: [&str; 31] = [
+    "host",
+    "port",
+    "mode",
+    "max_key_length",
+    "max_value_length",
+    "forbidden_keys",
+    "max_list_length",
+    "max_connections",
+    "max_connections_per_ip",
+    "connection_queue_timeout_ms",
+    "shutdown_timeout_ms",
+    "slowlog_threshold_us",
+    "slowlog_max_len",
+    "history_max_versions",
+    "history_max_age_secs",
+    "rate_limits",
+    "namespaces",
+    "max_namespaces",
+    "replica_of",
+    "cluster_nodes",
+    "shard_nodes",
+    "node_address",
+    "admin_password",
+    "encryption_key",
+    "metrics_address",
+    "audit_log",
+    "audit_log_max_bytes",
+    "audit_log_max_files",
+    "audit_key",
+    "log_format",
+    "otlp_endpoint",
+];
+
+/// Settings read only at startup, a change to them needs a restart
+pub const RESTART_REQUIRED: [&str; 11] = [
+    "host",
+    "port",
+    "node_address",
+    "cluster_nodes",
+    "shard_nodes",
+    "replica_of",
+    "encryption_key",
+    "metrics_address",
+    "audit_log",
+    "log_format",
+    "otlp_endpoint",
+];
+
 /// Configuration for the Kiwi Store Server
-/// This configuration is loaded from environment variables.
-/// It can also be used to set default values.
+/// The configuration is built from layers of settings: the defaults, a TOML configuration file,
+/// environment variables and command-line flags, each overriding the ones before it.
+/// The result is validated before the server starts.
 ///
 /// # Example:
 /// ```rust
-/// use kiwi_store_server::config::Configuration;
-/// let config = Configuration::from_env();
+/// use kiwi_store_server::config::{Configuration, Settings};
+/// let config = Configuration::load(None, Settings::default()).unwrap();
 /// println!("Server will run on: {}", config.get_listen_address());
 /// ```
-#[derive(Debug, Clone)]
+#[derive(Clone, Serialize)]
 pub struct Configuration {
     pub host: String,
     pub port: u16,
-    // pub max_connections: usize,
+    pub mode: String,
+    /// Maximum number of connections served at the same time
+    pub max_connections: usize,
+    /// Maximum number of connections served at the same time for a single client IP
+    pub max_connections_per_ip: usize,
+    /// How long a connection over the limits waits for a free slot before it is rejected.
+    /// Zero rejects it immediately
+    pub connection_queue_timeout_ms: u64,
+    /// How long a shutdown waits for the running commands to finish before the server exits
+    pub shutdown_timeout_ms: u64,
+    /// Microseconds a command must take to be recorded in the slow log. Zero records every command
+    pub slowlog_threshold_us: u64,
+    /// Number of commands the slow log keeps, the oldest are dropped first. Zero turns it off
+    pub slowlog_max_len: usize,
+    /// Number of versions kept in the history of each key. Zero keeps no history
+    pub history_max_versions: usize,
+    /// Seconds a version is kept in the history of a key. Zero keeps the versions until they are too many
+    pub history_max_age_secs: u64,
+    /// Commands allowed per second for a client IP, by command category (read, write, admin)
+    pub rate_limits: BTreeMap<String, u32>,
     pub max_key_length: usize,
     pub max_value_length: usize,
+    /// Characters a key can't contain, the line breaks and the null character are always included
+    #[serde(serialize_with = "serialize_chars")]
     pub forbidden_keys: Vec<char>,
+    pub max_list_length: usize,
+    /// Logical namespaces created at startup, mapped to the mode they run in
+    pub namespaces: BTreeMap<String, String>,
+    /// Number of namespaces that can exist, counting the default one and the listed ones.
+    /// SELECT of a new namespace past it is refused with `ERR_MAX_NAMESPACES`
+    pub max_namespaces: usize,
+    /// Address of the leader to replicate from at startup, in the format "IP:port"
+    pub replica_of: Option<String>,
+    /// Addresses of every node of the Raft cluster, in the format "IP:port". Empty for a standalone server
+    pub cluster_nodes: Vec<String>,
+    /// Addresses of the nodes sharing the key space by hash slot. Empty for a server holding every key
+    pub shard_nodes: Vec<String>,
+    /// Address other cluster nodes reach this server at, it must be one of `cluster_nodes` or `shard_nodes`
+    pub node_address: String,
+    /// Password of the admin sessions, see the `auth` module
+    #[serde(serialize_with = "serialize_secret")]
+    pub admin_password: Option<String>,
+    /// Key of the encrypted modes, 64 hex characters
+    #[serde(serialize_with = "serialize_secret")]
+    pub encryption_key: Option<String>,
+    /// Address the Prometheus metrics are served at over HTTP, in the format "IP:port".
+    /// `None` serves no metrics
+    pub metrics_address: Option<String>,
+    /// Path of the file the changes to the data are audited in, `None` audits nothing
+    pub audit_log: Option<String>,
+    /// Size the audit log grows to before it is rotated
+    pub audit_log_max_bytes: u64,
+    /// Number of rotated audit log files kept besides the current one
+    pub audit_log_max_files: usize,
+    /// Key the values in the audit log are hashed with, `None` leaves the values out
+    #[serde(serialize_with = "serialize_secret")]
+    pub audit_key: Option<String>,
+    /// Format of the logs written to the standard error, `text` or `json`
+    pub log_format: String,
+    /// Address of the OpenTelemetry collector the spans are exported to over OTLP/HTTP,
+    /// e.g. "http://localhost:4318/v1/traces". `None` exports no spans
+    pub otlp_endpoint: Option<String>,
+    /// Mode profiles by name, the built-in ones and the ones of the configuration file
+    pub modes: BTreeMap<String, ModeProfile>,
+    /// Profile of the mode the configuration runs in
+    #[serde(skip)]
+    pub profile: ModeProfile,
 }
 
-impl Configuration {
-    /// Creates a new `Configuration` instance from environment variables.
-    /// If the environment variables are not set, it uses default values.
+/// Masks the secrets, like `get`, since the configuration is logged at startup
+impl fmt::Debug for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Configuration {
+            host,
+            port,
+            mode,
+            max_connections,
+            max_connections_per_ip,
+            connection_queue_timeout_ms,
+            shutdown_timeout_ms,
+            slowlog_threshold_us,
+            slowlog_max_len,
+            history_max_versions,
+            history_max_age_secs,
+            rate_limits,
+            max_key_length,
+            max_value_length,
+            forbidden_keys,
+            max_list_length,
+            namespaces,
+            max_namespaces,
+            replica_of,
+            cluster_nodes,
+            shard_nodes,
+            node_address,
+            admin_password,
+            encryption_key,
+            metrics_address,
+            audit_log,
+            audit_log_max_bytes,
+            audit_log_max_files,
+            audit_key,
+            log_format,
+            otlp_endpoint,
+            modes,
+            profile,
+        } = self;
+        f.debug_struct("Configuration")
+            .field("host", host)
+            .field("port", port)
+            .field("mode", mode)
+            .field("max_connections", max_connections)
+            .field("max_connections_per_ip", max_connections_per_ip)
+            .field("connection_queue_timeout_ms", connection_queue_timeout_ms)
+            .field("shutdown_timeout_ms", shutdown_timeout_ms)
+            .field("slowlog_threshold_us", slowlog_threshold_us)
+            .field("slowlog_max_len", slowlog_max_len)
+            .field("history_max_versions", history_max_versions)
+            .field("history_max_age_secs", history_max_age_secs)
+            .field("rate_limits", rate_limits)
+            .field("max_key_length", max_key_length)
+            .field("max_value_length", max_value_length)
+            .field("forbidden_keys", forbidden_keys)
+            .field("max_list_length", max_list_length)
+            .field("namespaces", namespaces)
+            .field("max_namespaces", max_namespaces)
+            .field("replica_of", replica_of)
+            .field("cluster_nodes", cluster_nodes)
+            .field("shard_nodes", shard_nodes)
+            .field("node_address", node_address)
+            .field(
+                "admin_password",
+                &admin_password.as_ref().map(|_| "********"),
+            )
+            .field(
+                "encryption_key",
+                &encryption_key.as_ref().map(|_| "********"),
+            )
+            .field("metrics_address", metrics_address)
+            .field("audit_log", audit_log)
+            .field("audit_log_max_bytes", audit_log_max_bytes)
+            .field("audit_log_max_files", audit_log_max_files)
+            .field("audit_key", &audit_key.as_ref().map(|_| "********"))
+            .field("log_format", log_format)
+            .field("otlp_endpoint", otlp_endpoint)
+            .field("modes", modes)
+            .field("profile", profile)
+            .finish()
+    }
+}
+
+/// Represents one layer of settings, read from the configuration file, the environment
+/// variables or the command-line flags. Settings that are not given keep the value of
+/// the layer below.
+#[derive(Default, Clone, Serialize, Deserialize, Args)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[arg(long, help = "Host address to listen on")]
+    pub host: Option<String>,
+    #[arg(long, help = "Port to listen on")]
+    pub port: Option<u16>,
+    #[arg(
+        long,
+        help = "Mode of operation: DEFAULT_MODE, CACHE_MODE, VAULT_MODE or a mode of the configuration file"
+    )]
+    pub mode: Option<String>,
+    #[arg(long, help = "Maximum length of a key, the default follows the mode")]
+    pub max_key_length: Option<usize>,
+    #[arg(long, help = "Maximum length of a value, the default follows the mode")]
+    pub max_value_length: Option<usize>,
+    #[arg(long, help = "Characters a key can't contain, e.g. \"*?\"")]
+    pub forbidden_keys: Option<String>,
+    #[arg(long, help = "Maximum number of items a list can hold")]
+    pub max_list_length: Option<usize>,
+    #[arg(long, help = "Maximum number of connections served at the same time")]
+    pub max_connections: Option<usize>,
+    #[arg(long, help = "Maximum number of connections of a single client IP")]
+    pub max_connections_per_ip: Option<usize>,
+    #[arg(
+        long,
+        help = "Milliseconds a connection over the limits waits before it is rejected"
+    )]
+    pub connection_queue_timeout_ms: Option<u64>,
+    #[arg(long, help = "Milliseconds a shutdown waits for the running commands")]
+    pub shutdown_timeout_ms: Option<u64>,
+    #[arg(
+        long,
+        help = "Microseconds a command must take to be recorded in the slow log"
+    )]
+    pub slowlog_threshold_us: Option<u64>,
+    #[arg(long, help = "Number of slow commands kept, 0 turns the slow log off")]
+    pub slowlog_max_len: Option<usize>,
+    #[arg(
+        long,
+        help = "Number of versions kept per key, 0 turns the history off"
+    )]
+    pub history_max_versions: Option<usize>,
+    #[arg(
+        long,
+        help = "Seconds a version is kept in the history, 0 for no limit"
+    )]
+    pub history_max_age_secs: Option<u64>,
+    #[arg(long, value_parser = parse_rate_limits, help = "Commands per second by category, e.g. read=1000,write=100")]
+    pub rate_limits: Option<BTreeMap<String, u32>>,
+    #[arg(long, value_parser = parse_namespaces, help = "Namespaces with an optional mode, e.g. dev=CACHE_MODE,staging")]
+    pub namespaces: Option<BTreeMap<String, String>>,
+    #[arg(
+        long,
+        help = "Maximum number of namespaces, new ones past it can't be selected"
+    )]
+    pub max_namespaces: Option<usize>,
+    #[arg(
+        long,
+        help = "Address of a leader to follow at startup, in the format IP:port"
+    )]
+    pub replica_of: Option<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Addresses of the Raft cluster nodes"
+    )]
+    pub cluster_nodes: Option<Vec<String>>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Addresses of the nodes the hash slots are split between"
+    )]
+    pub shard_nodes: Option<Vec<String>>,
+    #[arg(long, help = "Address of this node in the cluster or shard nodes")]
+    pub node_address: Option<String>,
+    #[arg(long, help = "Password of the admin sessions opened with AUTH")]
+    pub admin_password: Option<String>,
+    #[arg(long, help = "Key of the encrypted modes, 64 hex characters")]
+    pub encryption_key: Option<String>,
+    #[arg(
+        long,
+        help = "Address to serve the Prometheus metrics at, in the format IP:port"
+    )]
+    pub metrics_address: Option<String>,
+    #[arg(long, help = "File to audit the changes to the data in, as JSON lines")]
+    pub audit_log: Option<String>,
+    #[arg(long, help = "Size in bytes the audit log is rotated at")]
+    pub audit_log_max_bytes: Option<u64>,
+    #[arg(long, help = "Number of rotated audit log files kept")]
+    pub audit_log_max_files: Option<usize>,
+    #[arg(long, help = "Key the values in the audit log are hashed with")]
+    pub audit_key: Option<String>,
+    #[arg(long, help = "Format of the logs, text or json")]
+    pub log_format: Option<String>,
+    #[arg(
+        long,
+        help = "OTLP/HTTP address to export the spans to, e.g. http://localhost:4318/v1/traces"
+    )]
+    pub otlp_endpoint: Option<String>,
+    /// Mode profiles, only read from the `[modes.<NAME>]` tables of the configuration file
+    #[arg(skip)]
+    pub modes: Option<BTreeMap<String, ModeProfile>>,
+}
+
+/// Masks the secrets, like the `Debug` of [`Configuration`]
+impl fmt::Debug for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Settings {
+            host,
+            port,
+            mode,
+            max_key_length,
+            max_value_length,
+            forbidden_keys,
+            max_list_length,
+            max_connections,
+            max_connections_per_ip,
+            connection_queue_timeout_ms,
+            shutdown_timeout_ms,
+            slowlog_threshold_us,
+            slowlog_max_len,
+            history_max_versions,
+            history_max_age_secs,
+            rate_limits,
+            namespaces,
+            max_namespaces,
+            replica_of,
+            cluster_nodes,
+            shard_nodes,
+            node_address,
+            admin_password,
+            encryption_key,
+            metrics_address,
+            audit_log,
+            audit_log_max_bytes,
+            audit_log_max_files,
+            audit_key,
+            log_format,
+            otlp_endpoint,
+            modes,
+        } = self;
+        f.debug_struct("Settings")
+            .field("host", host)
+            .field("port", port)
+            .field("mode", mode)
+            .field("max_key_length", max_key_length)
+            .field("max_value_length", max_value_length)
+            .field("forbidden_keys", forbidden_keys)
+            .field("max_list_length", max_list_length)
+            .field("max_connections", max_connections)
+            .field("max_connections_per_ip", max_connections_per_ip)
+            .field("connection_queue_timeout_ms", connection_queue_timeout_ms)
+            .field("shutdown_timeout_ms", shutdown_timeout_ms)
+            .field("slowlog_threshold_us", slowlog_threshold_us)
+            .field("slowlog_max_len", slowlog_max_len)
+            .field("history_max_versions", history_max_versions)
+            .field("history_max_age_secs", history_max_age_secs)
+            .field("rate_limits", rate_limits)
+            .field("namespaces", namespaces)
+            .field("max_namespaces", max_namespaces)
+            .field("replica_of", replica_of)
+            .field("cluster_nodes", cluster_nodes)
+            .field("shard_nodes", shard_nodes)
+            .field("node_address", node_address)
+            .field(
+                "admin_password",
+                &admin_password.as_ref().map(|_| "********"),
+            )
+            .field(
+                "encryption_key",
+                &encryption_key.as_ref().map(|_| "********"),
+            )
+            .field("metrics_address", metrics_address)
+            .field("audit_log", audit_log)
+            .field("audit_log_max_bytes", audit_log_max_bytes)
+            .field("audit_log_max_files", audit_log_max_files)
+            .field("audit_key", &audit_key.as_ref().map(|_| "********"))
+            .field("log_format", log_format)
+            .field("otlp_endpoint", otlp_endpoint)
+            .field("modes", modes)
+            .finish()
+    }
+}
+
+impl Settings {
+    /// Reads the settings of a TOML configuration file.
+    /// The keys of the file are the names of the settings, e.g. `port = 5544` or `[namespaces]`.
+    ///
+    /// # Returns
+    /// Returns the settings, or an error if the file can't be read or holds an unknown or invalid setting.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+    }
+
+    /// Reads the settings of the environment variables.
     ///
     /// # Environment Variables:
-    /// - `HOST`: The host address (default: "127.0.0.01")
-    /// - `PORT`: The port number (default: 5544)
-    /// - `MODE`: The mode of operation, which affects the maximum key and value lengths.
-    ///   - `CACHE_MODE`: max key length 20, max value length 255
-    ///   - `VAULT_MODE`: max key length 20, max value length 40
-    ///   - Any other value defaults to max key length 20, max value length 100
+    /// - `HOST`: The host address
+    /// - `PORT`: The port number
+    /// - `MODE`: The mode of operation, one of the built-in modes or of the modes of the
+    ///   configuration file, see the `mode` module.
+    ///   - `CACHE_MODE`: max key length 20, max value length 255, oldest keys evicted past 100000 keys
+    ///   - `VAULT_MODE`: max key length 20, max value length 40, values left out of the logs
+    ///   - `DEFAULT_MODE`: max key length 20, max value length 100
+    /// - `MAX_KEY_LENGTH`, `MAX_VALUE_LENGTH`: The maximum key and value lengths, overriding the ones of the mode
+    /// - `FORBIDDEN_KEYS`: Characters a key can't contain, e.g. `*?`
+    /// - `MAX_LIST_LENGTH`: The maximum number of items a list can hold
+    /// - `MAX_CONNECTIONS`: The maximum number of connections served at the same time
+    /// - `MAX_CONNECTIONS_PER_IP`: The maximum number of connections of a single client IP
+    /// - `CONNECTION_QUEUE_TIMEOUT_MS`: How long a connection over the limits waits before it is
+    ///   rejected with `ERR_MAX_CLIENTS`
+    /// - `SHUTDOWN_TIMEOUT_MS`: How long a shutdown waits for the running commands
+    /// - `RATE_LIMITS`: Commands allowed per second for a client IP by category, e.g. `read=1000,write=100,admin=10`
+    /// - `NAMESPACES`: Comma separated namespaces with an optional mode, e.g. `dev=CACHE_MODE,staging`.
+    ///   A namespace without a mode uses `MODE`.
+    /// - `MAX_NAMESPACES`: The maximum number of namespaces, SELECT can't create one past it
+    /// - `REPLICA_OF`: Address of a leader to follow at startup, in the format "IP:port"
+    /// - `CLUSTER_NODES`: Comma separated addresses of the Raft cluster nodes, e.g.
+    ///   `10.0.0.1:5544,10.0.0.2:5544,10.0.0.3:5544`
+    /// - `SHARD_NODES`: Comma separated addresses of the nodes the hash slots are split between,
+    ///   in the same order on every node
+    /// - `NODE_ADDRESS`: Address of this node in `CLUSTER_NODES` or `SHARD_NODES`
+    /// - `ADMIN_PASSWORD`: Password of the admin sessions opened with `AUTH`
+    /// - `ENCRYPTION_KEY`: Key of the modes with `encryption = true`, 64 hex characters
+    /// - `METRICS_ADDRESS`: Address to serve the Prometheus metrics at over HTTP, e.g. `0.0.0.0:9544`
     ///
-    /// # Returns:
-    /// A `Configuration` instance with the values from the environment variables or defaults.
-    pub fn from_env() -> Self {
-        let host = std::env::var("HOST").unwrap_or_else(|_| "127.0.0.0.1".to_string());
-        let port = std::env::var("PORT")
-            .unwrap_or_else(|_| "5544".to_string())
-            .parse::<u16>()
-            .unwrap_or(5544);
-        let mode = std::env::var("MODE").unwrap_or_else(|_| "DEFAULT_MODE".to_string());
-
-        let (max_key_length, max_value_length) = match mode.as_str() {
-            "CACHE_MODE" => (20, 255),
-            "VAULT_MODE" => (20, 40),
-            _ => (20, 100),
-        };
+    /// Empty variables are treated as not set.
+    ///
+    /// # Returns
+    /// Returns the settings, or an error naming the first variable that can't be parsed.
+    pub fn from_env() -> Result<Self, String> {
+        let mut settings = Self::default();
+        for name in SETTINGS {
+            let variable = name.to_uppercase();
+            if let Ok(value) = std::env::var(&variable)
+                && !value.trim().is_empty()
+            {
+                settings = settings.merge(Self::parse(&variable, &value)?);
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Parses a single setting given as text, the way environment variables and CONFIG SET give them.
+    /// Lists are comma separated, rate limits and namespaces are `name=value` pairs.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the setting, in any case
+    /// * `value` - The value of the setting
+    ///
+    /// # Returns
+    /// Returns the settings with only the given one set, or an error if the name is unknown
+    /// or the value can't be parsed.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::config::Settings;
+    /// let settings = Settings::parse("PORT", "6000").unwrap();
+    /// assert_eq!(settings.port, Some(6000));
+    /// assert_eq!(Settings::parse("PORT", "abc").unwrap_err(), "Invalid PORT: abc");
+    /// ```
+    pub fn parse(name: &str, value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let invalid = |reason: String| format!("Invalid {}: {}", name, reason);
+        fn number<T: FromStr>(value: &str) -> Result<Option<T>, String> {
+            value.parse().map(Some).map_err(|_| value.to_string())
+        }
+        let text = Some(value.to_string());
+        let mut settings = Self::default();
+        match name.to_lowercase().as_str() {
+            "host" => settings.host = text,
+            "port" => settings.port = number(value).map_err(invalid)?,
+            "mode" => settings.mode = text,
+            "max_key_length" => settings.max_key_length = number(value).map_err(invalid)?,
+            "max_value_length" => settings.max_value_length = number(value).map_err(invalid)?,
+            "forbidden_keys" => settings.forbidden_keys = text,
+            "max_list_length" => settings.max_list_length = number(value).map_err(invalid)?,
+            "max_connections" => settings.max_connections = number(value).map_err(invalid)?,
+            "max_connections_per_ip" => {
+                settings.max_connections_per_ip = number(value).map_err(invalid)?
+            }
+            "connection_queue_timeout_ms" => {
+                settings.connection_queue_timeout_ms = number(value).map_err(invalid)?
+            }
+            "shutdown_timeout_ms" => {
+                settings.shutdown_timeout_ms = number(value).map_err(invalid)?
+            }
+            "slowlog_threshold_us" => {
+                settings.slowlog_threshold_us = number(value).map_err(invalid)?
+            }
+            "slowlog_max_len" => settings.slowlog_max_len = number(value).map_err(invalid)?,
+            "history_max_versions" => {
+                settings.history_max_versions = number(value).map_err(invalid)?
+            }
+            "history_max_age_secs" => {
+                settings.history_max_age_secs = number(value).map_err(invalid)?
+            }
+            "rate_limits" => {
+                settings.rate_limits = Some(parse_rate_limits(value).map_err(invalid)?)
+            }
+            "namespaces" => settings.namespaces = Some(parse_namespaces(value).map_err(invalid)?),
+            "max_namespaces" => settings.max_namespaces = number(value).map_err(invalid)?,
+            "replica_of" => settings.replica_of = text.filter(|v| !v.is_empty()),
+            "cluster_nodes" => settings.cluster_nodes = Some(parse_list(value)),
+            "shard_nodes" => settings.shard_nodes = Some(parse_list(value)),
+            "node_address" => settings.node_address = text,
+            "admin_password" => settings.admin_password = text.filter(|v| !v.is_empty()),
+            "encryption_key" => settings.encryption_key = text.filter(|v| !v.is_empty()),
+            "metrics_address" => settings.metrics_address = text.filter(|v| !v.is_empty()),
+            "audit_log" => settings.audit_log = text.filter(|v| !v.is_empty()),
+            "audit_log_max_bytes" => {
+                settings.audit_log_max_bytes = number(value).map_err(invalid)?
+            }
+            "audit_log_max_files" => {
+                settings.audit_log_max_files = number(value).map_err(invalid)?
+            }
+            "audit_key" => settings.audit_key = text.filter(|v| !v.is_empty()),
+            "log_format" => settings.log_format = text,
+            "otlp_endpoint" => settings.otlp_endpoint = text.filter(|v| !v.is_empty()),
+            _ => return Err(format!("Unknown setting: {}", name)),
+        }
+        Ok(settings)
+    }
+
+    /// Puts another layer of settings over this one.
+    pub(crate) fn merge(self, over: Settings) -> Settings {
+        Settings {
+            host: over.host.or(self.host),
+            port: over.port.or(self.port),
+            mode: over.mode.or(self.mode),
+            max_key_length: over.max_key_length.or(self.max_key_length),
+            max_value_length: over.max_value_length.or(self.max_value_length),
+            forbidden_keys: over.forbidden_keys.or(self.forbidden_keys),
+            max_list_length: over.max_list_length.or(self.max_list_length),
+            max_connections: over.max_connections.or(self.max_connections),
+            max_connections_per_ip: over.max_connections_per_ip.or(self.max_connections_per_ip),
+            connection_queue_timeout_ms: over
+                .connection_queue_timeout_ms
+                .or(self.connection_queue_timeout_ms),
+            shutdown_timeout_ms: over.shutdown_timeout_ms.or(self.shutdown_timeout_ms),
+            slowlog_threshold_us: over.slowlog_threshold_us.or(self.slowlog_threshold_us),
+            slowlog_max_len: over.slowlog_max_len.or(self.slowlog_max_len),
+            history_max_versions: over.history_max_versions.or(self.history_max_versions),
+            history_max_age_secs: over.history_max_age_secs.or(self.history_max_age_secs),
+            rate_limits: over.rate_limits.or(self.rate_limits),
+            namespaces: over.namespaces.or(self.namespaces),
+            max_namespaces: over.max_namespaces.or(self.max_namespaces),
+            replica_of: over.replica_of.or(self.replica_of),
+            cluster_nodes: over.cluster_nodes.or(self.cluster_nodes),
+            shard_nodes: over.shard_nodes.or(self.shard_nodes),
+            node_address: over.node_address.or(self.node_address),
+            admin_password: over.admin_password.or(self.admin_password),
+            encryption_key: over.encryption_key.or(self.encryption_key),
+            metrics_address: over.metrics_address.or(self.metrics_address),
+            audit_log: over.audit_log.or(self.audit_log),
+            audit_log_max_bytes: over.audit_log_max_bytes.or(self.audit_log_max_bytes),
+            audit_log_max_files: over.audit_log_max_files.or(self.audit_log_max_files),
+            audit_key: over.audit_key.or(self.audit_key),
+            log_format: over.log_format.or(self.log_format),
+            otlp_endpoint: over.otlp_endpoint.or(self.otlp_endpoint),
+            modes: over.modes.or(self.modes),
+        }
+    }
+}
+
+impl Configuration {
+    /// Builds the configuration from the defaults, the configuration file, the environment
+    /// variables and the command-line flags, in this order of precedence from lowest to highest,
+    /// and validates it.
+    ///
+    /// # Arguments
+    /// * `file` - The path of a TOML configuration file, if one is given
+    /// * `flags` - The settings given as command-line flags
+    ///
+    /// # Returns
+    /// Returns the configuration, or an error describing the first invalid setting.
+    pub fn load(file: Option<&Path>, flags: Settings) -> Result<Self, String> {
+        let mut settings = Settings::default();
+        if let Some(path) = file {
+            settings = settings.merge(Settings::from_file(path)?);
+        }
+        let settings = settings.merge(Settings::from_env()?).merge(flags);
+        let config = Self::from_settings(settings);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Creates a configuration from merged settings, using the defaults for the missing ones.
+    pub(crate) fn from_settings(settings: Settings) -> Self {
+        let defaults = Self::default();
+        let host = settings.host.unwrap_or(defaults.host);
+        let port = settings.port.unwrap_or(defaults.port);
+        let mode = settings.mode.unwrap_or(defaults.mode);
+        let namespaces = settings
+            .namespaces
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, ns_mode)| {
+                let ns_mode = if ns_mode.is_empty() {
+                    mode.clone()
+                } else {
+                    ns_mode
+                };
+                (name, ns_mode)
+            })
+            .collect();
+        let mut modes = builtin_modes();
+        modes.extend(settings.modes.unwrap_or_default());
+        let profile = modes.get(&mode).cloned().unwrap_or_default();
+        let forbidden_keys =
+            forbidden_chars(&profile, &settings.forbidden_keys.unwrap_or_default());
         Self {
+            node_address: settings
+                .node_address
+                .unwrap_or_else(|| format!("{}:{}", host, port)),
             host,
             port,
-            // max_connections: 1000,
-            max_key_length,
-            max_value_length,
-            forbidden_keys: ['\n', '\r', '\0'].to_vec(),
+            mode,
+            max_key_length: settings.max_key_length.unwrap_or(profile.max_key_length),
+            max_value_length: settings
+                .max_value_length
+                .unwrap_or(profile.max_value_length),
+            forbidden_keys,
+            max_list_length: settings.max_list_length.unwrap_or(defaults.max_list_length),
+            max_connections: settings.max_connections.unwrap_or(defaults.max_connections),
+            max_connections_per_ip: settings
+                .max_connections_per_ip
+                .unwrap_or(defaults.max_connections_per_ip),
+            connection_queue_timeout_ms: settings
+                .connection_queue_timeout_ms
+                .unwrap_or(defaults.connection_queue_timeout_ms),
+            shutdown_timeout_ms: settings
+                .shutdown_timeout_ms
+                .unwrap_or(defaults.shutdown_timeout_ms),
+            slowlog_threshold_us: settings
+                .slowlog_threshold_us
+                .unwrap_or(defaults.slowlog_threshold_us),
+            slowlog_max_len: settings.slowlog_max_len.unwrap_or(defaults.slowlog_max_len),
+            history_max_versions: settings
+                .history_max_versions
+                .unwrap_or(defaults.history_max_versions),
+            history_max_age_secs: settings
+                .history_max_age_secs
+                .unwrap_or(defaults.history_max_age_secs),
+            rate_limits: settings.rate_limits.unwrap_or_default(),
+            namespaces,
+            max_namespaces: settings.max_namespaces.unwrap_or(defaults.max_namespaces),
+            replica_of: settings.replica_of,
+            cluster_nodes: settings.cluster_nodes.unwrap_or_default(),
+            shard_nodes: settings.shard_nodes.unwrap_or_default(),
+            admin_password: settings.admin_password,
+            encryption_key: settings.encryption_key,
+            metrics_address: settings.metrics_address,
+            audit_log: settings.audit_log,
+            audit_log_max_bytes: settings
+                .audit_log_max_bytes
+                .unwrap_or(defaults.audit_log_max_bytes),
+            audit_log_max_files: settings
+                .audit_log_max_files
+                .unwrap_or(defaults.audit_log_max_files),
+            audit_key: settings.audit_key,
+            log_format: settings.log_format.unwrap_or(defaults.log_format),
+            otlp_endpoint: settings.otlp_endpoint,
+            modes,
+            profile,
         }
     }
+
+    /// Returns the forbidden characters given in the settings, without the ones every key
+    /// is refused and the ones of the mode profile.
+    fn extra_forbidden_keys(&self) -> String {
+        self.forbidden_keys
+            .iter()
+            .filter(|c| {
+                !PROTOCOL_CHARACTERS.contains(c) && !self.profile.forbidden_keys.contains(**c)
+            })
+            .collect()
+    }
+
+    /// Creates a copy of the configuration running in another mode,
+    /// used to give each namespace the limits and the behaviour of its own mode.
+    /// The forbidden characters given in the settings are kept.
+    ///
+    /// # Example:
+    /// ```rust
+    /// use kiwi_store_server::config::Configuration;
+    /// let config = Configuration::default().for_mode("VAULT_MODE");
+    /// assert_eq!(config.max_value_length, 40);
+    /// ```
+    pub fn for_mode(&self, mode: &str) -> Self {
+        let profile = self.modes.get(mode).cloned().unwrap_or_default();
+        Self {
+            mode: mode.to_string(),
+            max_key_length: profile.max_key_length,
+            max_value_length: profile.max_value_length,
+            forbidden_keys: forbidden_chars(&profile, &self.extra_forbidden_keys()),
+            profile,
+            ..self.clone()
+        }
+    }
+
+    /// Checks every setting and refuses values the server can't run with.
+    ///
+    /// # Example:
+    /// ```rust
+    /// use kiwi_store_server::config::Configuration;
+    /// let mut config = Configuration::default();
+    /// config.host = "127.0.0.0.1".to_string();
+    /// assert_eq!(config.validate(), Err("Invalid host: 127.0.0.0.1".to_string()));
+    /// ```
+    pub fn validate(&self) -> Result<(), String> {
+        let looks_like_ip = self.host.chars().all(|c| c.is_ascii_digit() || c == '.');
+        let valid_host = if looks_like_ip || self.host.contains(':') {
+            self.host.parse::<IpAddr>().is_ok()
+        } else {
+            !self.host.is_empty()
+                && self
+                    .host
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        };
+        if !valid_host {
+            return Err(format!("Invalid host: {}", self.host));
+        }
+        if self.port == 0 {
+            return Err("Port must be between 1 and 65535".to_string());
+        }
+        for (name, profile) in &self.modes {
+            profile.validate(name)?;
+        }
+        if !self.modes.contains_key(&self.mode) {
+            return Err(format!(
+                "Invalid mode: {}, expected one of {}",
+                self.mode,
+                self.modes
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        for (name, value) in [
+            ("max_key_length", self.max_key_length),
+            ("max_value_length", self.max_value_length),
+            ("max_list_length", self.max_list_length),
+            ("max_connections", self.max_connections),
+            ("max_connections_per_ip", self.max_connections_per_ip),
+            ("audit_log_max_files", self.audit_log_max_files),
+            ("max_namespaces", self.max_namespaces),
+        ] {
+            if value == 0 {
+                return Err(format!("{} must be greater than 0", name));
+            }
+        }
+        if self.audit_log_max_bytes == 0 {
+            return Err("audit_log_max_bytes must be greater than 0".to_string());
+        }
+        if !LOG_FORMATS.contains(&self.log_format.as_str()) {
+            return Err(format!(
+                "Invalid log_format: {}, expected one of {}",
+                self.log_format,
+                LOG_FORMATS.join(", ")
+            ));
+        }
+        for (name, mode) in &self.namespaces {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(format!("Invalid namespace name: {}", name));
+            }
+            if !self.modes.contains_key(mode) {
+                return Err(format!("Invalid mode of namespace {}: {}", name, mode));
+            }
+        }
+        if let Some(key) = &self.encryption_key {
+            crypto::parse_key(key)?;
+        }
+        let encrypted = [&self.mode]
+            .into_iter()
+            .chain(self.namespaces.values())
+            .find(|mode| self.modes.get(*mode).is_some_and(|p| p.encryption));
+        if let Some(mode) = encrypted
+            && self.encryption_key.is_none()
+        {
+            return Err(format!("Mode {} needs an encryption_key", mode));
+        }
+        for category in self.rate_limits.keys() {
+            if !RATE_LIMIT_CATEGORIES.contains(&category.to_lowercase().as_str()) {
+                return Err(format!(
+                    "Invalid rate limit category: {}, expected one of {}",
+                    category,
+                    RATE_LIMIT_CATEGORIES.join(", ")
+                ));
+            }
+        }
+        let addresses = self
+            .replica_of
+            .iter()
+            .chain(&self.cluster_nodes)
+            .chain(&self.shard_nodes)
+            .chain(&self.metrics_address)
+            .chain([&self.node_address]);
+        for address in addresses {
+            if !is_address(address) {
+                return Err(format!("Invalid address: {}", address));
+            }
+        }
+        self.validate_cluster()
+    }
+
+    /// Checks the cluster settings. A Raft cluster needs at least three nodes, this node
+    /// must be one of them, and a cluster node can't follow a leader with `REPLICA_OF`.
+    /// A sharded server must be one of the shard nodes and can't also be a Raft cluster node.
+    ///
+    /// # Example:
+    /// ```rust
+    /// use kiwi_store_server::config::Configuration;
+    /// let mut config = Configuration::default();
+    /// config.cluster_nodes = vec!["127.0.0.1:5544".to_string(), "127.0.0.1:5545".to_string()];
+    /// assert!(config.validate_cluster().is_err());
+    /// ```
+    pub fn validate_cluster(&self) -> Result<(), String> {
+        if !self.shard_nodes.is_empty() {
+            if !self.cluster_nodes.is_empty() {
+                return Err("A server can't be both a Raft cluster node and a shard".to_string());
+            }
+            if !self.shard_nodes.contains(&self.node_address) {
+                return Err(format!(
+                    "Node address {} is not one of the shard nodes",
+                    self.node_address
+                ));
+            }
+        }
+        if self.cluster_nodes.is_empty() {
+            return Ok(());
+        }
+        if self.cluster_nodes.len() < 3 {
+            return Err("A cluster needs at least 3 nodes".to_string());
+        }
+        if !self.cluster_nodes.contains(&self.node_address) {
+            return Err(format!(
+                "Node address {} is not one of the cluster nodes",
+                self.node_address
+            ));
+        }
+        if self.replica_of.is_some() {
+            return Err("A cluster node can't replicate from a leader".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a setting in the format CONFIG SET and the environment variables take.
+    /// The admin password, the encryption key and the audit key are masked and the line breaks and null character are left out of
+    /// the forbidden keys, since they are always forbidden.
+    ///
+    /// # Example:
+    /// ```rust
+    /// use kiwi_store_server::config::Configuration;
+    /// let mut config = Configuration::default();
+    /// config.rate_limits.insert("write".to_string(), 100);
+    /// assert_eq!(config.get("rate_limits"), Some("write=100".to_string()));
+    /// assert_eq!(config.get("colour"), None);
+    /// ```
+    pub fn get(&self, name: &str) -> Option<String> {
+        fn pairs<T: std::fmt::Display>(map: &BTreeMap<String, T>) -> String {
+            map.iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<String>>()
+                .join(",")
+        }
+        let value = match name.to_lowercase().as_str() {
+            "host" => self.host.clone(),
+            "port" => self.port.to_string(),
+            "mode" => self.mode.clone(),
+            "max_key_length" => self.max_key_length.to_string(),
+            "max_value_length" => self.max_value_length.to_string(),
+            "forbidden_keys" => self
+                .forbidden_keys
+                .iter()
+                .filter(|c| !PROTOCOL_CHARACTERS.contains(c))
+                .collect(),
+            "max_list_length" => self.max_list_length.to_string(),
+            "max_connections" => self.max_connections.to_string(),
+            "max_connections_per_ip" => self.max_connections_per_ip.to_string(),
+            "connection_queue_timeout_ms" => self.connection_queue_timeout_ms.to_string(),
+            "shutdown_timeout_ms" => self.shutdown_timeout_ms.to_string(),
+            "slowlog_threshold_us" => self.slowlog_threshold_us.to_string(),
+            "slowlog_max_len" => self.slowlog_max_len.to_string(),
+            "history_max_versions" => self.history_max_versions.to_string(),
+            "history_max_age_secs" => self.history_max_age_secs.to_string(),
+            "rate_limits" => pairs(&self.rate_limits),
+            "namespaces" => pairs(&self.namespaces),
+            "max_namespaces" => self.max_namespaces.to_string(),
+            "replica_of" => self.replica_of.clone().unwrap_or_default(),
+            "cluster_nodes" => self.cluster_nodes.join(","),
+            "shard_nodes" => self.shard_nodes.join(","),
+            "node_address" => self.node_address.clone(),
+            "admin_password" => mask(&self.admin_password),
+            "encryption_key" => mask(&self.encryption_key),
+            "metrics_address" => self.metrics_address.clone().unwrap_or_default(),
+            "audit_log" => self.audit_log.clone().unwrap_or_default(),
+            "audit_log_max_bytes" => self.audit_log_max_bytes.to_string(),
+            "audit_log_max_files" => self.audit_log_max_files.to_string(),
+            "audit_key" => mask(&self.audit_key),
+            "log_format" => self.log_format.clone(),
+            "otlp_endpoint" => self.otlp_endpoint.clone().unwrap_or_default(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Lists the settings that differ between two configurations.
+    ///
+    /// # Example:
+    /// ```rust
+    /// use kiwi_store_server::config::Configuration;
+    /// let mut config = Configuration::default();
+    /// config.port = 6000;
+    /// config.max_value_length = 255;
+    /// assert_eq!(Configuration::default().changes(&config), vec!["max_value_length", "port"]);
+    /// ```
+    pub fn changes(&self, other: &Configuration) -> Vec<String> {
+        let (Ok(toml::Value::Table(current)), Ok(toml::Value::Table(other))) =
+            (toml::Value::try_from(self), toml::Value::try_from(other))
+        else {
+            return Vec::new();
+        };
+        let mut keys: Vec<String> = current
+            .keys()
+            .chain(other.keys())
+            .filter(|key| current.get(*key) != other.get(*key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    /// Returns the effective configuration in the TOML format of the configuration file.
+    /// The secrets are masked like in CONFIG GET.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// Returns how many versions of a key are kept in its history and for how long.
+    pub fn history_retention(&self) -> Retention {
+        Retention {
+            max_versions: self.history_max_versions,
+            max_age: Some(self.history_max_age_secs)
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+        }
+    }
+
     pub fn get_listen_address(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
@@ -63,21 +1005,195 @@ impl Default for Configuration {
     /// Returns a default `Configuration` instance with predefined values.
     /// - Host: "127.0.0.1"
     /// - Port: 5544
+    /// - Mode: "DEFAULT_MODE"
     /// - Max Key Length: 20
     /// - Max Value Length: 100
     /// - Forbidden Keys: ['\n', '\r', '\0']
+    /// - Max List Length: 1000
+    /// - Max Connections: 1000, and 100 per client IP, rejected without waiting
+    /// - Shutdown Timeout: 5000 milliseconds
+    /// - History: none, turned on with history_max_versions
+    /// - Rate Limits: none
+    /// - Namespaces: none besides the default one, at most 16
+    /// - Replica Of: none, the server starts as a leader
+    /// - Cluster Nodes: none, the server runs standalone
+    /// - Shard Nodes: none, the server holds every key
+    /// - Metrics Address: none, no metrics are served
+    /// - Modes: the built-in modes, see `mode::builtin_modes`
     ///
     /// # Returns:
     /// A `Configuration` instance with default values.
     fn default() -> Self {
         let host = "127.0.0.1".to_string();
         let port = 5544;
+        let node_address = format!("{}:{}", host, port);
         Self {
             host,
             port,
+            mode: "DEFAULT_MODE".to_string(),
             max_key_length: 20,
             max_value_length: 100,
-            forbidden_keys: ['\n', '\r', '\0'].to_vec(),
+            forbidden_keys: PROTOCOL_CHARACTERS.to_vec(),
+            max_list_length: 1000,
+            max_connections: 1000,
+            max_connections_per_ip: 100,
+            connection_queue_timeout_ms: 0,
+            shutdown_timeout_ms: 5000,
+            slowlog_threshold_us: 10_000,
+            slowlog_max_len: 128,
+            history_max_versions: 0,
+            history_max_age_secs: 0,
+            rate_limits: BTreeMap::new(),
+            namespaces: BTreeMap::new(),
+            max_namespaces: 16,
+            replica_of: None,
+            cluster_nodes: Vec::new(),
+            shard_nodes: Vec::new(),
+            node_address,
+            admin_password: None,
+            encryption_key: None,
+            metrics_address: None,
+            audit_log: None,
+            audit_log_max_bytes: 10 * 1024 * 1024,
+            audit_log_max_files: 5,
+            audit_key: None,
+            log_format: "text".to_string(),
+            otlp_endpoint: None,
+            modes: builtin_modes(),
+            profile: ModeProfile::default(),
         }
     }
 }
+
+impl From<&Configuration> for Settings {
+    /// Returns the settings a configuration is built from, e.g. to write it back to the
+    /// configuration file. Key and value lengths equal to the ones of the mode are left out,
+    /// so they keep following the mode, and so are the built-in modes that are not redefined.
+    fn from(config: &Configuration) -> Self {
+        let profile = &config.profile;
+        let builtin = builtin_modes();
+        let modes: BTreeMap<String, ModeProfile> = config
+            .modes
+            .iter()
+            .filter(|(name, mode)| builtin.get(*name) != Some(*mode))
+            .map(|(name, mode)| (name.clone(), mode.clone()))
+            .collect();
+        Self {
+            host: Some(config.host.clone()),
+            port: Some(config.port),
+            mode: Some(config.mode.clone()),
+            max_key_length: Some(config.max_key_length).filter(|l| *l != profile.max_key_length),
+            max_value_length: Some(config.max_value_length)
+                .filter(|l| *l != profile.max_value_length),
+            forbidden_keys: Some(config.extra_forbidden_keys()).filter(|k| !k.is_empty()),
+            max_list_length: Some(config.max_list_length),
+            max_connections: Some(config.max_connections),
+            max_connections_per_ip: Some(config.max_connections_per_ip),
+            connection_queue_timeout_ms: Some(config.connection_queue_timeout_ms),
+            shutdown_timeout_ms: Some(config.shutdown_timeout_ms),
+            slowlog_threshold_us: Some(config.slowlog_threshold_us),
+            slowlog_max_len: Some(config.slowlog_max_len),
+            history_max_versions: Some(config.history_max_versions),
+            history_max_age_secs: Some(config.history_max_age_secs),
+            rate_limits: Some(config.rate_limits.clone()),
+            namespaces: Some(config.namespaces.clone()),
+            max_namespaces: Some(config.max_namespaces),
+            replica_of: config.replica_of.clone(),
+            cluster_nodes: Some(config.cluster_nodes.clone()),
+            shard_nodes: Some(config.shard_nodes.clone()),
+            node_address: Some(config.node_address.clone()),
+            admin_password: config.admin_password.clone(),
+            encryption_key: config.encryption_key.clone(),
+            metrics_address: config.metrics_address.clone(),
+            audit_log: config.audit_log.clone(),
+            audit_log_max_bytes: Some(config.audit_log_max_bytes),
+            audit_log_max_files: Some(config.audit_log_max_files),
+            audit_key: config.audit_key.clone(),
+            log_format: Some(config.log_format.clone()),
+            otlp_endpoint: config.otlp_endpoint.clone(),
+            modes: Some(modes).filter(|modes| !modes.is_empty()),
+        }
+    }
+}
+
+/// Returns the characters a key can't contain in a mode: the line breaks and the null character,
+/// the ones of the mode profile and the ones given in the settings
+fn forbidden_chars(profile: &ModeProfile, extra: &str) -> Vec<char> {
+    let mut forbidden_keys = PROTOCOL_CHARACTERS.to_vec();
+    for c in profile.forbidden_keys.chars().chain(extra.chars()) {
+        if !forbidden_keys.contains(&c) {
+            forbidden_keys.push(c);
+        }
+    }
+    forbidden_keys
+}
+
+/// Masks a secret setting, an unset one is empty
+fn mask(secret: &Option<String>) -> String {
+    match secret {
+        Some(_) => "********".to_string(),
+        None => String::new(),
+    }
+}
+
+/// Writes a secret setting masked, an unset one is left out
+fn serialize_secret<S: serde::Serializer>(
+    secret: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_some(&mask(secret)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Writes the forbidden characters as a single string, the way they are given in the settings
+fn serialize_chars<S: serde::Serializer>(chars: &[char], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&chars.iter().collect::<String>())
+}
+
+/// Parses a comma separated list, skipping empty entries
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses rate limits given as `category=limit` pairs, e.g. `read=1000,write=100`
+fn parse_rate_limits(value: &str) -> Result<BTreeMap<String, u32>, String> {
+    parse_list(value)
+        .iter()
+        .map(|entry| {
+            let (category, limit) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected category=limit, got {}", entry))?;
+            let limit = limit
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid limit of {}: {}", category.trim(), limit))?;
+            Ok((category.trim().to_string(), limit))
+        })
+        .collect()
+}
+
+/// Parses namespaces given as `name=mode` or `name` entries, e.g. `dev=CACHE_MODE,staging`.
+/// A namespace without a mode gets an empty mode, which is replaced by the server mode.
+fn parse_namespaces(value: &str) -> Result<BTreeMap<String, String>, String> {
+    Ok(parse_list(value)
+        .iter()
+        .map(|entry| match entry.split_once('=') {
+            Some((name, mode)) => (name.trim().to_string(), mode.trim().to_string()),
+            None => (entry.to_string(), String::new()),
+        })
+        .collect())
+}
+
+/// Checks that an address is in the format "host:port"
+fn is_address(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p > 0))
+}
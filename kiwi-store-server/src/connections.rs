@@ -0,0 +1,204 @@
+//! Connection limits of the Kiwi Store server.
+//!
+//! The number of connections served at the same time is capped globally and per client IP.
+//! A connection over the limits waits up to the configured queue timeout for another
+//! connection to close, and is rejected with `ERR_MAX_CLIENTS` if none does.
+
+use crate::config::Configuration;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Error message sent to a connection rejected by the limits
+pub const MAX_CLIENTS: &str = "ERR_MAX_CLIENTS";
+
+#[derive(Default)]
+/// Represents the connections being served
+struct Active {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+struct Inner {
+    max_connections: AtomicUsize,
+    max_connections_per_ip: AtomicUsize,
+    queue_timeout_ms: AtomicU64,
+    active: Mutex<Active>,
+    /// Wakes the queued connections when a connection closes
+    released: Notify,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+#[derive(Clone)]
+/// Represents the connection limits of the server, shared by all connections
+pub struct Connections {
+    inner: Arc<Inner>,
+}
+
+/// Holds a connection slot, which is released when the guard is dropped
+pub struct ConnectionGuard {
+    connections: Connections,
+    ip: IpAddr,
+}
+
+impl Connections {
+    /// Creates the connection limits from the configuration.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::config::Configuration;
+    /// use kiwi_store_server::connections::Connections;
+    ///
+    /// let connections = Connections::new(&Configuration::default());
+    /// assert!(connections.stats().starts_with("ActiveConnections(0)"));
+    /// ```
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                max_connections: AtomicUsize::new(config.max_connections),
+                max_connections_per_ip: AtomicUsize::new(config.max_connections_per_ip),
+                queue_timeout_ms: AtomicU64::new(config.connection_queue_timeout_ms),
+                active: Mutex::new(Active::default()),
+                released: Notify::new(),
+                accepted: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Replaces the limits with the ones of a reloaded configuration.
+    /// Open connections are kept even if they are over the new limits.
+    pub fn reconfigure(&self, config: &Configuration) {
+        let inner = &self.inner;
+        inner
+            .max_connections
+            .store(config.max_connections, Ordering::SeqCst);
+        inner
+            .max_connections_per_ip
+            .store(config.max_connections_per_ip, Ordering::SeqCst);
+        inner
+            .queue_timeout_ms
+            .store(config.connection_queue_timeout_ms, Ordering::SeqCst);
+        // Raised limits may let the queued connections in
+        inner.released.notify_waiters();
+    }
+
+    /// Takes a connection slot for a client, waiting up to the queue timeout if the limits are reached.
+    ///
+    /// # Arguments
+    /// * `ip` - The IP address of the client
+    ///
+    /// # Returns
+    /// Returns a guard holding the slot, or the reason the connection is rejected.
+    pub async fn acquire(&self, ip: IpAddr) -> Result<ConnectionGuard, String> {
+        let deadline = Instant::now()
+            + Duration::from_millis(self.inner.queue_timeout_ms.load(Ordering::SeqCst));
+        loop {
+            let released = self.inner.released.notified();
+            tokio::pin!(released);
+            // Register for the wake up before checking, so a release in between is not missed
+            released.as_mut().enable();
+
+            match self.try_acquire(ip) {
+                Ok(guard) => {
+                    self.inner.accepted.fetch_add(1, Ordering::SeqCst);
+                    return Ok(guard);
+                }
+                Err(reason) => {
+                    if Instant::now() >= deadline
+                        || tokio::time::timeout_at(deadline, released).await.is_err()
+                    {
+                        self.inner.rejected.fetch_add(1, Ordering::SeqCst);
+                        return Err(reason);
+                    }
+                }
+            }
+        }
+    }
+
+    fn try_acquire(&self, ip: IpAddr) -> Result<ConnectionGuard, String> {
+        let max_connections = self.inner.max_connections.load(Ordering::SeqCst);
+        let max_connections_per_ip = self.inner.max_connections_per_ip.load(Ordering::SeqCst);
+        let mut active = self.inner.active.lock().unwrap();
+        if active.total >= max_connections {
+            return Err(format!(
+                "{} Max number of clients reached: {}",
+                MAX_CLIENTS, max_connections
+            ));
+        }
+        let per_ip = active.per_ip.entry(ip).or_insert(0);
+        if *per_ip >= max_connections_per_ip {
+            return Err(format!(
+                "{} Max number of clients reached for {}: {}",
+                MAX_CLIENTS, ip, max_connections_per_ip
+            ));
+        }
+        *per_ip += 1;
+        active.total += 1;
+        Ok(ConnectionGuard {
+            connections: self.clone(),
+            ip,
+        })
+    }
+
+    /// Waits until every connection is closed or the deadline passes.
+    ///
+    /// # Returns
+    /// Returns the number of connections still open.
+    pub async fn wait_idle(&self, deadline: Instant) -> usize {
+        loop {
+            let released = self.inner.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            let active = self.inner.active.lock().unwrap().total;
+            if active == 0 || tokio::time::timeout_at(deadline, released).await.is_err() {
+                return self.inner.active.lock().unwrap().total;
+            }
+        }
+    }
+
+    /// Get connection statistics for the STATS command.
+    /// Returns the number of active connections, and of the connections accepted and rejected
+    /// since the server started.
+    pub fn counts(&self) -> (usize, u64, u64) {
+        (
+            self.inner.active.lock().unwrap().total,
+            self.inner.accepted.load(Ordering::SeqCst),
+            self.inner.rejected.load(Ordering::SeqCst),
+        )
+    }
+
+    pub fn stats(&self) -> String {
+        let active = self.inner.active.lock().unwrap().total;
+        let accepted = self.inner.accepted.load(Ordering::SeqCst);
+        let rejected = self.inner.rejected.load(Ordering::SeqCst);
+        format!(
+            "ActiveConnections({}), RejectedConnections({}), TotalConnections({})",
+            active,
+            rejected,
+            accepted + rejected
+        )
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let inner = &self.connections.inner;
+        let mut active = inner.active.lock().unwrap();
+        active.total -= 1;
+        if let Some(count) = active.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.per_ip.remove(&self.ip);
+            }
+        }
+        drop(active);
+        inner.released.notify_waiters();
+    }
+}
@@ -0,0 +1,120 @@
+//! Encryption of the values of the encrypted modes.
+//!
+//! Values are encrypted with AES-256-GCM under the `encryption_key` of the server and a random
+//! nonce, and kept as the hex encoded nonce followed by the ciphertext. A value that was changed
+//! in memory or encrypted with another key fails to decrypt instead of returning garbage.
+
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Length of an encryption key in bytes, written as twice as many hex characters
+const KEY_LEN: usize = 32;
+
+/// Checks an encryption key given as 64 hex characters.
+///
+/// # Returns
+/// Returns the key bytes, or the reason the key is refused.
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::crypto::parse_key;
+///
+/// assert!(parse_key(&"ab".repeat(32)).is_ok());
+/// assert!(parse_key("secret").is_err());
+/// ```
+pub fn parse_key(key: &str) -> Result<Vec<u8>, String> {
+    match decode_hex(key) {
+        Some(bytes) if bytes.len() == KEY_LEN => Ok(bytes),
+        _ => Err(format!(
+            "Encryption key must be {} hex characters",
+            KEY_LEN * 2
+        )),
+    }
+}
+
+/// Encrypts a value.
+///
+/// # Arguments
+/// * `key` - The encryption key, as 64 hex characters
+/// * `value` - The value to encrypt
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::crypto::{decrypt, encrypt};
+///
+/// let key = "ab".repeat(32);
+/// let encrypted = encrypt(&key, "42-42").unwrap();
+/// assert_ne!(encrypted, "42-42");
+/// assert_eq!(decrypt(&key, &encrypted), Ok("42-42".to_string()));
+/// ```
+pub fn encrypt(key: &str, value: &str) -> Result<String, String> {
+    let key = sealing_key(key)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "Could not generate a nonce".to_string())?;
+    let mut sealed = value.as_bytes().to_vec();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::empty(),
+        &mut sealed,
+    )
+    .map_err(|_| "Could not encrypt the value".to_string())?;
+    Ok(encode_hex(&nonce) + &encode_hex(&sealed))
+}
+
+/// Decrypts a value encrypted by [`encrypt`].
+///
+/// # Returns
+/// Returns the value, or an error if it was not encrypted with the same key.
+pub fn decrypt(key: &str, value: &str) -> Result<String, String> {
+    let invalid = || "Value can't be decrypted with the encryption key".to_string();
+    let key = sealing_key(key)?;
+    let bytes = decode_hex(value).ok_or_else(invalid)?;
+    if bytes.len() < NONCE_LEN {
+        return Err(invalid());
+    }
+    let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| invalid())?;
+    let mut sealed = sealed.to_vec();
+    let opened = key
+        .open_in_place(nonce, Aad::empty(), &mut sealed)
+        .map_err(|_| invalid())?;
+    String::from_utf8(opened.to_vec()).map_err(|_| invalid())
+}
+
+/// Returns the HMAC-SHA256 of a value under a key as 64 hex characters. Unlike a plain hash,
+/// it can't be matched against guessed values without the key.
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::crypto::hmac_sha256_hex;
+///
+/// assert_eq!(hmac_sha256_hex("key", "42-42"), hmac_sha256_hex("key", "42-42"));
+/// assert_ne!(hmac_sha256_hex("key", "42-42"), hmac_sha256_hex("other", "42-42"));
+/// ```
+pub fn hmac_sha256_hex(key: &str, value: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    encode_hex(hmac::sign(&key, value.as_bytes()).as_ref())
+}
+
+fn sealing_key(key: &str) -> Result<LessSafeKey, String> {
+    let bytes = parse_key(key)?;
+    let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| "Invalid encryption key")?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,124 @@
+//! Bulk export and import of the store contents, for the DUMP and RESTORE commands.
+//!
+//! `DUMP` walks the store a page at a time, the way SCAN does, and streams every key as a JSON
+//! line with its type and value, ending with an `END` line. The lock is only held while a single
+//! page is collected, so a large store is never copied as a whole.
+//!
+//! `RESTORE` reads the same lines until `END` and writes every entry with the commands that
+//! rebuild it, one entry at a time. A key that already exists is overwritten, skipped or fails
+//! the restore according to the conflict policy, and a dry run only checks the entries.
+
+use crate::store::value::Value;
+use serde::{Deserialize, Serialize};
+
+/// Line that ends the entries of DUMP and RESTORE
+pub const END: &str = "END";
+
+/// Number of keys collected under the lock for a single page of DUMP
+pub const DUMP_PAGE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Represents what RESTORE does with an entry whose key already exists
+pub enum Conflict {
+    /// Replaces the value of the key
+    Overwrite,
+    /// Leaves the key as it is and goes on with the next entry
+    Skip,
+    /// Stops the restore at the entry, the entries before it stay written
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+/// Represents the value of a key the way it is dumped
+pub enum Contents {
+    String(String),
+    List(Vec<String>),
+    Set(Vec<String>),
+    /// Members with their scores, lowest score first
+    Zset(Vec<(String, f64)>),
+    Json(serde_json::Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Represents a key of the store as a line of DUMP and RESTORE
+///
+/// # Example
+/// ```text
+/// {"key":"Theme","type":"string","value":"Dark"}
+/// {"key":"Jobs","type":"list","value":["resize","upload"]}
+/// {"key":"Scores","type":"zset","value":[["alice",12.5],["bob",40.0]]}
+/// ```
+pub struct Entry {
+    pub key: String,
+    #[serde(flatten)]
+    pub contents: Contents,
+}
+
+impl Entry {
+    /// Creates the entry of a stored value. Text values are expected to be decrypted already.
+    pub fn new(key: String, value: Value) -> Self {
+        let contents = match value {
+            Value::Text(text) => Contents::String(text),
+            Value::List(items) => Contents::List(items.into_iter().collect()),
+            Value::Set(members) => {
+                let mut members: Vec<String> = members.into_iter().collect();
+                members.sort();
+                Contents::Set(members)
+            }
+            Value::SortedSet(set) => Contents::Zset(set.range(0, -1)),
+            Value::Json(document) => Contents::Json(document),
+        };
+        Self { key, contents }
+    }
+
+    /// Parses a line of RESTORE.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let entry: Self =
+            serde_json::from_str(line).map_err(|e| format!("Invalid entry: {}", e))?;
+        if entry.key.is_empty() || entry.key.contains(char::is_whitespace) {
+            return Err(format!("Invalid key: {:?}", entry.key));
+        }
+        Ok(entry)
+    }
+
+    /// Checks whether the entry is a plain string, which SET overwrites whatever the key holds.
+    pub fn is_string(&self) -> bool {
+        matches!(self.contents, Contents::String(_))
+    }
+
+    /// Returns the commands that write the entry, one per value, or per item for lists and sets.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::dump::Entry;
+    ///
+    /// let entry = Entry::parse(r#"{"key":"Jobs","type":"list","value":["resize","upload"]}"#).unwrap();
+    /// assert_eq!(entry.commands(), vec!["RPUSH Jobs resize", "RPUSH Jobs upload"]);
+    /// ```
+    pub fn commands(&self) -> Vec<String> {
+        let key = &self.key;
+        match &self.contents {
+            Contents::String(text) => vec![format!("SET {} {}", key, text)],
+            Contents::List(items) => items
+                .iter()
+                .map(|i| format!("RPUSH {} {}", key, i))
+                .collect(),
+            Contents::Set(members) => members
+                .iter()
+                .map(|m| format!("SADD {} {}", key, m))
+                .collect(),
+            Contents::Zset(members) => members
+                .iter()
+                .map(|(m, score)| format!("ZADD {} {} {}", key, score, m))
+                .collect(),
+            Contents::Json(document) => vec![format!("JSON.SET {} $ {}", key, document)],
+        }
+    }
+
+    /// Returns the entry as a line of DUMP, without the line break.
+    pub fn to_line(&self) -> String {
+        // An entry holds only strings, numbers and JSON values, which always serialize
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
@@ -1,83 +1,1420 @@
-use std::sync::Arc;
-
 /// Handlers module for the Kiwi Store Server
+use crate::auth::{self, NOT_ADMIN};
+use crate::clients::{ADMIN_USER, ClientHandle, ClientInfo, DEFAULT_USER};
 use crate::command::Command;
-use crate::config::Configuration;
+use crate::config::{Configuration, SETTINGS};
+use crate::crypto;
+use crate::dump::{Conflict, DUMP_PAGE, END, Entry};
+use crate::info;
+use crate::mode::{Eviction, MAX_KEYS, NOT_ALLOWED};
+use crate::namespace::{DEFAULT_NAMESPACE, Namespace};
+use crate::raft::Raft;
+use crate::rate_limit::{Category, RATE_LIMITED};
+use crate::replication::READ_ONLY;
+use crate::sharding::Sharding;
+use crate::state::ServerState;
 use crate::store::DataStore;
-use log::{error, info, warn};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::store::data::Popped;
+use crate::store::history::Version;
+use crate::store::pattern;
+use crate::store::value::{ListEnd, Value};
+use crate::telemetry::{self, TraceParent};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+use tracing::{Instrument, Span, debug, error, info, info_span, warn};
+
+/// Longest command line read from a client, a longer line closes the connection
+const MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+/// Serves an accepted connection within the connection limits of the server.
+/// A connection over the limits is answered with an `ERR_MAX_CLIENTS` error and closed.
+///
+/// # Arguments
+///
+/// * `stream` - The accepted TCP stream.
+/// * `state` - The shared server state.
+pub async fn handle_connection(mut stream: TcpStream, state: ServerState) {
+    let address = match stream.peer_addr() {
+        Ok(address) => address,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    match state.connections.acquire(address.ip()).await {
+        Ok(_guard) => {
+            let client = state.clients.register(address, DEFAULT_NAMESPACE);
+            let span = info_span!("connection", client = %address, id = client.id());
+            // Killing a connection drops its handler wherever it waits, closing the socket
+            async {
+                tokio::select! {
+                    _ = handle_request(stream, state.clone(), client.handle()) => {}
+                    _ = client.killed() => info!("Connection of {} killed", address),
+                }
+            }
+            .instrument(span)
+            .await
+        }
+        Err(reason) => {
+            warn!("Connection of {} rejected: {}", address, reason);
+            let _ = stream.write_all(respond_error(&reason).as_bytes()).await;
+        }
+    }
+}
+
+/// Represents the state of a client connection
+struct Session {
+    namespace: Namespace,
+    ip: IpAddr,
+    /// Whether the connection has authenticated with the admin password
+    authenticated: bool,
+    /// Entry of the connection in the client registry
+    client: ClientHandle,
+    /// Trace context sent with TRACEPARENT, the commands are traced as its children
+    trace: Option<TraceParent>,
+    /// Whether a migrating node sent CLUSTER IMPORTING, its writes skip the slot check
+    importing: bool,
+}
 
 #[allow(dead_code)]
 /// Handles incoming requests from a TCP stream.
-/// It reads the request, processes it on key-value store according to the command, and sends back the response.
+/// It reads the requests until the client disconnects, processes each of them on the key-value store
+/// of the connection's namespace according to the command, and sends back the responses.
+/// Every line is a separate command, however the lines are split across reads, and every response
+/// ends with a line break, so a client can send several commands without waiting.
 ///
 /// # Arguments
 ///
 /// * `stream` - The TCP stream to read from and write to.
-/// * `data_store` - The data store to keep the key-value pairs.
-/// * `config` - The configuration for the server, used to validate key and value lengths.
-pub async fn handle_request(
-    mut stream: TcpStream,
-    data_store: DataStore,
-    config: Arc<Configuration>,
-) {
-    let mut buffer = [0; 1024];
-    let size = match stream.read(&mut buffer).await {
-        Ok(0) => return,
-        Ok(n) => n,
+/// * `state` - The shared server state. The connection starts in the default namespace.
+/// * `client` - The entry of the connection in the client registry.
+pub async fn handle_request(stream: TcpStream, state: ServerState, client: ClientHandle) {
+    let address = match stream.peer_addr() {
+        Ok(address) => address,
         Err(e) => {
             error!("{}", e);
             return;
         }
     };
+    let mut session = Session {
+        namespace: state.namespaces.default_namespace().await,
+        ip: address.ip(),
+        authenticated: false,
+        client: client.clone(),
+        trace: None,
+        importing: false,
+    };
+    let mut reader = BufReader::new(stream);
+    let mut buffer = Vec::new();
 
-    info!("Read {}(bytes)", size);
+    loop {
+        if state.shutdown.is_triggered() {
+            return;
+        }
+        // An idle connection is closed as soon as the shutdown starts
+        let read = tokio::select! {
+            read = read_line(&mut reader, &mut buffer) => read,
+            _ = state.shutdown.wait() => return,
+        };
+        let size = match read {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                warn!(
+                    "Line of {} exceeds {} bytes, closing",
+                    address, MAX_LINE_LENGTH
+                );
+                let error = respond_error(&e.to_string());
+                let _ = reader.get_mut().write_all(error.as_bytes()).await;
+                return;
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
 
-    let request = String::from_utf8_lossy(&buffer[..size]);
-    let raw_cmd = Command::parse(&request);
-    let response = match raw_cmd.validate(&config) {
-        Ok(cmd) => match cmd {
-            Command::Ping => respond("PONG"),
-            Command::Set { key, value } => {
-                info!("Setting key: {}, value: {}", key, value);
-                data_store.set(&key, &value).await;
-                respond("OK")
+        debug!("Read {}(bytes)", size);
+        client.update(|info| info.bytes_in += size as u64);
+
+        let request = String::from_utf8_lossy(&buffer);
+        let line = request.trim_end_matches(['\n', '\r']);
+        let cmd = Command::parse(line);
+        match cmd {
+            Command::Sync => {
+                let config = session.namespace.config();
+                if auth::is_admin(&config, session.ip, session.authenticated) {
+                    tokio::select! {
+                        _ = state.replication.serve(reader.into_inner(), state.namespaces.clone()) => {}
+                        _ = state.shutdown.wait() => {}
+                    }
+                    return;
+                }
+                warn!("SYNC refused for {}, not an admin session", address);
+                if let Err(e) = reader
+                    .get_mut()
+                    .write_all(respond_error(NOT_ADMIN).as_bytes())
+                    .await
+                {
+                    error!("{}", e);
+                    return;
+                }
+                continue;
             }
-            Command::Get { key } => data_store
-                .get(&key)
-                .await
-                .unwrap_or_else(|| respond("NOT FOUND")),
-            Command::Remove { key } => {
-                if data_store.remove(&key).await {
-                    respond("OK")
-                } else {
-                    warn!("Key not found: {}", key);
-                    respond("NOT FOUND")
+            Command::Raft => {
+                let config = session.namespace.config();
+                if !auth::is_admin(&config, session.ip, session.authenticated) {
+                    warn!("RAFT refused for {}, not an admin session", address);
+                    let _ = reader
+                        .get_mut()
+                        .write_all(respond_error(NOT_ADMIN).as_bytes())
+                        .await;
+                    return;
+                }
+                if let Some(raft) = &state.raft {
+                    // The RPCs sent along with the RAFT line are already buffered
+                    let mut initial = buffer.clone();
+                    initial.extend_from_slice(reader.buffer());
+                    tokio::select! {
+                        _ = raft.serve(reader.into_inner(), initial) => {}
+                        _ = state.shutdown.wait() => {}
+                    }
+                }
+                return;
+            }
+            Command::Monitor => {
+                let config = session.namespace.config();
+                if auth::is_admin(&config, session.ip, session.authenticated) {
+                    tokio::select! {
+                        _ = state.monitor.serve(reader.into_inner()) => {}
+                        _ = state.shutdown.wait() => {}
+                    }
+                    return;
+                }
+                warn!("MONITOR refused for {}, not an admin session", address);
+                if let Err(e) = reader
+                    .get_mut()
+                    .write_all(respond_error(NOT_ADMIN).as_bytes())
+                    .await
+                {
+                    error!("{}", e);
+                    return;
+                }
+                continue;
+            }
+            Command::Dump { ref pattern } => {
+                let (span, started) = begin_command(&cmd, line, &state, &session, address);
+                let output = dump(
+                    reader.get_mut(),
+                    &state,
+                    &session,
+                    &cmd,
+                    line,
+                    pattern.as_deref(),
+                )
+                .instrument(span.clone())
+                .await;
+                match output {
+                    Ok(output) => end_command(
+                        &cmd, line, &output, &span, started, &state, &session, address,
+                    ),
+                    Err(e) => {
+                        error!("{}", e);
+                        return;
+                    }
+                }
+                continue;
+            }
+            Command::Restore { conflict, dry_run } => {
+                let (span, started) = begin_command(&cmd, line, &state, &session, address);
+                let output = restore(
+                    &mut reader,
+                    &state,
+                    &mut session,
+                    address,
+                    &cmd,
+                    line,
+                    conflict,
+                    dry_run,
+                )
+                .instrument(span.clone())
+                .await;
+                let Some(output) = output else {
+                    return;
+                };
+                end_command(
+                    &cmd, line, &output, &span, started, &state, &session, address,
+                );
+                if let Err(e) = reader.get_mut().write_all(output.as_bytes()).await {
+                    error!("{}", e);
+                    return;
+                }
+                client.update(|info| info.bytes_out += output.len() as u64);
+                continue;
+            }
+            _ => {}
+        }
+
+        let response = run_line(cmd, line, &state, &mut session, address).await;
+        if let Err(e) = reader.get_mut().write_all(response.as_bytes()).await {
+            error!("{}", e);
+            return;
+        }
+        client.update(|info| info.bytes_out += response.len() as u64);
+    }
+}
+
+/// Runs a command line sent by the client, parsed as `cmd`, and returns its response.
+/// The line is published to the monitors, traced, counted in the metrics and the slow log
+/// and, if it changes the data, written to the audit log.
+async fn run_line(
+    cmd: Command,
+    line: &str,
+    state: &ServerState,
+    session: &mut Session,
+    address: SocketAddr,
+) -> String {
+    let (span, started) = begin_command(&cmd, line, state, session, address);
+    let output = process(cmd.clone(), line, state, session)
+        .instrument(span.clone())
+        .await;
+    end_command(&cmd, line, &output, &span, started, state, session, address);
+    output
+}
+
+/// Publishes a command line to the monitors and creates its span.
+fn begin_command(
+    cmd: &Command,
+    line: &str,
+    state: &ServerState,
+    session: &Session,
+    address: SocketAddr,
+) -> (Span, Instant) {
+    state.monitor.publish(&session.namespace, address, line);
+    session.client.update(|info| {
+        info.active = Instant::now();
+        info.last_command = line.split_whitespace().next().map(|c| c.to_uppercase());
+    });
+    let span = telemetry::command_span(cmd, line, &session.namespace.name, session.trace.as_ref());
+    (span, Instant::now())
+}
+
+#[allow(clippy::too_many_arguments)]
+/// Records a processed command line in its span, the metrics, the slow log and the audit log.
+fn end_command(
+    cmd: &Command,
+    line: &str,
+    output: &str,
+    span: &Span,
+    started: Instant,
+    state: &ServerState,
+    session: &Session,
+    address: SocketAddr,
+) {
+    let elapsed = started.elapsed();
+    telemetry::finish(span, cmd, output, elapsed);
+    state.metrics.record(cmd, line, output, elapsed);
+    let config = session.namespace.config();
+    state.slowlog.record(&config, address, line, elapsed);
+    if state.audit.is_enabled() {
+        state.audit.record(
+            &config,
+            &session.client.info(),
+            &session.namespace.name,
+            cmd,
+            line,
+            output,
+        );
+    }
+}
+
+/// Streams the keys of the namespace for DUMP as JSON lines, a page at a time, ending with `END`.
+/// A refused command or a failure is sent as a single error line in place of the entries.
+///
+/// # Returns
+/// Returns the last line sent, the error or `END`.
+async fn dump(
+    stream: &mut TcpStream,
+    state: &ServerState,
+    session: &Session,
+    cmd: &Command,
+    line: &str,
+    pattern: Option<&str>,
+) -> std::io::Result<String> {
+    let config = session.namespace.config();
+    let store = &session.namespace.store;
+    let mut sent = 0;
+    let mut cursor = "0".to_string();
+    let last = match admit(
+        cmd.clone(),
+        line,
+        state,
+        &config,
+        session.ip,
+        session.authenticated,
+    ) {
+        Ok(_) => {
+            purge_expired(store, &config).await;
+            loop {
+                let (next, entries) = match store.entries(&cursor, pattern, DUMP_PAGE).await {
+                    Ok(page) => page,
+                    Err(e) => break respond_error(&e),
+                };
+                let mut page = String::new();
+                let mut failure = None;
+                for (key, value) in entries {
+                    let value = match value {
+                        Value::Text(text) => match decrypted(text, &config) {
+                            Ok(text) => Value::Text(text),
+                            Err(e) => {
+                                failure = Some(respond_error(&e));
+                                break;
+                            }
+                        },
+                        value => value,
+                    };
+                    page.push_str(&Entry::new(key, value).to_line());
+                    page.push('\n');
+                }
+                stream.write_all(page.as_bytes()).await?;
+                sent += page.len();
+                if let Some(failure) = failure {
+                    break failure;
+                }
+                if next == "0" {
+                    break format!("{}\n", END);
+                }
+                cursor = next;
+            }
+        }
+        Err(e) => respond_error(&e),
+    };
+    stream.write_all(last.as_bytes()).await?;
+    sent += last.len();
+    session.client.update(|info| info.bytes_out += sent as u64);
+    Ok(last)
+}
+
+#[allow(clippy::too_many_arguments)]
+/// Reads the entries of RESTORE until `END` and writes them one at a time, running their commands
+/// as if the client had sent them, so they are checked, replicated and audited like any other write.
+/// After a failure the remaining entries are read and dropped, keeping the connection in step.
+///
+/// # Returns
+/// Returns the response of RESTORE, or `None` if the connection was closed before `END`.
+async fn restore(
+    mut reader: impl AsyncBufRead + Unpin,
+    state: &ServerState,
+    session: &mut Session,
+    address: SocketAddr,
+    cmd: &Command,
+    line: &str,
+    conflict: Conflict,
+    dry_run: bool,
+) -> Option<String> {
+    let config = session.namespace.config();
+    let refused = admit(
+        cmd.clone(),
+        line,
+        state,
+        &config,
+        session.ip,
+        session.authenticated,
+    )
+    .err();
+    if refused.is_none() {
+        purge_expired(&session.namespace.store, &config).await;
+    }
+    let mut buffer = Vec::new();
+    let (mut number, mut restored, mut skipped) = (0, 0, 0);
+    let mut failure = None;
+    loop {
+        let size = match read_line(&mut reader, &mut buffer).await {
+            Ok(0) => return None,
+            Ok(size) => size,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                number += 1;
+                if failure.is_none() {
+                    failure = Some(format!("Entry {}: {}", number, e));
+                }
+                // The rest of the entry is dropped, so the next line is read as the next entry
+                loop {
+                    match read_line(&mut reader, &mut buffer).await {
+                        Ok(0) => return None,
+                        Ok(_) => break,
+                        Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+                        Err(e) => {
+                            error!("{}", e);
+                            return None;
+                        }
+                    }
                 }
+                continue;
             }
-            Command::List => {
-                if data_store.is_empty().await {
-                    warn!("Data store is empty");
-                    respond("EMPTY STORE")
-                } else {
-                    data_store.keys().await.join("\n")
+            Err(e) => {
+                error!("{}", e);
+                return None;
+            }
+        };
+        session.client.update(|info| info.bytes_in += size as u64);
+        let entry = String::from_utf8_lossy(&buffer);
+        let entry = entry.trim_end_matches(['\n', '\r']);
+        if entry.trim() == END {
+            break;
+        }
+        if entry.trim().is_empty() {
+            continue;
+        }
+        number += 1;
+        if refused.is_some() || failure.is_some() {
+            continue;
+        }
+        match restore_entry(entry, state, session, address, conflict, dry_run).await {
+            Ok(true) => restored += 1,
+            Ok(false) => skipped += 1,
+            Err(e) => failure = Some(format!("Entry {}: {}", number, e)),
+        }
+    }
+
+    Some(match (refused, failure) {
+        (Some(e), _) => respond_error(&e),
+        (None, Some(e)) => {
+            warn!("Restore stopped after {} keys: {}", restored, e);
+            respond_error(&format!("{}, {} keys restored before it", e, restored))
+        }
+        (None, None) => {
+            info!(
+                "Restored {} keys, skipped {}{}",
+                restored,
+                skipped,
+                if dry_run { " (dry run)" } else { "" }
+            );
+            let report = format!("RESTORED {} SKIPPED {}", restored, skipped);
+            respond(&if dry_run {
+                format!("DRY RUN {}", report)
+            } else {
+                report
+            })
+        }
+    })
+}
+
+/// Reads a line of at most `MAX_LINE_LENGTH` bytes, with its line break, into the buffer.
+///
+/// # Returns
+/// Returns the number of bytes read, zero at the end of the stream, or an `InvalidData` error
+/// if the line is longer. The rest of a longer line is left in the reader.
+async fn read_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    buffer: &mut Vec<u8>,
+) -> io::Result<usize> {
+    buffer.clear();
+    let size = reader
+        .take(MAX_LINE_LENGTH as u64)
+        .read_until(b'\n', buffer)
+        .await?;
+    if size == MAX_LINE_LENGTH && !buffer.ends_with(b"\n") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Line exceeds {} bytes", MAX_LINE_LENGTH),
+        ));
+    }
+    Ok(size)
+}
+
+/// Writes an entry of RESTORE in the namespace of the connection, or only checks it on a dry run.
+///
+/// # Returns
+/// Returns `true` if the entry is restored and `false` if its key is skipped.
+async fn restore_entry(
+    line: &str,
+    state: &ServerState,
+    session: &mut Session,
+    address: SocketAddr,
+    conflict: Conflict,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let entry = Entry::parse(line)?;
+    let exists = session.namespace.store.contains(&entry.key).await;
+    if exists {
+        match conflict {
+            Conflict::Overwrite => {}
+            Conflict::Skip => return Ok(false),
+            Conflict::Fail => return Err(format!("Key already exists: {}", entry.key)),
+        }
+    }
+    let mut commands = entry.commands();
+    // SET replaces whatever the key holds, the items of other types would be added to it
+    if exists && !entry.is_string() {
+        commands.insert(0, format!("REMOVE {}", entry.key));
+    }
+    for command in commands {
+        let cmd = Command::parse(&command);
+        if dry_run {
+            check(cmd, &command, &session.namespace.config())
+                .map_err(|e| format!("{}: {}", entry.key, e))?;
+            continue;
+        }
+        let response = run_line(cmd, &command, state, session, address).await;
+        if response.starts_with("ERROR") || response.starts_with("MOVED") {
+            let response = response.trim();
+            let reason = response.strip_prefix("ERROR: ").unwrap_or(response);
+            return Err(format!("{}: {}", entry.key, reason));
+        }
+    }
+    Ok(true)
+}
+
+/// Validates a parsed command line and checks that the mode of the namespace allows it.
+fn check(cmd: Command, line: &str, config: &Configuration) -> Result<Command, String> {
+    let cmd = cmd.validate(config)?;
+    if !config.profile.allows(&cmd, line) {
+        warn!(
+            "Command refused in {}: {}",
+            config.mode,
+            config.profile.redact(line)
+        );
+        let name = line.split_whitespace().next().unwrap_or_default();
+        return Err(format!(
+            "{} {} is not allowed in {}",
+            NOT_ALLOWED,
+            name.to_uppercase(),
+            config.mode
+        ));
+    }
+    Ok(cmd)
+}
+
+/// Checks a command line before it runs: it must be valid, allowed by the mode of the namespace
+/// and within the rate limits of the client IP and user, and a follower refuses the writes.
+fn admit(
+    cmd: Command,
+    line: &str,
+    state: &ServerState,
+    config: &Configuration,
+    ip: IpAddr,
+    authenticated: bool,
+) -> Result<Command, String> {
+    let cmd = check(cmd, line, config)?;
+    let user = if authenticated {
+        ADMIN_USER
+    } else {
+        DEFAULT_USER
+    };
+    if let Err(retry_after_ms) = state.rate_limiter.check(ip, user, Category::of(&cmd)) {
+        warn!(
+            "Rate limited {} ({}): {}",
+            ip,
+            user,
+            config.profile.redact(line)
+        );
+        return Err(format!("{} {}", RATE_LIMITED, retry_after_ms));
+    }
+    if cmd.is_mutating() && state.replication.is_follower() {
+        warn!("Write refused on follower: {}", config.profile.redact(line));
+        return Err(READ_ONLY.to_string());
+    }
+    Ok(cmd)
+}
+
+/// Processes a single command line in the given namespace.
+/// Namespace and replication commands are handled here since they change the state
+/// of the connection or the server. Writes are refused on a follower and streamed
+/// to the followers on a leader. Commands over the rate limit of the client IP and commands
+/// the mode of the namespace doesn't allow are refused.
+async fn process(cmd: Command, line: &str, state: &ServerState, session: &mut Session) -> String {
+    let Session {
+        namespace,
+        ip,
+        authenticated,
+        client,
+        trace,
+        importing,
+    } = session;
+    let ip = *ip;
+    let config = namespace.config();
+    let cmd = match admit(cmd, line, state, &config, ip, *authenticated) {
+        Ok(cmd) => cmd,
+        Err(err) => return respond_error(&err),
+    };
+    if let Some(raft) = &state.raft
+        && let Some(response) = process_in_cluster(line, &cmd, raft, namespace).await
+    {
+        return response;
+    }
+    if let Some(sharding) = &state.sharding
+        && !*importing
+        && let Some(response) = sharding.route(&cmd)
+    {
+        return response;
+    }
+
+    match cmd {
+        Command::Select { name } => {
+            *namespace = match state.namespaces.select(&name).await {
+                Ok(selected) => selected,
+                Err(e) => {
+                    warn!("SELECT {} refused for {}: {}", name, ip, e);
+                    return respond_error(&e);
+                }
+            };
+            client.update(|info| info.namespace = name.clone());
+            info!("Selected namespace: {} ({})", name, namespace.config().mode);
+            respond("OK")
+        }
+        Command::FlushNamespace { name } => {
+            let target = match name {
+                Some(name) => state.namespaces.get(&name).await,
+                None => Some(namespace.clone()),
+            };
+            match target {
+                Some(target) => {
+                    info!("Flushing namespace: {}", target.name);
+                    let _guard = state.replication.write_guard().await;
+                    target.store.clear().await;
+                    state.replication.publish(&target.name, "FLUSHNS");
+                    respond("OK")
                 }
+                None => respond("NOT FOUND"),
+            }
+        }
+        Command::ReplicaOf { .. } if !auth::is_admin(&config, ip, *authenticated) => {
+            warn!("REPLICAOF refused for {}, not an admin session", ip);
+            respond_error(NOT_ADMIN)
+        }
+        Command::ReplicaOf { leader } => {
+            match leader {
+                Some(leader) => state.replication.follow(&leader, state.namespaces.clone()),
+                None => state.replication.promote(),
+            }
+            respond("OK")
+        }
+        Command::Sync => respond_error("SYNC must be sent on its own"),
+        Command::Raft => respond_error("RAFT must be the first command of a connection"),
+        Command::Monitor => respond_error("MONITOR must be sent on its own"),
+        Command::Dump { .. } => respond_error("DUMP must be sent on its own"),
+        Command::Restore { .. } => respond_error("RESTORE must be sent on its own"),
+        Command::RateLimits if !auth::is_admin(&config, ip, *authenticated) => {
+            warn!("RATELIMITS refused for {}, not an admin session", ip);
+            respond_error(NOT_ADMIN)
+        }
+        Command::RateLimits => respond(&state.rate_limiter.describe()),
+        Command::SlowLogGet { count } => {
+            let entries = state.slowlog.get(count.unwrap_or_default());
+            if entries.is_empty() {
+                respond("EMPTY")
+            } else {
+                respond(&entries.join("\n"))
+            }
+        }
+        Command::SlowLogLen => respond(&state.slowlog.len().to_string()),
+        Command::SlowLogReset => {
+            state.slowlog.reset();
+            respond("OK")
+        }
+        Command::Mode => format!("MODE: {}, {}\n", config.mode, config.profile.describe()),
+        Command::Auth { password } => match auth::authenticate(&config, &password) {
+            Ok(()) => {
+                info!("Admin session opened by {}", ip);
+                *authenticated = true;
+                client.update(|info| info.user = ADMIN_USER.to_string());
+                respond("OK")
+            }
+            Err(e) => {
+                warn!("Authentication of {} failed: {}", ip, e);
+                respond_error(&e)
+            }
+        },
+        Command::ConfigGet { .. }
+        | Command::ConfigSet { .. }
+        | Command::ConfigRewrite
+        | Command::ConfigReload
+            if !auth::is_admin(&config, ip, *authenticated) =>
+        {
+            warn!("CONFIG refused for {}, not an admin session", ip);
+            respond_error(NOT_ADMIN)
+        }
+        Command::ClientSetName { name } => {
+            client.update(|info| info.name = Some(name.clone()));
+            respond("OK")
+        }
+        Command::TraceParent { value } => {
+            *trace = TraceParent::parse(&value).ok();
+            respond("OK")
+        }
+        Command::ClientList | Command::ClientKill { .. }
+            if !auth::is_admin(&config, ip, *authenticated) =>
+        {
+            warn!("CLIENT refused for {}, not an admin session", ip);
+            respond_error(NOT_ADMIN)
+        }
+        Command::ClientList => respond(
+            &state
+                .clients
+                .list()
+                .iter()
+                .map(ClientInfo::describe)
+                .collect::<Vec<String>>()
+                .join("\n"),
+        ),
+        Command::ClientKill { filter } => {
+            // A filter is always given once the command is validated
+            let killed = filter.map_or(0, |filter| state.clients.kill(&filter));
+            info!("{} killed {} connections", ip, killed);
+            respond(&killed.to_string())
+        }
+        Command::ConfigGet { pattern } => {
+            let config = state.namespaces.config();
+            let settings: Vec<String> = SETTINGS
+                .iter()
+                .filter(|name| pattern::matches(&pattern.to_lowercase(), name))
+                .filter_map(|name| config.get(name).map(|value| format!("{} {}", name, value)))
+                .collect();
+            if settings.is_empty() {
+                respond("NOT FOUND")
+            } else {
+                respond(&settings.join("\n"))
             }
-            Command::Stats => {
-                let stats = data_store.stats().await;
-                format!("STATS: {}\n", stats)
+        }
+        Command::ConfigSet { name, value } => {
+            match state.reloader.set(state, &name, &value).await {
+                Ok(()) => respond("OK"),
+                Err(e) => respond_error(&e),
             }
-            _ => unreachable!(),
+        }
+        Command::ConfigRewrite => match state.reloader.rewrite().await {
+            Ok(()) => respond("OK"),
+            Err(e) => respond_error(&e),
         },
-        Err(err) => format!("ERROR: {}\n", err),
+        Command::ConfigReload => match state.reloader.reload(state).await {
+            Ok(report) => respond(&format!("OK {}", report)),
+            Err(e) => respond_error(&e),
+        },
+        Command::Shutdown { .. } if !auth::is_admin(&config, ip, *authenticated) => {
+            warn!("SHUTDOWN refused for {}, not an admin session", ip);
+            respond_error(NOT_ADMIN)
+        }
+        Command::Shutdown { save } => {
+            info!("Shutdown requested by {}", ip);
+            state.shutdown.trigger(save.unwrap_or(true));
+            respond("OK")
+        }
+        Command::Info { section, json } => {
+            respond(&info::report(state, section.as_deref(), json).await)
+        }
+        Command::Stats => {
+            let mut stats = vec![
+                namespace.store.stats().await,
+                state.connections.stats(),
+                state.replication.stats(),
+            ];
+            stats.extend(state.raft.as_ref().map(|raft| raft.stats()));
+            stats.extend(state.sharding.as_ref().map(|sharding| sharding.stats()));
+            format!("STATS: {}\n", stats.join(", "))
+        }
+        Command::BlockingLeftPop { key, timeout } => {
+            // Blocking pops can't hold the write lock while they wait, a value already in the list
+            // is popped under it and a value handed over later is replicated by the push
+            let waiting = {
+                let _guard = state.replication.write_guard().await;
+                purge_expired(&namespace.store, &config).await;
+                match namespace.store.pop_or_wait(&key).await {
+                    Ok(Popped::Value(value)) => {
+                        state
+                            .replication
+                            .publish(&namespace.name, &format!("LPOP {}", key));
+                        return respond(&value);
+                    }
+                    Ok(Popped::Waiting(receiver)) => receiver,
+                    Err(e) => return respond_error(&e),
+                }
+            };
+            let timeout = timeout.filter(|t| *t > 0).map(Duration::from_secs);
+            match namespace.store.wait(&key, waiting, timeout).await {
+                Some(value) => respond(&value),
+                None => {
+                    warn!("Timed out waiting on list: {}", key);
+                    respond("TIMEOUT")
+                }
+            }
+        }
+        Command::ClusterSetSlot { .. }
+        | Command::ClusterImporting
+        | Command::ClusterMigrate { .. }
+            if !auth::is_admin(&config, ip, *authenticated) =>
+        {
+            warn!("CLUSTER refused for {}, not an admin session", ip);
+            respond_error(NOT_ADMIN)
+        }
+        Command::ClusterImporting if state.sharding.is_some() => {
+            info!("{} is importing slots on this connection", ip);
+            *importing = true;
+            respond("OK")
+        }
+        Command::ClusterSlots
+        | Command::ClusterSetSlot { .. }
+        | Command::ClusterImporting
+        | Command::ClusterMigrate { .. } => match &state.sharding {
+            Some(sharding) => process_sharding(cmd, sharding, state).await,
+            None => respond_error("Sharding is not enabled, start the server with SHARD_NODES"),
+        },
+        Command::Rollback { key, version } => {
+            let _guard = state.replication.write_guard().await;
+            let version = version.unwrap_or_default();
+            let command = match rollback_command(&namespace.store, &config, &key, version).await {
+                Ok(command) => command,
+                Err(e) => return respond_error(&e),
+            };
+            info!("Rolling back key: {} to version {}", key, version);
+            let cmd = match Command::parse(&command).validate(&config) {
+                Ok(cmd) => cmd,
+                Err(e) => return respond_error(&e),
+            };
+            let response = execute(cmd, &namespace.store, &config).await;
+            if !response.starts_with("ERROR") {
+                state.replication.publish(&namespace.name, &command);
+            }
+            response
+        }
+        cmd if cmd.is_mutating() => {
+            let _guard = state.replication.write_guard().await;
+            // The slot of the key may have been migrated while the write waited for the lock
+            if let Some(sharding) = &state.sharding
+                && !*importing
+                && let Some(response) = sharding.route(&cmd)
+            {
+                return response;
+            }
+            let key = cmd.keys().first().map(|key| key.to_string());
+            let handed_over = namespace.store.handed_over();
+            let response = execute(cmd, &namespace.store, &config).await;
+            if !response.starts_with("ERROR") {
+                state.replication.publish(&namespace.name, line.trim());
+                // The values a push handed to blocked clients are popped on the followers too
+                for _ in handed_over..namespace.store.handed_over() {
+                    let pop = format!("LPOP {}", key.as_deref().unwrap_or_default());
+                    state.replication.publish(&namespace.name, &pop);
+                }
+            }
+            response
+        }
+        cmd => execute(cmd, &namespace.store, &config).await,
+    }
+}
+
+/// Handles the slot map commands of a sharded server.
+async fn process_sharding(cmd: Command, sharding: &Sharding, state: &ServerState) -> String {
+    match cmd {
+        Command::ClusterSlots => respond(
+            &sharding
+                .ranges()
+                .iter()
+                .map(|(start, end, node)| format!("{} {} {}", start, end, node))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        ),
+        Command::ClusterSetSlot {
+            start: Some(start),
+            end: Some(end),
+            node,
+        } => {
+            sharding.assign(start, end, &node);
+            respond("OK")
+        }
+        Command::ClusterMigrate {
+            start: Some(start),
+            end: Some(end),
+            node,
+        } => {
+            match sharding
+                .migrate(start, end, &node, &state.namespaces, &state.replication)
+                .await
+            {
+                Ok(moved) => respond(&format!("MIGRATED {} KEYS", moved)),
+                Err(e) => respond_error(&e),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Routes a command through the Raft cluster.
+/// Nodes that are not the leader redirect data commands with `MOVED <leader>`, and the leader
+/// acknowledges a write only after a majority of the cluster has committed it.
+///
+/// # Returns
+/// Returns the response of the command, or `None` if the command is handled locally.
+async fn process_in_cluster(
+    line: &str,
+    cmd: &Command,
+    raft: &Raft,
+    namespace: &Namespace,
+) -> Option<String> {
+    match cmd {
+        Command::Ping
+        | Command::Stats
+        | Command::Info { .. }
+        | Command::SlowLogGet { .. }
+        | Command::SlowLogLen
+        | Command::SlowLogReset
+        | Command::Monitor
+        | Command::Dump { .. }
+        | Command::Restore { .. }
+        | Command::ClientList
+        | Command::ClientSetName { .. }
+        | Command::ClientKill { .. }
+        | Command::TraceParent { .. }
+        | Command::Auth { .. }
+        | Command::Mode
+        | Command::Select { .. } => return None,
+        Command::BlockingLeftPop { .. } | Command::ReplicaOf { .. } => {
+            return Some(respond_error("Command is not supported in cluster mode"));
+        }
+        _ => {}
+    }
+    if !raft.is_leader() {
+        return Some(match raft.leader() {
+            Some(leader) => respond(&format!("MOVED {}", leader)),
+            None => respond_error("CLUSTERDOWN No leader is elected yet"),
+        });
+    }
+    if !cmd.is_mutating() {
+        return None;
+    }
+
+    let rollback;
+    let (target, command) = match cmd {
+        Command::FlushNamespace { name: Some(name) } => (name.as_str(), "FLUSHNS"),
+        Command::Rollback { key, version } => {
+            let version = version.unwrap_or_default();
+            rollback =
+                match rollback_command(&namespace.store, &namespace.config(), key, version).await {
+                    Ok(command) => command,
+                    Err(e) => return Some(respond_error(&e)),
+                };
+            (namespace.name.as_str(), rollback.as_str())
+        }
+        _ => (namespace.name.as_str(), line.trim()),
+    };
+    Some(match raft.propose(target, command).await {
+        Ok(response) => response,
+        Err(e) => respond_error(&e),
+    })
+}
+
+/// Executes a validated command against a data store, within the mode of the configuration.
+/// Expired keys are removed first, a write adding a key to a full data store evicts the oldest
+/// key or is refused with `ERR_MAX_KEYS`, and a written key gets the default time to live of the mode.
+pub(crate) async fn execute(
+    cmd: Command,
+    data_store: &DataStore,
+    config: &Configuration,
+) -> String {
+    let profile = &config.profile;
+    purge_expired(data_store, config).await;
+    let Some(key) = written_key(&cmd).map(str::to_string) else {
+        return run(cmd, data_store, config).await;
+    };
+    if profile.max_keys > 0
+        && !data_store.contains(&key).await
+        && data_store.len().await >= profile.max_keys
+    {
+        let evicted = match profile.eviction {
+            Eviction::NoEviction => None,
+            Eviction::Oldest => data_store.evict_oldest().await,
+        };
+        match evicted {
+            Some(evicted) => info!("Evicted key: {}", evicted),
+            None => {
+                warn!("Data store is full, {} keys", profile.max_keys);
+                return respond_error(MAX_KEYS);
+            }
+        }
+    }
+    let response = run(cmd, data_store, config).await;
+    if !response.starts_with("ERROR") && data_store.contains(&key).await {
+        let ttl = Some(profile.default_ttl_secs)
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+        data_store.touch(&key, ttl).await;
+    }
+    response
+}
+
+/// Removes the expired keys of a data store, recording their removal in the history.
+async fn purge_expired(data_store: &DataStore, config: &Configuration) {
+    for key in data_store.purge_expired().await {
+        info!("Key expired: {}", key);
+        data_store
+            .record_version(&key, &config.history_retention())
+            .await;
+    }
+}
+
+/// Returns a stored value as the client wrote it, decrypting the values of an encrypted mode.
+fn decrypted(value: String, config: &Configuration) -> Result<String, String> {
+    if !config.profile.encryption {
+        return Ok(value);
+    }
+    crypto::decrypt(config.encryption_key.as_deref().unwrap_or_default(), &value)
+}
+
+/// Returns the command that writes a version from the history of a key as its current value:
+/// a SET of the value, or a REMOVE if the key was removed in that version. The command is run
+/// and replicated in place of the ROLLBACK, so every node ends up with the same value.
+async fn rollback_command(
+    data_store: &DataStore,
+    config: &Configuration,
+    key: &str,
+    version: u64,
+) -> Result<String, String> {
+    let Some(version) = data_store
+        .version(key, version, &config.history_retention())
+        .await
+    else {
+        return Err(format!(
+            "Version {} of {} is not in the history",
+            version, key
+        ));
     };
+    match version.value {
+        Some(value) => Ok(format!("SET {} {}", key, decrypted(value, config)?)),
+        None => Ok(format!("REMOVE {}", key)),
+    }
+}
+
+/// Returns the key a command stores a value at, `None` for commands that only read or remove.
+fn written_key(cmd: &Command) -> Option<&str> {
+    match cmd {
+        Command::Set { key, .. }
+        | Command::LeftPush { key, .. }
+        | Command::RightPush { key, .. }
+        | Command::SetAdd { key, .. }
+        | Command::SortedSetAdd { key, .. }
+        | Command::SortedSetIncrement { key, .. }
+        | Command::JsonSet { key, .. }
+        | Command::JsonIncrement { key, .. } => Some(key),
+        _ => None,
+    }
+}
 
-    if let Err(e) = stream.write_all(response.as_bytes()).await {
-        error!("{}", e);
+/// Runs a validated command on a data store.
+async fn run(cmd: Command, data_store: &DataStore, config: &Configuration) -> String {
+    let encryption_key = config.encryption_key.as_deref().unwrap_or_default();
+    match cmd {
+        Command::Ping => respond("PONG"),
+        Command::Set { key, value } => {
+            info!(
+                "Setting key: {}, value: {}",
+                key,
+                config.profile.redact_value(&value)
+            );
+            let value = if config.profile.encryption {
+                match crypto::encrypt(encryption_key, &value) {
+                    Ok(encrypted) => encrypted,
+                    Err(e) => return respond_error(&e),
+                }
+            } else {
+                value
+            };
+            data_store.set(&key, &value).await;
+            data_store
+                .record_version(&key, &config.history_retention())
+                .await;
+            respond("OK")
+        }
+        Command::Get { key } => match data_store.get(&key).await {
+            Ok(Some(value)) => {
+                decrypted(value, config).map_or_else(|e| respond_error(&e), |v| respond(&v))
+            }
+            Ok(None) => respond("NOT FOUND"),
+            Err(e) => respond_error(&e),
+        },
+        Command::Remove { key } => {
+            if data_store.remove(&key).await {
+                data_store
+                    .record_version(&key, &config.history_retention())
+                    .await;
+                respond("OK")
+            } else {
+                warn!("Key not found: {}", key);
+                respond("NOT FOUND")
+            }
+        }
+        Command::History { key } => {
+            let versions = data_store.history(&key, &config.history_retention()).await;
+            if versions.is_empty() {
+                return respond("NOT FOUND");
+            }
+            let mut lines = Vec::new();
+            for version in versions {
+                let time = DateTime::<Utc>::from(version.time)
+                    .to_rfc3339_opts(SecondsFormat::Millis, true);
+                let change = match version.value {
+                    Some(value) => match decrypted(value, config) {
+                        Ok(value) => format!("SET {}", value),
+                        Err(e) => return respond_error(&e),
+                    },
+                    None => "REMOVE".to_string(),
+                };
+                lines.push(format!("{} {} {}", version.number, time, change));
+            }
+            respond(&lines.join("\n"))
+        }
+        Command::GetAt { key, time } => {
+            let time = time.unwrap_or(UNIX_EPOCH);
+            match data_store
+                .version_at(&key, time, &config.history_retention())
+                .await
+            {
+                Ok(Some(Version {
+                    value: Some(value), ..
+                })) => decrypted(value, config).map_or_else(|e| respond_error(&e), |v| respond(&v)),
+                Ok(_) => respond("NOT FOUND"),
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::List => {
+            if data_store.is_empty().await {
+                warn!("Data store is empty");
+                respond("EMPTY STORE")
+            } else {
+                respond(&data_store.keys().await.join("\n"))
+            }
+        }
+        Command::LeftPush { key, value } => {
+            push(data_store, &key, &value, ListEnd::Left, config).await
+        }
+        Command::RightPush { key, value } => {
+            push(data_store, &key, &value, ListEnd::Right, config).await
+        }
+        Command::LeftPop { key } => pop(data_store, &key, ListEnd::Left).await,
+        Command::RightPop { key } => pop(data_store, &key, ListEnd::Right).await,
+        Command::ListRange { key, start, stop } => {
+            match data_store
+                .range(&key, start.unwrap_or(0), stop.unwrap_or(-1))
+                .await
+            {
+                Ok(items) if items.is_empty() => respond("EMPTY LIST"),
+                Ok(items) => respond(&items.join("\n")),
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::ListLength { key } => match data_store.length(&key).await {
+            Ok(length) => respond(&length.to_string()),
+            Err(e) => respond_error(&e),
+        },
+        Command::BlockingLeftPop { key, timeout } => {
+            let timeout = timeout.filter(|t| *t > 0).map(Duration::from_secs);
+            match data_store.blocking_pop(&key, timeout).await {
+                Ok(Some(value)) => respond(&value),
+                Ok(None) => {
+                    warn!("Timed out waiting on list: {}", key);
+                    respond("TIMEOUT")
+                }
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::SetAdd { key, member } => match data_store.set_add(&key, &member).await {
+            Ok(added) => respond(if added { "1" } else { "0" }),
+            Err(e) => respond_error(&e),
+        },
+        Command::SetRemove { key, member } => match data_store.set_remove(&key, &member).await {
+            Ok(removed) => respond(if removed { "1" } else { "0" }),
+            Err(e) => respond_error(&e),
+        },
+        Command::SetIsMember { key, member } => match data_store.is_member(&key, &member).await {
+            Ok(found) => respond(if found { "1" } else { "0" }),
+            Err(e) => respond_error(&e),
+        },
+        Command::SetMembers { key } => respond_members(data_store.members(&key).await),
+        Command::SetIntersect { keys } => respond_members(data_store.intersect(&keys).await),
+        Command::SetUnion { keys } => respond_members(data_store.union(&keys).await),
+        Command::SortedSetAdd { key, score, member } => {
+            match data_store
+                .sorted_add(&key, score.unwrap_or_default(), &member)
+                .await
+            {
+                Ok(added) => respond(if added { "1" } else { "0" }),
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::SortedSetIncrement {
+            key,
+            increment,
+            member,
+        } => {
+            match data_store
+                .sorted_increment(&key, increment.unwrap_or_default(), &member)
+                .await
+            {
+                Ok(score) => respond(&score.to_string()),
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::SortedSetRemove { key, member } => {
+            match data_store.sorted_remove(&key, &member).await {
+                Ok(removed) => respond(if removed { "1" } else { "0" }),
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::SortedSetRange {
+            key,
+            start,
+            stop,
+            with_scores,
+        } => respond_scored(
+            data_store
+                .sorted_range(&key, start.unwrap_or(0), stop.unwrap_or(-1))
+                .await,
+            with_scores,
+        ),
+        Command::SortedSetRangeByScore {
+            key,
+            min,
+            max,
+            with_scores,
+        } => respond_scored(
+            data_store
+                .sorted_range_by_score(
+                    &key,
+                    min.unwrap_or(f64::NEG_INFINITY),
+                    max.unwrap_or(f64::INFINITY),
+                )
+                .await,
+            with_scores,
+        ),
+        Command::JsonSet { key, path, json } => {
+            info!("Setting JSON key: {}, path: {}", key, path);
+            let result = match serde_json::from_str(&json) {
+                Ok(document) => {
+                    data_store
+                        .json_set(&key, &path, document, config.max_value_length)
+                        .await
+                }
+                Err(e) => Err(format!("Invalid JSON document: {}", e)),
+            };
+            match result {
+                Ok(()) => respond("OK"),
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::JsonGet { key, path } => match data_store.json_get(&key, &path).await {
+            Ok(Some(json)) => respond(&json),
+            Ok(None) => respond("NOT FOUND"),
+            Err(e) => respond_error(&e),
+        },
+        Command::JsonDelete { key, path } => match data_store.json_delete(&key, &path).await {
+            Ok(deleted) => respond(if deleted { "1" } else { "0" }),
+            Err(e) => respond_error(&e),
+        },
+        Command::JsonIncrement {
+            key,
+            path,
+            increment,
+        } => {
+            match data_store
+                .json_increment(
+                    &key,
+                    &path,
+                    increment.unwrap_or_default(),
+                    config.max_value_length,
+                )
+                .await
+            {
+                Ok(number) => respond(&number.to_string()),
+                Err(e) => respond_error(&e),
+            }
+        }
+        Command::Keys { pattern } => {
+            let keys = data_store.keys_matching(&pattern).await;
+            if keys.is_empty() {
+                respond("NO MATCHING KEYS")
+            } else {
+                respond(&keys.join("\n"))
+            }
+        }
+        Command::Scan {
+            cursor,
+            pattern,
+            count,
+        } => match data_store
+            .scan(&cursor, pattern.as_deref(), count.unwrap_or(10))
+            .await
+        {
+            Ok((next, keys)) if keys.is_empty() => respond(&next),
+            Ok((next, keys)) => respond(&format!("{}\n{}", next, keys.join("\n"))),
+            Err(e) => respond_error(&e),
+        },
+        _ => unreachable!(),
     }
 }
 
 fn respond(message: &str) -> String {
     format!("{}\n", message)
 }
+
+fn respond_error(message: &str) -> String {
+    format!("ERROR: {}\n", message)
+}
+
+async fn push(
+    data_store: &DataStore,
+    key: &str,
+    value: &str,
+    end: ListEnd,
+    config: &Configuration,
+) -> String {
+    info!(
+        "Pushing to list: {}, value: {}",
+        key,
+        config.profile.redact_value(value)
+    );
+    match data_store
+        .push(key, value, end, config.max_list_length)
+        .await
+    {
+        Ok(length) => respond(&length.to_string()),
+        Err(e) => respond_error(&e),
+    }
+}
+
+async fn pop(data_store: &DataStore, key: &str, end: ListEnd) -> String {
+    match data_store.pop(key, end).await {
+        Ok(Some(value)) => respond(&value),
+        Ok(None) => respond("NOT FOUND"),
+        Err(e) => respond_error(&e),
+    }
+}
+
+fn respond_members(result: Result<Vec<String>, String>) -> String {
+    match result {
+        Ok(members) if members.is_empty() => respond("EMPTY SET"),
+        Ok(members) => respond(&members.join("\n")),
+        Err(e) => respond_error(&e),
+    }
+}
+
+fn respond_scored(result: Result<Vec<(String, f64)>, String>, with_scores: bool) -> String {
+    match result {
+        Ok(members) if members.is_empty() => respond("EMPTY SET"),
+        Ok(members) => respond(
+            &members
+                .into_iter()
+                .map(|(member, score)| {
+                    if with_scores {
+                        format!("{} {}", member, score)
+                    } else {
+                        member
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        Err(e) => respond_error(&e),
+    }
+}
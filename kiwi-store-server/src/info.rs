@@ -0,0 +1,184 @@
+//! Structured report of the INFO command.
+//!
+//! The report is split into sections, each a list of fields. It is sent as `name:value` lines
+//! under a `# Section` header, sections separated by a blank line, or with `JSON` as a single
+//! line object holding an object per section. Fields that group several figures, like the
+//! command statistics, are sent as `name:a=1,b=2` lines and as nested objects in JSON.
+
+use crate::state::ServerState;
+use crate::store::data::{MemoryUsage, human_bytes};
+use serde_json::{Map, Value, json};
+
+/// Sections of the INFO report, in the order they are sent
+pub const INFO_SECTIONS: [&str; 7] = [
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "replication",
+    "commandstats",
+    "keyspace",
+];
+
+/// Fields of a section, in the order they are sent
+type Fields = Vec<(String, Value)>;
+
+/// Builds the INFO report of the server.
+///
+/// # Arguments
+/// * `state` - The state of the server
+/// * `section` - The section to report, every section if `None` or `all`
+/// * `json` - Whether to send the report as JSON instead of `name:value` lines
+///
+/// # Returns
+/// Returns the report, without a trailing line break.
+pub async fn report(state: &ServerState, section: Option<&str>, json: bool) -> String {
+    let mut sections = Vec::new();
+    for name in INFO_SECTIONS {
+        if section.is_none_or(|s| s == "all" || s == name) {
+            sections.push((name, fields(state, name).await));
+        }
+    }
+    if json {
+        let report: Map<String, Value> = sections
+            .into_iter()
+            .map(|(name, fields)| {
+                (
+                    name.to_string(),
+                    Value::Object(fields.into_iter().collect()),
+                )
+            })
+            .collect();
+        Value::Object(report).to_string()
+    } else {
+        sections
+            .iter()
+            .map(|(name, fields)| render(name, fields))
+            .collect::<Vec<String>>()
+            .join("\n\n")
+    }
+}
+
+async fn fields(state: &ServerState, section: &str) -> Fields {
+    let config = state.namespaces.config();
+    let field = |name: &str, value: Value| (name.to_string(), value);
+    match section {
+        "server" => vec![
+            field("version", json!(env!("CARGO_PKG_VERSION"))),
+            field("process_id", json!(std::process::id())),
+            field(
+                "tcp_address",
+                json!(format!("{}:{}", config.host, config.port)),
+            ),
+            field("node_address", json!(config.node_address)),
+            field("mode", json!(config.mode)),
+            field("uptime_in_seconds", json!(state.metrics.uptime().as_secs())),
+            field(
+                "metrics_address",
+                json!(config.metrics_address.as_deref().unwrap_or("none")),
+            ),
+            field("cluster_enabled", json!(u8::from(state.raft.is_some()))),
+            field(
+                "sharding_enabled",
+                json!(u8::from(state.sharding.is_some())),
+            ),
+        ],
+        "clients" => {
+            let (active, accepted, rejected) = state.connections.counts();
+            vec![
+                field("connected_clients", json!(active)),
+                field("max_connections", json!(config.max_connections)),
+                field(
+                    "max_connections_per_ip",
+                    json!(config.max_connections_per_ip),
+                ),
+                field("accepted_connections", json!(accepted)),
+                field("rejected_connections", json!(rejected)),
+            ]
+        }
+        "memory" => {
+            let mut usage = MemoryUsage::default();
+            for namespace in state.namespaces.all().await {
+                let namespace_usage = namespace.store.memory_usage().await;
+                usage.keys += namespace_usage.keys;
+                usage.values += namespace_usage.values;
+                usage.overhead += namespace_usage.overhead;
+                usage.history += namespace_usage.history;
+            }
+            vec![
+                field("used_memory", json!(usage.total())),
+                field("used_memory_human", json!(human_bytes(usage.total()))),
+                field("used_memory_keys", json!(usage.keys)),
+                field("used_memory_values", json!(usage.values)),
+                field("used_memory_overhead", json!(usage.overhead)),
+                field("used_memory_history", json!(usage.history)),
+            ]
+        }
+        // The data only lives in memory, it is handed over to replicas and shards instead
+        "persistence" => vec![field("enabled", json!(0))],
+        "replication" => state
+            .replication
+            .info()
+            .into_iter()
+            .map(|(name, value)| field(name, value))
+            .collect(),
+        "commandstats" => state
+            .metrics
+            .command_stats()
+            .into_iter()
+            .map(|stats| {
+                let usec = (stats.seconds * 1_000_000.0).round() as u64;
+                field(
+                    &format!("cmdstat_{}", stats.name),
+                    json!({
+                        "calls": stats.calls,
+                        "usec": usec,
+                        "usec_per_call": usec / stats.calls.max(1),
+                        "failed_calls": stats.failed,
+                    }),
+                )
+            })
+            .collect(),
+        "keyspace" => {
+            let mut fields = Vec::new();
+            for namespace in state.namespaces.all().await {
+                fields.push(field(
+                    &namespace.name,
+                    json!({
+                        "keys": namespace.store.len().await,
+                        "expires": namespace.store.expiring().await,
+                        "mode": namespace.config().mode,
+                    }),
+                ));
+            }
+            fields
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Renders a section as a `# Section` header followed by `name:value` lines.
+fn render(section: &str, fields: &Fields) -> String {
+    let mut title = section.to_string();
+    if let Some(first) = title.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+    let mut lines = vec![format!("# {}", title)];
+    for (name, value) in fields {
+        lines.push(format!("{}:{}", name, plain(value)));
+    }
+    lines.join("\n")
+}
+
+/// Writes a value the way it appears after the colon of a `name:value` line.
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, plain(value)))
+            .collect::<Vec<String>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
@@ -1,13 +1,59 @@
+mod audit;
+mod auth;
+mod cli;
+mod clients;
 mod command;
 mod config;
+mod connections;
+mod crypto;
+mod dump;
 mod handler;
+mod info;
+mod metrics;
+mod mode;
+mod monitor;
+mod namespace;
+mod raft;
+mod rate_limit;
+mod reload;
+mod replication;
 mod server;
+mod sharding;
+mod shutdown;
+mod slowlog;
+mod state;
 mod store;
+mod telemetry;
 mod tests;
 
+use crate::cli::Cli;
+use crate::config::Configuration;
+use crate::reload::Reloader;
+use clap::Parser;
+
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
     dotenv::dotenv().ok();
-    env_logger::init();
-    server::run().await
+    let cli = Cli::parse();
+    let config = match Configuration::load(cli.config.as_deref(), cli.settings.clone()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return Ok(());
+    }
+    let telemetry = match telemetry::init(&config) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = server::run(config, Reloader::new(cli.config, cli.settings)).await;
+    telemetry.shutdown();
+    result
 }
@@ -0,0 +1,317 @@
+//! Prometheus metrics of the Kiwi Store server.
+//!
+//! Commands are counted and timed by name as they are processed, and failed commands are
+//! counted by error type. Key counts, memory, evictions and connections are read from the
+//! server state when the metrics are scraped. With `metrics_address` set, the server serves
+//! them in the Prometheus text format at `http://<metrics_address>/metrics`.
+
+use crate::command::Command;
+use crate::state::ServerState;
+use crate::store::value::WRONG_TYPE;
+use axum::Router;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+/// Upper bounds of the command latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.1, 1.0,
+];
+
+/// Error codes sent at the start of an error message besides the `ERR_` ones
+const ERROR_CODES: [&str; 3] = ["READONLY", "CROSSSLOT", "CLUSTERDOWN"];
+
+#[derive(Default)]
+/// Represents the latency histogram of a command
+struct Timings {
+    /// Number of commands at or under each bucket bound
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+    /// Number of commands answered with an error
+    failed: u64,
+}
+
+struct Inner {
+    started: Instant,
+    commands: Mutex<BTreeMap<String, Timings>>,
+    errors: Mutex<BTreeMap<String, u64>>,
+}
+
+#[derive(Clone)]
+/// Represents the command metrics of the server, shared by all connections
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+/// Represents the totals of a command for INFO commandstats
+pub struct CommandStats {
+    pub name: String,
+    pub calls: u64,
+    pub seconds: f64,
+    pub failed: u64,
+}
+
+impl Metrics {
+    /// Creates the metrics of a server starting now.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                started: Instant::now(),
+                commands: Mutex::new(BTreeMap::new()),
+                errors: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Returns how long the server has been running.
+    pub fn uptime(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    /// Returns the totals of every command processed so far, ordered by name.
+    pub fn command_stats(&self) -> Vec<CommandStats> {
+        let commands = self.inner.commands.lock().unwrap();
+        commands
+            .iter()
+            .map(|(name, timings)| CommandStats {
+                name: name.clone(),
+                calls: timings.count,
+                seconds: timings.sum,
+                failed: timings.failed,
+            })
+            .collect()
+    }
+
+    /// Records a processed command line.
+    ///
+    /// # Arguments
+    /// * `cmd` - The parsed command line
+    /// * `line` - The command line
+    /// * `response` - The response sent for it
+    /// * `elapsed` - How long the command took
+    pub fn record(&self, cmd: &Command, line: &str, response: &str, elapsed: Duration) {
+        let known = !matches!(cmd, Command::Invalid(_));
+        // Unknown commands share a name, so a client can't create series at will
+        let name = match line.split_whitespace().next() {
+            Some(name) if known => name.to_uppercase(),
+            _ => "UNKNOWN".to_string(),
+        };
+        let seconds = elapsed.as_secs_f64();
+        let error_type = error_type(response, known);
+        {
+            let mut commands = self.inner.commands.lock().unwrap();
+            let timings = commands.entry(name).or_default();
+            for (count, bound) in timings.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+                if seconds <= bound {
+                    *count += 1;
+                }
+            }
+            timings.count += 1;
+            timings.sum += seconds;
+            timings.failed += u64::from(error_type.is_some());
+        }
+        if let Some(error_type) = error_type {
+            *self
+                .inner
+                .errors
+                .lock()
+                .unwrap()
+                .entry(error_type)
+                .or_default() += 1;
+        }
+    }
+
+    /// Returns the metrics of the server in the Prometheus text format.
+    ///
+    /// # Arguments
+    /// * `state` - The state of the server the key and connection figures are read from
+    pub async fn render(&self, state: &ServerState) -> String {
+        let mut out = String::new();
+        {
+            let commands = self.inner.commands.lock().unwrap();
+            header(
+                &mut out,
+                "kiwi_commands_total",
+                "counter",
+                "Commands processed, by command",
+            );
+            for (name, timings) in commands.iter() {
+                let _ = writeln!(
+                    out,
+                    "kiwi_commands_total{{command=\"{}\"}} {}",
+                    name, timings.count
+                );
+            }
+            header(
+                &mut out,
+                "kiwi_command_duration_seconds",
+                "histogram",
+                "Time taken to process a command, by command",
+            );
+            for (name, timings) in commands.iter() {
+                for (count, bound) in timings.buckets.iter().zip(LATENCY_BUCKETS) {
+                    let _ = writeln!(
+                        out,
+                        "kiwi_command_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                        name, bound, count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "kiwi_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+                    name, timings.count
+                );
+                let _ = writeln!(
+                    out,
+                    "kiwi_command_duration_seconds_sum{{command=\"{}\"}} {}",
+                    name, timings.sum
+                );
+                let _ = writeln!(
+                    out,
+                    "kiwi_command_duration_seconds_count{{command=\"{}\"}} {}",
+                    name, timings.count
+                );
+            }
+        }
+        {
+            let errors = self.inner.errors.lock().unwrap();
+            header(
+                &mut out,
+                "kiwi_errors_total",
+                "counter",
+                "Commands answered with an error, by error type",
+            );
+            for (error_type, count) in errors.iter() {
+                let _ = writeln!(
+                    out,
+                    "kiwi_errors_total{{type=\"{}\"}} {}",
+                    error_type, count
+                );
+            }
+        }
+
+        let namespaces = state.namespaces.all().await;
+        let mut keys = Vec::new();
+        let mut memory = Vec::new();
+        let mut evicted = Vec::new();
+        let mut expired = Vec::new();
+        for namespace in &namespaces {
+            let (evictions, expirations) = namespace.store.removal_counts();
+            keys.push((&namespace.name, namespace.store.len().await as u64));
+            let usage = namespace.store.memory_usage().await;
+            memory.push((&namespace.name, usage.total() as u64));
+            evicted.push((&namespace.name, evictions));
+            expired.push((&namespace.name, expirations));
+        }
+        for (name, kind, help, values) in [
+            ("kiwi_keys", "gauge", "Keys held, by namespace", keys),
+            (
+                "kiwi_memory_bytes",
+                "gauge",
+                "Bytes used by the keys, values and entries, by namespace",
+                memory,
+            ),
+            (
+                "kiwi_evictions_total",
+                "counter",
+                "Keys evicted to make room for new ones, by namespace",
+                evicted,
+            ),
+            (
+                "kiwi_expired_keys_total",
+                "counter",
+                "Keys removed after their time to live, by namespace",
+                expired,
+            ),
+        ] {
+            header(&mut out, name, kind, help);
+            for (namespace, value) in values {
+                let _ = writeln!(out, "{}{{namespace=\"{}\"}} {}", name, namespace, value);
+            }
+        }
+
+        let (active, accepted, rejected) = state.connections.counts();
+        for (name, kind, help, value) in [
+            (
+                "kiwi_connections",
+                "gauge",
+                "Connections being served",
+                active as u64,
+            ),
+            (
+                "kiwi_connections_accepted_total",
+                "counter",
+                "Connections accepted since the server started",
+                accepted,
+            ),
+            (
+                "kiwi_connections_rejected_total",
+                "counter",
+                "Connections rejected by the connection limits",
+                rejected,
+            ),
+        ] {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
+
+/// Serves the metrics at `/metrics` over HTTP until the server shuts down.
+///
+/// # Arguments
+/// * `listener` - The listener bound to the metrics address
+/// * `state` - The state of the server
+pub async fn serve(listener: TcpListener, state: ServerState) {
+    if let Ok(address) = listener.local_addr() {
+        info!("Metrics are served at http://{}/metrics", address);
+    }
+    let shutdown = state.shutdown.clone();
+    let app = Router::new()
+        .route("/metrics", get(scrape))
+        .with_state(state);
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(async move { shutdown.wait().await })
+        .await
+    {
+        error!("Metrics listener stopped: {}", e);
+    }
+}
+
+async fn scrape(State(state): State<ServerState>) -> impl IntoResponse {
+    let body = state.metrics.render(&state).await;
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Returns the type of the error a response carries, `None` if it is not an error.
+/// The type is the error code in lower case without the `ERR_` prefix, e.g. `max_keys`.
+pub fn error_type(response: &str, known: bool) -> Option<String> {
+    let message = response.strip_prefix("ERROR: ")?;
+    let code = message.split_whitespace().next().unwrap_or_default();
+    let error_type = if let Some(code) = code.strip_prefix("ERR_") {
+        code.to_lowercase()
+    } else if ERROR_CODES.contains(&code) {
+        code.to_lowercase()
+    } else if message.starts_with(WRONG_TYPE) {
+        "wrongtype".to_string()
+    } else if !known {
+        "unknown_command".to_string()
+    } else {
+        "other".to_string()
+    };
+    Some(error_type)
+}
@@ -0,0 +1,249 @@
+//! Mode profiles of the Kiwi Store server.
+//!
+//! A mode is a named profile bundling the limits and the behaviour of a namespace: key and value
+//! lengths, forbidden key characters, the commands allowed, the eviction policy, the default
+//! time to live of the keys, the encryption of the values and the redaction of the logs.
+//! DEFAULT_MODE, CACHE_MODE and VAULT_MODE are built in, and more profiles can be defined
+//! under `[modes.<NAME>]` in the configuration file, which can also redefine the built-in ones.
+
+use crate::command::Command;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Error message sent when a write would add a key to a full namespace with no eviction
+pub const MAX_KEYS: &str = "ERR_MAX_KEYS";
+
+/// Error code sent when the mode of the namespace doesn't allow a command
+pub const NOT_ALLOWED: &str = "ERR_NOT_ALLOWED";
+
+/// Text logged instead of a redacted value
+const REDACTED: &str = "***";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Represents what happens when a write would add a key to a full namespace
+pub enum Eviction {
+    /// The write is refused with `ERR_MAX_KEYS`
+    #[default]
+    NoEviction,
+    /// The key written the longest time ago is removed to make room.
+    /// It is the same key on every replica, since they apply the writes in the same order
+    Oldest,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Represents the limits and the behaviour of a mode
+pub struct ModeProfile {
+    pub max_key_length: usize,
+    pub max_value_length: usize,
+    /// Characters a key can't contain besides the line breaks and the null character
+    pub forbidden_keys: String,
+    /// Names of the data commands allowed, e.g. `["GET", "SET"]`. Empty allows every command.
+    /// Connection and admin commands like SELECT, PING, MODE or CONFIG are always allowed
+    pub allowed_commands: Vec<String>,
+    pub eviction: Eviction,
+    /// Maximum number of keys of a namespace, zero for no limit
+    pub max_keys: usize,
+    /// Seconds a key lives after it is written, zero for keys that never expire
+    pub default_ttl_secs: u64,
+    /// Whether the string values are stored encrypted with the `encryption_key` of the server.
+    /// Only SET, GET and REMOVE work on the keys of an encrypted mode
+    pub encryption: bool,
+    /// Whether the values are left out of the logs
+    pub redact_values: bool,
+}
+
+impl Default for ModeProfile {
+    fn default() -> Self {
+        Self {
+            max_key_length: 20,
+            max_value_length: 100,
+            forbidden_keys: String::new(),
+            allowed_commands: Vec::new(),
+            eviction: Eviction::NoEviction,
+            max_keys: 0,
+            default_ttl_secs: 0,
+            encryption: false,
+            redact_values: false,
+        }
+    }
+}
+
+/// Returns the built-in modes.
+/// - `DEFAULT_MODE`: max key length 20, max value length 100
+/// - `CACHE_MODE`: max key length 20, max value length 255, up to 100000 keys evicting the oldest
+/// - `VAULT_MODE`: max key length 20, max value length 40, values left out of the logs
+pub fn builtin_modes() -> BTreeMap<String, ModeProfile> {
+    BTreeMap::from([
+        ("DEFAULT_MODE".to_string(), ModeProfile::default()),
+        (
+            "CACHE_MODE".to_string(),
+            ModeProfile {
+                max_value_length: 255,
+                eviction: Eviction::Oldest,
+                max_keys: 100_000,
+                ..ModeProfile::default()
+            },
+        ),
+        (
+            "VAULT_MODE".to_string(),
+            ModeProfile {
+                max_value_length: 40,
+                redact_values: true,
+                ..ModeProfile::default()
+            },
+        ),
+    ])
+}
+
+impl ModeProfile {
+    /// Checks the limits of the profile.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the mode, used in the error messages
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid mode name: {}", name));
+        }
+        if self.max_key_length == 0 || self.max_value_length == 0 {
+            return Err(format!(
+                "Key and value lengths of mode {} must be greater than 0",
+                name
+            ));
+        }
+        if let Some(command) = self
+            .allowed_commands
+            .iter()
+            .find(|c| c.is_empty() || !c.chars().all(|c| c.is_ascii_alphabetic() || c == '.'))
+        {
+            return Err(format!(
+                "Invalid command name in mode {}: {}",
+                name, command
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks whether a command may run in the mode.
+    ///
+    /// # Arguments
+    /// * `cmd` - The parsed command
+    /// * `line` - The command line, whose first word is the name of the command
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::command::Command;
+    /// use kiwi_store_server::mode::ModeProfile;
+    ///
+    /// let profile = ModeProfile {
+    ///     allowed_commands: vec!["GET".to_string()],
+    ///     ..ModeProfile::default()
+    /// };
+    /// let allows = |line| profile.allows(&Command::parse(line), line);
+    /// assert!(allows("GET Theme"));
+    /// assert!(!allows("SET Theme Dark"));
+    /// assert!(allows("PING"));
+    /// ```
+    pub fn allows(&self, cmd: &Command, line: &str) -> bool {
+        if self.encryption
+            && !cmd.keys().is_empty()
+            && !matches!(
+                cmd,
+                Command::Set { .. }
+                    | Command::Get { .. }
+                    | Command::Remove { .. }
+                    | Command::History { .. }
+                    | Command::GetAt { .. }
+                    | Command::Rollback { .. }
+            )
+        {
+            return false;
+        }
+        let is_data_command = !cmd.keys().is_empty()
+            || matches!(
+                cmd,
+                Command::List
+                    | Command::Keys { .. }
+                    | Command::Scan { .. }
+                    | Command::Dump { .. }
+                    | Command::Restore { .. }
+            );
+        if self.allowed_commands.is_empty() || !is_data_command {
+            return true;
+        }
+        let name = line.split_whitespace().next().unwrap_or_default();
+        self.allowed_commands
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns a command line the way it can be logged in the mode,
+    /// with everything after the key left out when the values are redacted.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::mode::ModeProfile;
+    ///
+    /// let profile = ModeProfile {
+    ///     redact_values: true,
+    ///     ..ModeProfile::default()
+    /// };
+    /// assert_eq!(profile.redact("SET ApiKey 42-42"), "SET ApiKey ***");
+    /// assert_eq!(profile.redact("GET ApiKey"), "GET ApiKey");
+    /// ```
+    pub fn redact(&self, line: &str) -> String {
+        let line = line.trim();
+        if !self.redact_values {
+            return line.to_string();
+        }
+        let mut parts = line.splitn(3, ' ');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(cmd), Some(key), Some(_)) => format!("{} {} {}", cmd, key, REDACTED),
+            _ => line.to_string(),
+        }
+    }
+
+    /// Returns a value the way it can be logged in the mode.
+    pub fn redact_value<'a>(&self, value: &'a str) -> &'a str {
+        if self.redact_values { REDACTED } else { value }
+    }
+
+    /// Describes the profile for the MODE command.
+    pub fn describe(&self) -> String {
+        let allowed = if self.allowed_commands.is_empty() {
+            "all".to_string()
+        } else {
+            self.allowed_commands.join(" ")
+        };
+        let on_off = |flag: bool| if flag { "on" } else { "off" };
+        format!(
+            "MaxKeyLength({}), MaxValueLength({}), ForbiddenKeys({}), AllowedCommands({}), \
+             Eviction({}), MaxKeys({}), DefaultTtl({}), Encryption({}), Redaction({})",
+            self.max_key_length,
+            self.max_value_length,
+            if self.forbidden_keys.is_empty() {
+                "none"
+            } else {
+                &self.forbidden_keys
+            },
+            allowed,
+            match self.eviction {
+                Eviction::NoEviction => "noeviction",
+                Eviction::Oldest => "oldest",
+            },
+            if self.max_keys == 0 {
+                "unlimited".to_string()
+            } else {
+                self.max_keys.to_string()
+            },
+            if self.default_ttl_secs == 0 {
+                "none".to_string()
+            } else {
+                format!("{}s", self.default_ttl_secs)
+            },
+            on_off(self.encryption),
+            on_off(self.redact_values)
+        )
+    }
+}
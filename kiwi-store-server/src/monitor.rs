@@ -0,0 +1,101 @@
+//! Live feed of the commands processed by the server, for the MONITOR command.
+//!
+//! Every command line a client sends is published to the connections running MONITOR as
+//! `<unix time> [<namespace> <client>] <command>`, with the values redacted the way the mode of
+//! the namespace logs them and the passwords and keys left out. A line is only built when a
+//! monitor is attached, so without monitors the feed costs a counter read per command.
+
+use crate::auth::redact_secrets;
+use crate::namespace::Namespace;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+/// Number of lines a slow monitor can fall behind before it skips some
+const FEED_SIZE: usize = 1024;
+
+#[derive(Clone)]
+/// Represents the command feed of the server, shared by all connections
+pub struct Monitor {
+    feed: broadcast::Sender<Arc<str>>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        let (feed, _) = broadcast::channel(FEED_SIZE);
+        Self { feed }
+    }
+
+    /// Publishes a command line to the attached monitors, if there are any.
+    ///
+    /// # Arguments
+    /// * `namespace` - The namespace the command runs in
+    /// * `client` - The address of the client that sent the command
+    /// * `line` - The command line
+    pub fn publish(&self, namespace: &Namespace, client: SocketAddr, line: &str) {
+        if self.feed.receiver_count() == 0 {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{}.{:06} [{} {}] {}",
+            time.as_secs(),
+            time.subsec_micros(),
+            namespace.name,
+            client,
+            namespace.config().profile.redact(&redact_secrets(line))
+        );
+        // Sending only fails when the last monitor detached in the meantime
+        let _ = self.feed.send(line.into());
+    }
+
+    /// Turns a connection into a monitor, streaming it every command line until the client
+    /// closes the connection. Anything the client sends meanwhile is ignored.
+    ///
+    /// # Arguments
+    /// * `stream` - The connection that sent MONITOR
+    pub async fn serve(&self, mut stream: TcpStream) {
+        let mut feed = self.feed.subscribe();
+        let client = stream
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        info!("Monitor attached: {}", client);
+        if stream.write_all(b"OK\n").await.is_err() {
+            return;
+        }
+        let mut buffer = [0; 256];
+        loop {
+            let received = tokio::select! {
+                received = feed.recv() => received,
+                read = stream.read(&mut buffer) => match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => continue,
+                },
+            };
+            let line: Arc<str> = match received {
+                Ok(line) => line,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Monitor {} fell behind, {} lines skipped", client, skipped);
+                    format!("SKIPPED {}", skipped).into()
+                }
+                Err(RecvError::Closed) => break,
+            };
+            if stream
+                .write_all(format!("{}\n", line).as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        info!("Monitor detached: {}", client);
+    }
+}
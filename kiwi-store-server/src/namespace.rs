@@ -0,0 +1,223 @@
+//! Logical namespaces of the Kiwi Store server.
+//!
+//! Every namespace has its own data store and its own configuration derived from its mode,
+//! so dev, test and staging settings can live side by side without prefixing keys.
+
+use crate::config::Configuration;
+use crate::crypto;
+use crate::store::DataStore;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+
+/// Name of the namespace every connection starts in
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Error code of a SELECT that would create more namespaces than `max_namespaces`
+pub const MAX_NAMESPACES: &str = "ERR_MAX_NAMESPACES";
+
+/// Configuration shared by a namespace and the connections using it, swapped on a reload
+type SharedConfig = Arc<RwLock<Arc<Configuration>>>;
+
+#[derive(Clone)]
+/// Represents a single namespace with its data and limits
+pub struct Namespace {
+    pub name: String,
+    pub store: DataStore,
+    config: SharedConfig,
+}
+
+impl Namespace {
+    fn new(name: &str, config: Arc<Configuration>) -> Self {
+        Self {
+            name: name.to_string(),
+            store: DataStore::new(),
+            config: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    /// Returns the current configuration of the namespace.
+    /// A command keeps the configuration it started with even if a reload happens meanwhile.
+    pub fn config(&self) -> Arc<Configuration> {
+        Arc::clone(&self.config.read().unwrap())
+    }
+
+    /// Returns the contents of the namespace as the commands that rebuild it, see `DataStore::snapshot`.
+    /// The values of an encrypted mode are decrypted, since the node replaying the commands
+    /// encrypts them again with its own key. The snapshot must only be sent to admin sessions,
+    /// as SYNC, RAFT and CLUSTER MIGRATE are.
+    pub async fn snapshot(&self) -> Vec<String> {
+        let commands = self.store.snapshot().await;
+        let config = self.config();
+        if !config.profile.encryption {
+            return commands;
+        }
+        let key = config.encryption_key.as_deref().unwrap_or_default();
+        commands
+            .into_iter()
+            .map(|command| {
+                let decrypted = command
+                    .strip_prefix("SET ")
+                    .and_then(|rest| rest.split_once(' '))
+                    .and_then(|(name, value)| {
+                        crypto::decrypt(key, value)
+                            .ok()
+                            .map(|value| format!("SET {} {}", name, value))
+                    });
+                decrypted.unwrap_or(command)
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+/// Represents the registry of namespaces shared by all connections
+pub struct Namespaces {
+    base: SharedConfig,
+    entries: Arc<Mutex<HashMap<String, Namespace>>>,
+}
+
+impl Namespaces {
+    /// Creates the registry with the default namespace and the namespaces listed in the configuration.
+    ///
+    /// # Arguments
+    /// * `config` - The server configuration, used as is by the default namespace
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::config::Configuration;
+    /// use kiwi_store_server::namespace::Namespaces;
+    /// use std::sync::Arc;
+    ///
+    /// let namespaces = Namespaces::new(Arc::new(Configuration::default()));
+    /// let default = namespaces.default_namespace().await;
+    /// assert_eq!(default.name, "default");
+    /// ```
+    pub fn new(config: Arc<Configuration>) -> Self {
+        let mut entries = HashMap::new();
+        entries.insert(
+            DEFAULT_NAMESPACE.to_string(),
+            Namespace::new(DEFAULT_NAMESPACE, Arc::clone(&config)),
+        );
+        for (name, mode) in &config.namespaces {
+            entries.insert(
+                name.clone(),
+                Namespace::new(name, Arc::new(config.for_mode(mode))),
+            );
+        }
+        Self {
+            base: Arc::new(RwLock::new(config)),
+            entries: Arc::new(Mutex::new(entries)),
+        }
+    }
+
+    /// Returns the current server configuration.
+    pub fn config(&self) -> Arc<Configuration> {
+        Arc::clone(&self.base.read().unwrap())
+    }
+
+    /// Swaps the configuration of the server and of every namespace.
+    /// Namespaces listed in the configuration get the limits of their mode and are created
+    /// if they don't exist yet. The others, including the default one, use the server configuration.
+    /// Connections pick up the new configuration with their next command.
+    ///
+    /// # Arguments
+    /// * `config` - The new server configuration
+    pub async fn reconfigure(&self, config: Arc<Configuration>) {
+        let mut entries = self.entries.lock().await;
+        *self.base.write().unwrap() = Arc::clone(&config);
+        for name in config.namespaces.keys() {
+            entries
+                .entry(name.clone())
+                .or_insert_with(|| Namespace::new(name, Arc::clone(&config)));
+        }
+        for namespace in entries.values() {
+            let namespace_config = match config.namespaces.get(&namespace.name) {
+                Some(mode) => Arc::new(config.for_mode(mode)),
+                None => Arc::clone(&config),
+            };
+            *namespace.config.write().unwrap() = namespace_config;
+        }
+    }
+
+    /// Checks that a new configuration can be swapped in without leaving values in the wrong
+    /// form. A namespace holding keys can't switch between an encrypted and a plain mode, since
+    /// its values were stored in the form of the running mode.
+    ///
+    /// # Returns
+    /// Returns `Ok(())` if the configuration can be applied, or the namespaces that would switch.
+    pub async fn check_reconfigure(&self, config: &Configuration) -> Result<(), String> {
+        let entries = self.entries.lock().await;
+        let mut switching = Vec::new();
+        for namespace in entries.values() {
+            let encrypted = match config.namespaces.get(&namespace.name) {
+                Some(mode) => config.for_mode(mode).profile.encryption,
+                None => config.profile.encryption,
+            };
+            if encrypted != namespace.config().profile.encryption && namespace.store.len().await > 0
+            {
+                switching.push(namespace.name.clone());
+            }
+        }
+        if switching.is_empty() {
+            return Ok(());
+        }
+        switching.sort();
+        Err(format!(
+            "Encryption can't be turned on or off for namespaces holding keys: {}, flush them or restart the server",
+            switching.join(", ")
+        ))
+    }
+
+    /// Returns the namespace every connection starts in.
+    pub async fn default_namespace(&self) -> Namespace {
+        self.get_or_create(DEFAULT_NAMESPACE).await
+    }
+
+    /// Returns a namespace by name, if it exists.
+    pub async fn get(&self, name: &str) -> Option<Namespace> {
+        let entries = self.entries.lock().await;
+        entries.get(name).cloned()
+    }
+
+    /// Returns every namespace, ordered by name.
+    pub async fn all(&self) -> Vec<Namespace> {
+        let entries = self.entries.lock().await;
+        let mut namespaces: Vec<Namespace> = entries.values().cloned().collect();
+        namespaces.sort_by(|a, b| a.name.cmp(&b.name));
+        namespaces
+    }
+
+    /// Returns a namespace selected by a client, creating it with the server mode if it does not
+    /// exist and the server holds fewer namespaces than `max_namespaces`.
+    ///
+    /// # Returns
+    /// Returns the namespace, or an `ERR_MAX_NAMESPACES` error if a new one can't be created.
+    pub async fn select(&self, name: &str) -> Result<Namespace, String> {
+        let mut entries = self.entries.lock().await;
+        if let Some(namespace) = entries.get(name) {
+            return Ok(namespace.clone());
+        }
+        let config = self.config();
+        if entries.len() >= config.max_namespaces {
+            return Err(format!(
+                "{} {} namespaces exist, the limit is reached",
+                MAX_NAMESPACES,
+                entries.len()
+            ));
+        }
+        let namespace = Namespace::new(name, config);
+        entries.insert(name.to_string(), namespace.clone());
+        Ok(namespace)
+    }
+
+    /// Returns a namespace by name, creating it with the server mode if it does not exist.
+    /// Used for the namespaces a leader sends, which were already selected there.
+    pub async fn get_or_create(&self, name: &str) -> Namespace {
+        let mut entries = self.entries.lock().await;
+        entries
+            .entry(name.to_string())
+            .or_insert_with(|| Namespace::new(name, self.config()))
+            .clone()
+    }
+}
@@ -0,0 +1,915 @@
+//! Raft consensus for a cluster of Kiwi Store servers.
+//!
+//! Every write goes through a replicated log. A leader is elected automatically and
+//! a write is acknowledged only after a majority of the nodes has stored it and the
+//! leader has applied it. Peers talk over the client port: a connection that starts with
+//! `RAFT` carries one JSON request per line and receives one JSON response per line.
+//!
+//! The log, term and vote are kept in memory like the data itself, so a node that
+//! restarts must rejoin as a new member with an empty log.
+//!
+//! Applied entries are dropped from the log once it grows past a limit. A peer that needs an
+//! entry the leader has dropped receives a snapshot of the data instead, the same commands
+//! a replication follower gets on a full sync. Peers must be admin sessions of each other,
+//! so a node sends `AUTH` with its own admin password before `RAFT` when one is configured.
+
+use crate::command::Command;
+use crate::handler::execute;
+use crate::namespace::Namespaces;
+use crate::replication::Replication;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{Notify, oneshot};
+use tracing::{error, info, warn};
+
+/// Interval of the heartbeats sent by the leader
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+/// Range of the randomized election timeout, in milliseconds
+const ELECTION_TIMEOUT_MS: (u64, u64) = (300, 600);
+/// How long a peer may take to answer a request
+const RPC_TIMEOUT: Duration = Duration::from_millis(200);
+/// How long a client waits for its write to be committed
+const PROPOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a peer may take to install a snapshot
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of entries sent in a single append request
+const MAX_BATCH: usize = 128;
+/// Number of applied entries kept in the log for peers that are slightly behind. The log is
+/// compacted when twice as many applied entries have accumulated.
+const RETAINED_ENTRIES: u64 = 512;
+/// Command of the entry a new leader appends to commit the entries of earlier terms
+const NOOP: &str = "NOOP";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// Represents a write in the replicated log
+pub struct Entry {
+    pub term: u64,
+    pub namespace: String,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Represents a request sent between peers
+pub enum Request {
+    Vote {
+        term: u64,
+        candidate: String,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    Append {
+        term: u64,
+        leader: String,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: u64,
+    },
+    Snapshot {
+        term: u64,
+        leader: String,
+        last_index: u64,
+        last_term: u64,
+        /// The namespaces and commands that rebuild the data up to `last_index`
+        data: Vec<(String, String)>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Represents the answer of a peer to a request
+pub enum Response {
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    Append {
+        term: u64,
+        success: bool,
+        last_log_index: u64,
+    },
+    Snapshot {
+        term: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Represents the role of a node in the cluster
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Represents the volatile and log state of a node
+struct State {
+    term: u64,
+    voted_for: Option<String>,
+    /// Log entries after the snapshot, the entry at index `i` is stored at `log[i - snapshot_index - 1]`
+    log: Vec<Entry>,
+    /// Index and term of the last entry dropped from the log, its effects are in the data
+    snapshot_index: u64,
+    snapshot_term: u64,
+    commit_index: u64,
+    last_applied: u64,
+    role: Role,
+    leader: Option<String>,
+    votes: HashSet<String>,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    last_contact: Instant,
+    election_timeout: Duration,
+    /// Clients waiting for the entry they proposed at an index and term
+    waiters: HashMap<u64, (u64, oneshot::Sender<String>)>,
+}
+
+impl State {
+    fn last_log_index(&self) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    fn entry(&self, index: u64) -> Option<&Entry> {
+        let position = index.checked_sub(self.snapshot_index + 1)?;
+        self.log.get(position as usize)
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        if index == self.snapshot_index {
+            return self.snapshot_term;
+        }
+        self.entry(index).map_or(0, |e| e.term)
+    }
+
+    /// Drops the oldest applied entries once the log holds too many of them.
+    fn compact(&mut self) {
+        if self.last_applied < self.snapshot_index + 2 * RETAINED_ENTRIES {
+            return;
+        }
+        let index = self.last_applied - RETAINED_ENTRIES;
+        self.snapshot_term = self.term_at(index);
+        self.log.drain(..(index - self.snapshot_index) as usize);
+        self.snapshot_index = index;
+    }
+
+    fn step_down(&mut self, term: u64) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+        }
+        self.role = Role::Follower;
+        self.votes.clear();
+    }
+
+    fn reset_election_timer(&mut self) {
+        self.last_contact = Instant::now();
+        self.election_timeout = random_election_timeout();
+    }
+}
+
+struct Inner {
+    id: String,
+    peers: Vec<String>,
+    state: Mutex<State>,
+    /// Wakes the replicator of each peer when new entries are appended
+    replicators: HashMap<String, Notify>,
+    /// Wakes the applier when the commit index moves
+    applier: Notify,
+    namespaces: Namespaces,
+    replication: Replication,
+}
+
+#[derive(Clone)]
+/// Represents this node's membership in a Raft cluster
+pub struct Raft {
+    inner: Arc<Inner>,
+}
+
+impl Raft {
+    /// Joins the cluster and starts the election timer, the applier and a replicator per peer.
+    ///
+    /// # Arguments
+    /// * `id` - The address of this node, as listed in the cluster members
+    /// * `members` - The addresses of every node of the cluster, including this one
+    /// * `namespaces` - The namespaces committed writes are applied to
+    /// * `replication` - Committed writes are also streamed to the followers of this node
+    pub fn start(
+        id: &str,
+        members: &[String],
+        namespaces: Namespaces,
+        replication: Replication,
+    ) -> Self {
+        let peers: Vec<String> = members.iter().filter(|m| *m != id).cloned().collect();
+        let raft = Raft {
+            inner: Arc::new(Inner {
+                id: id.to_string(),
+                replicators: peers.iter().map(|p| (p.clone(), Notify::new())).collect(),
+                peers,
+                state: Mutex::new(State {
+                    term: 0,
+                    voted_for: None,
+                    log: Vec::new(),
+                    snapshot_index: 0,
+                    snapshot_term: 0,
+                    commit_index: 0,
+                    last_applied: 0,
+                    role: Role::Follower,
+                    leader: None,
+                    votes: HashSet::new(),
+                    next_index: HashMap::new(),
+                    match_index: HashMap::new(),
+                    last_contact: Instant::now(),
+                    election_timeout: random_election_timeout(),
+                    waiters: HashMap::new(),
+                }),
+                applier: Notify::new(),
+                namespaces,
+                replication,
+            }),
+        };
+
+        tokio::spawn(raft.clone().run_election_timer());
+        tokio::spawn(raft.clone().run_applier());
+        for peer in &raft.inner.peers {
+            tokio::spawn(raft.clone().run_replicator(peer.clone()));
+        }
+        info!(
+            "Joined Raft cluster as {} with peers {:?}",
+            id, raft.inner.peers
+        );
+        raft
+    }
+
+    /// Checks whether this node is the leader of the cluster.
+    pub fn is_leader(&self) -> bool {
+        self.inner.state.lock().unwrap().role == Role::Leader
+    }
+
+    /// Returns the address of the current leader, if one is known.
+    pub fn leader(&self) -> Option<String> {
+        self.inner.state.lock().unwrap().leader.clone()
+    }
+
+    /// Get Raft statistics for the STATS command.
+    pub fn stats(&self) -> String {
+        let state = self.inner.state.lock().unwrap();
+        format!(
+            "Raft({}), Term({}), Leader({}), Commit({})",
+            format!("{:?}", state.role).to_lowercase(),
+            state.term,
+            state.leader.as_deref().unwrap_or("none"),
+            state.commit_index
+        )
+    }
+
+    /// Appends a write to the log and waits until a majority has stored it and it has been applied.
+    ///
+    /// # Arguments
+    /// * `namespace` - The namespace the write applies to
+    /// * `command` - The command line of the write
+    ///
+    /// # Returns
+    /// Returns the response of the applied command, or an error if this node is not the
+    /// leader or the write could not be committed in time.
+    pub async fn propose(&self, namespace: &str, command: &str) -> Result<String, String> {
+        let receiver = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.role != Role::Leader {
+                return Err("This node is not the leader".to_string());
+            }
+            let term = state.term;
+            state.log.push(Entry {
+                term,
+                namespace: namespace.to_string(),
+                command: command.to_string(),
+            });
+            let index = state.last_log_index();
+            let (sender, receiver) = oneshot::channel();
+            state.waiters.insert(index, (term, sender));
+            receiver
+        };
+        self.notify_replicators();
+        self.advance_commit();
+
+        match tokio::time::timeout(PROPOSE_TIMEOUT, receiver).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err("Write was not committed, leadership changed".to_string()),
+            Err(_) => Err("Write timed out, it may or may not be committed".to_string()),
+        }
+    }
+
+    /// Serves a peer that opened this connection with `RAFT`.
+    ///
+    /// # Arguments
+    /// * `stream` - The connection of the peer
+    /// * `initial` - The bytes already read from the connection, starting with the `RAFT` line
+    pub async fn serve(&self, stream: TcpStream, initial: Vec<u8>) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(std::io::Cursor::new(initial).chain(reader)).lines();
+        // Skip the RAFT line that opened the connection
+        if !matches!(lines.next_line().await, Ok(Some(_))) {
+            return;
+        }
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(Request::Vote {
+                    term,
+                    candidate,
+                    last_log_index,
+                    last_log_term,
+                }) => self.handle_vote(term, &candidate, last_log_index, last_log_term),
+                Ok(Request::Append {
+                    term,
+                    leader,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                }) => self.handle_append(
+                    term,
+                    &leader,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                ),
+                Ok(Request::Snapshot {
+                    term,
+                    leader,
+                    last_index,
+                    last_term,
+                    data,
+                }) => {
+                    self.install_snapshot(term, &leader, last_index, last_term, data)
+                        .await
+                }
+                Err(e) => {
+                    error!("Invalid Raft request: {}", e);
+                    return;
+                }
+            };
+            let mut message = serde_json::to_string(&response).unwrap_or_default();
+            message.push('\n');
+            if writer.write_all(message.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn handle_vote(
+        &self,
+        term: u64,
+        candidate: &str,
+        last_log_index: u64,
+        last_log_term: u64,
+    ) -> Response {
+        let mut state = self.inner.state.lock().unwrap();
+        if term > state.term {
+            state.step_down(term);
+        }
+        let my_last_term = state.term_at(state.last_log_index());
+        let up_to_date = last_log_term > my_last_term
+            || (last_log_term == my_last_term && last_log_index >= state.last_log_index());
+        let granted = term == state.term
+            && up_to_date
+            && state.voted_for.as_deref().is_none_or(|v| v == candidate);
+        if granted {
+            state.voted_for = Some(candidate.to_string());
+            state.reset_election_timer();
+        }
+        Response::Vote {
+            term: state.term,
+            granted,
+        }
+    }
+
+    fn handle_append(
+        &self,
+        term: u64,
+        leader: &str,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: u64,
+    ) -> Response {
+        let mut state = self.inner.state.lock().unwrap();
+        if term < state.term {
+            return Response::Append {
+                term: state.term,
+                success: false,
+                last_log_index: state.last_log_index(),
+            };
+        }
+        state.step_down(term);
+        state.leader = Some(leader.to_string());
+        state.reset_election_timer();
+
+        let last_new_index = prev_log_index + entries.len() as u64;
+        let (prev_log_index, prev_log_term, entries) = if prev_log_index < state.snapshot_index {
+            // The entries up to the snapshot are committed, so they match the leader's
+            let skipped = (state.snapshot_index - prev_log_index) as usize;
+            let entries = entries.into_iter().skip(skipped).collect();
+            (state.snapshot_index, state.snapshot_term, entries)
+        } else {
+            (prev_log_index, prev_log_term, entries)
+        };
+
+        if prev_log_index > state.last_log_index() || state.term_at(prev_log_index) != prev_log_term
+        {
+            let last_log_index = state.last_log_index().min(prev_log_index.saturating_sub(1));
+            return Response::Append {
+                term: state.term,
+                success: false,
+                last_log_index,
+            };
+        }
+
+        for (offset, entry) in entries.into_iter().enumerate() {
+            let index = prev_log_index + 1 + offset as u64;
+            if index <= state.last_log_index() {
+                if state.term_at(index) == entry.term {
+                    continue;
+                }
+                // A conflicting suffix was never committed, drop it with its waiting clients
+                let position = (index - state.snapshot_index - 1) as usize;
+                state.log.truncate(position);
+                state.waiters.retain(|i, _| *i < index);
+            }
+            state.log.push(entry);
+        }
+
+        let commit_index = leader_commit.min(last_new_index);
+        if commit_index > state.commit_index {
+            state.commit_index = commit_index;
+            self.inner.applier.notify_one();
+        }
+        Response::Append {
+            term: state.term,
+            success: true,
+            last_log_index: state.last_log_index(),
+        }
+    }
+
+    /// Replaces the data and the log with a snapshot sent by the leader.
+    async fn install_snapshot(
+        &self,
+        term: u64,
+        leader: &str,
+        last_index: u64,
+        last_term: u64,
+        data: Vec<(String, String)>,
+    ) -> Response {
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            if term < state.term {
+                return Response::Snapshot { term: state.term };
+            }
+            state.step_down(term);
+            state.leader = Some(leader.to_string());
+            state.reset_election_timer();
+        }
+
+        let _guard = self.inner.replication.write_guard().await;
+        if last_index <= self.inner.state.lock().unwrap().last_applied {
+            return Response::Snapshot { term };
+        }
+        for namespace in self.inner.namespaces.all().await {
+            namespace.store.clear().await;
+        }
+        let commands = data.len();
+        for (name, command) in data {
+            let namespace = self.inner.namespaces.get_or_create(&name).await;
+            execute(
+                Command::parse(&command),
+                &namespace.store,
+                &namespace.config(),
+            )
+            .await;
+        }
+
+        let mut state = self.inner.state.lock().unwrap();
+        if last_index < state.last_log_index() && state.term_at(last_index) == last_term {
+            let position = (last_index - state.snapshot_index) as usize;
+            state.log.drain(..position);
+        } else {
+            state.log.clear();
+        }
+        state.snapshot_index = last_index;
+        state.snapshot_term = last_term;
+        state.commit_index = state.commit_index.max(last_index);
+        state.last_applied = last_index;
+        state.waiters.retain(|index, _| *index > last_index);
+        info!(
+            "Installed a snapshot of {} commands from {} at index {}",
+            commands, leader, last_index
+        );
+        Response::Snapshot { term: state.term }
+    }
+
+    /// Starts an election when the leader has been silent for longer than the election timeout.
+    async fn run_election_timer(self) {
+        loop {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let request = {
+                let mut state = self.inner.state.lock().unwrap();
+                if state.role == Role::Leader
+                    || state.last_contact.elapsed() < state.election_timeout
+                {
+                    continue;
+                }
+                state.term += 1;
+                state.role = Role::Candidate;
+                state.leader = None;
+                state.voted_for = Some(self.inner.id.clone());
+                state.votes = HashSet::from([self.inner.id.clone()]);
+                state.reset_election_timer();
+                info!("Starting election for term {}", state.term);
+                Request::Vote {
+                    term: state.term,
+                    candidate: self.inner.id.clone(),
+                    last_log_index: state.last_log_index(),
+                    last_log_term: state.term_at(state.last_log_index()),
+                }
+            };
+
+            self.count_vote(&self.inner.id, &request, true);
+            for peer in &self.inner.peers {
+                let raft = self.clone();
+                let peer = peer.clone();
+                let request = request.clone();
+                tokio::spawn(async move {
+                    let mut connection = PeerConnection::new(&peer, &raft.inner.namespaces);
+                    if let Some(Response::Vote { term, granted }) = connection.call(&request).await
+                    {
+                        if term > raft.current_term() {
+                            raft.inner.state.lock().unwrap().step_down(term);
+                        } else {
+                            raft.count_vote(&peer, &request, granted);
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    fn current_term(&self) -> u64 {
+        self.inner.state.lock().unwrap().term
+    }
+
+    fn count_vote(&self, voter: &str, request: &Request, granted: bool) {
+        let Request::Vote { term, .. } = request else {
+            return;
+        };
+        let mut state = self.inner.state.lock().unwrap();
+        if !granted || state.role != Role::Candidate || state.term != *term {
+            return;
+        }
+        state.votes.insert(voter.to_string());
+        if state.votes.len() * 2 > self.inner.peers.len() + 1 {
+            info!("Elected leader for term {}", state.term);
+            state.role = Role::Leader;
+            state.leader = Some(self.inner.id.clone());
+            let next = state.last_log_index() + 1;
+            for peer in &self.inner.peers {
+                state.next_index.insert(peer.clone(), next);
+                state.match_index.insert(peer.clone(), 0);
+            }
+            let term = state.term;
+            state.log.push(Entry {
+                term,
+                namespace: String::new(),
+                command: NOOP.to_string(),
+            });
+            drop(state);
+            self.notify_replicators();
+            self.advance_commit();
+        }
+    }
+
+    /// Keeps a peer's log in line with the leader's, sending heartbeats when there is nothing new.
+    async fn run_replicator(self, peer: String) {
+        let mut connection = PeerConnection::new(&peer, &self.inner.namespaces);
+        loop {
+            let needs_snapshot = {
+                let state = self.inner.state.lock().unwrap();
+                state.role == Role::Leader
+                    && *state.next_index.get(&peer).unwrap_or(&1) <= state.snapshot_index
+            };
+            let request = if needs_snapshot {
+                self.snapshot_request().await
+            } else {
+                self.append_request(&peer)
+            };
+
+            let mut more = false;
+            if let Some(request) = request {
+                match connection.call(&request).await {
+                    Some(Response::Append {
+                        term,
+                        success,
+                        last_log_index,
+                    }) => {
+                        more = self.handle_append_response(
+                            &peer,
+                            &request,
+                            term,
+                            success,
+                            last_log_index,
+                        );
+                    }
+                    Some(Response::Snapshot { term }) => {
+                        more = self.handle_snapshot_response(&peer, &request, term);
+                    }
+                    _ => {}
+                }
+            }
+
+            if !more {
+                let notify = &self.inner.replicators[&peer];
+                tokio::select! {
+                    _ = notify.notified() => {}
+                    _ = tokio::time::sleep(HEARTBEAT_INTERVAL) => {}
+                }
+            }
+        }
+    }
+
+    /// Builds the request carrying the entries a peer misses, or a heartbeat if it misses none.
+    /// Returns `None` if this node is not the leader or the entries were dropped from the log.
+    fn append_request(&self, peer: &str) -> Option<Request> {
+        let state = self.inner.state.lock().unwrap();
+        let next = *state.next_index.get(peer).unwrap_or(&1);
+        if state.role != Role::Leader || next <= state.snapshot_index {
+            return None;
+        }
+        let prev_log_index = next - 1;
+        let start = (prev_log_index - state.snapshot_index) as usize;
+        let end = state.log.len().min(start + MAX_BATCH);
+        Some(Request::Append {
+            term: state.term,
+            leader: self.inner.id.clone(),
+            prev_log_index,
+            prev_log_term: state.term_at(prev_log_index),
+            entries: state.log[start..end].to_vec(),
+            leader_commit: state.commit_index,
+        })
+    }
+
+    /// Builds a snapshot of the data as of the last applied entry.
+    /// Returns `None` if this node is not the leader.
+    async fn snapshot_request(&self) -> Option<Request> {
+        // Nothing is applied while the snapshot is taken
+        let _guard = self.inner.replication.write_guard().await;
+        let mut data = Vec::new();
+        for namespace in self.inner.namespaces.all().await {
+            for command in namespace.snapshot().await {
+                data.push((namespace.name.clone(), command));
+            }
+        }
+        let state = self.inner.state.lock().unwrap();
+        if state.role != Role::Leader {
+            return None;
+        }
+        Some(Request::Snapshot {
+            term: state.term,
+            leader: self.inner.id.clone(),
+            last_index: state.last_applied,
+            last_term: state.term_at(state.last_applied),
+            data,
+        })
+    }
+
+    /// Updates the progress of a peer that installed a snapshot.
+    /// Returns `true` if the peer still misses entries.
+    fn handle_snapshot_response(&self, peer: &str, request: &Request, term: u64) -> bool {
+        let Request::Snapshot {
+            term: request_term,
+            last_index,
+            ..
+        } = request
+        else {
+            return false;
+        };
+        let mut state = self.inner.state.lock().unwrap();
+        if term > state.term {
+            warn!("Stepping down, peer {} is at term {}", peer, term);
+            state.step_down(term);
+            return false;
+        }
+        if state.role != Role::Leader || state.term != *request_term {
+            return false;
+        }
+        state.match_index.insert(peer.to_string(), *last_index);
+        state.next_index.insert(peer.to_string(), last_index + 1);
+        let more = *last_index < state.last_log_index();
+        drop(state);
+        self.advance_commit();
+        more
+    }
+
+    /// Updates the progress of a peer. Returns `true` if the peer still misses entries.
+    fn handle_append_response(
+        &self,
+        peer: &str,
+        request: &Request,
+        term: u64,
+        success: bool,
+        last_log_index: u64,
+    ) -> bool {
+        let Request::Append {
+            term: request_term,
+            prev_log_index,
+            entries,
+            ..
+        } = request
+        else {
+            return false;
+        };
+        let mut state = self.inner.state.lock().unwrap();
+        if term > state.term {
+            warn!("Stepping down, peer {} is at term {}", peer, term);
+            state.step_down(term);
+            return false;
+        }
+        if state.role != Role::Leader || state.term != *request_term {
+            return false;
+        }
+        if success {
+            let matched = prev_log_index + entries.len() as u64;
+            state.match_index.insert(peer.to_string(), matched);
+            state.next_index.insert(peer.to_string(), matched + 1);
+            let more = matched < state.last_log_index();
+            drop(state);
+            self.advance_commit();
+            more
+        } else {
+            let next = (last_log_index + 1).min(*prev_log_index).max(1);
+            state.next_index.insert(peer.to_string(), next);
+            true
+        }
+    }
+
+    /// Moves the commit index to the highest entry of the current term stored on a majority.
+    fn advance_commit(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.role != Role::Leader {
+            return;
+        }
+        let mut commit = state.commit_index;
+        for index in (state.commit_index + 1)..=state.last_log_index() {
+            if state.term_at(index) != state.term {
+                continue;
+            }
+            let replicas = 1 + state.match_index.values().filter(|m| **m >= index).count();
+            if replicas * 2 > self.inner.peers.len() + 1 {
+                commit = index;
+            }
+        }
+        if commit > state.commit_index {
+            state.commit_index = commit;
+            self.inner.applier.notify_one();
+        }
+    }
+
+    /// Applies the committed entries in order and answers the clients waiting for them.
+    async fn run_applier(self) {
+        loop {
+            tokio::select! {
+                _ = self.inner.applier.notified() => {}
+                _ = tokio::time::sleep(HEARTBEAT_INTERVAL) => {}
+            }
+            let entries: Vec<(u64, Entry)> = {
+                let state = self.inner.state.lock().unwrap();
+                ((state.last_applied + 1)..=state.commit_index)
+                    .filter_map(|i| state.entry(i).map(|entry| (i, entry.clone())))
+                    .collect()
+            };
+
+            for (index, entry) in entries {
+                // An entry and its index are applied together, so a snapshot never sees one without the other
+                let _guard = self.inner.replication.write_guard().await;
+                if index <= self.inner.state.lock().unwrap().last_applied {
+                    // A snapshot installed meanwhile already holds the entry
+                    continue;
+                }
+                let response = self.apply(&entry).await;
+                let mut state = self.inner.state.lock().unwrap();
+                state.last_applied = index;
+                state.compact();
+                if let Some((term, waiter)) = state.waiters.remove(&index)
+                    && term == entry.term
+                {
+                    let _ = waiter.send(response);
+                }
+            }
+        }
+    }
+
+    /// Applies a committed entry. The caller holds the write guard of the replication.
+    async fn apply(&self, entry: &Entry) -> String {
+        if entry.command == NOOP {
+            return String::new();
+        }
+        let namespace = self.inner.namespaces.get_or_create(&entry.namespace).await;
+        let response = match Command::parse(&entry.command) {
+            Command::FlushNamespace { .. } => {
+                namespace.store.clear().await;
+                "OK\n".to_string()
+            }
+            cmd => execute(cmd, &namespace.store, &namespace.config()).await,
+        };
+        if !response.starts_with("ERROR") {
+            self.inner
+                .replication
+                .publish(&entry.namespace, &entry.command);
+        }
+        response
+    }
+
+    fn notify_replicators(&self) {
+        for notify in self.inner.replicators.values() {
+            notify.notify_one();
+        }
+    }
+}
+
+/// Represents a reusable connection to a peer
+struct PeerConnection {
+    address: String,
+    /// Read for the admin password each time the connection is opened
+    namespaces: Namespaces,
+    stream: Option<BufReader<TcpStream>>,
+}
+
+impl PeerConnection {
+    fn new(address: &str, namespaces: &Namespaces) -> Self {
+        Self {
+            address: address.to_string(),
+            namespaces: namespaces.clone(),
+            stream: None,
+        }
+    }
+
+    /// Opens the connection, as an admin session when an admin password is configured.
+    async fn connect(&self) -> Option<BufReader<TcpStream>> {
+        let mut stream = BufReader::new(TcpStream::connect(&self.address).await.ok()?);
+        let password = self.namespaces.config().admin_password.clone();
+        if let Some(password) = &password {
+            let auth = format!("AUTH {}\n", password);
+            stream.get_mut().write_all(auth.as_bytes()).await.ok()?;
+            let mut reply = String::new();
+            stream.read_line(&mut reply).await.ok()?;
+            if reply.trim_end() != "OK" {
+                warn!(
+                    "Peer {} refused the admin password: {}",
+                    self.address,
+                    reply.trim_end()
+                );
+                return None;
+            }
+        }
+        stream.get_mut().write_all(b"RAFT\n").await.ok()?;
+        Some(stream)
+    }
+
+    /// Sends a request and waits for the response, reconnecting if needed.
+    /// Returns `None` if the peer could not be reached in time.
+    async fn call(&mut self, request: &Request) -> Option<Response> {
+        let timeout = match request {
+            Request::Snapshot { .. } => SNAPSHOT_TIMEOUT,
+            _ => RPC_TIMEOUT,
+        };
+        let result = tokio::time::timeout(timeout, async {
+            if self.stream.is_none() {
+                self.stream = Some(self.connect().await?);
+            }
+            let stream = self.stream.as_mut()?;
+            let mut message = serde_json::to_string(request).ok()?;
+            message.push('\n');
+            stream.get_mut().write_all(message.as_bytes()).await.ok()?;
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.ok()? == 0 {
+                return None;
+            }
+            serde_json::from_str(&line).ok()
+        })
+        .await;
+
+        match result {
+            Ok(Some(response)) => Some(response),
+            _ => {
+                self.stream = None;
+                None
+            }
+        }
+    }
+}
+
+fn random_election_timeout() -> Duration {
+    let (min, max) = ELECTION_TIMEOUT_MS;
+    Duration::from_millis(rand::rng().random_range(min..max))
+}
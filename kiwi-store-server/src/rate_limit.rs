@@ -0,0 +1,233 @@
+//! Per-client rate limiting of the Kiwi Store server.
+//!
+//! Every client IP gets a token bucket for each user and command category, so the admin
+//! sessions of an address are limited apart from its other clients. A bucket holds up to one
+//! second worth of commands and refills continuously at the configured rate, so short bursts
+//! are allowed while a client looping on writes is slowed down to its limit.
+//!
+//! The server has no user accounts, so the user is only `admin` for a session authenticated
+//! with AUTH and `default` for the others. Clients sharing an address and a user share a bucket.
+
+use crate::command::Command;
+use crate::config::Configuration;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use tracing::warn;
+
+/// Error message sent to a client over its rate limit, followed by the milliseconds to wait
+pub const RATE_LIMITED: &str = "ERR_RATE_LIMITED";
+
+/// Number of buckets kept before the least recently used half is dropped
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Represents the category a command is rate limited in
+pub enum Category {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Category {
+    /// Returns the category of a command.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::command::Command;
+    /// use kiwi_store_server::rate_limit::Category;
+    ///
+    /// assert_eq!(Category::of(&Command::parse("SET UseHttps Off")), Category::Write);
+    /// assert_eq!(Category::of(&Command::parse("STATS")), Category::Admin);
+    /// ```
+    pub fn of(cmd: &Command) -> Self {
+        match cmd {
+            Command::Stats
+            | Command::Info { .. }
+            | Command::SlowLogGet { .. }
+            | Command::SlowLogLen
+            | Command::SlowLogReset
+            | Command::ClientList
+            | Command::ClientSetName { .. }
+            | Command::ClientKill { .. }
+            | Command::FlushNamespace { .. }
+            | Command::ReplicaOf { .. }
+            | Command::ClusterSlots
+            | Command::ClusterSetSlot { .. }
+            | Command::ClusterImporting
+            | Command::ClusterMigrate { .. }
+            | Command::RateLimits
+            | Command::ConfigGet { .. }
+            | Command::ConfigSet { .. }
+            | Command::ConfigRewrite
+            | Command::ConfigReload
+            | Command::Auth { .. }
+            | Command::Shutdown { .. } => Category::Admin,
+            cmd if cmd.is_mutating() => Category::Write,
+            _ => Category::Read,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "read" => Some(Category::Read),
+            "write" => Some(Category::Write),
+            "admin" => Some(Category::Admin),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Category::Read => "read",
+            Category::Write => "write",
+            Category::Admin => "admin",
+        }
+    }
+}
+
+/// Represents the tokens left to a client in a category
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Inner {
+    /// Commands allowed per second in each category, categories without a limit are not listed
+    limits: RwLock<HashMap<Category, u32>>,
+    buckets: Mutex<HashMap<(IpAddr, String, Category), Bucket>>,
+    limited: AtomicU64,
+}
+
+#[derive(Clone)]
+/// Represents the rate limits of the server, shared by all connections
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+impl RateLimiter {
+    /// Creates the rate limiter from the limits of the configuration.
+    /// Unknown categories are ignored with a warning.
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limits: RwLock::new(Self::limits(config)),
+                buckets: Mutex::new(HashMap::new()),
+                limited: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Replaces the limits with the ones of a reloaded configuration.
+    /// The buckets are dropped, so every client starts over with a full bucket.
+    pub fn reconfigure(&self, config: &Configuration) {
+        let mut buckets = self.inner.buckets.lock().unwrap();
+        *self.inner.limits.write().unwrap() = Self::limits(config);
+        buckets.clear();
+    }
+
+    fn limits(config: &Configuration) -> HashMap<Category, u32> {
+        config
+            .rate_limits
+            .iter()
+            .filter_map(|(name, limit)| match Category::parse(name) {
+                Some(category) => Some((category, *limit)),
+                None => {
+                    warn!("Unknown rate limit category: {}", name);
+                    None
+                }
+            })
+            .filter(|(_, limit)| *limit > 0)
+            .collect()
+    }
+
+    /// Takes a token for a command of a client.
+    ///
+    /// # Arguments
+    /// * `ip` - The IP address of the client
+    /// * `user` - The user of the connection, `admin` for an authenticated one and `default` otherwise
+    /// * `category` - The category of the command
+    ///
+    /// # Returns
+    /// Returns `Ok(())` if the command may run, or the milliseconds until the next token is available.
+    pub fn check(&self, ip: IpAddr, user: &str, category: Category) -> Result<(), u64> {
+        let limits = self.inner.limits.read().unwrap();
+        let Some(limit) = limits.get(&category).map(|l| *l as f64) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut buckets = self.inner.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            // A bucket refills within a second, so the least recently used ones are mostly full
+            // and a new bucket starts full anyway
+            let mut updated: Vec<Instant> = buckets.values().map(|b| b.updated).collect();
+            let (_, cutoff, _) = updated.select_nth_unstable(MAX_BUCKETS / 2);
+            let cutoff = *cutoff;
+            buckets.retain(|_, b| b.updated > cutoff);
+        }
+        drop(limits);
+        let bucket = buckets
+            .entry((ip, user.to_string(), category))
+            .or_insert(Bucket {
+                tokens: limit,
+                updated: now,
+            });
+        bucket.tokens =
+            (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * limit).min(limit);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            self.inner.limited.fetch_add(1, Ordering::SeqCst);
+            Err(((1.0 - bucket.tokens) / limit * 1000.0).ceil() as u64)
+        }
+    }
+
+    /// Describes the limits and the buckets of the clients for the RATELIMITS command.
+    ///
+    /// # Returns
+    /// Returns a `Limits` line followed by a `<ip> <user> <category> <tokens>/<limit>` line per bucket.
+    pub fn describe(&self) -> String {
+        let all_limits = self.inner.limits.read().unwrap().clone();
+        let mut limits: Vec<(&Category, &u32)> = all_limits.iter().collect();
+        limits.sort();
+        let limits: Vec<String> = limits
+            .iter()
+            .map(|(category, limit)| format!("{}={}/s", category.name(), limit))
+            .collect();
+        let mut lines = vec![format!(
+            "Limits({}), Limited({})",
+            if limits.is_empty() {
+                "none".to_string()
+            } else {
+                limits.join(", ")
+            },
+            self.inner.limited.load(Ordering::SeqCst)
+        )];
+
+        let now = Instant::now();
+        let buckets = self.inner.buckets.lock().unwrap();
+        let mut entries: Vec<(&(IpAddr, String, Category), &Bucket)> = buckets.iter().collect();
+        entries.sort_by_key(|(key, _)| *key);
+        for ((ip, user, category), bucket) in entries {
+            let Some(limit) = all_limits.get(category).map(|l| *l as f64) else {
+                continue;
+            };
+            let tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * limit)
+                .min(limit);
+            lines.push(format!(
+                "{} {} {} {}/{}",
+                ip,
+                user,
+                category.name(),
+                tokens.floor(),
+                limit
+            ));
+        }
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,256 @@
+//! Runtime changes of the Kiwi Store server configuration.
+//!
+//! The configuration is loaded again from the same file, environment variables and flags the
+//! server started with, when the file changes, on SIGHUP or with the CONFIG RELOAD command.
+//! CONFIG SET changes a single setting and takes precedence over every other source until the
+//! server restarts, and CONFIG REWRITE writes the settings of the file with the ones changed by
+//! CONFIG SET back to the file.
+//! A new configuration is validated and swapped in for every namespace, the connection limits
+//! and the rate limits without dropping any data. Settings read only at startup, like the listen
+//! address, keep their running value and are reported as needing a restart.
+
+use crate::config::{Configuration, RESTART_REQUIRED, Settings};
+use crate::state::ServerState;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// How often the configuration file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Inner {
+    file: Option<PathBuf>,
+    flags: Settings,
+    /// Settings changed with CONFIG SET, put over the flags
+    overrides: std::sync::Mutex<Settings>,
+    /// Serializes the reloads, so two of them can't interleave their swaps
+    lock: Mutex<()>,
+}
+
+#[derive(Clone)]
+/// Represents the sources the configuration is loaded from, shared by all connections
+pub struct Reloader {
+    inner: Arc<Inner>,
+}
+
+impl Reloader {
+    /// Creates a reloader loading the configuration the way the server was started.
+    ///
+    /// # Arguments
+    /// * `file` - The path of the TOML configuration file, if one is given
+    /// * `flags` - The settings given as command-line flags
+    pub fn new(file: Option<PathBuf>, flags: Settings) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                file,
+                flags,
+                overrides: std::sync::Mutex::new(Settings::default()),
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Loads the configuration again and applies it to the server.
+    /// An invalid configuration is refused and the running one is kept.
+    ///
+    /// # Arguments
+    /// * `state` - The state of the server to apply the configuration to
+    ///
+    /// # Returns
+    /// Returns the changed settings and the ones needing a restart,
+    /// e.g. `Changed(max_value_length), RestartRequired(port)`, or the validation error.
+    pub async fn reload(&self, state: &ServerState) -> Result<String, String> {
+        let _lock = self.inner.lock.lock().await;
+        let overrides = self.inner.overrides.lock().unwrap().clone();
+        let config = self.load(overrides)?;
+        state.namespaces.check_reconfigure(&config).await?;
+        Ok(self.apply(state, config).await)
+    }
+
+    /// Changes a setting of the running server, as the CONFIG SET command.
+    /// The new value is kept over the file, environment variables and flags by later reloads.
+    ///
+    /// # Arguments
+    /// * `state` - The state of the server to apply the configuration to
+    /// * `name` - The name of the setting
+    /// * `value` - The new value, in the format of the environment variables
+    ///
+    /// # Returns
+    /// Returns `Ok(())` if the setting is changed, or the reason it is refused.
+    pub async fn set(&self, state: &ServerState, name: &str, value: &str) -> Result<(), String> {
+        let _lock = self.inner.lock.lock().await;
+        let name = name.to_lowercase();
+        if RESTART_REQUIRED.contains(&name.as_str()) {
+            return Err(format!(
+                "{} can't be changed at runtime, restart the server",
+                name
+            ));
+        }
+        let overrides = self
+            .inner
+            .overrides
+            .lock()
+            .unwrap()
+            .clone()
+            .merge(Settings::parse(&name, value)?);
+        let config = self.load(overrides.clone())?;
+        state.namespaces.check_reconfigure(&config).await?;
+        *self.inner.overrides.lock().unwrap() = overrides;
+        info!("Setting {} is changed", name);
+        self.apply(state, config).await;
+        Ok(())
+    }
+
+    /// Writes the settings of the configuration file with the ones changed by CONFIG SET over
+    /// them, as the CONFIG REWRITE command. Settings given by the environment variables or the
+    /// flags are left out, so their secrets don't end up in the file. The file is replaced as a
+    /// whole, so its comments are not kept.
+    ///
+    /// # Returns
+    /// Returns `Ok(())` if the file is written, or the reason it can't be.
+    pub async fn rewrite(&self) -> Result<(), String> {
+        let _lock = self.inner.lock.lock().await;
+        let Some(file) = &self.inner.file else {
+            return Err("No configuration file, start the server with --config".to_string());
+        };
+        let file_settings = if file.exists() {
+            Settings::from_file(file)?
+        } else {
+            Settings::default()
+        };
+        let settings = file_settings.merge(self.inner.overrides.lock().unwrap().clone());
+        let content = toml::to_string_pretty(&settings).map_err(|e| e.to_string())?;
+        // Written next to the file and renamed, so a reader never sees half of it
+        let temporary = file.with_extension("tmp");
+        std::fs::write(&temporary, content)
+            .and_then(|_| std::fs::rename(&temporary, file))
+            .map_err(|e| format!("Could not write {}: {}", file.display(), e))?;
+        info!("Configuration is written to {}", file.display());
+        Ok(())
+    }
+
+    /// Loads the configuration from its sources with the given settings over the flags.
+    fn load(&self, overrides: Settings) -> Result<Configuration, String> {
+        let flags = self.inner.flags.clone().merge(overrides);
+        Configuration::load(self.inner.file.as_deref(), flags)
+    }
+
+    /// Swaps in a new configuration, keeping the running value of the settings read only at startup.
+    async fn apply(&self, state: &ServerState, mut config: Configuration) -> String {
+        let current = state.namespaces.config();
+
+        let (restart, changed): (Vec<String>, Vec<String>) = current
+            .changes(&config)
+            .into_iter()
+            .partition(|key| RESTART_REQUIRED.contains(&key.as_str()));
+        if !restart.is_empty() {
+            warn!(
+                "Configuration reload can't change {}, restart the server to apply them",
+                restart.join(", ")
+            );
+            config.host = current.host.clone();
+            config.port = current.port;
+            config.node_address = current.node_address.clone();
+            config.cluster_nodes = current.cluster_nodes.clone();
+            config.shard_nodes = current.shard_nodes.clone();
+            config.replica_of = current.replica_of.clone();
+            // Values already encrypted can only be read with the key they were written with
+            config.encryption_key = current.encryption_key.clone();
+            config.metrics_address = current.metrics_address.clone();
+            config.audit_log = current.audit_log.clone();
+            config.log_format = current.log_format.clone();
+            config.otlp_endpoint = current.otlp_endpoint.clone();
+        }
+
+        state.connections.reconfigure(&config);
+        state.rate_limiter.reconfigure(&config);
+        state.namespaces.reconfigure(Arc::new(config)).await;
+        info!("Configuration is applied, changed: {}", list(&changed));
+        format!(
+            "Changed({}), RestartRequired({})",
+            list(&changed),
+            list(&restart)
+        )
+    }
+
+    /// Reloads the configuration whenever the configuration file is modified or SIGHUP
+    /// is received, until the server shuts down.
+    ///
+    /// # Arguments
+    /// * `state` - The state of the server to apply the configuration to
+    pub fn watch(&self, state: ServerState) {
+        if let Some(file) = self.inner.file.clone() {
+            let reloader = self.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                let mut modified = modified_time(&file);
+                let mut interval = tokio::time::interval(WATCH_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = state.shutdown.wait() => return,
+                    }
+                    let current = modified_time(&file);
+                    if current != modified {
+                        modified = current;
+                        info!("Configuration file {} is modified", file.display());
+                        reloader.reload_logged(&state).await;
+                    }
+                }
+            });
+        }
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            let reloader = self.clone();
+            tokio::spawn(async move {
+                let mut hangup = match signal(SignalKind::hangup()) {
+                    Ok(hangup) => hangup,
+                    Err(e) => {
+                        error!("Could not listen for SIGHUP: {}", e);
+                        return;
+                    }
+                };
+                loop {
+                    tokio::select! {
+                        _ = hangup.recv() => {}
+                        _ = state.shutdown.wait() => return,
+                    }
+                    info!("SIGHUP received");
+                    reloader.reload_logged(&state).await;
+                }
+            });
+        }
+    }
+
+    async fn reload_logged(&self, state: &ServerState) {
+        if let Err(e) = self.reload(state).await {
+            error!(
+                "Configuration reload failed, keeping the running one: {}",
+                e
+            );
+        }
+    }
+}
+
+impl Default for Reloader {
+    fn default() -> Self {
+        Self::new(None, Settings::default())
+    }
+}
+
+/// Returns the modification time of a file, `None` if it can't be read
+fn modified_time(file: &Path) -> Option<SystemTime> {
+    std::fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+fn list(keys: &[String]) -> String {
+    if keys.is_empty() {
+        "none".to_string()
+    } else {
+        keys.join(", ")
+    }
+}
@@ -0,0 +1,376 @@
+//! Leader-follower replication of the Kiwi Store server.
+//!
+//! A follower connects to its leader and sends `SYNC`, after `AUTH` with its own admin password
+//! when one is configured, since the leader only serves admin sessions. The leader answers with a
+//! `FULLSYNC <offset>` line followed by the commands that rebuild every namespace, and then
+//! keeps streaming every later write. Data lines are formatted as `<offset> <namespace> <command>`.
+//! `PING <offset>` heartbeats let the follower know how far behind the leader it is.
+
+use crate::command::Command;
+use crate::handler::execute;
+use crate::namespace::Namespaces;
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, MutexGuard, broadcast};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Error message returned when a write is sent to a follower
+pub const READ_ONLY: &str = "READONLY You can't write against a read only replica";
+
+/// Number of writes a slow follower may fall behind before it has to resync
+const BACKLOG_SIZE: usize = 10_000;
+/// Interval of the heartbeats sent by the leader
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// A follower reconnects if it hears nothing from its leader for this long
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay between the reconnection attempts of a follower
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+/// Represents a write streamed to the followers
+struct Event {
+    offset: u64,
+    namespace: String,
+    command: String,
+}
+
+#[derive(Default)]
+/// Represents the state of the link between a follower and its leader
+struct LinkStatus {
+    up: AtomicBool,
+    applied_offset: AtomicU64,
+    leader_offset: AtomicU64,
+}
+
+/// Represents the leader this server follows
+struct Follower {
+    leader: String,
+    task: JoinHandle<()>,
+    status: Arc<LinkStatus>,
+}
+
+struct Inner {
+    events: broadcast::Sender<Event>,
+    offset: AtomicU64,
+    /// Serializes writes with their publication, so followers apply them in the same order
+    write_lock: Mutex<()>,
+    followers: AtomicUsize,
+    follower: StdMutex<Option<Follower>>,
+}
+
+#[derive(Clone)]
+/// Represents the replication role and state of the server, shared by all connections
+pub struct Replication {
+    inner: Arc<Inner>,
+}
+
+impl Replication {
+    /// Creates the replication state of a server that starts as a leader.
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(BACKLOG_SIZE);
+        Self {
+            inner: Arc::new(Inner {
+                events,
+                offset: AtomicU64::new(0),
+                write_lock: Mutex::new(()),
+                followers: AtomicUsize::new(0),
+                follower: StdMutex::new(None),
+            }),
+        }
+    }
+
+    /// Checks whether the server is following a leader and therefore read-only.
+    pub fn is_follower(&self) -> bool {
+        self.inner.follower.lock().unwrap().is_some()
+    }
+
+    /// Locks the write path. A write and its publication must happen under this guard.
+    pub async fn write_guard(&self) -> MutexGuard<'_, ()> {
+        self.inner.write_lock.lock().await
+    }
+
+    /// Streams a successful write to the connected followers.
+    ///
+    /// # Arguments
+    /// * `namespace` - The namespace the write was applied to
+    /// * `command` - The command line that reproduces the write
+    pub fn publish(&self, namespace: &str, command: &str) {
+        let offset = self.inner.offset.fetch_add(1, Ordering::SeqCst) + 1;
+        // Sending only fails when no follower is connected
+        let _ = self.inner.events.send(Event {
+            offset,
+            namespace: namespace.to_string(),
+            command: command.to_string(),
+        });
+    }
+
+    /// Returns the replication fields of INFO.
+    pub fn info(&self) -> Vec<(&'static str, Value)> {
+        match self.inner.follower.lock().unwrap().as_ref() {
+            Some(follower) => {
+                let applied = follower.status.applied_offset.load(Ordering::SeqCst);
+                let leader = follower.status.leader_offset.load(Ordering::SeqCst);
+                let link = if follower.status.up.load(Ordering::SeqCst) {
+                    "up"
+                } else {
+                    "down"
+                };
+                vec![
+                    ("role", json!("follower")),
+                    ("leader", json!(follower.leader)),
+                    ("link", json!(link)),
+                    ("offset", json!(applied)),
+                    ("lag", json!(leader.saturating_sub(applied))),
+                ]
+            }
+            None => vec![
+                ("role", json!("leader")),
+                ("offset", json!(self.inner.offset.load(Ordering::SeqCst))),
+                (
+                    "connected_followers",
+                    json!(self.inner.followers.load(Ordering::SeqCst)),
+                ),
+            ],
+        }
+    }
+
+    /// Starts following a leader, replacing the current leader if there is one.
+    /// The follower keeps reconnecting and resyncing until it is promoted.
+    ///
+    /// # Arguments
+    /// * `leader` - The address of the leader, in the format "IP:port"
+    /// * `namespaces` - The namespaces to replicate into
+    pub fn follow(&self, leader: &str, namespaces: Namespaces) {
+        let status = Arc::new(LinkStatus::default());
+        let task = tokio::spawn(run_follower(
+            leader.to_string(),
+            namespaces,
+            self.clone(),
+            Arc::clone(&status),
+        ));
+        let previous = self.inner.follower.lock().unwrap().replace(Follower {
+            leader: leader.to_string(),
+            task,
+            status,
+        });
+        if let Some(previous) = previous {
+            previous.task.abort();
+        }
+        info!("Following leader {}", leader);
+    }
+
+    /// Stops following the leader, making the server writable again.
+    pub fn promote(&self) {
+        if let Some(follower) = self.inner.follower.lock().unwrap().take() {
+            follower.task.abort();
+            info!("Stopped following leader {}", follower.leader);
+        }
+    }
+
+    /// Get replication statistics for the STATS command.
+    ///
+    /// # Returns
+    /// Returns the role with its offset, and the link state and lag for a follower.
+    pub fn stats(&self) -> String {
+        match self.inner.follower.lock().unwrap().as_ref() {
+            Some(follower) => {
+                let applied = follower.status.applied_offset.load(Ordering::SeqCst);
+                let leader = follower.status.leader_offset.load(Ordering::SeqCst);
+                let link = if follower.status.up.load(Ordering::SeqCst) {
+                    "up"
+                } else {
+                    "down"
+                };
+                format!(
+                    "Role(follower), Leader({}), Link({}), Offset({}), Lag({})",
+                    follower.leader,
+                    link,
+                    applied,
+                    leader.saturating_sub(applied)
+                )
+            }
+            None => format!(
+                "Role(leader), Offset({}), Followers({})",
+                self.inner.offset.load(Ordering::SeqCst),
+                self.inner.followers.load(Ordering::SeqCst)
+            ),
+        }
+    }
+
+    /// Serves a follower that sent `SYNC` on this connection.
+    /// Sends a full copy of every namespace and then streams the writes until the follower disconnects.
+    ///
+    /// # Arguments
+    /// * `stream` - The connection of the follower
+    /// * `namespaces` - The namespaces to replicate
+    pub async fn serve(&self, stream: TcpStream, namespaces: Namespaces) {
+        let address = stream
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let (offset, snapshot, mut receiver) = {
+            let _guard = self.write_guard().await;
+            let mut snapshot = Vec::new();
+            for namespace in namespaces.all().await {
+                for command in namespace.snapshot().await {
+                    snapshot.push(format!("{} {}", namespace.name, command));
+                }
+            }
+            let offset = self.inner.offset.load(Ordering::SeqCst);
+            (offset, snapshot, self.inner.events.subscribe())
+        };
+
+        info!(
+            "Follower {} syncing {} commands at offset {}",
+            address,
+            snapshot.len(),
+            offset
+        );
+        self.inner.followers.fetch_add(1, Ordering::SeqCst);
+
+        let mut writer = BufWriter::new(stream);
+        let result: tokio::io::Result<()> = async {
+            writer
+                .write_all(format!("FULLSYNC {}\n", offset).as_bytes())
+                .await?;
+            for line in snapshot {
+                writer
+                    .write_all(format!("{} {}\n", offset, line).as_bytes())
+                    .await?;
+            }
+            writer.flush().await?;
+
+            let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                let line = tokio::select! {
+                    event = receiver.recv() => match event {
+                        Ok(event) => format!("{} {} {}\n", event.offset, event.namespace, event.command),
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Follower {} fell {} writes behind, it has to resync", address, skipped);
+                            return Ok(());
+                        }
+                        Err(RecvError::Closed) => return Ok(()),
+                    },
+                    _ = heartbeat.tick() => format!("PING {}\n", self.inner.offset.load(Ordering::SeqCst)),
+                };
+                writer.write_all(line.as_bytes()).await?;
+                writer.flush().await?;
+            }
+        }
+        .await;
+
+        if let Err(e) = result {
+            warn!("Follower {} disconnected: {}", address, e);
+        }
+        self.inner.followers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps a follower connected to its leader, reconnecting after every failure.
+async fn run_follower(
+    leader: String,
+    namespaces: Namespaces,
+    replication: Replication,
+    status: Arc<LinkStatus>,
+) {
+    loop {
+        if let Err(e) = sync_with_leader(&leader, &namespaces, &replication, &status).await {
+            warn!("Replication link to {} is down: {}", leader, e);
+        }
+        status.up.store(false, Ordering::SeqCst);
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Runs one replication session: a full sync followed by the stream of writes.
+async fn sync_with_leader(
+    leader: &str,
+    namespaces: &Namespaces,
+    replication: &Replication,
+    status: &LinkStatus,
+) -> tokio::io::Result<()> {
+    let mut stream = TcpStream::connect(leader).await?;
+    // The leader streams its data only to admin sessions, a follower uses its own admin password
+    let password = namespaces.config().admin_password.clone();
+    if let Some(password) = &password {
+        stream
+            .write_all(format!("AUTH {}\n", password).as_bytes())
+            .await?;
+    }
+    stream.write_all(b"SYNC\n").await?;
+    let mut lines = BufReader::new(stream).lines();
+    if password.is_some() {
+        match lines.next_line().await? {
+            Some(reply) if reply == "OK" => {}
+            Some(reply) => return Err(tokio::io::Error::other(reply)),
+            None => return Err(tokio::io::Error::other("closed by the leader")),
+        }
+    }
+
+    loop {
+        let line = match tokio::time::timeout(LINK_TIMEOUT, lines.next_line()).await {
+            Ok(Ok(Some(line))) => line,
+            Ok(Ok(None)) => return Err(tokio::io::Error::other("closed by the leader")),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err(tokio::io::Error::other("leader stopped responding")),
+        };
+
+        let (head, rest) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        match head {
+            "ERROR:" => return Err(tokio::io::Error::other(rest.to_string())),
+            "FULLSYNC" => {
+                let offset = rest.trim().parse().unwrap_or(0);
+                for namespace in namespaces.all().await {
+                    namespace.store.clear().await;
+                }
+                status.applied_offset.store(offset, Ordering::SeqCst);
+                status.leader_offset.store(offset, Ordering::SeqCst);
+                status.up.store(true, Ordering::SeqCst);
+                info!("Full sync from {} at offset {}", leader, offset);
+            }
+            "PING" => {
+                let offset = rest.trim().parse().unwrap_or(0);
+                status.leader_offset.store(offset, Ordering::SeqCst);
+            }
+            offset => {
+                let Some((namespace, command)) = rest.split_once(' ') else {
+                    error!("Malformed replication line: {}", line);
+                    continue;
+                };
+                let offset = offset.parse().unwrap_or(0);
+                apply(namespaces, replication, namespace, command).await;
+                status.applied_offset.store(offset, Ordering::SeqCst);
+                status.leader_offset.fetch_max(offset, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// Applies a replicated write and passes it on to this server's own followers.
+async fn apply(namespaces: &Namespaces, replication: &Replication, namespace: &str, command: &str) {
+    let target = namespaces.get_or_create(namespace).await;
+    let _guard = replication.write_guard().await;
+    match Command::parse(command) {
+        Command::FlushNamespace { .. } => target.store.clear().await,
+        Command::Invalid(cmd) => {
+            error!("Invalid replicated command: {}", cmd);
+            return;
+        }
+        cmd => {
+            execute(cmd, &target.store, &target.config()).await;
+        }
+    }
+    replication.publish(namespace, command);
+}
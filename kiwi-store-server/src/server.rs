@@ -1,35 +1,118 @@
 use std::sync::Arc;
 
+use crate::audit::AuditLog;
+use crate::clients::Clients;
 use crate::config::Configuration;
+use crate::connections::Connections;
 /// Server module for the Kiwi Store application
-use crate::handler::handle_request;
-use crate::store::DataStore;
-use log::info;
+use crate::handler::handle_connection;
+use crate::metrics::{self, Metrics};
+use crate::monitor::Monitor;
+use crate::namespace::Namespaces;
+use crate::raft::Raft;
+use crate::rate_limit::RateLimiter;
+use crate::reload::Reloader;
+use crate::replication::Replication;
+use crate::sharding::Sharding;
+use crate::shutdown::{self, Shutdown};
+use crate::slowlog::SlowLog;
+use crate::state::ServerState;
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tracing::{info, warn};
 
 /// Runs the server, listening for incoming TCP connections on the specified address.
+/// Returns after a graceful shutdown triggered by SIGINT, SIGTERM or the SHUTDOWN command.
 ///
 /// # Arguments
 ///
-/// * `address` - The address to bind the server to, in the format "IP:port".
+/// * `config` - The validated configuration of the server.
+/// * `reloader` - The sources the configuration is reloaded from.
 ///
 /// # Returns
 ///
 /// Returns a `tokio::io::Result<()>` indicating success or failure of the operation.
-pub async fn run() -> tokio::io::Result<()> {
-    let config = Arc::new(Configuration::from_env());
+pub async fn run(config: Configuration, reloader: Reloader) -> tokio::io::Result<()> {
+    let config = Arc::new(config);
     info!("Configuration is loaded: {:?}", config);
     let listener = TcpListener::bind(config.get_listen_address()).await?;
-    let store = DataStore::new();
+    let namespaces = Namespaces::new(Arc::clone(&config));
+    let replication = Replication::new();
+    let raft = (!config.cluster_nodes.is_empty()).then(|| {
+        Raft::start(
+            &config.node_address,
+            &config.cluster_nodes,
+            namespaces.clone(),
+            replication.clone(),
+        )
+    });
+    let sharding = (!config.shard_nodes.is_empty())
+        .then(|| Sharding::new(&config.node_address, &config.shard_nodes));
+    let audit = match &config.audit_log {
+        Some(path) => AuditLog::open(path).map_err(tokio::io::Error::other)?,
+        None => AuditLog::default(),
+    };
+    let state = ServerState {
+        namespaces,
+        connections: Connections::new(&config),
+        rate_limiter: RateLimiter::new(&config),
+        shutdown: Shutdown::new(),
+        reloader,
+        metrics: Metrics::new(),
+        slowlog: SlowLog::new(),
+        clients: Clients::new(),
+        audit,
+        monitor: Monitor::new(),
+        replication,
+        raft,
+        sharding,
+    };
+    if let Some(leader) = &config.replica_of {
+        state.replication.follow(leader, state.namespaces.clone());
+    }
+
+    state.reloader.watch(state.clone());
+
+    if let Some(address) = &config.metrics_address {
+        let metrics_listener = TcpListener::bind(address).await?;
+        tokio::spawn(metrics::serve(metrics_listener, state.clone()));
+    }
+
+    let signal_shutdown = state.shutdown.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        signal_shutdown.trigger(true);
+    });
 
     loop {
-        let (stream, addr) = listener.accept().await?;
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = state.shutdown.wait() => break,
+        };
         info!("Client {} connected", addr);
-        let store = store.clone();
-        let config = Arc::clone(&config);
+        let state = state.clone();
 
         tokio::spawn(async move {
-            handle_request(stream, store, config).await;
+            handle_connection(stream, state).await;
         });
     }
+
+    drop(listener);
+    let timeout = Duration::from_millis(state.namespaces.config().shutdown_timeout_ms);
+    info!(
+        "Stopped accepting connections, draining for up to {:?}",
+        timeout
+    );
+    let open = state.connections.wait_idle(Instant::now() + timeout).await;
+    if open > 0 {
+        warn!("Closing {} connections that did not finish in time", open);
+    }
+    state.audit.flush().await;
+    if state.shutdown.should_save() {
+        // The store is in memory only, there is no persistence to flush yet
+        info!("Nothing to save, the data is kept in memory only");
+    }
+    info!("Server stopped");
+    Ok(())
 }
@@ -0,0 +1,358 @@
+//! Hash-slot sharding of the keys across several Kiwi Store servers.
+//!
+//! The key space is split into 16384 slots. A key belongs to the slot `CRC16(key) % 16384`,
+//! and every slot is owned by a single node. A node answers commands for keys it does not own
+//! with `MOVED <slot> <address>`. If a key contains a `{tag}`, only the tag is hashed, so keys
+//! sharing a tag are stored on the same node and can be used together by SINTER and SUNION.
+//!
+//! Slot ranges are moved between live nodes with `CLUSTER MIGRATE`. The data of the range is
+//! copied to the target node on a connection marked with `CLUSTER IMPORTING`, which may write
+//! keys of slots the target doesn't own yet. Only then does the target take the range, the new
+//! owner is announced to every node, and the keys are removed from the source. The nodes talk
+//! to each other as admin sessions, authenticating with their own admin password.
+
+use crate::auth::redact_secrets;
+use crate::command::Command;
+use crate::namespace::Namespaces;
+use crate::replication::Replication;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+/// Number of hash slots the key space is split into
+pub const SLOT_COUNT: u16 = 16384;
+
+/// Error message returned when the keys of a command belong to different slots
+pub const CROSS_SLOT: &str = "CROSSSLOT Keys in request don't hash to the same slot";
+
+/// Returns the hash slot of a key.
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::sharding::key_slot;
+///
+/// assert_eq!(key_slot("foo"), 12182);
+/// assert_eq!(key_slot("{user}.name"), key_slot("{user}.email"));
+/// ```
+pub fn key_slot(key: &str) -> u16 {
+    let bytes = key.as_bytes();
+    let hashed = match key.find('{') {
+        Some(open) => match key[open + 1..].find('}') {
+            Some(close) if close > 0 => &bytes[open + 1..open + 1 + close],
+            _ => bytes,
+        },
+        None => bytes,
+    };
+    crc16(hashed) % SLOT_COUNT
+}
+
+/// CRC16 with the XMODEM polynomial, as used for the hash slots of Redis Cluster
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+struct Inner {
+    node: String,
+    /// Every node known to this node, announcements of slot moves are sent to them
+    nodes: Mutex<Vec<String>>,
+    /// Owner of each slot, indexed by slot number
+    owners: Mutex<Vec<String>>,
+}
+
+#[derive(Clone)]
+/// Represents the slot map of a sharded server, shared by all connections
+pub struct Sharding {
+    inner: Arc<Inner>,
+}
+
+impl Sharding {
+    /// Creates the slot map, splitting the slots evenly between the nodes in the order they are listed.
+    /// Every node must be started with the same list, so they agree on the initial owners.
+    ///
+    /// # Arguments
+    /// * `node` - The address of this node
+    /// * `nodes` - The addresses of every node of the sharded cluster
+    pub fn new(node: &str, nodes: &[String]) -> Self {
+        let owners = (0..SLOT_COUNT as usize)
+            .map(|slot| nodes[slot * nodes.len() / SLOT_COUNT as usize].clone())
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                node: node.to_string(),
+                nodes: Mutex::new(nodes.to_vec()),
+                owners: Mutex::new(owners),
+            }),
+        }
+    }
+
+    /// Checks whether this node owns the keys of a command.
+    ///
+    /// # Returns
+    /// Returns `None` if the command can run on this node, or the response to send instead:
+    /// a `MOVED` redirect or a `CROSSSLOT` error.
+    pub fn route(&self, cmd: &Command) -> Option<String> {
+        let mut slots = cmd.keys().into_iter().map(key_slot);
+        let slot = slots.next()?;
+        if slots.any(|s| s != slot) {
+            return Some(format!("ERROR: {}\n", CROSS_SLOT));
+        }
+        let owner = self.owner(slot);
+        (owner != self.inner.node).then(|| format!("MOVED {} {}\n", slot, owner))
+    }
+
+    /// Returns the address of the node owning a slot.
+    pub fn owner(&self, slot: u16) -> String {
+        self.inner.owners.lock().unwrap()[slot as usize].clone()
+    }
+
+    /// Returns the slot map as ranges of consecutive slots with the same owner.
+    ///
+    /// # Returns
+    /// Returns `(start, end, owner)` tuples, ordered by slot and with inclusive bounds.
+    pub fn ranges(&self) -> Vec<(u16, u16, String)> {
+        let owners = self.inner.owners.lock().unwrap();
+        let mut ranges: Vec<(u16, u16, String)> = Vec::new();
+        for (slot, owner) in owners.iter().enumerate() {
+            match ranges.last_mut() {
+                Some((_, end, last)) if last == owner => *end = slot as u16,
+                _ => ranges.push((slot as u16, slot as u16, owner.clone())),
+            }
+        }
+        ranges
+    }
+
+    /// Assigns a range of slots to a node.
+    ///
+    /// # Arguments
+    /// * `start` - The first slot of the range
+    /// * `end` - The last slot of the range, inclusive
+    /// * `node` - The address of the new owner
+    pub fn assign(&self, start: u16, end: u16, node: &str) {
+        {
+            let mut nodes = self.inner.nodes.lock().unwrap();
+            if !nodes.iter().any(|n| n == node) {
+                nodes.push(node.to_string());
+            }
+        }
+        let mut owners = self.inner.owners.lock().unwrap();
+        for owner in &mut owners[start as usize..=end as usize] {
+            *owner = node.to_string();
+        }
+        info!("Slots {}-{} are assigned to {}", start, end, node);
+    }
+
+    /// Get sharding statistics for the STATS command.
+    pub fn stats(&self) -> String {
+        let owners = self.inner.owners.lock().unwrap();
+        let owned = owners.iter().filter(|o| **o == self.inner.node).count();
+        format!("Slots({}/{})", owned, SLOT_COUNT)
+    }
+
+    /// Moves a range of slots owned by this node to another live node.
+    /// Writes are paused while the keys of the range are copied, then the target takes the range,
+    /// the new owner is announced to every node and the keys are removed from this node.
+    ///
+    /// # Arguments
+    /// * `start` - The first slot of the range
+    /// * `end` - The last slot of the range, inclusive
+    /// * `target` - The address of the node receiving the range
+    /// * `namespaces` - The namespaces holding the keys to move
+    /// * `replication` - Removed keys are also removed from the followers of this node
+    ///
+    /// # Returns
+    /// Returns the number of moved keys, or an error if the range can't be moved.
+    pub async fn migrate(
+        &self,
+        start: u16,
+        end: u16,
+        target: &str,
+        namespaces: &Namespaces,
+        replication: &Replication,
+    ) -> Result<usize, String> {
+        if target == self.inner.node {
+            return Err("Slots can't be migrated to the node that owns them".to_string());
+        }
+        if self
+            .ranges()
+            .iter()
+            .any(|(s, e, owner)| *s <= end && *e >= start && *owner != self.inner.node)
+        {
+            return Err(format!(
+                "Slots {}-{} are not all owned by this node",
+                start, end
+            ));
+        }
+
+        let _guard = replication.write_guard().await;
+        let password = namespaces.config().admin_password.clone();
+        let announcement = format!("CLUSTER SETSLOT {} {} {}", start, end, target);
+        let mut connection = NodeConnection::connect(target, password.as_deref()).await?;
+        // The target keeps redirecting the range to this node until the copy is complete
+        connection.call("CLUSTER IMPORTING").await?;
+        let mut moved = Vec::new();
+        let copied = match copy_range(&mut connection, start, end, namespaces, &mut moved).await {
+            Ok(()) => connection.call(&announcement).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = copied {
+            // The target doesn't serve the range, the partial copy is only removed from it
+            if let Err(e) = remove_copies(&mut connection, &moved).await {
+                warn!(
+                    "Could not remove the copied keys of slots {}-{} from {}: {}",
+                    start, end, target, e
+                );
+            }
+            return Err(e);
+        }
+
+        self.assign(start, end, target);
+        let nodes = self.inner.nodes.lock().unwrap().clone();
+        for node in nodes
+            .iter()
+            .filter(|n| **n != self.inner.node && *n != target)
+        {
+            // Nodes that miss the announcement keep redirecting to this node, which redirects again
+            if let Err(e) = announce(node, &announcement, password.as_deref()).await {
+                warn!(
+                    "Could not announce slots {}-{} to {}: {}",
+                    start, end, node, e
+                );
+            }
+        }
+
+        for (name, key) in &moved {
+            if let Some(namespace) = namespaces.get(name).await {
+                namespace.store.remove(key).await;
+                replication.publish(name, &format!("REMOVE {}", key));
+            }
+        }
+        info!(
+            "Migrated slots {}-{} with {} keys to {}",
+            start,
+            end,
+            moved.len(),
+            target
+        );
+        Ok(moved.len())
+    }
+}
+
+/// Copies the keys of a slot range to the target node.
+/// The namespace and name of every copied key is added to `moved`, also when the copy fails.
+async fn copy_range(
+    connection: &mut NodeConnection,
+    start: u16,
+    end: u16,
+    namespaces: &Namespaces,
+    moved: &mut Vec<(String, String)>,
+) -> Result<(), String> {
+    for namespace in namespaces.all().await {
+        let commands: Vec<(String, String)> = namespace
+            .snapshot()
+            .await
+            .into_iter()
+            .filter_map(|command| {
+                let key = command.split_whitespace().nth(1)?.to_string();
+                let slot = key_slot(&key);
+                (slot >= start && slot <= end).then_some((key, command))
+            })
+            .collect();
+        if commands.is_empty() {
+            continue;
+        }
+        connection
+            .call(&format!("SELECT {}", namespace.name))
+            .await?;
+        for (key, command) in commands {
+            // The snapshot lists the items of a key one after another
+            if moved
+                .last()
+                .is_none_or(|(n, k)| *n != namespace.name || *k != key)
+            {
+                moved.push((namespace.name.clone(), key));
+            }
+            connection.call(&command).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the keys copied by a failed migration from the target node.
+async fn remove_copies(
+    connection: &mut NodeConnection,
+    moved: &[(String, String)],
+) -> Result<(), String> {
+    let mut selected = None;
+    for (name, key) in moved {
+        if selected != Some(name) {
+            connection.call(&format!("SELECT {}", name)).await?;
+            selected = Some(name);
+        }
+        connection.call(&format!("REMOVE {}", key)).await?;
+    }
+    Ok(())
+}
+
+/// Sends a slot announcement to a node.
+async fn announce(node: &str, announcement: &str, password: Option<&str>) -> Result<(), String> {
+    NodeConnection::connect(node, password)
+        .await?
+        .call(announcement)
+        .await
+}
+
+/// Represents a connection to another node, sending one command at a time
+struct NodeConnection {
+    address: String,
+    stream: BufReader<TcpStream>,
+}
+
+impl NodeConnection {
+    /// Opens a connection to a node, as an admin session when an admin password is given.
+    async fn connect(address: &str, password: Option<&str>) -> Result<Self, String> {
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|e| format!("Could not connect to {}: {}", address, e))?;
+        let mut connection = Self {
+            address: address.to_string(),
+            stream: BufReader::new(stream),
+        };
+        if let Some(password) = password {
+            connection.call(&format!("AUTH {}", password)).await?;
+        }
+        Ok(connection)
+    }
+
+    /// Sends a write command and checks that the node accepted it.
+    async fn call(&mut self, command: &str) -> Result<(), String> {
+        let error = |e: std::io::Error| format!("Connection to {} failed: {}", self.address, e);
+        self.stream
+            .get_mut()
+            .write_all(format!("{}\n", command).as_bytes())
+            .await
+            .map_err(error)?;
+        let mut response = String::new();
+        self.stream.read_line(&mut response).await.map_err(error)?;
+        if response.is_empty() || response.starts_with("ERROR") || response.starts_with("MOVED") {
+            return Err(format!(
+                "{} refused '{}': {}",
+                self.address,
+                redact_secrets(command),
+                response.trim()
+            ));
+        }
+        Ok(())
+    }
+}
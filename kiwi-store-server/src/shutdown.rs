@@ -0,0 +1,91 @@
+//! Graceful shutdown of the Kiwi Store server.
+//!
+//! A shutdown is triggered by SIGINT, SIGTERM or the SHUTDOWN command. The server stops
+//! accepting connections, idle connections are closed, and connections running a command
+//! are closed once its response is sent. The server waits for them up to the configured
+//! deadline before it exits.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::info;
+
+#[derive(Clone)]
+/// Represents the shutdown state of the server, shared by all connections
+pub struct Shutdown {
+    /// `None` while the server runs, then whether the data should be saved before exiting
+    sender: Arc<watch::Sender<Option<bool>>>,
+}
+
+impl Shutdown {
+    /// Creates the shutdown state of a running server.
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(None);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Starts the shutdown. Only the first trigger counts, later ones are ignored.
+    ///
+    /// # Arguments
+    /// * `save` - Whether the data should be saved before the server exits
+    pub fn trigger(&self, save: bool) {
+        let triggered = self.sender.send_if_modified(|state| {
+            if state.is_none() {
+                *state = Some(save);
+                true
+            } else {
+                false
+            }
+        });
+        if triggered {
+            info!("Shutdown requested (save: {})", save);
+        }
+    }
+
+    /// Checks whether the shutdown has started.
+    pub fn is_triggered(&self) -> bool {
+        self.sender.borrow().is_some()
+    }
+
+    /// Checks whether the data should be saved before the server exits.
+    pub fn should_save(&self) -> bool {
+        self.sender.borrow().unwrap_or(false)
+    }
+
+    /// Waits until the shutdown starts.
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as `self`, so waiting can't fail
+        let _ = receiver.wait_for(|state| state.is_some()).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waits for SIGINT, or SIGTERM on Unix systems.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
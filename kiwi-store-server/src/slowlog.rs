@@ -0,0 +1,124 @@
+//! Slow command log of the Kiwi Store server.
+//!
+//! Commands that take longer than `slowlog_threshold_us` are kept in a ring buffer holding the
+//! last `slowlog_max_len` of them, and read with `SLOWLOG GET`, newest first. The command line is
+//! logged the way the mode of its namespace logs it, so redacted values stay out of the slow log,
+//! and passwords and keys are always left out.
+
+use crate::auth::redact_secrets;
+use crate::config::Configuration;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest command line kept in an entry, longer lines are cut
+const MAX_COMMAND_LENGTH: usize = 128;
+
+/// Represents a command recorded in the slow log
+pub struct SlowEntry {
+    /// Unique and increasing number of the entry
+    pub id: u64,
+    /// Unix time the command finished at, in seconds
+    pub timestamp: u64,
+    pub duration: Duration,
+    pub client: SocketAddr,
+    /// The command line, with the values redacted if the mode redacts them
+    pub command: String,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: VecDeque<SlowEntry>,
+    next_id: u64,
+}
+
+#[derive(Clone, Default)]
+/// Represents the slow log of the server, shared by all connections
+pub struct SlowLog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl SlowLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a command if it took longer than the threshold of the configuration.
+    /// The oldest entries are dropped once the log holds `slowlog_max_len` of them.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration of the namespace the command ran in
+    /// * `client` - The address of the client that sent the command
+    /// * `line` - The command line
+    /// * `elapsed` - How long the command took
+    pub fn record(
+        &self,
+        config: &Configuration,
+        client: SocketAddr,
+        line: &str,
+        elapsed: Duration,
+    ) {
+        if config.slowlog_max_len == 0
+            || elapsed < Duration::from_micros(config.slowlog_threshold_us)
+        {
+            return;
+        }
+        let mut command = config.profile.redact(&redact_secrets(line));
+        if let Some((cut, _)) = command.char_indices().nth(MAX_COMMAND_LENGTH) {
+            let more = command.len() - cut;
+            command.truncate(cut);
+            command.push_str(&format!("... ({} more bytes)", more));
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_secs())
+            .unwrap_or_default();
+
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.entries.push_front(SlowEntry {
+            id,
+            timestamp,
+            duration: elapsed,
+            client,
+            command,
+        });
+        inner.entries.truncate(config.slowlog_max_len);
+    }
+
+    /// Returns the latest entries as lines, newest first.
+    /// A line is `<id> <timestamp> <duration>us <client> <command>`.
+    ///
+    /// # Arguments
+    /// * `count` - The maximum number of entries to return
+    pub fn get(&self, count: usize) -> Vec<String> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .entries
+            .iter()
+            .take(count)
+            .map(|entry| {
+                format!(
+                    "{} {} {}us {} {}",
+                    entry.id,
+                    entry.timestamp,
+                    entry.duration.as_micros(),
+                    entry.client,
+                    entry.command
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the number of entries in the log.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Removes every entry, the ids keep increasing.
+    pub fn reset(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+}
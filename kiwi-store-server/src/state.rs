@@ -0,0 +1,35 @@
+//! Shared state of the Kiwi Store server
+
+use crate::audit::AuditLog;
+use crate::clients::Clients;
+use crate::connections::Connections;
+use crate::metrics::Metrics;
+use crate::monitor::Monitor;
+use crate::namespace::Namespaces;
+use crate::raft::Raft;
+use crate::rate_limit::RateLimiter;
+use crate::reload::Reloader;
+use crate::replication::Replication;
+use crate::sharding::Sharding;
+use crate::shutdown::Shutdown;
+use crate::slowlog::SlowLog;
+
+#[derive(Clone)]
+/// Represents the state shared by every connection of the server
+pub struct ServerState {
+    pub namespaces: Namespaces,
+    pub connections: Connections,
+    pub clients: Clients,
+    pub audit: AuditLog,
+    pub rate_limiter: RateLimiter,
+    pub shutdown: Shutdown,
+    pub reloader: Reloader,
+    pub metrics: Metrics,
+    pub slowlog: SlowLog,
+    pub monitor: Monitor,
+    pub replication: Replication,
+    /// Consensus of the cluster this server belongs to, if it runs as a cluster node
+    pub raft: Option<Raft>,
+    /// Slot map of the sharded cluster this server belongs to, if the key space is split between nodes
+    pub sharding: Option<Sharding>,
+}
@@ -1,12 +1,101 @@
-use std::collections::HashMap;
+use crate::store::history::{Histories, Retention, Version};
+use crate::store::json_path;
+use crate::store::pattern;
+use crate::store::sorted_set::SortedSet;
+use crate::store::value::{ListEnd, Value, WRONG_TYPE};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::mem::size_of;
+use std::ops::Bound::{Excluded, Unbounded};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{Mutex, oneshot};
+
+/// Represents the outcome of [`DataStore::pop_or_wait`]
+pub enum Popped {
+    Value(String),
+    /// The list was empty, the receiver gets the next value pushed to it
+    Waiting(oneshot::Receiver<String>),
+}
 
 #[derive(Clone)]
 #[allow(dead_code)]
 /// Represents a simple in-memory key-value store
 pub struct DataStore {
-    context: Arc<Mutex<HashMap<String, String>>>,
+    /// Entries ordered by key, so a SCAN can resume right after the last key it returned.
+    context: Arc<Mutex<BTreeMap<String, Value>>>,
+    /// Clients blocked on an empty list, in the order they started waiting.
+    /// Always locked after `context` to keep the lock order consistent.
+    waiters: Arc<Mutex<HashMap<String, VecDeque<oneshot::Sender<String>>>>>,
+    /// Write order and expiry of the keys, used by the eviction and the TTL of the modes.
+    /// Always locked after `context`.
+    lifetimes: Arc<Mutex<Lifetimes>>,
+    /// Number of keys removed by the eviction since the store was created
+    evicted: Arc<AtomicU64>,
+    /// Number of keys removed since their time to live passed
+    expired: Arc<AtomicU64>,
+    /// Number of values a push handed straight to a blocked client since the store was created
+    handed_over: Arc<AtomicU64>,
+    /// Versions of the string values, kept by the retention of the configuration.
+    /// Always locked after `context` and `lifetimes`.
+    history: Arc<Mutex<Histories>>,
+}
+
+/// Approximate bytes of an entry besides its content: the key and value headers
+/// and its share of the tree nodes
+const ENTRY_OVERHEAD: usize = size_of::<String>() + size_of::<Value>() + 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// Represents the memory used by a data store, in bytes
+pub struct MemoryUsage {
+    /// Bytes of the key names
+    pub keys: usize,
+    /// Bytes of the values, see `Value::size`
+    pub values: usize,
+    /// Bytes used besides the content, see `Value::overhead`
+    pub overhead: usize,
+    /// Bytes of the versions kept in the history of the keys
+    pub history: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.keys + self.values + self.overhead + self.history
+    }
+}
+
+/// Formats a number of bytes for people, e.g. `1.20 KB`
+pub fn human_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.2} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.2} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[derive(Default)]
+/// Represents when the keys were last written and when they expire.
+/// Entries of keys removed by a command are left behind and skipped when they come up.
+struct Lifetimes {
+    /// Increased on every write, so the oldest write has the lowest number
+    counter: u64,
+    written: HashMap<String, u64>,
+    order: BTreeMap<u64, String>,
+    expiry: HashMap<String, Instant>,
+    deadlines: BTreeSet<(Instant, String)>,
+}
+
+impl Lifetimes {
+    fn forget(&mut self, key: &str) {
+        if let Some(number) = self.written.remove(key) {
+            self.order.remove(&number);
+        }
+        if let Some(deadline) = self.expiry.remove(key) {
+            self.deadlines.remove(&(deadline, key.to_string()));
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -24,7 +113,13 @@ impl DataStore {
     /// ```
     pub fn new() -> Self {
         DataStore {
-            context: Arc::new(Mutex::new(HashMap::new())),
+            context: Arc::new(Mutex::new(BTreeMap::new())),
+            waiters: Arc::new(Mutex::new(HashMap::new())),
+            lifetimes: Arc::new(Mutex::new(Lifetimes::default())),
+            evicted: Arc::new(AtomicU64::new(0)),
+            expired: Arc::new(AtomicU64::new(0)),
+            handed_over: Arc::new(AtomicU64::new(0)),
+            history: Arc::new(Mutex::new(Histories::default())),
         }
     }
 
@@ -43,7 +138,7 @@ impl DataStore {
     /// ```
     pub async fn set(&self, key: &str, value: &str) {
         let mut context = self.context.lock().await;
-        context.insert(key.to_string(), value.to_string());
+        context.insert(key.to_string(), Value::Text(value.to_string()));
     }
 
     /// Remove a key from the store.
@@ -75,7 +170,8 @@ impl DataStore {
     /// * `key` - Key variable
     ///
     /// # Returns
-    /// Returns `Some(value)` if the key exists, or `None` if it does not.
+    /// Returns `Ok(Some(value))` if the key exists, `Ok(None)` if it does not,
+    /// or an error if the key holds a value other than a string.
     ///
     /// # Example
     /// ```rust
@@ -85,11 +181,15 @@ impl DataStore {
     /// store.set("UseHttps", "Off").await;
     /// let value = store.get("UseHttps").await;
     ///
-    /// assert_eq!(value, Some("Off".to_string()));
+    /// assert_eq!(value, Ok(Some("Off".to_string())));
     /// ```
-    pub async fn get(&self, key: &str) -> Option<String> {
+    pub async fn get(&self, key: &str) -> Result<Option<String>, String> {
         let context = self.context.lock().await;
-        context.get(key).cloned()
+        match context.get(key) {
+            Some(Value::Text(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+            None => Ok(None),
+        }
     }
 
     /// Get all keys in the store.
@@ -112,6 +212,132 @@ impl DataStore {
         context.keys().cloned().collect()
     }
 
+    /// Get all keys matching a glob pattern.
+    ///
+    /// # Arguments
+    /// * `pattern` - Glob pattern supporting `*`, `?` and `[...]`
+    ///
+    /// # Returns
+    /// Returns the matching keys in order.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// store.set("DbConn", "localhost").await;
+    /// store.set("UseHttps", "Off").await;
+    /// let keys = store.keys_matching("Db*").await;
+    ///
+    /// assert_eq!(keys, vec!["DbConn".to_string()]);
+    /// ```
+    pub async fn keys_matching(&self, pattern: &str) -> Vec<String> {
+        let context = self.context.lock().await;
+        context
+            .keys()
+            .filter(|key| pattern::matches(pattern, key))
+            .cloned()
+            .collect()
+    }
+
+    /// Walk the keys incrementally, one page per call.
+    /// The lock is only held while a single page is collected. Keys are visited in order
+    /// and the cursor is the last key visited, so every key that exists for the whole walk
+    /// is returned exactly once, while keys added or removed during the walk may or may not be.
+    ///
+    /// # Arguments
+    /// * `cursor` - `"0"` to start a new walk, or the cursor returned by the previous call
+    /// * `pattern` - Optional glob pattern the returned keys must match
+    /// * `count` - Number of keys to visit in this call
+    ///
+    /// # Returns
+    /// Returns the cursor for the next call, `"0"` once the walk is complete, with the keys
+    /// of this page. Returns an error if the cursor is malformed.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// store.set("DbConn", "localhost").await;
+    /// store.set("UseHttps", "Off").await;
+    /// let (cursor, keys) = store.scan("0", None, 1).await.unwrap();
+    /// assert_eq!(keys, vec!["DbConn".to_string()]);
+    /// let (cursor, keys) = store.scan(&cursor, None, 1).await.unwrap();
+    /// assert_eq!(keys, vec!["UseHttps".to_string()]);
+    /// assert_eq!(cursor, "0");
+    /// ```
+    pub async fn scan(
+        &self,
+        cursor: &str,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(String, Vec<String>), String> {
+        self.page(cursor, pattern, count, |key, _| key.clone())
+            .await
+    }
+
+    /// Walk the keys with their values incrementally, one page per call, the way [`scan`](Self::scan) does.
+    ///
+    /// # Returns
+    /// Returns the cursor for the next call, `"0"` once the walk is complete, with copies of the
+    /// keys and values of this page. Returns an error if the cursor is malformed.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use kiwi_store_server::store::value::Value;
+    ///
+    /// let store = DataStore::new();
+    /// store.set("UseHttps", "Off").await;
+    /// let (cursor, entries) = store.entries("0", None, 100).await.unwrap();
+    /// assert_eq!(entries, vec![("UseHttps".to_string(), Value::Text("Off".to_string()))]);
+    /// assert_eq!(cursor, "0");
+    /// ```
+    pub async fn entries(
+        &self,
+        cursor: &str,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(String, Vec<(String, Value)>), String> {
+        self.page(cursor, pattern, count, |key, value| {
+            (key.clone(), value.clone())
+        })
+        .await
+    }
+
+    async fn page<T>(
+        &self,
+        cursor: &str,
+        pattern: Option<&str>,
+        count: usize,
+        collect: impl Fn(&String, &Value) -> T,
+    ) -> Result<(String, Vec<T>), String> {
+        let start = decode_cursor(cursor)?;
+        let context = self.context.lock().await;
+        let range = match &start {
+            Some(last) => context.range::<String, _>((Excluded(last), Unbounded)),
+            None => context.range::<String, _>(..),
+        };
+
+        let mut visited = 0;
+        let mut last = None;
+        let mut items = Vec::new();
+        for (key, value) in range.take(count.max(1)) {
+            visited += 1;
+            last = Some(key);
+            if pattern.is_none_or(|p| pattern::matches(p, key)) {
+                items.push(collect(key, value));
+            }
+        }
+
+        let next = match last {
+            Some(key) if visited == count.max(1) => encode_cursor(key),
+            _ => "0".to_string(),
+        };
+        Ok((next, items))
+    }
+
     /// Get statistics about the store.
     /// This includes the number of keys and the total size of the store in a human-readable format.
     ///
@@ -128,34 +354,1004 @@ impl DataStore {
     /// assert!(stats.contains("Size:"));
     /// ```
     pub async fn stats(&self) -> String {
+        let usage = self.memory_usage().await;
+        format!(
+            "Keys({}), Size({})",
+            self.len().await,
+            human_bytes(usage.total())
+        )
+    }
+
+    /// Get the contents of the store as the commands that rebuild it.
+    /// Replicas replay these commands to catch up with a full copy of the store.
+    ///
+    /// # Returns
+    /// Returns one command line per value, or per item for lists and sets.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// store.set("UseHttps", "Off").await;
+    /// let commands = store.snapshot().await;
+    ///
+    /// assert_eq!(commands, vec!["SET UseHttps Off".to_string()]);
+    /// ```
+    pub async fn snapshot(&self) -> Vec<String> {
+        let context = self.context.lock().await;
+        let mut commands = Vec::new();
+        for (key, value) in context.iter() {
+            match value {
+                Value::Text(text) => commands.push(format!("SET {} {}", key, text)),
+                Value::List(items) => {
+                    commands.extend(items.iter().map(|i| format!("RPUSH {} {}", key, i)))
+                }
+                Value::Set(members) => {
+                    commands.extend(members.iter().map(|m| format!("SADD {} {}", key, m)))
+                }
+                Value::SortedSet(set) => commands.extend(
+                    set.range(0, -1)
+                        .into_iter()
+                        .map(|(m, score)| format!("ZADD {} {} {}", key, score, m)),
+                ),
+                Value::Json(document) => commands.push(format!("JSON.SET {} $ {}", key, document)),
+            }
+        }
+        commands
+    }
+
+    /// Remove every key from the store.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// store.set("UseHttps", "Off").await;
+    /// store.clear().await;
+    ///
+    /// assert!(store.is_empty().await);
+    /// ```
+    pub async fn clear(&self) {
+        let mut context = self.context.lock().await;
+        context.clear();
+        *self.lifetimes.lock().await = Lifetimes::default();
+        self.history.lock().await.clear();
+    }
+
+    pub async fn is_empty(&self) -> bool {
         let context = self.context.lock().await;
-        if context.is_empty() {
-            return "Keys(0), Size(0 B)".to_string();
+        context.is_empty()
+    }
+
+    /// Get the number of keys in the store.
+    pub async fn len(&self) -> usize {
+        let context = self.context.lock().await;
+        context.len()
+    }
+
+    /// Get the number of values a push handed straight to a blocked client since the store
+    /// was created.
+    pub fn handed_over(&self) -> u64 {
+        self.handed_over.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of keys evicted and expired since the store was created.
+    pub fn removal_counts(&self) -> (u64, u64) {
+        (
+            self.evicted.load(Ordering::Relaxed),
+            self.expired.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Get the memory used by the keys, the values and the bookkeeping of the entries.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// store.set("UseHttps", "Off").await;
+    /// let usage = store.memory_usage().await;
+    ///
+    /// assert_eq!((usage.keys, usage.values), (8, 3));
+    /// assert!(usage.total() > 11);
+    /// ```
+    pub async fn memory_usage(&self) -> MemoryUsage {
+        let context = self.context.lock().await;
+        let mut usage = MemoryUsage::default();
+        for (key, value) in context.iter() {
+            usage.keys += key.len();
+            usage.values += value.size();
+            usage.overhead += ENTRY_OVERHEAD + key.capacity() - key.len() + value.overhead();
+        }
+        usage.history = self.history.lock().await.size();
+        usage
+    }
+
+    /// Get the number of keys that expire after their time to live.
+    pub async fn expiring(&self) -> usize {
+        let context = self.context.lock().await;
+        let lifetimes = self.lifetimes.lock().await;
+        lifetimes
+            .expiry
+            .keys()
+            .filter(|key| context.contains_key(*key))
+            .count()
+    }
+
+    /// Check whether a key exists, whatever value it holds.
+    pub async fn contains(&self, key: &str) -> bool {
+        let context = self.context.lock().await;
+        context.contains_key(key)
+    }
+
+    /// Record a write of a key, for the eviction of the oldest keys and the expiry of the keys.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `ttl` - How long the key lives from now, `None` for a key that never expires
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use std::time::Duration;
+    ///
+    /// let store = DataStore::new();
+    /// store.set("Session", "42").await;
+    /// store.touch("Session", Some(Duration::ZERO)).await;
+    ///
+    /// assert_eq!(store.purge_expired().await, vec!["Session".to_string()]);
+    /// ```
+    pub async fn touch(&self, key: &str, ttl: Option<Duration>) {
+        let mut lifetimes = self.lifetimes.lock().await;
+        lifetimes.forget(key);
+        lifetimes.counter += 1;
+        let number = lifetimes.counter;
+        lifetimes.written.insert(key.to_string(), number);
+        lifetimes.order.insert(number, key.to_string());
+        if let Some(ttl) = ttl {
+            let deadline = Instant::now() + ttl;
+            lifetimes.expiry.insert(key.to_string(), deadline);
+            lifetimes.deadlines.insert((deadline, key.to_string()));
+        }
+    }
+
+    /// Remove the keys whose time to live has passed.
+    ///
+    /// # Returns
+    /// Returns the removed keys.
+    pub async fn purge_expired(&self) -> Vec<String> {
+        let mut context = self.context.lock().await;
+        let mut lifetimes = self.lifetimes.lock().await;
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        while let Some((deadline, key)) = lifetimes.deadlines.first().cloned()
+            && deadline <= now
+        {
+            lifetimes.forget(&key);
+            if context.remove(&key).is_some() {
+                expired.push(key);
+            }
+        }
+        self.expired
+            .fetch_add(expired.len() as u64, Ordering::Relaxed);
+        expired
+    }
+
+    /// Remove the key written the longest time ago, along with its history.
+    ///
+    /// # Returns
+    /// Returns the removed key, `None` if no written key is left.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// for key in ["First", "Second"] {
+    ///     store.set(key, "1").await;
+    ///     store.touch(key, None).await;
+    /// }
+    ///
+    /// assert_eq!(store.evict_oldest().await, Some("First".to_string()));
+    /// ```
+    pub async fn evict_oldest(&self) -> Option<String> {
+        let mut context = self.context.lock().await;
+        let mut lifetimes = self.lifetimes.lock().await;
+        while let Some((_, key)) = lifetimes.order.first_key_value() {
+            let key = key.clone();
+            lifetimes.forget(&key);
+            if context.remove(&key).is_some() {
+                self.history.lock().await.forget(&key);
+                self.evicted.fetch_add(1, Ordering::Relaxed);
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    /// Record the current value of a key as a new version in its history.
+    /// A string value is recorded as it is, a missing key as a removal if the key has a history.
+    /// Keys holding other data types are not versioned.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `retention` - How many versions are kept and for how long
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use kiwi_store_server::store::history::Retention;
+    ///
+    /// let store = DataStore::new();
+    /// let retention = Retention { max_versions: 10, max_age: None };
+    /// store.set("Theme", "Dark").await;
+    /// store.record_version("Theme", &retention).await;
+    /// store.remove("Theme").await;
+    /// store.record_version("Theme", &retention).await;
+    ///
+    /// let versions = store.history("Theme", &retention).await;
+    /// assert_eq!(versions[0].value, None);
+    /// assert_eq!(versions[1].value, Some("Dark".to_string()));
+    /// ```
+    pub async fn record_version(&self, key: &str, retention: &Retention) {
+        if retention.max_versions == 0 {
+            return;
+        }
+        let context = self.context.lock().await;
+        let mut history = self.history.lock().await;
+        let value = match context.get(key) {
+            Some(Value::Text(value)) => Some(value.clone()),
+            Some(_) => return,
+            None if !history.contains(key) => return,
+            None => None,
+        };
+        history.push(key, value, retention);
+    }
+
+    /// Get the versions of a key, newest first.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `retention` - The retention the versions past it are dropped with first
+    pub async fn history(&self, key: &str, retention: &Retention) -> Vec<Version> {
+        let mut history = self.history.lock().await;
+        match history.kept(key, retention) {
+            Some(versions) => versions.versions().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get a version of a key by its number.
+    ///
+    /// # Returns
+    /// Returns the version, `None` if it was dropped or never existed.
+    pub async fn version(&self, key: &str, number: u64, retention: &Retention) -> Option<Version> {
+        let mut history = self.history.lock().await;
+        history.kept(key, retention)?.version(number).cloned()
+    }
+
+    /// Get the version a key held at a point in time.
+    ///
+    /// # Returns
+    /// Returns `Ok(None)` if the key had no version yet at that time, or an error if the versions
+    /// of that time were already dropped by the retention.
+    pub async fn version_at(
+        &self,
+        key: &str,
+        time: SystemTime,
+        retention: &Retention,
+    ) -> Result<Option<Version>, String> {
+        let mut history = self.history.lock().await;
+        match history.kept(key, retention) {
+            Some(versions) => Ok(versions.at(time)?.cloned()),
+            None => Ok(None),
+        }
+    }
+
+    /// Push a value to one end of the list stored at key.
+    /// If clients are blocked waiting on the key, the value is handed to the
+    /// one that has been waiting the longest instead of staying in the list.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `value` - Value to push
+    /// * `end` - The end of the list to push to
+    /// * `max_length` - Maximum number of items the list may hold
+    ///
+    /// # Returns
+    /// Returns the length of the list after the push, or an error if the key holds
+    /// another data type or the list is already full.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use kiwi_store_server::store::value::ListEnd;
+    ///
+    /// let store = DataStore::new();
+    /// let length = store.push("Jobs", "resize", ListEnd::Right, 100).await;
+    ///
+    /// assert_eq!(length, Ok(1));
+    /// ```
+    pub async fn push(
+        &self,
+        key: &str,
+        value: &str,
+        end: ListEnd,
+        max_length: usize,
+    ) -> Result<usize, String> {
+        let mut context = self.context.lock().await;
+        let entry = context
+            .entry(key.to_string())
+            .or_insert_with(|| Value::List(VecDeque::new()));
+        let Value::List(items) = entry else {
+            return Err(WRONG_TYPE.to_string());
+        };
+        if items.len() >= max_length {
+            return Err(format!("List exceeds maximum length: {}", max_length));
+        }
+        match end {
+            ListEnd::Left => items.push_front(value.to_string()),
+            ListEnd::Right => items.push_back(value.to_string()),
+        }
+        let length = items.len();
+
+        let mut waiters = self.waiters.lock().await;
+        if let Some(queue) = waiters.get_mut(key) {
+            while let Some(item) = items.pop_front() {
+                match queue.pop_front() {
+                    Some(waiter) => match waiter.send(item) {
+                        Ok(()) => {
+                            self.handed_over.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(item) => items.push_front(item),
+                    },
+                    None => {
+                        items.push_front(item);
+                        break;
+                    }
+                }
+            }
+            if queue.is_empty() {
+                waiters.remove(key);
+            }
+        }
+        if items.is_empty() {
+            context.remove(key);
+        }
+
+        Ok(length)
+    }
+
+    /// Pop a value from one end of the list stored at key.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `end` - The end of the list to pop from
+    ///
+    /// # Returns
+    /// Returns `Ok(Some(value))` if the list had an item, `Ok(None)` if the key does not exist,
+    /// or an error if the key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use kiwi_store_server::store::value::ListEnd;
+    ///
+    /// let store = DataStore::new();
+    /// store.push("Jobs", "resize", ListEnd::Right, 100).await;
+    /// let value = store.pop("Jobs", ListEnd::Left).await;
+    ///
+    /// assert_eq!(value, Ok(Some("resize".to_string())));
+    /// ```
+    pub async fn pop(&self, key: &str, end: ListEnd) -> Result<Option<String>, String> {
+        let mut context = self.context.lock().await;
+        Self::pop_locked(&mut context, key, end)
+    }
+
+    /// Pop a value from the head of the list stored at key, waiting for one to arrive
+    /// if the list is empty. Blocked clients are served in the order they arrived.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `timeout` - How long to wait for a value, `None` waits forever
+    ///
+    /// # Returns
+    /// Returns `Ok(Some(value))` if a value was popped, `Ok(None)` if the timeout elapsed,
+    /// or an error if the key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use std::time::Duration;
+    ///
+    /// let store = DataStore::new();
+    /// let value = store.blocking_pop("Jobs", Some(Duration::from_millis(10))).await;
+    ///
+    /// assert_eq!(value, Ok(None));
+    /// ```
+    pub async fn blocking_pop(
+        &self,
+        key: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>, String> {
+        match self.pop_or_wait(key).await? {
+            Popped::Value(value) => Ok(Some(value)),
+            Popped::Waiting(receiver) => Ok(self.wait(key, receiver, timeout).await),
         }
+    }
 
-        let key_count = context.len();
-        if key_count == 0 {
-            return "Keys(0), Size(0 B)".to_string();
+    /// Pop a value from the head of the list stored at key, or line up for the next value pushed
+    /// to it if the list is empty. The first half of [`DataStore::blocking_pop`].
+    ///
+    /// # Returns
+    /// Returns the popped value or the receiver to wait on with [`DataStore::wait`],
+    /// or an error if the key holds another data type.
+    pub async fn pop_or_wait(&self, key: &str) -> Result<Popped, String> {
+        let mut context = self.context.lock().await;
+        if let Some(value) = Self::pop_locked(&mut context, key, ListEnd::Left)? {
+            return Ok(Popped::Value(value));
         }
-        let total_bytes: usize = context
-            .values()
-            .map(|v| v.len())
-            .map(|len| len * std::mem::size_of::<char>())
-            .sum();
+        let (sender, receiver) = oneshot::channel();
+        let mut waiters = self.waiters.lock().await;
+        waiters
+            .entry(key.to_string())
+            .or_default()
+            .push_back(sender);
+        Ok(Popped::Waiting(receiver))
+    }
 
-        let size = if total_bytes < 1024 {
-            format!("{} B", total_bytes)
-        } else if total_bytes < 1024 * 1024 {
-            format!("{:.2} KB", total_bytes as f64 / 1024.0)
+    /// Wait for the value a push hands over to a client lined up by [`DataStore::pop_or_wait`].
+    ///
+    /// # Returns
+    /// Returns the value, `None` if the timeout elapsed.
+    pub async fn wait(
+        &self,
+        key: &str,
+        mut receiver: oneshot::Receiver<String>,
+        timeout: Option<Duration>,
+    ) -> Option<String> {
+        let received = match timeout {
+            Some(duration) => tokio::time::timeout(duration, &mut receiver).await.ok(),
+            None => Some((&mut receiver).await),
+        };
+        match received {
+            Some(Ok(value)) => Some(value),
+            Some(Err(_)) => None,
+            None => {
+                // A push may have handed us a value right as the timeout fired
+                receiver.close();
+                let mut waiters = self.waiters.lock().await;
+                if let Some(queue) = waiters.get_mut(key) {
+                    queue.retain(|waiter| !waiter.is_closed());
+                    if queue.is_empty() {
+                        waiters.remove(key);
+                    }
+                }
+                receiver.try_recv().ok()
+            }
+        }
+    }
+
+    /// Get a range of items from the list stored at key.
+    /// Negative indexes count from the end of the list, `-1` being the last item.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `start` - Index of the first item
+    /// * `stop` - Index of the last item, inclusive
+    ///
+    /// # Returns
+    /// Returns the items in the range, or an error if the key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use kiwi_store_server::store::value::ListEnd;
+    ///
+    /// let store = DataStore::new();
+    /// store.push("Jobs", "resize", ListEnd::Right, 100).await;
+    /// store.push("Jobs", "upload", ListEnd::Right, 100).await;
+    /// let items = store.range("Jobs", 0, -1).await;
+    ///
+    /// assert_eq!(items, Ok(vec!["resize".to_string(), "upload".to_string()]));
+    /// ```
+    pub async fn range(&self, key: &str, start: i64, stop: i64) -> Result<Vec<String>, String> {
+        let context = self.context.lock().await;
+        let items = match context.get(key) {
+            Some(Value::List(items)) => items,
+            Some(_) => return Err(WRONG_TYPE.to_string()),
+            None => return Ok(Vec::new()),
+        };
+        let length = items.len() as i64;
+        let start = if start < 0 {
+            (length + start).max(0)
         } else {
-            format!("{:.2} MB", total_bytes as f64 / (1024.0 * 1024.0))
+            start
         };
+        let stop = if stop < 0 {
+            length + stop
+        } else {
+            stop.min(length - 1)
+        };
+        if start > stop {
+            return Ok(Vec::new());
+        }
+        Ok(items
+            .range(start as usize..=stop as usize)
+            .cloned()
+            .collect())
+    }
 
-        format!("Keys({}), Size({})", key_count, size)
+    /// Get the length of the list stored at key.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    ///
+    /// # Returns
+    /// Returns the number of items, `0` if the key does not exist,
+    /// or an error if the key holds another data type.
+    pub async fn length(&self, key: &str) -> Result<usize, String> {
+        let context = self.context.lock().await;
+        match context.get(key) {
+            Some(Value::List(items)) => Ok(items.len()),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+            None => Ok(0),
+        }
     }
 
-    pub async fn is_empty(&self) -> bool {
+    fn pop_locked(
+        context: &mut BTreeMap<String, Value>,
+        key: &str,
+        end: ListEnd,
+    ) -> Result<Option<String>, String> {
+        let Some(entry) = context.get_mut(key) else {
+            return Ok(None);
+        };
+        let Value::List(items) = entry else {
+            return Err(WRONG_TYPE.to_string());
+        };
+        let value = match end {
+            ListEnd::Left => items.pop_front(),
+            ListEnd::Right => items.pop_back(),
+        };
+        if items.is_empty() {
+            context.remove(key);
+        }
+        Ok(value)
+    }
+
+    /// Add a member to the set stored at key.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `member` - Member to add
+    ///
+    /// # Returns
+    /// Returns `true` if the member was added, `false` if it was already in the set,
+    /// or an error if the key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// let added = store.set_add("BetaUsers", "burak").await;
+    ///
+    /// assert_eq!(added, Ok(true));
+    /// ```
+    pub async fn set_add(&self, key: &str, member: &str) -> Result<bool, String> {
+        let mut context = self.context.lock().await;
+        let entry = context
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Set(HashSet::new()));
+        match entry {
+            Value::Set(members) => Ok(members.insert(member.to_string())),
+            _ => Err(WRONG_TYPE.to_string()),
+        }
+    }
+
+    /// Remove a member from the set stored at key.
+    ///
+    /// # Returns
+    /// Returns `true` if the member was removed, `false` if it was not in the set,
+    /// or an error if the key holds another data type.
+    pub async fn set_remove(&self, key: &str, member: &str) -> Result<bool, String> {
+        let mut context = self.context.lock().await;
+        let Some(entry) = context.get_mut(key) else {
+            return Ok(false);
+        };
+        let Value::Set(members) = entry else {
+            return Err(WRONG_TYPE.to_string());
+        };
+        let removed = members.remove(member);
+        if members.is_empty() {
+            context.remove(key);
+        }
+        Ok(removed)
+    }
+
+    /// Check whether a member is in the set stored at key.
+    ///
+    /// # Returns
+    /// Returns `true` if the member is in the set, or an error if the key holds another data type.
+    pub async fn is_member(&self, key: &str, member: &str) -> Result<bool, String> {
         let context = self.context.lock().await;
-        context.is_empty()
+        match context.get(key) {
+            Some(Value::Set(members)) => Ok(members.contains(member)),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+            None => Ok(false),
+        }
+    }
+
+    /// Get all members of the set stored at key, sorted by name.
+    ///
+    /// # Returns
+    /// Returns the members of the set, or an error if the key holds another data type.
+    pub async fn members(&self, key: &str) -> Result<Vec<String>, String> {
+        self.union(&[key.to_string()]).await
+    }
+
+    /// Get the members that are present in all of the sets stored at the given keys.
+    /// A missing key counts as an empty set.
+    ///
+    /// # Arguments
+    /// * `keys` - Keys of the sets to intersect
+    ///
+    /// # Returns
+    /// Returns the common members sorted by name, or an error if any key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// store.set_add("BetaUsers", "burak").await;
+    /// store.set_add("BetaUsers", "selim").await;
+    /// store.set_add("Admins", "burak").await;
+    /// let members = store.intersect(&["BetaUsers".to_string(), "Admins".to_string()]).await;
+    ///
+    /// assert_eq!(members, Ok(vec!["burak".to_string()]));
+    /// ```
+    pub async fn intersect(&self, keys: &[String]) -> Result<Vec<String>, String> {
+        let context = self.context.lock().await;
+        let sets = Self::sets_locked(&context, keys)?;
+        let Some((first, rest)) = sets.split_first() else {
+            return Ok(Vec::new());
+        };
+        let mut members: Vec<String> = match first {
+            Some(first) => first
+                .iter()
+                .filter(|m| rest.iter().all(|s| s.is_some_and(|s| s.contains(*m))))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        members.sort();
+        Ok(members)
+    }
+
+    /// Get the members that are present in any of the sets stored at the given keys.
+    ///
+    /// # Arguments
+    /// * `keys` - Keys of the sets to unite
+    ///
+    /// # Returns
+    /// Returns all members sorted by name, or an error if any key holds another data type.
+    pub async fn union(&self, keys: &[String]) -> Result<Vec<String>, String> {
+        let context = self.context.lock().await;
+        let sets = Self::sets_locked(&context, keys)?;
+        let mut members: Vec<String> = sets
+            .into_iter()
+            .flatten()
+            .flatten()
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        members.sort();
+        Ok(members)
+    }
+
+    /// Add a member with a score to the sorted set stored at key, or update its score.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `score` - Score of the member
+    /// * `member` - Member to add
+    ///
+    /// # Returns
+    /// Returns `true` if the member was added, `false` if its score was updated,
+    /// or an error if the key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// let added = store.sorted_add("SlowEndpoints", 120.5, "/orders").await;
+    ///
+    /// assert_eq!(added, Ok(true));
+    /// ```
+    pub async fn sorted_add(&self, key: &str, score: f64, member: &str) -> Result<bool, String> {
+        let mut context = self.context.lock().await;
+        let set = Self::sorted_set_entry(&mut context, key)?;
+        Ok(set.add(member, score))
+    }
+
+    /// Increment the score of a member in the sorted set stored at key.
+    /// A missing member is added with the increment as its score.
+    ///
+    /// # Returns
+    /// Returns the new score, or an error if the key holds another data type
+    /// or the new score is not a finite number.
+    pub async fn sorted_increment(
+        &self,
+        key: &str,
+        increment: f64,
+        member: &str,
+    ) -> Result<f64, String> {
+        let mut context = self.context.lock().await;
+        let set = Self::sorted_set_entry(&mut context, key)?;
+        let score = set.increment(member, increment);
+        if set.is_empty() {
+            context.remove(key);
+        }
+        score
+    }
+
+    /// Remove a member from the sorted set stored at key.
+    ///
+    /// # Returns
+    /// Returns `true` if the member was removed, or an error if the key holds another data type.
+    pub async fn sorted_remove(&self, key: &str, member: &str) -> Result<bool, String> {
+        let mut context = self.context.lock().await;
+        let Some(entry) = context.get_mut(key) else {
+            return Ok(false);
+        };
+        let Value::SortedSet(set) = entry else {
+            return Err(WRONG_TYPE.to_string());
+        };
+        let removed = set.remove(member);
+        if set.is_empty() {
+            context.remove(key);
+        }
+        Ok(removed)
+    }
+
+    /// Get members of the sorted set stored at key by rank, from lowest to highest score.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `start` - Rank of the first member
+    /// * `stop` - Rank of the last member, inclusive. Negative ranks count from the end
+    ///
+    /// # Returns
+    /// Returns the members with their scores, or an error if the key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    ///
+    /// let store = DataStore::new();
+    /// store.sorted_add("SlowEndpoints", 120.5, "/orders").await;
+    /// store.sorted_add("SlowEndpoints", 80.0, "/users").await;
+    /// let members = store.sorted_range("SlowEndpoints", 0, -1).await.unwrap();
+    ///
+    /// assert_eq!(members[0], ("/users".to_string(), 80.0));
+    /// ```
+    pub async fn sorted_range(
+        &self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<(String, f64)>, String> {
+        let context = self.context.lock().await;
+        match context.get(key) {
+            Some(Value::SortedSet(set)) => Ok(set.range(start, stop)),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Get members of the sorted set stored at key whose score is between `min` and `max`.
+    ///
+    /// # Returns
+    /// Returns the members with their scores, or an error if the key holds another data type.
+    pub async fn sorted_range_by_score(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+    ) -> Result<Vec<(String, f64)>, String> {
+        let context = self.context.lock().await;
+        match context.get(key) {
+            Some(Value::SortedSet(set)) => Ok(set.range_by_score(min, max)),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn sets_locked<'a>(
+        context: &'a BTreeMap<String, Value>,
+        keys: &[String],
+    ) -> Result<Vec<Option<&'a HashSet<String>>>, String> {
+        keys.iter()
+            .map(|key| match context.get(key) {
+                Some(Value::Set(members)) => Ok(Some(members)),
+                Some(_) => Err(WRONG_TYPE.to_string()),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    fn sorted_set_entry<'a>(
+        context: &'a mut BTreeMap<String, Value>,
+        key: &str,
+    ) -> Result<&'a mut SortedSet, String> {
+        let entry = context
+            .entry(key.to_string())
+            .or_insert_with(|| Value::SortedSet(SortedSet::default()));
+        match entry {
+            Value::SortedSet(set) => Ok(set),
+            _ => Err(WRONG_TYPE.to_string()),
+        }
+    }
+
+    /// Set the value at a path of the JSON document stored at key.
+    /// A new document can only be created at the root path.
+    ///
+    /// # Arguments
+    /// * `key` - Key variable
+    /// * `path` - Path inside the document, `$` for the root
+    /// * `value` - JSON value to set
+    /// * `max_length` - Maximum serialized size of the whole document
+    ///
+    /// # Returns
+    /// Returns `Ok(())` on success, or an error if the path is invalid, the document
+    /// grows beyond `max_length` or the key holds another data type.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::store::data::DataStore;
+    /// use serde_json::json;
+    ///
+    /// let store = DataStore::new();
+    /// store.json_set("Db", "$", json!({ "host": "localhost" }), 100).await.unwrap();
+    /// store.json_set("Db", "$.port", json!(5432), 100).await.unwrap();
+    ///
+    /// assert_eq!(store.json_get("Db", "$.port").await, Ok(Some("5432".to_string())));
+    /// ```
+    pub async fn json_set(
+        &self,
+        key: &str,
+        path: &str,
+        value: serde_json::Value,
+        max_length: usize,
+    ) -> Result<(), String> {
+        let segments = json_path::parse(path)?;
+        let mut context = self.context.lock().await;
+        let mut document = match context.get(key) {
+            Some(Value::Json(document)) => document.clone(),
+            Some(_) => return Err(WRONG_TYPE.to_string()),
+            None if segments.is_empty() => serde_json::Value::Null,
+            None => return Err("New documents must be set at the root path".to_string()),
+        };
+        json_path::set(&mut document, &segments, value)?;
+        Self::check_document_length(&document, max_length)?;
+        context.insert(key.to_string(), Value::Json(document));
+        Ok(())
+    }
+
+    /// Get the value at a path of the JSON document stored at key.
+    ///
+    /// # Returns
+    /// Returns the serialized JSON value, `Ok(None)` if the key or path does not exist,
+    /// or an error if the path is invalid or the key holds another data type.
+    pub async fn json_get(&self, key: &str, path: &str) -> Result<Option<String>, String> {
+        let segments = json_path::parse(path)?;
+        let context = self.context.lock().await;
+        match context.get(key) {
+            Some(Value::Json(document)) => {
+                Ok(json_path::get(document, &segments).map(|v| v.to_string()))
+            }
+            Some(_) => Err(WRONG_TYPE.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the value at a path of the JSON document stored at key.
+    /// Deleting the root path removes the key.
+    ///
+    /// # Returns
+    /// Returns `true` if a value was deleted, or an error if the path is invalid
+    /// or the key holds another data type.
+    pub async fn json_delete(&self, key: &str, path: &str) -> Result<bool, String> {
+        let segments = json_path::parse(path)?;
+        let mut context = self.context.lock().await;
+        match context.get_mut(key) {
+            Some(Value::Json(_)) if segments.is_empty() => Ok(context.remove(key).is_some()),
+            Some(Value::Json(document)) => Ok(json_path::delete(document, &segments)),
+            Some(_) => Err(WRONG_TYPE.to_string()),
+            None => Ok(false),
+        }
+    }
+
+    /// Increment the number at a path of the JSON document stored at key.
+    /// Integers stay integers when the increment is a whole number and the sum fits in an `i64`,
+    /// otherwise the sum is a floating point number.
+    ///
+    /// # Returns
+    /// Returns the new number, or an error if the path does not point to a number,
+    /// the document grows beyond `max_length` or the key holds another data type.
+    pub async fn json_increment(
+        &self,
+        key: &str,
+        path: &str,
+        increment: f64,
+        max_length: usize,
+    ) -> Result<serde_json::Value, String> {
+        let segments = json_path::parse(path)?;
+        let mut context = self.context.lock().await;
+        let Some(entry) = context.get_mut(key) else {
+            return Err("Key does not exist".to_string());
+        };
+        let Value::Json(document) = entry else {
+            return Err(WRONG_TYPE.to_string());
+        };
+        let mut updated = document.clone();
+        let target = json_path::get_mut(&mut updated, &segments).ok_or("Path does not exist")?;
+        let whole = (increment.fract() == 0.0 && increment.abs() < i64::MAX as f64)
+            .then_some(increment as i64);
+        let sum = target
+            .as_i64()
+            .zip(whole)
+            .and_then(|(current, whole)| current.checked_add(whole));
+        let number = match sum {
+            Some(sum) => serde_json::Value::from(sum),
+            None => {
+                let current = target.as_f64().ok_or("Value at path is not a number")?;
+                serde_json::Number::from_f64(current + increment)
+                    .map(serde_json::Value::Number)
+                    .ok_or("Result is not a finite number")?
+            }
+        };
+        *target = number.clone();
+        Self::check_document_length(&updated, max_length)?;
+        *document = updated;
+        Ok(number)
+    }
+
+    fn check_document_length(
+        document: &serde_json::Value,
+        max_length: usize,
+    ) -> Result<(), String> {
+        let length = document.to_string().len();
+        if length > max_length {
+            return Err(format!("Value exceeds maximum length: {}", length));
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a key into an opaque SCAN cursor, so keys with spaces or a literal `0` are safe.
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<Option<String>, String> {
+    if cursor == "0" {
+        return Ok(None);
+    }
+    let invalid = || format!("Invalid cursor: {}", cursor);
+    if !cursor.is_ascii() || !cursor.len().is_multiple_of(2) {
+        return Err(invalid());
     }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16).map_err(|_| invalid()))
+        .collect::<Result<Vec<u8>, String>>()?;
+    String::from_utf8(bytes).map(Some).map_err(|_| invalid())
 }
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem::size_of;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq)]
+/// Represents a value a key held from the time it was written until the next version
+pub struct Version {
+    /// Increasing number of the version within the history of the key, starting from 1
+    pub number: u64,
+    pub time: SystemTime,
+    /// The written value, `None` if the key was removed
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Represents how many versions of a key are kept and for how long.
+/// The latest version is always kept, since it is the current value of the key, unless it is
+/// the removal of the key and older than the age: the history of the key is dropped then.
+pub struct Retention {
+    /// Number of versions kept per key, zero keeps no history at all
+    pub max_versions: usize,
+    /// Age after which a version is dropped, `None` keeps the versions until they are too many
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+/// Represents the versions kept for a key, oldest first
+pub struct KeyHistory {
+    last_number: u64,
+    versions: VecDeque<Version>,
+    /// Whether older versions were dropped, so the history doesn't reach back to the first write
+    truncated: bool,
+}
+
+#[derive(Debug, Default)]
+/// Represents the histories of the keys of a data store
+pub struct Histories {
+    keys: HashMap<String, KeyHistory>,
+    /// Removed keys in the order they were removed, to drop their histories once the removal
+    /// is older than the age of the retention. Only kept while the retention has an age
+    removals: VecDeque<(SystemTime, String)>,
+}
+
+impl Histories {
+    /// Checks whether a key has a history.
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    /// Appends a version to the history of a key, `None` for its removal.
+    pub fn push(&mut self, key: &str, value: Option<String>, retention: &Retention) {
+        if value.is_none() && retention.max_age.is_some() {
+            self.removals
+                .push_back((SystemTime::now(), key.to_string()));
+        }
+        self.keys
+            .entry(key.to_string())
+            .or_default()
+            .push(value, retention);
+        self.drop_removed(retention);
+    }
+
+    /// Returns the history of a key, after dropping the versions past the retention.
+    /// A retention without versions drops the history of every key.
+    pub fn kept(&mut self, key: &str, retention: &Retention) -> Option<&KeyHistory> {
+        if retention.max_versions == 0 {
+            self.clear();
+            return None;
+        }
+        self.drop_removed(retention);
+        let versions = self.keys.get_mut(key)?;
+        versions.prune(retention);
+        Some(versions)
+    }
+
+    /// Drops the history of a key, e.g. once the key is evicted.
+    pub fn forget(&mut self, key: &str) {
+        self.keys.remove(key);
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.removals.clear();
+    }
+
+    /// Returns the approximate number of bytes held by the histories.
+    pub fn size(&self) -> usize {
+        self.keys
+            .iter()
+            .map(|(key, versions)| key.capacity() + versions.size())
+            .sum::<usize>()
+            + self
+                .removals
+                .iter()
+                .map(|(_, key)| size_of::<(SystemTime, String)>() + key.capacity())
+                .sum::<usize>()
+    }
+
+    /// Drops the histories left with only a removal older than the age of the retention.
+    fn drop_removed(&mut self, retention: &Retention) {
+        let Some(age) = retention.max_age else {
+            self.removals.clear();
+            return;
+        };
+        let now = SystemTime::now();
+        while let Some((time, _)) = self.removals.front()
+            && now.duration_since(*time).unwrap_or_default() > age
+            && let Some((_, key)) = self.removals.pop_front()
+        {
+            if let Some(versions) = self.keys.get_mut(&key) {
+                versions.prune(retention);
+                if versions.is_removed() {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+impl KeyHistory {
+    /// Appends a version and drops the ones the retention no longer keeps.
+    pub fn push(&mut self, value: Option<String>, retention: &Retention) {
+        self.last_number += 1;
+        self.versions.push_back(Version {
+            number: self.last_number,
+            time: SystemTime::now(),
+            value,
+        });
+        self.prune(retention);
+    }
+
+    /// Drops the versions over the count or older than the age of the retention.
+    pub fn prune(&mut self, retention: &Retention) {
+        let now = SystemTime::now();
+        let expired = |version: &Version| {
+            retention
+                .max_age
+                .is_some_and(|age| now.duration_since(version.time).unwrap_or_default() > age)
+        };
+        while self.versions.len() > 1
+            && (self.versions.len() > retention.max_versions || expired(&self.versions[0]))
+        {
+            self.versions.pop_front();
+            self.truncated = true;
+        }
+    }
+
+    /// Checks whether the history holds only the removal of the key.
+    fn is_removed(&self) -> bool {
+        self.versions.len() == 1 && self.versions[0].value.is_none()
+    }
+
+    /// Returns the versions, newest first.
+    pub fn versions(&self) -> impl Iterator<Item = &Version> {
+        self.versions.iter().rev()
+    }
+
+    /// Returns the version with a number, if it is still kept.
+    pub fn version(&self, number: u64) -> Option<&Version> {
+        self.versions.iter().find(|v| v.number == number)
+    }
+
+    /// Returns the version the key held at a time.
+    ///
+    /// # Returns
+    /// Returns `Ok(None)` if the key was not written yet at that time, or an error if the versions
+    /// of that time were already dropped.
+    pub fn at(&self, time: SystemTime) -> Result<Option<&Version>, String> {
+        match self.versions.iter().rev().find(|v| v.time <= time) {
+            Some(version) => Ok(Some(version)),
+            None if self.truncated => {
+                Err("History of the key doesn't reach back to that time".to_string())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the approximate number of bytes held by the versions.
+    pub fn size(&self) -> usize {
+        self.versions
+            .iter()
+            .map(|v| size_of::<Version>() + v.value.as_ref().map_or(0, |value| value.capacity()))
+            .sum()
+    }
+}
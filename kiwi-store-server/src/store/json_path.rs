@@ -0,0 +1,118 @@
+//! A small subset of JSONPath used by the JSON.* commands.
+//!
+//! Supported forms are the root (`$` or `.`), object fields separated by dots
+//! and array indexes in brackets, e.g. `$.database.replicas[0].host`.
+
+use serde_json::Value as Json;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Represents one step of a JSON path
+pub enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// Parses a path into its segments. The root path has no segments.
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::store::json_path::{parse, Segment};
+///
+/// let segments = parse("$.replicas[1].host").unwrap();
+/// assert_eq!(segments, vec![
+///     Segment::Field("replicas".to_string()),
+///     Segment::Index(1),
+///     Segment::Field("host".to_string()),
+/// ]);
+/// ```
+pub fn parse(path: &str) -> Result<Vec<Segment>, String> {
+    let invalid = || format!("Invalid JSON path: {}", path);
+    let trimmed = path.strip_prefix('$').unwrap_or(path);
+    let mut segments = Vec::new();
+
+    for part in trimmed.split('.') {
+        if part.is_empty() {
+            continue;
+        }
+        let (field, mut rest) = match part.find('[') {
+            Some(position) => part.split_at(position),
+            None => (part, ""),
+        };
+        if !field.is_empty() {
+            segments.push(Segment::Field(field.to_string()));
+        }
+        while !rest.is_empty() {
+            let close = rest.find(']').ok_or_else(invalid)?;
+            let index = rest[1..close].parse::<usize>().map_err(|_| invalid())?;
+            segments.push(Segment::Index(index));
+            rest = &rest[close + 1..];
+            if !rest.is_empty() && !rest.starts_with('[') {
+                return Err(invalid());
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Returns the value at the given path, if it exists.
+pub fn get<'a>(root: &'a Json, segments: &[Segment]) -> Option<&'a Json> {
+    segments
+        .iter()
+        .try_fold(root, |current, segment| match segment {
+            Segment::Field(name) => current.get(name),
+            Segment::Index(index) => current.get(index),
+        })
+}
+
+/// Returns a mutable reference to the value at the given path, if it exists.
+pub fn get_mut<'a>(root: &'a mut Json, segments: &[Segment]) -> Option<&'a mut Json> {
+    segments
+        .iter()
+        .try_fold(root, |current, segment| match segment {
+            Segment::Field(name) => current.get_mut(name),
+            Segment::Index(index) => current.get_mut(index),
+        })
+}
+
+/// Sets the value at the given path. The parent of the target must exist;
+/// a missing field is created on an object, while array indexes must already exist.
+pub fn set(root: &mut Json, segments: &[Segment], value: Json) -> Result<(), String> {
+    let Some((last, parents)) = segments.split_last() else {
+        *root = value;
+        return Ok(());
+    };
+    let parent = get_mut(root, parents).ok_or("Path does not exist")?;
+    match (parent, last) {
+        (Json::Object(map), Segment::Field(name)) => {
+            map.insert(name.clone(), value);
+            Ok(())
+        }
+        (Json::Array(items), Segment::Index(index)) => match items.get_mut(*index) {
+            Some(item) => {
+                *item = value;
+                Ok(())
+            }
+            None => Err("Array index out of range".to_string()),
+        },
+        _ => Err("Path does not match the document structure".to_string()),
+    }
+}
+
+/// Deletes the value at the given path.
+///
+/// # Returns
+/// Returns `true` if a value was deleted.
+pub fn delete(root: &mut Json, segments: &[Segment]) -> bool {
+    let Some((last, parents)) = segments.split_last() else {
+        return false;
+    };
+    match (get_mut(root, parents), last) {
+        (Some(Json::Object(map)), Segment::Field(name)) => map.remove(name).is_some(),
+        (Some(Json::Array(items)), Segment::Index(index)) if *index < items.len() => {
+            items.remove(*index);
+            true
+        }
+        _ => false,
+    }
+}
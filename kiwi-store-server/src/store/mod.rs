@@ -1,4 +1,9 @@
 /// `kiwi-store-server` - A data store for tiny key-value pairs.
 pub mod data;
+pub mod history;
+pub mod json_path;
+pub mod pattern;
+pub mod sorted_set;
+pub mod value;
 
 pub use data::DataStore;
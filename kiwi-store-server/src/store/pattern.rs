@@ -0,0 +1,86 @@
+//! Glob style pattern matching used by the KEYS and SCAN commands.
+//!
+//! Supported syntax:
+//! - `*` matches any sequence of characters, including an empty one
+//! - `?` matches exactly one character
+//! - `[abc]`, `[a-z]` match one character from the set, `[^abc]` or `[!abc]` negates it
+//! - `\` escapes the next character
+
+/// Checks whether the text matches the glob pattern.
+///
+/// # Example
+/// ```rust
+/// use kiwi_store_server::store::pattern::matches;
+///
+/// assert!(matches("Db*", "DbConn"));
+/// assert!(matches("user:[0-9]?", "user:42"));
+/// assert!(!matches("Db?", "DbConn"));
+/// ```
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_from(&pattern, &text)
+}
+
+fn matches_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position to resume from when the last `*` has to swallow one more character
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match_class(&pattern[p..], text[t]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(2),
+            Some(c) => (*c == text[t]).then_some(1),
+            None => None,
+        };
+        match (step, backtrack) {
+            (Some(width), _) => {
+                p += width;
+                t += 1;
+            }
+            (None, Some((star, position))) => {
+                p = star + 1;
+                t = position + 1;
+                backtrack = Some((star, position + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches a character against a `[...]` class at the start of the pattern.
+/// Returns the width of the class when it matches.
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('^') | Some('!'));
+    if negate {
+        i += 1;
+    }
+    let mut found = false;
+    while i < pattern.len() && pattern[i] != ']' {
+        if pattern[i] == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            found |= pattern[i] == c;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            found |= pattern[i] <= c && c <= pattern[i + 2];
+            i += 2;
+        } else {
+            found |= pattern[i] == c;
+        }
+        i += 1;
+    }
+    if i >= pattern.len() {
+        // Unterminated class, treat the bracket as a literal
+        return (c == '[').then_some(1);
+    }
+    (found != negate).then_some(i + 1)
+}
@@ -0,0 +1,133 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy)]
+/// Score wrapper giving `f64` the total order needed to keep members sorted
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Represents a set of unique members ordered by score, then by member name
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    /// Adds a member or updates its score.
+    ///
+    /// # Returns
+    /// Returns `true` if the member is new, `false` if only its score was updated.
+    pub fn add(&mut self, member: &str, score: f64) -> bool {
+        let previous = self.scores.insert(member.to_string(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.to_string()));
+        }
+        self.ordered.insert((Score(score), member.to_string()));
+        previous.is_none()
+    }
+
+    /// Increments the score of a member, adding it with the increment as score if missing.
+    ///
+    /// # Returns
+    /// Returns the new score of the member, or an error if it is not a finite number,
+    /// e.g. `inf` incremented by `-inf`. The member is left as it was then.
+    pub fn increment(&mut self, member: &str, increment: f64) -> Result<f64, String> {
+        let score = self.scores.get(member).copied().unwrap_or(0.0) + increment;
+        if !score.is_finite() {
+            return Err("Resulting score is not a finite number".to_string());
+        }
+        self.add(member, score);
+        Ok(score)
+    }
+
+    /// Removes a member from the set.
+    ///
+    /// # Returns
+    /// Returns `true` if the member existed.
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.ordered.remove(&(Score(score), member.to_string())),
+            None => false,
+        }
+    }
+
+    /// Returns members with their scores by rank, from lowest to highest score.
+    /// Negative indexes count from the highest score, `-1` being the last member.
+    pub fn range(&self, start: i64, stop: i64) -> Vec<(String, f64)> {
+        let length = self.ordered.len() as i64;
+        let start = if start < 0 {
+            (length + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            length + stop
+        } else {
+            stop.min(length - 1)
+        };
+        if start > stop {
+            return Vec::new();
+        }
+        self.ordered
+            .iter()
+            .skip(start as usize)
+            .take((stop - start + 1) as usize)
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    /// Returns members with their scores whose score is between `min` and `max`, inclusive.
+    pub fn range_by_score(&self, min: f64, max: f64) -> Vec<(String, f64)> {
+        self.ordered
+            .iter()
+            .skip_while(|(score, _)| score.0 < min)
+            .take_while(|(score, _)| score.0 <= max)
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Returns the approximate number of bytes held by the members and their scores.
+    pub fn size(&self) -> usize {
+        self.scores
+            .keys()
+            .map(|member| member.len() + std::mem::size_of::<f64>())
+            .sum()
+    }
+
+    /// Returns the approximate number of bytes the set uses besides its members and scores:
+    /// the second copy of every member kept for the ordering and the bookkeeping of both indexes.
+    pub fn overhead(&self) -> usize {
+        let entry = std::mem::size_of::<String>() + std::mem::size_of::<f64>();
+        // A hash table slot has a control byte besides the entry
+        self.scores.capacity() * (entry + 1)
+            + self
+                .ordered
+                .iter()
+                .map(|(_, member)| entry + member.capacity())
+                .sum::<usize>()
+    }
+}
@@ -0,0 +1,71 @@
+use crate::store::sorted_set::SortedSet;
+use std::collections::{HashSet, VecDeque};
+use std::mem::size_of;
+
+/// Error message returned when a command is used against a key of another data type
+pub const WRONG_TYPE: &str = "Operation against a key holding the wrong kind of value";
+
+#[derive(Debug, Clone, PartialEq)]
+/// Represents a value kept in the data store
+pub enum Value {
+    /// Plain string value set by the SET command
+    Text(String),
+    /// Double ended list used by the LPUSH/RPUSH family
+    List(VecDeque<String>),
+    /// Unordered set of unique members used by the SADD family
+    Set(HashSet<String>),
+    /// Set of unique members ordered by score used by the ZADD family
+    SortedSet(SortedSet),
+    /// JSON document used by the JSON.* commands
+    Json(serde_json::Value),
+}
+
+impl Value {
+    /// Returns the approximate number of bytes held by the value.
+    pub fn size(&self) -> usize {
+        match self {
+            Value::Text(text) => text.len(),
+            Value::List(items) => items.iter().map(|i| i.len()).sum(),
+            Value::Set(members) => members.iter().map(|m| m.len()).sum(),
+            Value::SortedSet(set) => set.size(),
+            Value::Json(document) => document.to_string().len(),
+        }
+    }
+
+    /// Returns the approximate number of bytes the value uses besides its content:
+    /// the unused capacity of its strings and the bookkeeping of its items.
+    pub fn overhead(&self) -> usize {
+        fn slack(text: &String) -> usize {
+            text.capacity() - text.len()
+        }
+        match self {
+            Value::Text(text) => slack(text),
+            Value::List(items) => {
+                items.capacity() * size_of::<String>() + items.iter().map(slack).sum::<usize>()
+            }
+            // A hash table slot has a control byte besides the entry
+            Value::Set(members) => {
+                members.capacity() * (size_of::<String>() + 1)
+                    + members.iter().map(slack).sum::<usize>()
+            }
+            Value::SortedSet(set) => set.overhead(),
+            Value::Json(document) => json_nodes(document) * size_of::<serde_json::Value>(),
+        }
+    }
+}
+
+/// Counts the values of a JSON document, including the document itself
+fn json_nodes(document: &serde_json::Value) -> usize {
+    1 + match document {
+        serde_json::Value::Array(items) => items.iter().map(json_nodes).sum(),
+        serde_json::Value::Object(fields) => fields.values().map(json_nodes).sum(),
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Represents the end of a list that push and pop operations work on
+pub enum ListEnd {
+    Left,
+    Right,
+}
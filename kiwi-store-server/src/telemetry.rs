@@ -0,0 +1,238 @@
+//! Logs and traces of the Kiwi Store server.
+//!
+//! Everything the server logs goes through `tracing`. Every connection runs in a `connection`
+//! span with the client address, and every command in a `command` span inside it that carries
+//! the command, its first key, the namespace, the result code and the duration. The events are
+//! written to the standard error as text or as JSON lines, filtered with `RUST_LOG`, and the
+//! spans can be exported to an OpenTelemetry collector over OTLP/HTTP.
+//!
+//! A client that is part of a trace sends its W3C trace context with `TRACEPARENT`. The command
+//! spans of the connection then carry its trace id and are exported as children of its span.
+
+use crate::command::Command;
+use crate::config::Configuration;
+use crate::metrics::error_type;
+use opentelemetry::trace::{
+    SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider,
+};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::time::Duration;
+use tracing::{Span, error, field, info_span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry};
+
+/// Formats the logs can be written in
+pub const LOG_FORMATS: [&str; 2] = ["text", "json"];
+
+/// Name the server reports itself with to the collector
+const SERVICE_NAME: &str = "kiwi-store-server";
+
+/// Represents the W3C trace context a client sent with `TRACEPARENT`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceParent {
+    /// Id of the trace, 32 lowercase hex characters
+    pub trace_id: String,
+    /// Id of the client span the commands are children of, 16 lowercase hex characters
+    pub span_id: String,
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    /// Parses a `traceparent` value in the `<version>-<trace id>-<span id>-<flags>` format.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kiwi_store_server::telemetry::TraceParent;
+    ///
+    /// let parent = TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+    /// assert_eq!(parent.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+    /// assert!(parent.sampled);
+    /// assert!(TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_err());
+    /// ```
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "Invalid traceparent: {}, expected 00-<32 hex trace id>-<16 hex span id>-<2 hex flags>",
+                value
+            )
+        };
+        let hex = |part: &str, len: usize| {
+            part.len() == len
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        };
+        let parts: Vec<&str> = value.split('-').collect();
+        let [version, trace_id, span_id, flags] = parts[..] else {
+            return Err(invalid());
+        };
+        if !hex(version, 2) || version == "ff" || !hex(trace_id, 32) || !hex(span_id, 16) {
+            return Err(invalid());
+        }
+        if !hex(flags, 2)
+            || trace_id.bytes().all(|b| b == b'0')
+            || span_id.bytes().all(|b| b == b'0')
+        {
+            return Err(invalid());
+        }
+        let flags = u8::from_str_radix(flags, 16).map_err(|_| invalid())?;
+        Ok(Self {
+            trace_id: trace_id.to_string(),
+            span_id: span_id.to_string(),
+            sampled: flags & 1 == 1,
+        })
+    }
+
+    fn context(&self) -> Context {
+        let span = SpanContext::new(
+            TraceId::from_hex(&self.trace_id).unwrap_or(TraceId::INVALID),
+            SpanId::from_hex(&self.span_id).unwrap_or(SpanId::INVALID),
+            if self.sampled {
+                TraceFlags::SAMPLED
+            } else {
+                TraceFlags::default()
+            },
+            true,
+            TraceState::default(),
+        );
+        Context::new().with_remote_span_context(span)
+    }
+}
+
+/// Holds the span exporter, which sends the spans still buffered when it is shut down
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+/// Starts writing the logs in the configured format and exporting the spans if a collector is set.
+/// `RUST_LOG` filters the logs, only errors are written without it. The spans of the server are
+/// exported at the info level whatever the filter is.
+///
+/// # Arguments
+/// * `config` - The configuration holding `log_format` and `otlp_endpoint`
+pub fn init(config: &Configuration) -> Result<Telemetry, String> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::ERROR.into())
+        .from_env_lossy();
+    let logs = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let logs = match config.log_format.as_str() {
+        "json" => logs
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_filter(filter)
+            .boxed(),
+        _ => logs.with_filter(filter).boxed(),
+    };
+
+    let (provider, spans) = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()
+                .map_err(|e| format!("OTLP exporter can't be created: {}", e))?;
+            let provider = SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name(SERVICE_NAME)
+                        .with_attribute(KeyValue::new(
+                            "service.instance.id",
+                            config.node_address.clone(),
+                        ))
+                        .build(),
+                )
+                .build();
+            let layer: Box<dyn Layer<Registry> + Send + Sync> = tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(SERVICE_NAME))
+                .with_filter(LevelFilter::INFO)
+                .boxed();
+            (Some(provider), Some(layer))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(spans)
+        .with(logs)
+        .try_init()
+        .map_err(|e| format!("Tracing can't be started: {}", e))?;
+    Ok(Telemetry { provider })
+}
+
+impl Telemetry {
+    /// Exports the spans still buffered and stops the exporter.
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider
+            && let Err(e) = provider.shutdown()
+        {
+            error!("OTLP exporter can't be shut down: {}", e);
+        }
+    }
+}
+
+/// Creates the span of a command, to be finished with [`finish`] once it is processed.
+///
+/// # Arguments
+/// * `cmd` - The parsed command line
+/// * `line` - The command line
+/// * `namespace` - The namespace the command runs in
+/// * `parent` - The trace context the client sent, if any
+pub fn command_span(
+    cmd: &Command,
+    line: &str,
+    namespace: &str,
+    parent: Option<&TraceParent>,
+) -> Span {
+    let name = match cmd {
+        Command::Invalid(_) => "UNKNOWN".to_string(),
+        _ => line
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_uppercase(),
+    };
+    let span = info_span!(
+        "command",
+        otel.name = %name,
+        command = %name,
+        key = field::Empty,
+        namespace,
+        result = field::Empty,
+        duration_us = field::Empty,
+        trace_id = field::Empty,
+        otel.status_code = field::Empty,
+    );
+    if let Some(key) = cmd.keys().first() {
+        span.record("key", key);
+    }
+    if let Some(parent) = parent {
+        span.record("trace_id", parent.trace_id.as_str());
+        // Fails only when spans are not exported, the trace id is still recorded
+        let _ = span.set_parent(parent.context());
+    }
+    span
+}
+
+/// Records the result code and the duration of a command on its span.
+/// The result code is `ok` or the error type the metrics count the error as.
+pub fn finish(span: &Span, cmd: &Command, response: &str, elapsed: Duration) {
+    let known = !matches!(cmd, Command::Invalid(_));
+    match error_type(response, known) {
+        Some(error_type) => {
+            span.record("result", error_type.as_str());
+            span.record("otel.status_code", "ERROR");
+        }
+        None => {
+            span.record("result", "ok");
+        }
+    }
+    span.record("duration_us", elapsed.as_micros() as u64);
+}
@@ -1,14 +1,83 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
+    use crate::audit::AuditLog;
+    use crate::clients::Clients;
     use crate::command::Command;
-    use crate::config::Configuration;
+    use crate::config::{Configuration, Settings};
+    use crate::connections::Connections;
+    use crate::crypto;
+    use crate::handler::{execute, handle_connection};
+    use crate::metrics::{self, Metrics};
+    use crate::mode::{Eviction, ModeProfile};
+    use crate::monitor::Monitor;
+    use crate::namespace::Namespaces;
+    use crate::rate_limit::{Category, RateLimiter};
+    use crate::reload::Reloader;
+    use crate::replication::Replication;
+    use crate::sharding::{Sharding, key_slot};
+    use crate::shutdown::Shutdown;
+    use crate::slowlog::SlowLog;
+    use crate::state::ServerState;
     use crate::store::DataStore;
+    use crate::store::history::Retention;
+    use crate::store::value::{ListEnd, WRONG_TYPE};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    fn server_state(config: Configuration) -> ServerState {
+        ServerState {
+            connections: Connections::new(&config),
+            rate_limiter: RateLimiter::new(&config),
+            shutdown: Shutdown::new(),
+            reloader: Reloader::default(),
+            metrics: Metrics::new(),
+            slowlog: SlowLog::new(),
+            clients: Clients::new(),
+            audit: AuditLog::default(),
+            monitor: Monitor::new(),
+            namespaces: Namespaces::new(Arc::new(config)),
+            replication: Replication::new(),
+            raft: None,
+            sharding: None,
+        }
+    }
+
+    async fn start_server(state: ServerState) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        start_server_on(listener, state)
+    }
+
+    fn start_server_on(listener: TcpListener, state: ServerState) -> SocketAddr {
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(stream, state.clone()));
+            }
+        });
+        address
+    }
+
+    async fn send(stream: &mut TcpStream, command: &str) -> String {
+        stream
+            .write_all(format!("{}\n", command).as_bytes())
+            .await
+            .unwrap();
+        let mut buffer = [0; 1024];
+        let size = stream.read(&mut buffer).await.unwrap();
+        String::from_utf8_lossy(&buffer[..size]).to_string()
+    }
 
     #[tokio::test]
     async fn test_set_and_get() {
         let data_store = DataStore::new();
         data_store.set("Resilience", "on").await;
-        let expected = data_store.get("Resilience").await.unwrap();
+        let expected = data_store.get("Resilience").await.unwrap().unwrap();
         assert_eq!(expected, "on");
     }
 
@@ -76,4 +145,1541 @@ mod tests {
             _ => panic!("Expected to parse SET command!"),
         }
     }
+
+    #[tokio::test]
+    async fn test_list_push_and_pop() {
+        let data_store = DataStore::new();
+        data_store
+            .push("Jobs", "b", ListEnd::Left, 10)
+            .await
+            .unwrap();
+        data_store
+            .push("Jobs", "a", ListEnd::Left, 10)
+            .await
+            .unwrap();
+        data_store
+            .push("Jobs", "c", ListEnd::Right, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            data_store.range("Jobs", 0, -1).await.unwrap(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            data_store.range("Jobs", -2, 5).await.unwrap(),
+            vec!["b", "c"]
+        );
+        assert_eq!(
+            data_store.pop("Jobs", ListEnd::Right).await,
+            Ok(Some("c".to_string()))
+        );
+        assert_eq!(
+            data_store.pop("Jobs", ListEnd::Left).await,
+            Ok(Some("a".to_string()))
+        );
+        assert_eq!(data_store.length("Jobs").await, Ok(1));
+        data_store.pop("Jobs", ListEnd::Left).await.unwrap();
+        assert!(data_store.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_list_max_length_and_wrong_type() {
+        let data_store = DataStore::new();
+        data_store
+            .push("Jobs", "a", ListEnd::Right, 1)
+            .await
+            .unwrap();
+        let full = data_store.push("Jobs", "b", ListEnd::Right, 1).await;
+        assert_eq!(full.unwrap_err(), "List exceeds maximum length: 1");
+
+        data_store.set("Mode", "On").await;
+        let wrong = data_store.push("Mode", "b", ListEnd::Right, 10).await;
+        assert_eq!(wrong.unwrap_err(), WRONG_TYPE);
+        assert_eq!(data_store.get("Jobs").await.unwrap_err(), WRONG_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_blocking_pop_wakes_waiters_in_order() {
+        let data_store = DataStore::new();
+        let mut handles = vec![];
+        for _ in 0..3 {
+            let store = data_store.clone();
+            handles.push(tokio::spawn(async move {
+                store.blocking_pop("Jobs", None).await.unwrap()
+            }));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        for job in ["first", "second", "third"] {
+            data_store
+                .push("Jobs", job, ListEnd::Right, 10)
+                .await
+                .unwrap();
+        }
+        let mut results = vec![];
+        for handle in handles {
+            results.push(handle.await.unwrap().unwrap());
+        }
+        assert_eq!(results, vec!["first", "second", "third"]);
+        assert!(data_store.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_blocking_pop_timeout() {
+        let data_store = DataStore::new();
+        let value = data_store
+            .blocking_pop("Jobs", Some(Duration::from_millis(10)))
+            .await;
+        assert_eq!(value, Ok(None));
+        data_store
+            .push("Jobs", "late", ListEnd::Right, 10)
+            .await
+            .unwrap();
+        assert_eq!(data_store.length("Jobs").await, Ok(1));
+    }
+
+    #[test]
+    fn test_blpop_invalid_timeout() {
+        let config = Configuration::default();
+        let validation = Command::parse("BLPOP Jobs soon").validate(&config);
+        assert_eq!(
+            validation.unwrap_err(),
+            "Timeout must be a non-negative integer"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_operations() {
+        let data_store = DataStore::new();
+        assert_eq!(data_store.set_add("BetaUsers", "burak").await, Ok(true));
+        assert_eq!(data_store.set_add("BetaUsers", "burak").await, Ok(false));
+        data_store.set_add("BetaUsers", "selim").await.unwrap();
+        data_store.set_add("Admins", "burak").await.unwrap();
+        data_store.set_add("Admins", "can").await.unwrap();
+
+        assert_eq!(data_store.is_member("BetaUsers", "selim").await, Ok(true));
+        assert_eq!(
+            data_store.members("BetaUsers").await.unwrap(),
+            vec!["burak", "selim"]
+        );
+        let keys = vec!["BetaUsers".to_string(), "Admins".to_string()];
+        assert_eq!(data_store.intersect(&keys).await.unwrap(), vec!["burak"]);
+        assert_eq!(
+            data_store.union(&keys).await.unwrap(),
+            vec!["burak", "can", "selim"]
+        );
+
+        let with_missing = vec!["BetaUsers".to_string(), "Nobody".to_string()];
+        assert!(
+            data_store
+                .intersect(&with_missing)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(data_store.set_remove("Admins", "can").await, Ok(true));
+        assert_eq!(data_store.set_remove("Admins", "burak").await, Ok(true));
+        assert_eq!(data_store.keys().await, vec!["BetaUsers"]);
+    }
+
+    #[tokio::test]
+    async fn test_sorted_set_operations() {
+        let data_store = DataStore::new();
+        data_store
+            .sorted_add("Slow", 120.5, "/orders")
+            .await
+            .unwrap();
+        data_store.sorted_add("Slow", 80.0, "/users").await.unwrap();
+        data_store
+            .sorted_add("Slow", 300.0, "/reports")
+            .await
+            .unwrap();
+        assert_eq!(
+            data_store.sorted_add("Slow", 10.0, "/users").await,
+            Ok(false)
+        );
+
+        let members = data_store.sorted_range("Slow", 0, -1).await.unwrap();
+        let names: Vec<&str> = members.iter().map(|(m, _)| m.as_str()).collect();
+        assert_eq!(names, vec!["/users", "/orders", "/reports"]);
+
+        assert_eq!(
+            data_store.sorted_increment("Slow", 500.0, "/users").await,
+            Ok(510.0)
+        );
+        let top = data_store.sorted_range("Slow", -1, -1).await.unwrap();
+        assert_eq!(top, vec![("/users".to_string(), 510.0)]);
+
+        let between = data_store
+            .sorted_range_by_score("Slow", 100.0, 300.0)
+            .await
+            .unwrap();
+        assert_eq!(between.len(), 2);
+
+        assert_eq!(data_store.sorted_remove("Slow", "/orders").await, Ok(true));
+        assert_eq!(
+            data_store.sorted_range("Slow", 0, -1).await.unwrap().len(),
+            2
+        );
+
+        assert!(
+            data_store
+                .sorted_increment("Slow", f64::INFINITY, "/reports")
+                .await
+                .is_err()
+        );
+        data_store
+            .sorted_add("Slow", f64::INFINITY, "/reports")
+            .await
+            .unwrap();
+        assert!(
+            data_store
+                .sorted_increment("Slow", f64::NEG_INFINITY, "/reports")
+                .await
+                .is_err()
+        );
+        let scores = data_store.sorted_range("Slow", -1, -1).await.unwrap();
+        assert_eq!(scores, vec![("/reports".to_string(), f64::INFINITY)]);
+    }
+
+    #[tokio::test]
+    async fn test_stats_include_collections() {
+        let data_store = DataStore::new();
+        data_store.set_add("BetaUsers", "burak").await.unwrap();
+        data_store.sorted_add("Slow", 1.0, "/orders").await.unwrap();
+        let stats = data_store.stats().await;
+        assert!(stats.starts_with("Keys(2)"));
+        assert!(!stats.contains("Size(0 B)"));
+    }
+
+    #[test]
+    fn test_zadd_invalid_score() {
+        let config = Configuration::default();
+        let validation = Command::parse("ZADD Slow fast /orders").validate(&config);
+        assert_eq!(validation.unwrap_err(), "Score must be a number");
+    }
+
+    #[tokio::test]
+    async fn test_json_document_paths() {
+        let data_store = DataStore::new();
+        let document = serde_json::json!({ "host": "localhost", "replicas": [{ "port": 1 }] });
+        data_store.json_set("Db", "$", document, 100).await.unwrap();
+        data_store
+            .json_set("Db", "$.replicas[0].port", serde_json::json!(5432), 100)
+            .await
+            .unwrap();
+
+        let port = data_store.json_get("Db", "$.replicas[0].port").await;
+        assert_eq!(port, Ok(Some("5432".to_string())));
+        let port = data_store
+            .json_increment("Db", "$.replicas[0].port", 1.0, 100)
+            .await;
+        assert_eq!(port, Ok(serde_json::json!(5433)));
+        assert_eq!(data_store.json_get("Db", "$.missing").await, Ok(None));
+
+        assert_eq!(data_store.json_delete("Db", "$.host").await, Ok(true));
+        assert_eq!(data_store.json_get("Db", "$.host").await, Ok(None));
+        assert_eq!(data_store.json_delete("Db", "$").await, Ok(true));
+        assert!(data_store.is_empty().await);
+
+        let counter = serde_json::json!({ "n": i64::MAX });
+        data_store
+            .json_set("Counter", "$", counter, 100)
+            .await
+            .unwrap();
+        let n = data_store.json_increment("Counter", "$.n", 1.0, 100).await;
+        assert_eq!(n, Ok(serde_json::json!(i64::MAX as f64 + 1.0)));
+    }
+
+    #[tokio::test]
+    async fn test_json_document_max_length() {
+        let data_store = DataStore::new();
+        data_store
+            .json_set("Db", "$", serde_json::json!({ "a": 1 }), 12)
+            .await
+            .unwrap();
+        let grown = data_store
+            .json_set("Db", "$.name", serde_json::json!("too long"), 12)
+            .await;
+        assert_eq!(grown.unwrap_err(), "Value exceeds maximum length: 25");
+        assert_eq!(
+            data_store.json_get("Db", "$").await,
+            Ok(Some("{\"a\":1}".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_json_set_invalid_document() {
+        let config = Configuration::default();
+        let validation = Command::parse("JSON.SET Db $ {\"host\":").validate(&config);
+        assert!(validation.unwrap_err().starts_with("Invalid JSON document"));
+        let validation = Command::parse("JSON.GET Db $.items[x]").validate(&config);
+        assert_eq!(validation.unwrap_err(), "Invalid JSON path: $.items[x]");
+    }
+
+    #[test]
+    fn test_json_set_keeps_the_whitespace_of_the_document() {
+        let document = "{\"motd\": \"Welcome  to\tKiwi\"}";
+        match Command::parse(&format!("JSON.SET  Motd \t$  {}", document)) {
+            Command::JsonSet { key, path, json } => {
+                assert_eq!(
+                    (key.as_str(), path.as_str(), json.as_str()),
+                    ("Motd", "$", document)
+                )
+            }
+            cmd => panic!("Unexpected command: {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn test_glob_pattern_matching() {
+        use crate::store::pattern::matches;
+        assert!(matches("*", "anything"));
+        assert!(matches("Db*Conn", "DbMainConn"));
+        assert!(matches("user:?", "user:1"));
+        assert!(!matches("user:?", "user:10"));
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[!e]llo", "hello"));
+        assert!(matches("v[0-9]", "v7"));
+        assert!(matches("literal\\*", "literal*"));
+        assert!(!matches("literal\\*", "literalX"));
+    }
+
+    #[tokio::test]
+    async fn test_scan_returns_every_stable_key() {
+        let data_store = DataStore::new();
+        for i in 0..25 {
+            data_store.set(&format!("key:{:02}", i), "v").await;
+        }
+
+        let mut cursor = "0".to_string();
+        let mut seen = vec![];
+        let mut round = 0;
+        loop {
+            let (next, keys) = data_store.scan(&cursor, None, 4).await.unwrap();
+            seen.extend(keys);
+            // Keys churn between pages while the stable ones must all be returned
+            data_store.set(&format!("new:{}", round), "v").await;
+            data_store.remove(&format!("key:{:02}", 24 - round)).await;
+            round += 1;
+            if next == "0" {
+                break;
+            }
+            cursor = next;
+        }
+
+        for i in 0..(25 - round) {
+            assert!(seen.contains(&format!("key:{:02}", i)));
+        }
+        let unique: std::collections::HashSet<_> = seen.iter().collect();
+        assert_eq!(unique.len(), seen.len());
+    }
+
+    #[tokio::test]
+    async fn test_scan_with_match_and_keys_pattern() {
+        let data_store = DataStore::new();
+        data_store.set("DbConn", "localhost").await;
+        data_store.set("DbUser", "admin").await;
+        data_store.set("UseHttps", "Off").await;
+
+        assert_eq!(
+            data_store.keys_matching("Db*").await,
+            vec!["DbConn", "DbUser"]
+        );
+        let (cursor, keys) = data_store.scan("0", Some("*Http*"), 10).await.unwrap();
+        assert_eq!(cursor, "0");
+        assert_eq!(keys, vec!["UseHttps"]);
+        assert!(data_store.scan("zz", None, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_are_isolated_per_connection() {
+        let mut config = Configuration::default();
+        config
+            .namespaces
+            .insert("vault".to_string(), "VAULT_MODE".to_string());
+        config.max_namespaces = 3;
+        let address = start_server(server_state(config)).await;
+
+        let mut dev = TcpStream::connect(address).await.unwrap();
+        assert_eq!(send(&mut dev, "SELECT dev").await, "OK\n");
+        assert_eq!(send(&mut dev, "SET DbConn dev-db").await, "OK\n");
+        assert_eq!(send(&mut dev, "GET DbConn").await, "dev-db\n");
+
+        let mut other = TcpStream::connect(address).await.unwrap();
+        assert_eq!(send(&mut other, "GET DbConn").await, "NOT FOUND\n");
+        assert_eq!(send(&mut other, "SELECT vault").await, "OK\n");
+        let long_value = "x".repeat(50);
+        let response = send(&mut other, &format!("SET Secret {}", long_value)).await;
+        assert_eq!(response, "ERROR: Value exceeds maximum length: 50\n");
+
+        assert_eq!(send(&mut other, "FLUSHNS dev").await, "OK\n");
+        assert_eq!(send(&mut dev, "GET DbConn").await, "NOT FOUND\n");
+        assert_eq!(send(&mut dev, "FLUSHNS missing").await, "NOT FOUND\n");
+
+        assert!(
+            send(&mut other, "SELECT qa")
+                .await
+                .starts_with("ERROR: ERR_MAX_NAMESPACES")
+        );
+        assert_eq!(send(&mut other, "SELECT dev").await, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_split_and_pipelined_commands() {
+        let address = start_server(server_state(Configuration::default())).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+
+        client.write_all(b"SET Th").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(send(&mut client, "eme Dark").await, "OK\n");
+
+        let pipeline = "RPUSH Jobs resize\nRPUSH Jobs upload\nLRANGE Jobs 0 -1\nGET Theme\nPING\n";
+        client.write_all(pipeline.as_bytes()).await.unwrap();
+        let expected = "1\n2\nresize\nupload\nDark\nPONG\n";
+        let mut received = Vec::new();
+        while received.len() < expected.len() {
+            let mut buffer = [0; 1024];
+            let size = client.read(&mut buffer).await.unwrap();
+            assert!(size > 0);
+            received.extend_from_slice(&buffer[..size]);
+        }
+        assert_eq!(String::from_utf8_lossy(&received), expected);
+    }
+
+    #[test]
+    fn test_namespace_mode_and_name_validation() {
+        let config = Configuration::default();
+        assert_eq!(config.for_mode("CACHE_MODE").max_value_length, 255);
+        assert_eq!(config.for_mode("VAULT_MODE").max_value_length, 40);
+        let validation = Command::parse("SELECT dev:main").validate(&config);
+        assert_eq!(
+            validation.unwrap_err(),
+            "Namespace contains invalid characters: dev:main"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replication_full_sync_and_stream() {
+        let leader = server_state(Configuration::default());
+        let leader_address = start_server(leader.clone()).await;
+        let mut client = TcpStream::connect(leader_address).await.unwrap();
+        send(&mut client, "SET DbConn leader-db").await;
+        send(&mut client, "RPUSH Jobs resize").await;
+        send(&mut client, "SELECT dev").await;
+        send(&mut client, "SADD Flags dark-mode").await;
+
+        let follower = server_state(Configuration::default());
+        let follower_address = start_server(follower.clone()).await;
+        let mut replica = TcpStream::connect(follower_address).await.unwrap();
+        let command = format!("REPLICAOF 127.0.0.1 {}", leader_address.port());
+        assert_eq!(send(&mut replica, &command).await, "OK\n");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert_eq!(send(&mut replica, "GET DbConn").await, "leader-db\n");
+        assert_eq!(send(&mut replica, "LRANGE Jobs 0 -1").await, "resize\n");
+
+        send(&mut client, "SADD Flags beta").await;
+        send(&mut client, "FLUSHNS default").await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert_eq!(send(&mut replica, "GET DbConn").await, "NOT FOUND\n");
+        assert_eq!(send(&mut replica, "SELECT dev").await, "OK\n");
+        assert_eq!(
+            send(&mut replica, "SMEMBERS Flags").await,
+            "beta\ndark-mode\n"
+        );
+        assert_eq!(
+            send(&mut replica, "SET DbConn local").await,
+            "ERROR: READONLY You can't write against a read only replica\n"
+        );
+        let stats = send(&mut replica, "STATS").await;
+        assert!(stats.contains("Role(follower)"));
+        assert!(stats.contains("Link(up)"));
+        assert!(stats.contains("Offset(5)"));
+        assert!(stats.contains("Lag(0)"));
+
+        assert_eq!(send(&mut replica, "REPLICAOF NO ONE").await, "OK\n");
+        assert_eq!(send(&mut replica, "SET DbConn local").await, "OK\n");
+    }
+
+    #[test]
+    fn test_cluster_config_is_validated() {
+        let mut config = Configuration::default();
+        assert!(config.validate_cluster().is_ok());
+
+        config.cluster_nodes = vec!["127.0.0.1:5545".to_string(), "127.0.0.1:5546".to_string()];
+        assert!(config.validate_cluster().is_err());
+
+        config.cluster_nodes.push("127.0.0.1:5547".to_string());
+        assert_eq!(
+            config.validate_cluster(),
+            Err("Node address 127.0.0.1:5544 is not one of the cluster nodes".to_string())
+        );
+
+        config.cluster_nodes.push("127.0.0.1:5544".to_string());
+        assert!(config.validate_cluster().is_ok());
+        config.replica_of = Some("127.0.0.1:6000".to_string());
+        assert!(config.validate_cluster().is_err());
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let path = std::env::temp_dir().join(format!("kiwi-store-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "port = 6000\nmode = \"CACHE_MODE\"\nmax_connections = 10\n\n[namespaces]\ndev = \"VAULT_MODE\"\n",
+        )
+        .unwrap();
+        let file = Settings::from_file(&path).unwrap();
+        let flags = Settings {
+            port: Some(7000),
+            admin_password: Some("s3cret".to_string()),
+            ..Settings::default()
+        };
+        assert!(!format!("{:?}", flags).contains("s3cret"));
+        let config = Configuration::from_settings(file.merge(flags));
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.mode, "CACHE_MODE");
+        assert_eq!(config.max_value_length, 255);
+        assert_eq!(config.max_connections, 10);
+        assert_eq!(config.namespaces["dev"], "VAULT_MODE");
+        assert_eq!(config.node_address, "127.0.0.1:7000");
+        assert!(config.validate().is_ok());
+        let toml = config.to_toml();
+        assert!(toml.contains("port = 7000"));
+        assert!(toml.contains("admin_password = \"********\""));
+        assert!(!toml.contains("s3cret"));
+        assert!(!toml.contains("encryption_key"));
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("admin_password: Some(\"********\")"));
+        assert!(!debug.contains("s3cret"));
+
+        std::fs::write(&path, "prot = 6000\n").unwrap();
+        assert!(Settings::from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let invalid = |change: fn(&mut Configuration)| {
+            let mut config = Configuration::default();
+            change(&mut config);
+            config.validate()
+        };
+        assert!(Configuration::default().validate().is_ok());
+        assert_eq!(
+            invalid(|c| c.host = "127.0.0.0.1".to_string()),
+            Err("Invalid host: 127.0.0.0.1".to_string())
+        );
+        assert!(invalid(|c| c.port = 0).is_err());
+        assert!(invalid(|c| c.mode = "FAST_MODE".to_string()).is_err());
+        assert!(invalid(|c| c.max_connections = 0).is_err());
+        assert!(
+            invalid(|c| {
+                c.namespaces
+                    .insert("dev".to_string(), "FAST_MODE".to_string());
+            })
+            .is_err()
+        );
+        assert!(
+            invalid(|c| {
+                c.rate_limits.insert("delete".to_string(), 10);
+            })
+            .is_err()
+        );
+        assert!(invalid(|c| c.replica_of = Some("127.0.0.1".to_string())).is_err());
+        assert!(invalid(|c| c.host = "localhost".to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(key_slot("foo"), 12182);
+        assert_eq!(key_slot("123456789"), 12739);
+        assert_eq!(key_slot("{user1000}.following"), key_slot("user1000"));
+        assert_eq!(key_slot("foo{}{bar}"), key_slot("foo{}{bar}"));
+        assert_ne!(key_slot("foo{}{bar}"), key_slot("bar"));
+    }
+
+    #[tokio::test]
+    async fn test_sharding_redirects_and_migrates_slots() {
+        let listeners = [
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        ];
+        let nodes: Vec<String> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().to_string())
+            .collect();
+        let mut clients = Vec::new();
+        for (listener, node) in listeners.into_iter().zip(&nodes) {
+            let mut state = server_state(Configuration {
+                admin_password: Some("s3cret".to_string()),
+                ..Configuration::default()
+            });
+            state.sharding = Some(Sharding::new(node, &nodes));
+            let address = start_server_on(listener, state);
+            clients.push(TcpStream::connect(address).await.unwrap());
+        }
+        let [first, second] = &mut clients[..] else {
+            unreachable!()
+        };
+
+        assert_eq!(
+            send(first, "SET foo bar").await,
+            format!("MOVED 12182 {}\n", nodes[1])
+        );
+        assert_eq!(send(second, "SET foo bar").await, "OK\n");
+        assert_eq!(send(second, "RPUSH {foo}.jobs resize").await, "1\n");
+        assert_eq!(
+            send(second, "SINTER foo bar").await,
+            "ERROR: CROSSSLOT Keys in request don't hash to the same slot\n"
+        );
+        assert_eq!(
+            send(first, "CLUSTER SLOTS").await,
+            format!("0 8191 {}\n8192 16383 {}\n", nodes[0], nodes[1])
+        );
+
+        let migrate = format!("CLUSTER MIGRATE 12182 12182 {}", nodes[0]);
+        assert!(send(second, &migrate).await.contains("ERR_NOT_ADMIN"));
+        assert!(
+            send(first, &format!("CLUSTER SETSLOT 0 8191 {}", nodes[1]))
+                .await
+                .contains("ERR_NOT_ADMIN")
+        );
+        assert!(
+            send(first, "CLUSTER IMPORTING")
+                .await
+                .contains("ERR_NOT_ADMIN")
+        );
+        assert_eq!(send(second, "AUTH s3cret").await, "OK\n");
+        assert_eq!(send(second, &migrate).await, "MIGRATED 2 KEYS\n");
+        assert_eq!(send(first, "GET foo").await, "bar\n");
+        assert_eq!(send(first, "LRANGE {foo}.jobs 0 -1").await, "resize\n");
+        assert_eq!(
+            send(second, "GET foo").await,
+            format!("MOVED 12182 {}\n", nodes[0])
+        );
+        assert!(send(first, "STATS").await.contains("Slots(8193/16384)"));
+        assert_eq!(send(first, "AUTH s3cret").await, "OK\n");
+        assert!(
+            send(first, &format!("CLUSTER MIGRATE 100 9000 {}", nodes[1]))
+                .await
+                .contains("not all owned by this node")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_limits() {
+        let config = Configuration {
+            max_connections_per_ip: 1,
+            ..Configuration::default()
+        };
+        let address = start_server(server_state(config)).await;
+        let mut first = TcpStream::connect(address).await.unwrap();
+        assert_eq!(send(&mut first, "PING").await, "PONG\n");
+
+        let mut rejected = TcpStream::connect(address).await.unwrap();
+        let mut buffer = [0; 1024];
+        let size = rejected.read(&mut buffer).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buffer[..size]),
+            "ERROR: ERR_MAX_CLIENTS Max number of clients reached for 127.0.0.1: 1\n"
+        );
+        let stats = send(&mut first, "STATS").await;
+        assert!(
+            stats.contains("ActiveConnections(1), RejectedConnections(1), TotalConnections(2)")
+        );
+
+        let config = Configuration {
+            max_connections: 1,
+            connection_queue_timeout_ms: 2000,
+            ..Configuration::default()
+        };
+        let address = start_server(server_state(config)).await;
+        let mut first = TcpStream::connect(address).await.unwrap();
+        assert_eq!(send(&mut first, "PING").await, "PONG\n");
+        let mut queued = TcpStream::connect(address).await.unwrap();
+        let waiting = tokio::spawn(async move { send(&mut queued, "PING").await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert_eq!(waiting.await.unwrap(), "PONG\n");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_needs_an_admin_session() {
+        let config = Configuration {
+            admin_password: Some("s3cret".to_string()),
+            ..Configuration::default()
+        };
+        let address = start_server(server_state(config)).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+
+        assert!(
+            send(&mut client, "SHUTDOWN NOSAVE")
+                .await
+                .contains("ERR_NOT_ADMIN")
+        );
+        assert_eq!(send(&mut client, "PING").await, "PONG\n");
+        assert_eq!(send(&mut client, "AUTH s3cret").await, "OK\n");
+        assert_eq!(send(&mut client, "SHUTDOWN NOSAVE").await, "OK\n");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() {
+        let config = Configuration {
+            rate_limits: [("write".to_string(), 2)].into_iter().collect(),
+            admin_password: Some("s3cret".to_string()),
+            ..Configuration::default()
+        };
+        let address = start_server(server_state(config)).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let mut admin = TcpStream::connect(address).await.unwrap();
+
+        assert_eq!(send(&mut client, "SET Retry 1").await, "OK\n");
+        assert_eq!(send(&mut client, "SET Retry 2").await, "OK\n");
+        let limited = send(&mut client, "SET Retry 3").await;
+        let retry_after: u64 = limited
+            .strip_prefix("ERROR: ERR_RATE_LIMITED ")
+            .and_then(|ms| ms.trim().parse().ok())
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 500);
+        assert_eq!(send(&mut client, "GET Retry").await, "2\n");
+
+        // An admin session from the same address has its own bucket
+        assert_eq!(send(&mut admin, "AUTH s3cret").await, "OK\n");
+        assert_eq!(send(&mut admin, "SET Retry 4").await, "OK\n");
+
+        assert!(
+            send(&mut client, "RATELIMITS")
+                .await
+                .contains("ERR_NOT_ADMIN")
+        );
+        let limits = send(&mut admin, "RATELIMITS").await;
+        assert!(limits.starts_with("Limits(write=2/s), Limited(1)"));
+        assert!(limits.contains("127.0.0.1 default write 0/2"));
+        assert!(limits.contains("127.0.0.1 admin write 1/2"));
+
+        tokio::time::sleep(Duration::from_millis(retry_after)).await;
+        assert_eq!(send(&mut client, "SET Retry 3").await, "OK\n");
+    }
+
+    #[test]
+    fn test_rate_limiter_drops_the_least_recently_used_buckets() {
+        let limiter = RateLimiter::new(&Configuration {
+            rate_limits: [("read".to_string(), 1)].into_iter().collect(),
+            ..Configuration::default()
+        });
+        for i in 0..10_000u32 {
+            let ip = std::net::IpAddr::from(std::net::Ipv4Addr::from(i));
+            assert!(limiter.check(ip, "default", Category::Read).is_ok());
+        }
+        let newest = std::net::IpAddr::from(std::net::Ipv4Addr::from(9_999u32));
+        assert!(limiter.check(newest, "default", Category::Read).is_err());
+
+        let buckets = limiter.describe().lines().count() - 1;
+        assert!(buckets <= 5_001, "{} buckets kept", buckets);
+        assert!(limiter.describe().contains("0.0.39.15 default read 0/1"));
+    }
+
+    #[tokio::test]
+    async fn test_config_reload() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi-store-reload-{}.toml", std::process::id()));
+        std::fs::write(&path, "forbidden_keys = \"*\"\n").unwrap();
+        let mut state = server_state(Configuration::default());
+        state.reloader = Reloader::new(Some(path.clone()), Settings::default());
+        let address = start_server(state).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        assert_eq!(send(&mut client, "SET Theme Solarized").await, "OK\n");
+
+        std::fs::write(
+            &path,
+            "port = 6000\nmax_value_length = 5\nforbidden_keys = \"*\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            send(&mut client, "CONFIG RELOAD").await,
+            "OK Changed(forbidden_keys, max_value_length), RestartRequired(node_address, port)\n"
+        );
+        assert!(
+            send(&mut client, "SET Theme Monokai")
+                .await
+                .starts_with("ERROR")
+        );
+        assert!(send(&mut client, "SET Dark* On").await.starts_with("ERROR"));
+        assert_eq!(send(&mut client, "GET Theme").await, "Solarized\n");
+
+        std::fs::write(&path, "max_value_length = 0\n").unwrap();
+        assert!(
+            send(&mut client, "CONFIG RELOAD")
+                .await
+                .starts_with("ERROR")
+        );
+        assert!(send(&mut client, "SET Dark* On").await.starts_with("ERROR"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_get_set_rewrite() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi-store-rewrite-{}.toml", std::process::id()));
+        std::fs::write(&path, "# Staging server\nmax_list_length = 50\n").unwrap();
+        let flags = Settings {
+            encryption_key: Some("ab".repeat(32)),
+            ..Settings::default()
+        };
+        let mut state = server_state(Configuration::from_settings(flags.clone()));
+        state.reloader = Reloader::new(Some(path.clone()), flags);
+        let address = start_server(state).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+
+        assert_eq!(
+            send(&mut client, "CONFIG GET max_value_length").await,
+            "max_value_length 100\n"
+        );
+        assert_eq!(
+            send(&mut client, "CONFIG SET mode CACHE_MODE").await,
+            "OK\n"
+        );
+        assert_eq!(
+            send(&mut client, "CONFIG GET max_*_length").await,
+            "max_key_length 20\nmax_value_length 255\nmax_list_length 50\n"
+        );
+        assert!(
+            send(&mut client, "CONFIG SET port 6000")
+                .await
+                .contains("restart")
+        );
+        assert!(
+            send(&mut client, "CONFIG SET max_connections 0")
+                .await
+                .starts_with("ERROR")
+        );
+        assert!(
+            send(&mut client, "CONFIG SET colour blue")
+                .await
+                .starts_with("ERROR")
+        );
+
+        // Once a password is set, even local connections need to authenticate
+        assert_eq!(
+            send(&mut client, "CONFIG SET admin_password s3cret").await,
+            "OK\n"
+        );
+        assert!(
+            send(&mut client, "CONFIG GET mode")
+                .await
+                .contains("ERR_NOT_ADMIN")
+        );
+        assert!(send(&mut client, "AUTH guess").await.starts_with("ERROR"));
+        assert_eq!(send(&mut client, "AUTH s3cret").await, "OK\n");
+        assert_eq!(
+            send(&mut client, "CONFIG GET admin_password").await,
+            "admin_password ********\n"
+        );
+
+        assert_eq!(send(&mut client, "CONFIG REWRITE").await, "OK\n");
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("mode = \"CACHE_MODE\""));
+        assert!(content.contains("max_list_length = 50"));
+        assert!(content.contains("admin_password = \"s3cret\""));
+        assert!(!content.contains("max_value_length"));
+        assert!(!content.contains("encryption_key"));
+        assert!(!content.contains("host"));
+        assert_eq!(
+            send(&mut client, "CONFIG RELOAD").await,
+            "OK Changed(none), RestartRequired(none)\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_set_refuses_to_switch_encryption() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi-store-encrypt-{}.toml", std::process::id()));
+        std::fs::write(&path, "[modes.SECRETS]\nencryption = true\n").unwrap();
+        let flags = Settings {
+            encryption_key: Some("ab".repeat(32)),
+            ..Settings::default()
+        };
+        let config = Configuration::load(Some(&path), flags.clone()).unwrap();
+        let mut state = server_state(config);
+        state.reloader = Reloader::new(Some(path.clone()), flags);
+        let address = start_server(state).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+
+        assert_eq!(send(&mut client, "SET Theme Dark").await, "OK\n");
+        assert!(
+            send(&mut client, "CONFIG SET mode SECRETS").await.contains(
+                "Encryption can't be turned on or off for namespaces holding keys: default"
+            )
+        );
+        assert_eq!(send(&mut client, "GET Theme").await, "Dark\n");
+
+        assert_eq!(send(&mut client, "FLUSHNS").await, "OK\n");
+        assert_eq!(send(&mut client, "CONFIG SET mode SECRETS").await, "OK\n");
+        assert_eq!(send(&mut client, "SET Theme Dark").await, "OK\n");
+        assert_eq!(send(&mut client, "GET Theme").await, "Dark\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mode_profiles() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi-store-modes-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "mode = \"SESSIONS\"\n\n[modes.SESSIONS]\nmax_value_length = 64\nforbidden_keys = \"*\"\n\
+             eviction = \"oldest\"\nmax_keys = 2\n\n[modes.SECRETS]\nencryption = true\n",
+        )
+        .unwrap();
+        let config = Configuration::from_settings(Settings::from_file(&path).unwrap());
+        assert_eq!(config.max_value_length, 64);
+        assert!(config.forbidden_keys.contains(&'*'));
+        assert_eq!(config.profile.max_keys, 2);
+        assert_eq!(config.for_mode("CACHE_MODE").max_value_length, 255);
+        assert!(!config.for_mode("CACHE_MODE").forbidden_keys.contains(&'*'));
+        assert!(config.validate().is_ok());
+
+        let settings = Settings::from(&config);
+        assert_eq!(settings.max_value_length, None);
+        assert_eq!(settings.forbidden_keys, None);
+        let modes = settings.modes.unwrap();
+        assert_eq!(
+            modes.keys().collect::<Vec<_>>(),
+            vec!["SECRETS", "SESSIONS"]
+        );
+
+        let mut encrypted = config.clone();
+        encrypted
+            .namespaces
+            .insert("vault".to_string(), "SECRETS".to_string());
+        assert_eq!(
+            encrypted.validate(),
+            Err("Mode SECRETS needs an encryption_key".to_string())
+        );
+        encrypted.encryption_key = Some("ab".repeat(32));
+        assert!(encrypted.validate().is_ok());
+
+        std::fs::write(&path, "[modes.FAST]\nmax_keys = \"many\"\n").unwrap();
+        assert!(Settings::from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mode_enforcement() {
+        let mut config = Configuration {
+            encryption_key: Some("ab".repeat(32)),
+            ..Configuration::default()
+        };
+        for (name, profile) in [
+            (
+                "SESSIONS",
+                ModeProfile {
+                    eviction: Eviction::Oldest,
+                    max_keys: 2,
+                    default_ttl_secs: 1,
+                    ..ModeProfile::default()
+                },
+            ),
+            (
+                "READER",
+                ModeProfile {
+                    allowed_commands: vec!["GET".to_string()],
+                    max_keys: 1,
+                    ..ModeProfile::default()
+                },
+            ),
+            (
+                "SECRETS",
+                ModeProfile {
+                    encryption: true,
+                    ..ModeProfile::default()
+                },
+            ),
+        ] {
+            config.modes.insert(name.to_string(), profile);
+            config
+                .namespaces
+                .insert(name.to_lowercase(), name.to_string());
+        }
+        let state = server_state(config);
+        let address = start_server(state.clone()).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+
+        assert_eq!(send(&mut client, "SELECT sessions").await, "OK\n");
+        for key in ["First", "Second", "Third"] {
+            assert_eq!(send(&mut client, &format!("SET {} 1", key)).await, "OK\n");
+        }
+        assert_eq!(send(&mut client, "GET First").await, "NOT FOUND\n");
+        assert_eq!(send(&mut client, "GET Third").await, "1\n");
+        assert!(send(&mut client, "MODE").await.starts_with(
+            "MODE: SESSIONS, MaxKeyLength(20), MaxValueLength(100), ForbiddenKeys(none), \
+             AllowedCommands(all), Eviction(oldest), MaxKeys(2), DefaultTtl(1s)"
+        ));
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(send(&mut client, "GET Third").await, "NOT FOUND\n");
+
+        assert_eq!(send(&mut client, "SELECT reader").await, "OK\n");
+        assert_eq!(
+            send(&mut client, "SET Theme Dark").await,
+            "ERROR: ERR_NOT_ALLOWED SET is not allowed in READER\n"
+        );
+        assert_eq!(send(&mut client, "GET Theme").await, "NOT FOUND\n");
+        let reader = state.namespaces.get("reader").await.unwrap();
+        execute(
+            Command::parse("SET Theme Dark"),
+            &reader.store,
+            &reader.config(),
+        )
+        .await;
+        assert_eq!(
+            execute(
+                Command::parse("SET Font Mono"),
+                &reader.store,
+                &reader.config()
+            )
+            .await,
+            "ERROR: ERR_MAX_KEYS\n"
+        );
+
+        assert_eq!(send(&mut client, "SELECT secrets").await, "OK\n");
+        assert_eq!(send(&mut client, "SET ApiKey 42-42").await, "OK\n");
+        assert_eq!(send(&mut client, "GET ApiKey").await, "42-42\n");
+        assert!(
+            send(&mut client, "LPUSH Tokens a")
+                .await
+                .starts_with("ERROR")
+        );
+        let secrets = state.namespaces.get("secrets").await.unwrap();
+        let stored = secrets.store.get("ApiKey").await.unwrap().unwrap();
+        assert!(!stored.contains("42-42"));
+        assert_eq!(
+            secrets.snapshot().await,
+            vec!["SET ApiKey 42-42".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let state = server_state(Configuration::default());
+        let address = start_server(state.clone()).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        send(&mut client, "SET Theme Dark").await;
+        send(&mut client, "GET Theme").await;
+        send(&mut client, "GET Theme").await;
+        send(&mut client, "FETCH Theme").await;
+        send(&mut client, "SADD Theme Dark").await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics_address = listener.local_addr().unwrap();
+        tokio::spawn(metrics::serve(listener, state.clone()));
+        let mut http = TcpStream::connect(metrics_address).await.unwrap();
+        http.write_all(b"GET /metrics HTTP/1.1\r\nHost: kiwi\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        http.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("content-type: text/plain; version=0.0.4"));
+        for line in [
+            "kiwi_commands_total{command=\"GET\"} 2",
+            "kiwi_commands_total{command=\"UNKNOWN\"} 1",
+            "kiwi_command_duration_seconds_bucket{command=\"SET\",le=\"+Inf\"} 1",
+            "kiwi_command_duration_seconds_count{command=\"GET\"} 2",
+            "kiwi_errors_total{type=\"unknown_command\"} 1",
+            "kiwi_errors_total{type=\"wrongtype\"} 1",
+            "kiwi_keys{namespace=\"default\"} 1",
+            "kiwi_memory_bytes{namespace=\"default\"} ",
+            "kiwi_evictions_total{namespace=\"default\"} 0",
+            "kiwi_connections 1",
+        ] {
+            assert!(response.contains(line), "missing {}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_info_sections() {
+        let state = server_state(Configuration::default());
+        let address = start_server(state).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        send(&mut client, "SET Theme Dark").await;
+        send(&mut client, "GET Theme").await;
+        send(&mut client, "LPOP Theme").await;
+
+        let memory = send(&mut client, "INFO memory").await;
+        assert!(memory.starts_with("# Memory\nused_memory:"));
+        assert!(memory.contains("used_memory_keys:5\n"));
+        assert!(memory.contains("used_memory_values:4\n"));
+        assert!(!memory.contains("used_memory_overhead:0"));
+
+        let keyspace = send(&mut client, "info KEYSPACE").await;
+        assert_eq!(
+            keyspace,
+            "# Keyspace\ndefault:expires=0,keys=1,mode=DEFAULT_MODE\n"
+        );
+
+        let stats = send(&mut client, "INFO commandstats JSON").await;
+        let stats: serde_json::Value = serde_json::from_str(&stats).unwrap();
+        let get = &stats["commandstats"]["cmdstat_GET"];
+        assert_eq!(
+            (get["calls"].as_u64(), get["failed_calls"].as_u64()),
+            (Some(1), Some(0))
+        );
+        assert_eq!(stats["commandstats"]["cmdstat_LPOP"]["failed_calls"], 1);
+
+        let server = send(&mut client, "INFO server JSON").await;
+        let server: serde_json::Value = serde_json::from_str(&server).unwrap();
+        assert_eq!(server["server"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(server["server"]["mode"], "DEFAULT_MODE");
+
+        let replication = send(&mut client, "INFO replication").await;
+        assert!(replication.starts_with("# Replication\nrole:leader\n"));
+        assert!(
+            send(&mut client, "INFO disk")
+                .await
+                .starts_with("ERROR: Unknown INFO section: disk")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slowlog() {
+        let config = Configuration::from_settings(Settings {
+            mode: Some("VAULT_MODE".to_string()),
+            slowlog_threshold_us: Some(0),
+            slowlog_max_len: Some(2),
+            ..Settings::default()
+        });
+        let address = start_server(server_state(config)).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let local = client.local_addr().unwrap();
+        send(&mut client, "SET ApiKey 42-42").await;
+        send(&mut client, "GET ApiKey").await;
+        assert_eq!(send(&mut client, "SLOWLOG LEN").await, "2\n");
+
+        let entries = send(&mut client, "SLOWLOG GET 5").await;
+        let entries: Vec<&str> = entries.lines().collect();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].starts_with("2 "));
+        assert!(entries[0].ends_with(&format!("us {} SLOWLOG LEN", local)));
+        assert!(entries[1].ends_with("GET ApiKey"));
+        assert_eq!(send(&mut client, "SLOWLOG GET 1").await.lines().count(), 1);
+
+        assert_eq!(send(&mut client, "SLOWLOG RESET").await, "OK\n");
+        send(&mut client, "SET ApiKey 42-42").await;
+        send(&mut client, "AUTH hunter2").await;
+        let entries = send(&mut client, "SLOWLOG GET").await;
+        assert!(entries.contains("AUTH ***"));
+        assert!(entries.contains("SET ApiKey ***"));
+        assert!(!entries.contains("42-42") && !entries.contains("hunter2"));
+        assert!(
+            send(&mut client, "SLOWLOG GET x")
+                .await
+                .starts_with("ERROR: Count must be")
+        );
+
+        let address = start_server(server_state(Configuration::default())).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        send(&mut client, "GET ApiKey").await;
+        assert_eq!(send(&mut client, "SLOWLOG GET").await, "EMPTY\n");
+    }
+
+    #[tokio::test]
+    async fn test_monitor() {
+        let config = Configuration::from_settings(Settings {
+            mode: Some("VAULT_MODE".to_string()),
+            admin_password: Some("hunter2".to_string()),
+            ..Settings::default()
+        });
+        let address = start_server(server_state(config)).await;
+        let mut monitor = TcpStream::connect(address).await.unwrap();
+        assert!(
+            send(&mut monitor, "MONITOR")
+                .await
+                .starts_with("ERROR: ERR_NOT_ADMIN")
+        );
+        assert_eq!(send(&mut monitor, "AUTH hunter2").await, "OK\n");
+        assert_eq!(send(&mut monitor, "MONITOR").await, "OK\n");
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        send(&mut client, "SET ApiKey 42-42").await;
+        send(&mut client, "AUTH hunter2").await;
+        let mut feed = BufReader::new(monitor).lines();
+        let mut next = async || {
+            tokio::time::timeout(Duration::from_secs(1), feed.next_line())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        };
+        let client = client.local_addr().unwrap();
+        assert!(
+            next()
+                .await
+                .ends_with(&format!("[default {}] SET ApiKey ***", client))
+        );
+        assert!(next().await.ends_with("AUTH ***"));
+    }
+
+    #[tokio::test]
+    async fn test_client_registry() {
+        let address = start_server(server_state(Configuration::default())).await;
+        let mut admin = TcpStream::connect(address).await.unwrap();
+        let mut worker = TcpStream::connect(address).await.unwrap();
+        let worker_address = worker.local_addr().unwrap();
+        assert_eq!(send(&mut worker, "CLIENT SETNAME worker").await, "OK\n");
+        send(&mut worker, "SELECT jobs").await;
+        send(&mut worker, "RPUSH Queue resize").await;
+
+        let clients = send(&mut admin, "CLIENT LIST").await;
+        assert_eq!(clients.lines().count(), 2);
+        let line = clients
+            .lines()
+            .find(|l| l.contains(&format!("addr={} ", worker_address)))
+            .unwrap();
+        for field in [
+            "name=worker",
+            "user=default",
+            "namespace=jobs",
+            "cmd=RPUSH",
+            "bytes_in=53",
+            "bytes_out=8",
+        ] {
+            assert!(line.contains(field), "missing {} in {}", field, line);
+        }
+        assert!(
+            send(&mut admin, "CLIENT KILL")
+                .await
+                .starts_with("ERROR: Expected CLIENT KILL")
+        );
+        assert_eq!(send(&mut admin, "CLIENT KILL USER admin").await, "0\n");
+
+        // A blocked connection is ended too
+        worker.write_all(b"BLPOP Empty 0\n").await.unwrap();
+        let kill = format!("CLIENT KILL {}", worker_address);
+        assert_eq!(send(&mut admin, &kill).await, "1\n");
+        let mut buffer = [0; 16];
+        let read = tokio::time::timeout(Duration::from_secs(1), worker.read(&mut buffer)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(send(&mut admin, "CLIENT LIST").await.lines().count(), 1);
+
+        let config = Configuration {
+            admin_password: Some("hunter2".to_string()),
+            ..Configuration::default()
+        };
+        let address = start_server(server_state(config)).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        assert!(
+            send(&mut client, "CLIENT LIST")
+                .await
+                .starts_with("ERROR: ERR_NOT_ADMIN")
+        );
+        send(&mut client, "AUTH hunter2").await;
+        assert!(
+            send(&mut client, "CLIENT LIST")
+                .await
+                .contains("user=admin")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let path =
+            std::env::temp_dir().join(format!("kiwi-store-audit-{}.log", std::process::id()));
+        let rotated = |n: usize| format!("{}.{}", path.display(), n);
+        let config = Configuration {
+            audit_log: Some(path.display().to_string()),
+            audit_log_max_bytes: 1000,
+            audit_log_max_files: 2,
+            audit_key: Some("audit-secret".to_string()),
+            ..Configuration::default()
+        };
+        let mut state = server_state(config);
+        let audit = AuditLog::open(&path.display().to_string()).unwrap();
+        state.audit = audit.clone();
+        let address = start_server(state).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        send(&mut client, "CLIENT SETNAME deployer").await;
+        send(&mut client, "SET ApiKey 42-42").await;
+        send(&mut client, "GET ApiKey").await;
+        send(&mut client, "SADD ApiKey 42-42").await;
+        send(&mut client, "REMOVE ApiKey").await;
+
+        audit.flush().await;
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(!log.contains("42-42"));
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        let set = &records[0];
+        assert_eq!(set["command"], "SET");
+        assert_eq!(set["keys"], serde_json::json!(["ApiKey"]));
+        assert_eq!(
+            set["value_hmac"],
+            crypto::hmac_sha256_hex("audit-secret", "42-42")
+        );
+        assert_eq!(set["client"], client.local_addr().unwrap().to_string());
+        assert_eq!(
+            (set["name"].as_str(), set["user"].as_str()),
+            (Some("deployer"), Some("default"))
+        );
+        assert_eq!(set["namespace"], "default");
+        assert!(set["time"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(
+            (set["ok"].as_bool(), records[1]["ok"].as_bool()),
+            (Some(true), Some(false))
+        );
+        assert!(records[2]["value_hmac"].is_null());
+
+        for i in 0..20 {
+            send(&mut client, &format!("SET Key{} {}", i, i)).await;
+        }
+        audit.flush().await;
+        assert!(std::path::Path::new(&rotated(2)).exists());
+        assert!(!std::path::Path::new(&rotated(3)).exists());
+        assert!(std::fs::metadata(&path).unwrap().len() <= 1000);
+        for file in [path.display().to_string(), rotated(1), rotated(2)] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_spans() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .finish();
+        // The test runtime runs the server on this thread, so its spans go to this subscriber
+        let _default = tracing::subscriber::set_default(subscriber);
+        let address = start_server(server_state(Configuration::default())).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        assert!(
+            send(
+                &mut client,
+                "TRACEPARENT 00-4bf92f3577b34da6a3ce929d0e0e4736"
+            )
+            .await
+            .starts_with("ERROR: Invalid traceparent")
+        );
+        let traceparent = format!("TRACEPARENT 00-{}-00f067aa0ba902b7-01", trace_id);
+        assert_eq!(send(&mut client, &traceparent).await, "OK\n");
+        send(&mut client, "SET Theme Dark").await;
+        send(&mut client, "LPUSH Theme Light").await;
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let closed = |command: &str| {
+            logs.lines()
+                .find(|l| l.contains(&format!("command={} ", command)) && l.contains(": close "))
+                .unwrap_or_else(|| panic!("no span of {} in {}", command, logs))
+                .to_string()
+        };
+        let set = closed("SET");
+        for field in [
+            "key=\"Theme\"",
+            "namespace=\"default\"",
+            "result=\"ok\"",
+            "duration_us=",
+            &format!("trace_id=\"{}\"", trace_id),
+        ] {
+            assert!(set.contains(field), "missing {} in {}", field, set);
+        }
+        assert!(set.contains(&format!(
+            "connection{{client={}",
+            client.local_addr().unwrap()
+        )));
+        assert!(closed("LPUSH").contains("result=\"wrongtype\""));
+    }
+
+    #[tokio::test]
+    async fn test_key_history() {
+        let config = Configuration {
+            history_max_versions: 3,
+            ..Configuration::default()
+        };
+        let address = start_server(server_state(config)).await;
+        let mut client = TcpStream::connect(address).await.unwrap();
+        for command in [
+            "SET Theme Dark",
+            "SET Theme Light",
+            "REMOVE Theme",
+            "SET Theme Blue",
+        ] {
+            send(&mut client, command).await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let history = send(&mut client, "HISTORY Theme").await;
+        let versions: Vec<Vec<&str>> = history
+            .lines()
+            .map(|l| l.splitn(3, ' ').collect())
+            .collect();
+        let changes: Vec<(&str, &str)> = versions.iter().map(|v| (v[0], v[2])).collect();
+        assert_eq!(
+            changes,
+            vec![("4", "SET Blue"), ("3", "REMOVE"), ("2", "SET Light")]
+        );
+        // Times are written in milliseconds, a millisecond later is surely past the write
+        let after = |version: &Vec<&str>| {
+            let time = chrono::DateTime::parse_from_rfc3339(version[1]).unwrap();
+            (time + chrono::Duration::milliseconds(1)).to_rfc3339()
+        };
+        let light = send(&mut client, &format!("GETAT Theme {}", after(&versions[2]))).await;
+        assert_eq!(light, "Light\n");
+        let removed = send(&mut client, &format!("GETAT Theme {}", after(&versions[1]))).await;
+        assert_eq!(removed, "NOT FOUND\n");
+        assert!(
+            send(&mut client, "GETAT Theme 0")
+                .await
+                .starts_with("ERROR: History")
+        );
+        assert!(
+            send(&mut client, "GETAT Theme yesterday")
+                .await
+                .starts_with("ERROR: Time")
+        );
+
+        assert_eq!(send(&mut client, "ROLLBACK Theme 2").await, "OK\n");
+        assert_eq!(send(&mut client, "GET Theme").await, "Light\n");
+        let latest = send(&mut client, "HISTORY Theme").await;
+        assert!(latest.lines().next().unwrap().ends_with(" SET Light"));
+        assert!(latest.starts_with("5 "));
+        assert_eq!(
+            send(&mut client, "ROLLBACK Theme 1").await,
+            "ERROR: Version 1 of Theme is not in the history\n"
+        );
+        assert_eq!(send(&mut client, "ROLLBACK Theme 3").await, "OK\n");
+        assert_eq!(send(&mut client, "GET Theme").await, "NOT FOUND\n");
+        assert_eq!(send(&mut client, "HISTORY Nothing").await, "NOT FOUND\n");
+    }
+
+    #[tokio::test]
+    async fn test_history_of_removed_and_evicted_keys_is_dropped() {
+        let store = DataStore::new();
+        let retention = Retention {
+            max_versions: 10,
+            max_age: Some(Duration::from_millis(20)),
+        };
+        store.set("Theme", "Dark").await;
+        store.record_version("Theme", &retention).await;
+        store.remove("Theme").await;
+        store.record_version("Theme", &retention).await;
+        assert_eq!(store.history("Theme", &retention).await.len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(store.history("Theme", &retention).await.is_empty());
+        assert_eq!(store.memory_usage().await.history, 0);
+
+        store.set("First", "1").await;
+        store.touch("First", None).await;
+        store.record_version("First", &retention).await;
+        assert_eq!(store.evict_oldest().await, Some("First".to_string()));
+        assert!(store.history("First", &retention).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dump_and_restore() {
+        let source = start_server(server_state(Configuration::default())).await;
+        let mut client = TcpStream::connect(source).await.unwrap();
+        let mut commands: Vec<String> =
+            (0..250).map(|i| format!("SET Key{:03} {}", i, i)).collect();
+        commands.extend(
+            [
+                "RPUSH Jobs resize",
+                "RPUSH Jobs upload",
+                "ZADD Scores 12.5 alice",
+                "JSON.SET Db $ {\"port\":5432}",
+            ]
+            .map(String::from),
+        );
+        for command in commands {
+            send(&mut client, &command).await;
+        }
+
+        client.write_all(b"DUMP\n").await.unwrap();
+        let mut lines = BufReader::new(client).lines();
+        let mut entries = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if line == "END" {
+                break;
+            }
+            entries.push(line);
+        }
+        assert_eq!(entries.len(), 253);
+        assert!(
+            entries.contains(
+                &r#"{"key":"Jobs","type":"list","value":["resize","upload"]}"#.to_string()
+            )
+        );
+        assert!(
+            entries.contains(
+                &r#"{"key":"Scores","type":"zset","value":[["alice",12.5]]}"#.to_string()
+            )
+        );
+
+        let target = start_server(server_state(Configuration::default())).await;
+        let mut client = TcpStream::connect(target).await.unwrap();
+        send(&mut client, "SET Key000 Changed").await;
+        send(&mut client, "RPUSH Jobs old").await;
+        let restore = |options: &str| format!("RESTORE {}\n{}\nEND", options, entries.join("\n"));
+
+        let response = send(&mut client, &restore("FAIL")).await;
+        assert_eq!(
+            response,
+            "ERROR: Entry 2: Key already exists: Jobs, 1 keys restored before it\n"
+        );
+        let response = send(&mut client, &restore("SKIP DRYRUN")).await;
+        assert_eq!(response, "DRY RUN RESTORED 250 SKIPPED 3\n");
+        assert_eq!(send(&mut client, "GET Key001").await, "NOT FOUND\n");
+
+        let response = send(&mut client, &restore("SKIP")).await;
+        assert_eq!(response, "RESTORED 250 SKIPPED 3\n");
+        assert_eq!(send(&mut client, "GET Key000").await, "Changed\n");
+        assert_eq!(send(&mut client, "GET Key249").await, "249\n");
+
+        let response = send(&mut client, &restore("OVERWRITE")).await;
+        assert_eq!(response, "RESTORED 253 SKIPPED 0\n");
+        assert_eq!(send(&mut client, "GET Key000").await, "0\n");
+        assert_eq!(
+            send(&mut client, "LRANGE Jobs 0 -1").await,
+            "resize\nupload\n"
+        );
+        assert_eq!(send(&mut client, "JSON.GET Db $.port").await, "5432\n");
+
+        let response = send(&mut client, "RESTORE\n{\"key\":\"Bad\"}\nEND").await;
+        assert!(response.starts_with("ERROR: Entry 1: Invalid entry"));
+        assert_eq!(send(&mut client, "PING").await, "PONG\n");
+
+        let oversize = format!("{}\n{}\nEND", "x".repeat(3 * 1024 * 1024), entries[0]);
+        let response = send(&mut client, &format!("RESTORE OVERWRITE\n{}", oversize)).await;
+        assert_eq!(
+            response,
+            "ERROR: Entry 1: Line exceeds 1048576 bytes, 0 keys restored before it\n"
+        );
+        assert_eq!(send(&mut client, "PING").await, "PONG\n");
+    }
 }
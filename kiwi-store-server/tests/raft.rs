@@ -0,0 +1,185 @@
+//! Runs a three node Raft cluster as server processes on localhost.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+struct Node {
+    process: Child,
+}
+
+impl Node {
+    fn start(port: u16, cluster: &[u16]) -> Self {
+        Self::start_with(port, cluster, &[])
+    }
+
+    fn start_with(port: u16, cluster: &[u16], envs: &[(&str, &str)]) -> Self {
+        let nodes: Vec<String> = cluster.iter().map(|p| format!("127.0.0.1:{}", p)).collect();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_kiwi-store-server"));
+        command
+            .env("HOST", "127.0.0.1")
+            .env("PORT", port.to_string())
+            .env("CLUSTER_NODES", nodes.join(","))
+            .env_remove("NODE_ADDRESS")
+            .env_remove("REPLICA_OF")
+            .envs(envs.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let node = Node {
+            process: command.spawn().expect("Failed to start the server"),
+        };
+        wait_until(|| TcpStream::connect(("127.0.0.1", port)).is_ok());
+        node
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Sends a command to a node, returning `None` if the node can't be reached.
+fn send(address: &str, command: &str) -> Option<String> {
+    let mut stream = TcpStream::connect(address).ok()?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .ok()?;
+    stream.write_all(format!("{}\n", command).as_bytes()).ok()?;
+    let mut buffer = [0; 1024];
+    let size = stream.read(&mut buffer).ok()?;
+    Some(String::from_utf8_lossy(&buffer[..size]).to_string())
+}
+
+/// Sends a command to the cluster, following `MOVED` redirects and trying
+/// the other nodes while no leader is reachable.
+fn send_to_cluster(addresses: &[String], command: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let mut target = addresses[0].clone();
+    let mut next = 0;
+    loop {
+        assert!(
+            Instant::now() < deadline,
+            "Cluster did not answer: {}",
+            command
+        );
+        match send(&target, command) {
+            Some(response) if response.starts_with("MOVED ") => {
+                target = response["MOVED ".len()..].trim().to_string();
+            }
+            Some(response) if !response.starts_with("ERROR") => return response,
+            _ => {
+                sleep(Duration::from_millis(100));
+                next = (next + 1) % addresses.len();
+                target = addresses[next].clone();
+            }
+        }
+    }
+}
+
+fn leader(addresses: &[String]) -> Option<String> {
+    addresses
+        .iter()
+        .find(|a| send(a, "STATS").is_some_and(|s| s.contains("Raft(leader)")))
+        .cloned()
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out waiting for condition");
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn acknowledged_writes_survive_leader_failure() {
+    let ports: Vec<u16> = (0..3).map(|_| free_port()).collect();
+    let addresses: Vec<String> = ports.iter().map(|p| format!("127.0.0.1:{}", p)).collect();
+    let mut nodes: Vec<Option<Node>> = ports
+        .iter()
+        .map(|p| Some(Node::start(*p, &ports)))
+        .collect();
+
+    wait_until(|| leader(&addresses).is_some());
+    let first_leader = leader(&addresses).unwrap();
+    let follower = addresses.iter().find(|a| **a != first_leader).unwrap();
+    wait_until(|| {
+        send(follower, "STATS").is_some_and(|s| s.contains(&format!("Leader({})", first_leader)))
+    });
+    assert_eq!(
+        send(follower, "SET Region eu-west").unwrap(),
+        format!("MOVED {}\n", first_leader)
+    );
+
+    let mut acknowledged = Vec::new();
+    for i in 0..40 {
+        if i == 20 {
+            let index = addresses.iter().position(|a| *a == first_leader).unwrap();
+            nodes[index].take();
+        }
+        let key = format!("Key{}", i);
+        if send_to_cluster(&addresses, &format!("SET {} Value{}", key, i)) == "OK\n" {
+            acknowledged.push((key, format!("Value{}\n", i)));
+        }
+    }
+
+    let new_leader = leader(&addresses).expect("A new leader should be elected");
+    assert_ne!(new_leader, first_leader);
+    assert_eq!(acknowledged.len(), 40);
+    for (key, value) in &acknowledged {
+        assert_eq!(&send(&new_leader, &format!("GET {}", key)).unwrap(), value);
+    }
+    assert!(
+        send(&new_leader, "STATS")
+            .unwrap()
+            .contains(&format!("Leader({})", new_leader))
+    );
+}
+
+#[test]
+fn restarted_node_catches_up_from_a_snapshot() {
+    let password = [("ADMIN_PASSWORD", "s3cret")];
+    let ports: Vec<u16> = (0..3).map(|_| free_port()).collect();
+    let addresses: Vec<String> = ports.iter().map(|p| format!("127.0.0.1:{}", p)).collect();
+    let mut nodes: Vec<Option<Node>> = ports
+        .iter()
+        .map(|p| Some(Node::start_with(*p, &ports, &password)))
+        .collect();
+    wait_until(|| leader(&addresses).is_some());
+    let leader = leader(&addresses).unwrap();
+    assert!(send(&leader, "RAFT").unwrap().contains("ERR_NOT_ADMIN"));
+
+    // Enough writes for the leader to drop the oldest entries from its log
+    let writes = 1100;
+    let mut stream = TcpStream::connect(&leader).unwrap();
+    let pipeline: String = (0..writes)
+        .map(|i| format!("SET Key{} Value{}\n", i, i))
+        .collect();
+    stream.write_all(pipeline.as_bytes()).unwrap();
+    let mut reader = BufReader::new(stream);
+    for _ in 0..writes {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "OK\n");
+    }
+
+    let index = addresses.iter().position(|a| *a != leader).unwrap();
+    nodes[index].take();
+    nodes[index] = Some(Node::start_with(ports[index], &ports, &password));
+    let keys = format!("keys={},", writes);
+    wait_until(|| {
+        send(&addresses[index], "INFO keyspace").is_some_and(|info| info.contains(&keys))
+    });
+}
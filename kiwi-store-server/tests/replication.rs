@@ -0,0 +1,143 @@
+//! Runs a leader and a follower as two server processes on localhost.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start(port: u16, replica_of: Option<u16>) -> Self {
+        Self::start_with(port, replica_of, &[])
+    }
+
+    fn start_with(port: u16, replica_of: Option<u16>, envs: &[(&str, &str)]) -> Self {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_kiwi-store-server"));
+        command
+            .env("HOST", "127.0.0.1")
+            .env("PORT", port.to_string())
+            .env(
+                "REPLICA_OF",
+                replica_of
+                    .map(|p| format!("127.0.0.1:{}", p))
+                    .unwrap_or_default(),
+            )
+            .envs(envs.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let server = Server {
+            process: command.spawn().expect("Failed to start the server"),
+            port,
+        };
+        wait_until(|| TcpStream::connect(("127.0.0.1", port)).is_ok());
+        server
+    }
+
+    fn send(&self, command: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream
+            .write_all(format!("{}\n", command).as_bytes())
+            .unwrap();
+        let mut buffer = [0; 1024];
+        let size = stream.read(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..size]).to_string()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out waiting for condition");
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn follower_syncs_streams_and_reconnects() {
+    let leader_port = free_port();
+    let leader = Server::start(leader_port, None);
+    assert_eq!(leader.send("SET DbConn leader-db"), "OK\n");
+
+    let follower = Server::start(free_port(), Some(leader_port));
+    wait_until(|| follower.send("GET DbConn") == "leader-db\n");
+
+    assert_eq!(leader.send("SET UseHttps On"), "OK\n");
+    wait_until(|| follower.send("GET UseHttps") == "On\n");
+    assert!(follower.send("SET UseHttps Off").contains("READONLY"));
+    assert!(follower.send("STATS").contains("Role(follower)"));
+
+    drop(leader);
+    wait_until(|| follower.send("STATS").contains("Link(down)"));
+    assert_eq!(follower.send("GET UseHttps"), "On\n");
+
+    let leader = Server::start(leader_port, None);
+    assert_eq!(leader.send("SET Region eu-west"), "OK\n");
+    wait_until(|| follower.send("GET Region") == "eu-west\n");
+    assert_eq!(follower.send("GET UseHttps"), "NOT FOUND\n");
+}
+
+#[test]
+fn sync_needs_an_admin_session() {
+    let password = [("ADMIN_PASSWORD", "s3cret")];
+    let leader_port = free_port();
+    let leader = Server::start_with(leader_port, None, &password);
+    assert_eq!(leader.send("SET ApiKey abc123"), "OK\n");
+    assert!(leader.send("SYNC").contains("ERR_NOT_ADMIN"));
+    assert!(
+        leader
+            .send("REPLICAOF 127.0.0.1 1")
+            .contains("ERR_NOT_ADMIN")
+    );
+
+    let follower = Server::start_with(free_port(), Some(leader_port), &password);
+    wait_until(|| follower.send("GET ApiKey") == "abc123\n");
+
+    let stranger = Server::start(free_port(), Some(leader_port));
+    sleep(Duration::from_millis(500));
+    assert_eq!(stranger.send("GET ApiKey"), "NOT FOUND\n");
+    assert!(stranger.send("STATS").contains("Link(down)"));
+}
+
+#[test]
+fn follower_pops_what_a_push_hands_to_a_blocked_client() {
+    let leader_port = free_port();
+    let leader = Server::start(leader_port, None);
+    let follower = Server::start(free_port(), Some(leader_port));
+    assert_eq!(leader.send("RPUSH Jobs seed"), "1\n");
+    wait_until(|| follower.send("LRANGE Jobs 0 -1") == "seed\n");
+    assert_eq!(leader.send("LPOP Jobs"), "seed\n");
+
+    let blocked = std::thread::spawn(move || {
+        let mut stream = TcpStream::connect(("127.0.0.1", leader_port)).unwrap();
+        stream.write_all(b"BLPOP Jobs 5\n").unwrap();
+        let mut buffer = [0; 1024];
+        let size = stream.read(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..size]).to_string()
+    });
+    sleep(Duration::from_millis(300));
+    assert_eq!(leader.send("RPUSH Jobs first"), "1\n");
+    assert_eq!(leader.send("RPUSH Jobs second"), "1\n");
+    assert_eq!(blocked.join().unwrap(), "first\n");
+
+    wait_until(|| follower.send("LRANGE Jobs 0 -1") == "second\n");
+}
@@ -0,0 +1,106 @@
+//! Stops a server process with the SHUTDOWN command and with SIGTERM.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn start(port: u16) -> Child {
+    let child = Command::new(env!("CARGO_BIN_EXE_kiwi-store-server"))
+        .env("HOST", "127.0.0.1")
+        .env("PORT", port.to_string())
+        .env("SHUTDOWN_TIMEOUT_MS", "3000")
+        .env_remove("REPLICA_OF")
+        .env_remove("CLUSTER_NODES")
+        .env_remove("SHARD_NODES")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start the server");
+    wait_until(|| TcpStream::connect(("127.0.0.1", port)).is_ok());
+    child
+}
+
+fn send(stream: &mut TcpStream, command: &str) -> String {
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .unwrap();
+    let mut buffer = [0; 1024];
+    let size = stream.read(&mut buffer).unwrap();
+    String::from_utf8_lossy(&buffer[..size]).to_string()
+}
+
+fn wait_for_exit(child: &mut Child) -> ExitStatus {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            panic!("Server did not exit");
+        }
+        sleep(Duration::from_millis(50));
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out waiting for condition");
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn shutdown_command_drains_in_flight_commands() {
+    let port = free_port();
+    let mut server = start(port);
+
+    let mut idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    assert_eq!(send(&mut idle, "SET Maintenance On"), "OK\n");
+
+    // A blocking pop is still running when the shutdown starts and must get its answer
+    let mut busy = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    busy.write_all(b"BLPOP Jobs 1\n").unwrap();
+    sleep(Duration::from_millis(200));
+
+    let mut admin = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    assert_eq!(send(&mut admin, "SHUTDOWN NOSAVE"), "OK\n");
+
+    let mut buffer = [0; 1024];
+    let size = busy.read(&mut buffer).unwrap();
+    assert_eq!(String::from_utf8_lossy(&buffer[..size]), "TIMEOUT\n");
+    assert_eq!(idle.read(&mut buffer).unwrap(), 0);
+
+    assert!(wait_for_exit(&mut server).success());
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_stops_the_server() {
+    let port = free_port();
+    let mut server = start(port);
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    assert_eq!(send(&mut client, "PING"), "PONG\n");
+
+    let status = Command::new("kill")
+        .args(["-TERM", &server.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    assert!(wait_for_exit(&mut server).success());
+    let mut buffer = [0; 16];
+    assert_eq!(client.read(&mut buffer).unwrap_or(0), 0);
+}